@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS source_setting (
+    `user_id` int not null,
+    `source` text not null,
+    `reminders` text,
+    PRIMARY KEY (`user_id`, `source`)
+);
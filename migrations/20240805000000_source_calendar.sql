@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE `source_setting` ADD COLUMN `calendar_id` TEXT;
+ALTER TABLE `source_setting` ADD COLUMN `color_id` TEXT;
+ALTER TABLE `google_event` ADD COLUMN `calendar_id` TEXT;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE `reservation` ADD COLUMN `recurrence` TEXT;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE `reservation` ADD COLUMN `tentative` BOOLEAN NOT NULL DEFAULT FALSE;
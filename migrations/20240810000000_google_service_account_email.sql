@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE `google_user` ADD COLUMN `service_account_email` TEXT;
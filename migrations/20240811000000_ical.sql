@@ -0,0 +1,5 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS ical_user (
+    `user_id` int primary key not null,
+    `urls` text not null
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE `source_setting` ADD COLUMN `tentative_color_id` TEXT;
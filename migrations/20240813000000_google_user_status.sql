@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE `google_user` ADD COLUMN `email` TEXT;
+ALTER TABLE `google_user` ADD COLUMN `last_error` TEXT;
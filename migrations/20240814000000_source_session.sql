@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE `source_session` (
+    `user_id` int not null,
+    `source` text not null,
+    `expired` bool not null,
+    `last_error` text,
+    `checked_at` datetime not null,
+    PRIMARY KEY (`user_id`, `source`)
+);
@@ -0,0 +1,5 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS lotte_cinema_user (
+    `user_id` int primary key not null,
+    `session_id` text not null
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE `reservation` ADD COLUMN `title_parts` TEXT;
+ALTER TABLE `google_user` ADD COLUMN `locale` TEXT NOT NULL DEFAULT 'ko';
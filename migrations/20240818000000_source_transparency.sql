@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE `source_setting` ADD COLUMN `transparency` TEXT NOT NULL DEFAULT 'opaque';
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE `google_user` ADD COLUMN `calendar_revoked` BOOLEAN NOT NULL DEFAULT FALSE;
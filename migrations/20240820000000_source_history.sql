@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE `source_history` (
+    `user_id` int not null,
+    `source` text not null,
+    `imported_at` datetime not null,
+    PRIMARY KEY (`user_id`, `source`)
+);
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE `sync_failure` (
+    `user_id` int not null,
+    `reservation_id` text not null,
+    `last_error` text not null,
+    `attempts` int not null,
+    `failed_at` datetime not null,
+    PRIMARY KEY (`user_id`, `reservation_id`)
+);
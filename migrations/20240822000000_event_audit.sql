@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE `event_audit` (
+    `id` integer primary key autoincrement,
+    `user_id` int not null,
+    `batch` text not null,
+    `action` text not null,
+    `event_id` text not null,
+    `calendar_id` text not null,
+    `reservation_id` text not null,
+    `before` text,
+    `after` text,
+    `created_at` datetime not null,
+    `undone_at` datetime
+);
+CREATE INDEX `event_audit_user_batch` ON `event_audit` (`user_id`, `batch`);
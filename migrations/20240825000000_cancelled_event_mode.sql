@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE `google_user` ADD COLUMN `cancelled_event_mode` TEXT NOT NULL DEFAULT 'delete';
@@ -0,0 +1,5 @@
+-- Add migration script here
+CREATE TABLE `allowed_email` (
+    `email` text primary key not null,
+    `created_at` datetime not null
+);
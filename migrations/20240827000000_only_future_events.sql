@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE `google_user` ADD COLUMN `only_future_events` BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE `reservation` ADD COLUMN `last_seen_at` DATETIME NOT NULL DEFAULT '1970-01-01 00:00:00';
+UPDATE `reservation` SET `last_seen_at` = `updated_at`;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE `google_event` ADD COLUMN `written_at` DATETIME;
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE `event_audit` ADD COLUMN `before_calendar_id` text;
+CREATE INDEX `event_audit_created_at` ON `event_audit` (`created_at`);
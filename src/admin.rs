@@ -0,0 +1,765 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use axum::{
+    extract::Path,
+    response::{IntoResponse as _, Response},
+    routing::{delete, get, post},
+    Extension, Json, Router,
+};
+use axum_sessions::extractors::ReadableSession;
+use futures::TryStreamExt;
+use hyper::StatusCode;
+use log::{debug, error, info};
+use sqlx::SqlitePool;
+
+use crate::{google_calendar, UserId};
+
+#[derive(Debug, serde::Serialize)]
+pub struct UserStatus {
+    pub user_id: UserId,
+    /// Not known until the user logs in again after the email is kept
+    pub email: Option<String>,
+    /// Sources the user has registered sessions of
+    pub sources: Vec<String>,
+    /// Sources of which the last ping failed
+    pub expired_sources: Vec<String>,
+    /// `None` when the user has never logged in to google
+    pub last_synced: Option<chrono::DateTime<chrono::Utc>>,
+    /// Error of the last sync, `None` when it succeeded
+    pub last_error: Option<String>,
+    /// Events failed to sync
+    pub sync_failures: google_calendar::SyncFailures,
+}
+
+/// All users with their sources and sync status
+pub async fn list_users(db: &SqlitePool) -> anyhow::Result<Vec<UserStatus>> {
+    let mut sources = BTreeMap::<UserId, Vec<String>>::new();
+    let mut rows = sqlx::query!(
+        r#"SELECT `user_id` as `user_id!: UserId`, `source` as `source!: String` FROM (
+            SELECT `user_id`, 'naver' as `source` FROM `naver_user`
+            UNION ALL SELECT `user_id`, 'kobus' FROM `kobus_user`
+            UNION ALL SELECT `user_id`, 'catch_table' FROM `catch_table_user`
+            UNION ALL SELECT `user_id`, 'cgv' FROM `cgv_user`
+            UNION ALL SELECT `user_id`, 'megabox' FROM `megabox_user`
+            UNION ALL SELECT `user_id`, 'lotte_cinema' FROM `lotte_cinema_user`
+            UNION ALL SELECT `user_id`, 'bustago' FROM `bustago_user`
+            UNION ALL SELECT `user_id`, 'ical' FROM `ical_user`
+        )"#
+    )
+    .fetch(db);
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .context("Failed to get sources of users")?
+    {
+        sources.entry(row.user_id).or_default().push(row.source);
+    }
+
+    let mut expired_sources = BTreeMap::<UserId, Vec<String>>::new();
+    for row in sqlx::query!(
+        r#"SELECT `user_id` as `user_id: UserId`, `source` FROM `source_session` WHERE `expired`
+        ORDER BY `source`"#
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to get expired sessions")?
+    {
+        expired_sources
+            .entry(row.user_id)
+            .or_default()
+            .push(row.source);
+    }
+
+    let mut sync_failures = BTreeMap::<UserId, google_calendar::SyncFailures>::new();
+    for row in sqlx::query!(
+        r#"SELECT
+            `user_id` as `user_id: UserId`,
+            COUNT(*) FILTER (WHERE `attempts` < ?1) as `retrying!: i64`,
+            COUNT(*) FILTER (WHERE `attempts` >= ?1) as `given_up!: i64`
+        FROM `sync_failure`
+        GROUP BY `user_id`"#,
+        google_calendar::MAX_SYNC_ATTEMPTS
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to count sync failures")?
+    {
+        sync_failures.insert(
+            row.user_id,
+            google_calendar::SyncFailures {
+                retrying: row.retrying,
+                given_up: row.given_up,
+            },
+        );
+    }
+
+    let users = sqlx::query!(
+        r#"SELECT
+            `user`.`user_id` as `user_id: UserId`,
+            `google_user`.`email`,
+            `google_user`.`last_synced` as `last_synced?: chrono::NaiveDateTime`,
+            `google_user`.`last_error`
+        FROM `user` LEFT JOIN `google_user` USING (`user_id`)
+        ORDER BY `user`.`user_id`"#
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to get users")?;
+
+    Ok(users
+        .into_iter()
+        .map(|user| UserStatus {
+            sources: sources.remove(&user.user_id).unwrap_or_default(),
+            expired_sources: expired_sources.remove(&user.user_id).unwrap_or_default(),
+            user_id: user.user_id,
+            email: user.email,
+            last_synced: user.last_synced.map(|date_time| {
+                chrono::DateTime::from_naive_utc_and_offset(date_time, chrono::Utc)
+            }),
+            last_error: user.last_error,
+            sync_failures: sync_failures.remove(&user.user_id).unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Emails allowed to login in the DB, on top of the `allowed-emails` file
+pub async fn list_allowed_emails(db: &SqlitePool) -> anyhow::Result<Vec<String>> {
+    sqlx::query_scalar!("SELECT `email` FROM `allowed_email` ORDER BY `email`")
+        .fetch_all(db)
+        .await
+        .context("Failed to get allowed emails")
+}
+
+pub(crate) async fn is_allowed_email(db: &SqlitePool, email: &str) -> anyhow::Result<bool> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM `allowed_email` WHERE `email` = ?) as `allowed!: bool`"#,
+        email
+    )
+    .fetch_one(db)
+    .await
+    .context("Failed to check allowed email")
+}
+
+/// `false` when it is already allowed
+pub async fn add_allowed_email(db: &SqlitePool, email: &str) -> anyhow::Result<bool> {
+    let now = chrono::Utc::now().naive_utc();
+    let result = sqlx::query!(
+        "INSERT INTO `allowed_email` (`email`, `created_at`) VALUES (?, ?) ON CONFLICT DO NOTHING",
+        email,
+        now
+    )
+    .execute(db)
+    .await
+    .context("Failed to add allowed email")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// `false` when it is not in the DB. Emails in the `allowed-emails` file are
+/// still allowed.
+pub async fn remove_allowed_email(db: &SqlitePool, email: &str) -> anyhow::Result<bool> {
+    let result = sqlx::query!("DELETE FROM `allowed_email` WHERE `email` = ?", email)
+        .execute(db)
+        .await
+        .context("Failed to remove allowed email")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Tables keyed by the user alone or with a source. Rows of the user merged
+/// into are kept when both have them.
+const MERGED_TABLES: &[&str] = &[
+    "google_user",
+    "naver_user",
+    "kobus_user",
+    "catch_table_user",
+    "cgv_user",
+    "megabox_user",
+    "lotte_cinema_user",
+    "bustago_user",
+    "ical_user",
+    "source_setting",
+    "source_session",
+    "source_history",
+    "sync_failure",
+];
+
+/// Google event left behind by [`merge_users`]
+#[derive(Debug, PartialEq, Eq)]
+pub struct DuplicateEvent {
+    pub calendar_id: String,
+    pub event_id: String,
+}
+
+/// Moves everything of `from` into `into` and deletes `from`, for a user who
+/// logged in with another google account by accident. On the same
+/// reservation, the one updated later is kept. Google events of `from` stay
+/// in its calendar, and are updated there by the syncs of `into`. The ones of
+/// reservations `into` has events of already are forgotten and returned, to
+/// be deleted from google calendar. `None` when either of the users doesn't
+/// exist.
+pub async fn merge_users(
+    db: &SqlitePool,
+    into: UserId,
+    from: UserId,
+) -> anyhow::Result<Option<Vec<DuplicateEvent>>> {
+    anyhow::ensure!(into != from, "Can't merge {into:?} into itself");
+    let mut tx = db.begin().await?;
+    let existing = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as `count!: i64` FROM `user` WHERE `user_id` IN (?, ?)"#,
+        into,
+        from
+    )
+    .fetch_one(&mut tx)
+    .await
+    .context("Failed to get users")?;
+    if existing < 2 {
+        return Ok(None);
+    }
+
+    // `google_event` rows without `calendar_id` are in the calendar of the
+    // user, which is gone when the google user of `into` is kept
+    sqlx::query!(
+        "UPDATE OR IGNORE `google_event` SET `user_id` = ?1, `calendar_id` = COALESCE(
+            `calendar_id`,
+            (SELECT `calendar_id` FROM `google_user` WHERE `user_id` = ?2)
+        ) WHERE `user_id` = ?2",
+        into,
+        from
+    )
+    .execute(&mut tx)
+    .await
+    .context("Failed to merge google events")?;
+    // left on `from` as `into` has events of the same reservations
+    let duplicates = sqlx::query!(
+        r#"SELECT `event_id`, COALESCE(
+            `calendar_id`,
+            (SELECT `calendar_id` FROM `google_user` WHERE `user_id` = ?1)
+        ) as `calendar_id: String`
+        FROM `google_event` WHERE `user_id` = ?1"#,
+        from
+    )
+    .fetch_all(&mut tx)
+    .await
+    .context("Failed to get duplicate google events")?
+    .into_iter()
+    .filter_map(|row| match row.calendar_id {
+        Some(calendar_id) => Some(DuplicateEvent {
+            calendar_id,
+            event_id: row.event_id,
+        }),
+        None => {
+            error!(
+                "Calendar of duplicate event {} of {from:?} is unknown",
+                row.event_id
+            );
+            None
+        }
+    })
+    .collect();
+
+    sqlx::query!(
+        "DELETE FROM `reservation` WHERE `user_id` = ?1 AND EXISTS (
+            SELECT 1 FROM `reservation` AS `other`
+            WHERE `other`.`user_id` = ?2 AND `other`.`id` = `reservation`.`id`
+                AND `other`.`updated_at` > `reservation`.`updated_at`
+        )",
+        into,
+        from
+    )
+    .execute(&mut tx)
+    .await
+    .context("Failed to delete outdated reservations")?;
+    // synced again, to the calendar of `into` unless they have events already
+    let now = chrono::Utc::now().naive_utc();
+    sqlx::query!(
+        "UPDATE OR IGNORE `reservation` SET `user_id` = ?, `updated_at` = ? WHERE `user_id` = ?",
+        into,
+        now,
+        from
+    )
+    .execute(&mut tx)
+    .await
+    .context("Failed to merge reservations")?;
+
+    for table in MERGED_TABLES {
+        sqlx::query(&format!(
+            "UPDATE OR IGNORE `{table}` SET `user_id` = ? WHERE `user_id` = ?"
+        ))
+        .bind(into)
+        .bind(from)
+        .execute(&mut tx)
+        .await
+        .with_context(|| format!("Failed to merge {table}"))?;
+        sqlx::query(&format!("DELETE FROM `{table}` WHERE `user_id` = ?"))
+            .bind(from)
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("Failed to delete {table} of {from:?}"))?;
+    }
+    sqlx::query!("DELETE FROM `google_event` WHERE `user_id` = ?", from)
+        .execute(&mut tx)
+        .await
+        .context("Failed to delete google events of the merged user")?;
+    sqlx::query!("DELETE FROM `reservation` WHERE `user_id` = ?", from)
+        .execute(&mut tx)
+        .await
+        .context("Failed to delete reservations of the merged user")?;
+    sqlx::query!(
+        "UPDATE `event_audit` SET `user_id` = ? WHERE `user_id` = ?",
+        into,
+        from
+    )
+    .execute(&mut tx)
+    .await
+    .context("Failed to merge event audit")?;
+    sqlx::query!("DELETE FROM `user` WHERE `user_id` = ?", from)
+        .execute(&mut tx)
+        .await
+        .context("Failed to delete user")?;
+
+    tx.commit().await?;
+    Ok(Some(duplicates))
+}
+
+/// Rejects the request unless the user is an admin
+async fn ensure_admin(session: &ReadableSession, db: &SqlitePool) -> Result<UserId, Response> {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return Err(StatusCode::FORBIDDEN.into_response());
+    };
+
+    let admin = match google_calendar::get_email(db, user_id).await {
+        Ok(Some(email)) => google_calendar::Config::get().is_admin(db, &email).await,
+        Ok(None) => Ok(false),
+        Err(e) => Err(e),
+    };
+    match admin {
+        Ok(true) => Ok(user_id),
+        Ok(false) => {
+            debug!("Not an admin - {user_id:?}");
+            Err(StatusCode::FORBIDDEN.into_response())
+        }
+        Err(e) => {
+            error!("Error occurred while check admin - {e:?}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+async fn get_users(session: ReadableSession, Extension(db): Extension<SqlitePool>) -> Response {
+    if let Err(response) = ensure_admin(&session, &db).await {
+        return response;
+    }
+
+    match list_users(&db).await {
+        Ok(users) => Json(users).into_response(),
+        Err(e) => {
+            error!("Error occurred while list users - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct AllowedEmail {
+    email: String,
+}
+
+async fn get_allowed_emails(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Response {
+    if let Err(response) = ensure_admin(&session, &db).await {
+        return response;
+    }
+
+    match list_allowed_emails(&db).await {
+        Ok(emails) => Json(emails).into_response(),
+        Err(e) => {
+            error!("Error occurred while list allowed emails - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn allow_email(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Json(AllowedEmail { email }): Json<AllowedEmail>,
+) -> Response {
+    let user_id = match ensure_admin(&session, &db).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    let email = email.trim();
+    if !email.contains('@') {
+        debug!("Invalid email - {email}");
+        return (StatusCode::BAD_REQUEST, format!("Invalid email - {email}")).into_response();
+    }
+    match add_allowed_email(&db, email).await {
+        Ok(added) => {
+            if added {
+                info!("{email} is allowed by {user_id:?}");
+            }
+            StatusCode::ACCEPTED.into_response()
+        }
+        Err(e) => {
+            error!("Error occurred while add allowed email - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn disallow_email(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Path(email): Path<String>,
+) -> Response {
+    let user_id = match ensure_admin(&session, &db).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    match remove_allowed_email(&db, &email).await {
+        Ok(true) => {
+            info!("{email} is disallowed by {user_id:?}");
+            StatusCode::ACCEPTED.into_response()
+        }
+        Ok(false) => {
+            debug!("{email} is not allowed in the DB");
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(e) => {
+            error!("Error occurred while remove allowed email - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct MergedUser {
+    from: UserId,
+}
+
+async fn merge_user(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Path(into): Path<UserId>,
+    Json(MergedUser { from }): Json<MergedUser>,
+) -> Response {
+    let user_id = match ensure_admin(&session, &db).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+
+    if into == from {
+        debug!("Can't merge {into:?} into itself");
+        return (StatusCode::BAD_REQUEST, "Can't merge a user into itself").into_response();
+    }
+    match merge_users(&db, into, from).await {
+        Ok(Some(duplicates)) => {
+            info!("{from:?} is merged into {into:?} by {user_id:?}");
+            let events = duplicates
+                .into_iter()
+                .map(|event| (event.calendar_id, event.event_id))
+                .collect::<Vec<_>>();
+            match google_calendar::delete_events(&events).await {
+                Ok(left) if left.is_empty() => {}
+                Ok(left) => error!("Duplicate events of {from:?} are left - {left:?}"),
+                Err(e) => error!("Duplicate events of {from:?} are left - {events:?}, {e:?}"),
+            }
+            StatusCode::ACCEPTED.into_response()
+        }
+        Ok(None) => {
+            debug!("User to merge is not found - {into:?}, {from:?}");
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(e) => {
+            error!("Error occurred while merge users - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn web_router() -> Router {
+    Router::new()
+        .route("/users", get(get_users))
+        .route("/users/:user_id/merge", post(merge_user))
+        .route("/allowed_emails", get(get_allowed_emails).post(allow_email))
+        .route("/allowed_emails/:email", delete(disallow_email))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, USER_ID};
+
+    #[tokio::test]
+    async fn users_are_listed_with_sources_and_sync_status() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+
+        for _ in 0..2 {
+            sqlx::query("INSERT INTO `user` (`dummy`) VALUES (TRUE)")
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+        let last_synced = chrono::NaiveDate::from_ymd_opt(2024, 5, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO `google_user` (`user_id`, `subject`, `calendar_id`, `last_synced`, `email`, `last_error`)
+            VALUES (1, 'subject', 'calendar', ?, 'admin@example.com', 'Failed to patch event')",
+        )
+        .bind(last_synced)
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO `cgv_user` (`user_id`, `webauth`, `aspxauth`) VALUES (1, '', '')")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO `ical_user` (`user_id`, `urls`) VALUES (1, '')")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO `source_session` (`user_id`, `source`, `expired`, `checked_at`)
+            VALUES (1, 'cgv', TRUE, ?)",
+        )
+        .bind(last_synced)
+        .execute(&db)
+        .await
+        .unwrap();
+        for (reservation_id, attempts) in [("cgv/1", 1), ("cgv/2", 5)] {
+            sqlx::query(
+                "INSERT INTO `sync_failure` (`user_id`, `reservation_id`, `last_error`, `attempts`, `failed_at`)
+                VALUES (1, ?, 'Failed to insert event', ?, ?)",
+            )
+            .bind(reservation_id)
+            .bind(attempts)
+            .bind(last_synced)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        let users = list_users(&db).await.unwrap();
+
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].user_id, UserId(1));
+        assert_eq!(users[0].email.as_deref(), Some("admin@example.com"));
+        assert_eq!(users[0].sources, vec!["cgv", "ical"]);
+        assert_eq!(users[0].expired_sources, vec!["cgv"]);
+        assert_eq!(
+            users[0].last_synced.map(|d| d.naive_utc()),
+            Some(last_synced)
+        );
+        assert_eq!(
+            users[0].last_error.as_deref(),
+            Some("Failed to patch event")
+        );
+        assert_eq!(
+            users[0].sync_failures,
+            google_calendar::SyncFailures {
+                retrying: 1,
+                given_up: 1
+            }
+        );
+        // not logged in to google yet
+        assert_eq!(users[1].user_id, UserId(2));
+        assert!(users[1].sources.is_empty());
+        assert_eq!(users[1].last_synced, None);
+    }
+
+    #[tokio::test]
+    async fn users_are_merged_with_their_reservations() {
+        let db = testing::db().await;
+        let into = USER_ID;
+        let from = UserId(2);
+        sqlx::query("INSERT INTO `user` (`user_id`, `dummy`) VALUES (?, TRUE)")
+            .bind(from)
+            .execute(&db)
+            .await
+            .unwrap();
+        let at = |day| {
+            chrono::NaiveDate::from_ymd_opt(2024, 5, day)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        };
+        for (id, user_id, title, updated_at) in [
+            ("cgv/1", into, "kept", at(2)),
+            ("cgv/1", from, "outdated", at(1)),
+            ("cgv/2", into, "outdated", at(1)),
+            ("cgv/2", from, "updated", at(2)),
+            ("cgv/3", into, "only into", at(1)),
+            ("cgv/4", from, "only from", at(1)),
+        ] {
+            sqlx::query(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `invalid`, `updated_at`)
+                VALUES (?, ?, ?, '', '2024-06-01', FALSE, ?)",
+            )
+            .bind(id)
+            .bind(user_id)
+            .bind(title)
+            .bind(updated_at)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        for (user_id, calendar_id) in [(into, "calendar"), (from, "calendar-from")] {
+            sqlx::query(
+                "INSERT INTO `google_user` (`user_id`, `subject`, `calendar_id`, `last_synced`)
+                VALUES (?, 'subject', ?, ?)",
+            )
+            .bind(user_id)
+            .bind(calendar_id)
+            .bind(at(1))
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        for (event_id, user_id, reservation_id) in [
+            ("event-1", into, "cgv/1"),
+            ("event-from-1", from, "cgv/1"),
+            ("event-from-4", from, "cgv/4"),
+        ] {
+            sqlx::query(
+                "INSERT INTO `google_event` (`event_id`, `user_id`, `reservation_id`) VALUES (?, ?, ?)",
+            )
+            .bind(event_id)
+            .bind(user_id)
+            .bind(reservation_id)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        sqlx::query(
+            "INSERT INTO `cgv_user` (`user_id`, `webauth`, `aspxauth`) VALUES (?, 'into', '')",
+        )
+        .bind(into)
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO `ical_user` (`user_id`, `urls`) VALUES (?, 'from')")
+            .bind(from)
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO `cgv_user` (`user_id`, `webauth`, `aspxauth`) VALUES (?, 'from', '')",
+        )
+        .bind(from)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            merge_users(&db, into, from).await.unwrap(),
+            Some(vec![DuplicateEvent {
+                calendar_id: "calendar-from".to_string(),
+                event_id: "event-from-1".to_string(),
+            }])
+        );
+
+        let reservations: Vec<(String, String)> = sqlx::query_as(
+            "SELECT `id`, `title` FROM `reservation` WHERE `user_id` = ? ORDER BY `id`",
+        )
+        .bind(into)
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(
+            reservations,
+            [
+                ("cgv/1".to_string(), "kept".to_string()),
+                ("cgv/2".to_string(), "updated".to_string()),
+                ("cgv/3".to_string(), "only into".to_string()),
+                ("cgv/4".to_string(), "only from".to_string()),
+            ]
+        );
+        let events: Vec<(String, String, Option<String>)> = sqlx::query_as(
+            "SELECT `event_id`, `reservation_id`, `calendar_id` FROM `google_event` WHERE `user_id` = ? ORDER BY `event_id`",
+        )
+        .bind(into)
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(
+            events,
+            [
+                ("event-1".to_string(), "cgv/1".to_string(), None),
+                (
+                    "event-from-4".to_string(),
+                    "cgv/4".to_string(),
+                    Some("calendar-from".to_string())
+                ),
+            ]
+        );
+        let sessions: Vec<(String, String)> = sqlx::query_as(
+            "SELECT 'cgv', `webauth` FROM `cgv_user` WHERE `user_id` = ?1
+            UNION ALL SELECT 'ical', `urls` FROM `ical_user` WHERE `user_id` = ?1",
+        )
+        .bind(into)
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(
+            sessions,
+            [
+                ("cgv".to_string(), "into".to_string()),
+                ("ical".to_string(), "from".to_string()),
+            ]
+        );
+        let calendar_id: String =
+            sqlx::query_scalar("SELECT `calendar_id` FROM `google_user` WHERE `user_id` = ?")
+                .bind(into)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(calendar_id, "calendar");
+
+        for table in [
+            "user",
+            "reservation",
+            "google_event",
+            "google_user",
+            "cgv_user",
+        ] {
+            let left: i64 = sqlx::query_scalar(&format!(
+                "SELECT COUNT(*) FROM `{table}` WHERE `user_id` = ?"
+            ))
+            .bind(from)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+            assert_eq!(left, 0, "{table}");
+        }
+        assert_eq!(merge_users(&db, into, from).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn emails_are_allowed_in_db() {
+        let db = testing::db().await;
+        assert!(!is_allowed_email(&db, "user@example.com").await.unwrap());
+
+        assert!(add_allowed_email(&db, "user@example.com").await.unwrap());
+        assert!(!add_allowed_email(&db, "user@example.com").await.unwrap());
+        add_allowed_email(&db, "another@example.com").await.unwrap();
+        assert!(is_allowed_email(&db, "user@example.com").await.unwrap());
+        assert_eq!(
+            list_allowed_emails(&db).await.unwrap(),
+            ["another@example.com", "user@example.com"]
+        );
+
+        assert!(remove_allowed_email(&db, "user@example.com").await.unwrap());
+        assert!(!remove_allowed_email(&db, "user@example.com").await.unwrap());
+        assert!(!is_allowed_email(&db, "user@example.com").await.unwrap());
+    }
+}
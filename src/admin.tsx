@@ -0,0 +1,113 @@
+import React from "react";
+import { ActionFunctionArgs, Form, Navigate, useActionData, useLoaderData } from "react-router-dom";
+import { ActionResult, AsyncReturnType, formValue, postJson, request as sendRequest } from './utils';
+
+export async function loader() {
+    const [resp, emailsResp] = await Promise.all([
+        fetch("/admin/users", {
+            credentials: "same-origin",
+        }),
+        fetch("/admin/allowed_emails", {
+            credentials: "same-origin",
+        }),
+    ]);
+
+    if (resp.ok && emailsResp.ok) {
+        const parsed = await resp.json() as any[];
+        return {
+            users: parsed.map((user) => ({
+                user_id: user.user_id as number,
+                email: user.email as string | null,
+                sources: user.sources as string[],
+                expired_sources: user.expired_sources as string[],
+                last_synced: user.last_synced !== null ? new Date(user.last_synced) : null,
+                last_error: user.last_error as string | null,
+                sync_failures: user.sync_failures as { retrying: number, given_up: number },
+            })),
+            allowed_emails: await emailsResp.json() as string[],
+        };
+    } else {
+        return null;
+    }
+}
+
+export async function action({ request }: ActionFunctionArgs) {
+    const formData = await request.formData();
+    if (formData.get("intent") === "merge") {
+        const into = formValue(formData, "into");
+        const from = Number(formValue(formData, "from"));
+        return await postJson(`/admin/users/${encodeURIComponent(into)}/merge`, JSON.stringify({ from }));
+    }
+    const email = formValue(formData, "email").trim();
+    if (formData.get("intent") === "disallow") {
+        return await sendRequest(`/admin/allowed_emails/${encodeURIComponent(email)}`, { method: "delete" });
+    }
+    return await postJson("/admin/allowed_emails", JSON.stringify({ email }));
+}
+
+export function Component() {
+    const data = useLoaderData() as AsyncReturnType<typeof loader>;
+    const result = useActionData() as ActionResult | undefined;
+
+    if (data !== null) {
+        return <div>
+            <table>
+                <thead>
+                    <tr>
+                        <th>User</th>
+                        <th>Email</th>
+                        <th>Sources</th>
+                        <th>Expired sessions</th>
+                        <th>Last synced</th>
+                        <th>Last error</th>
+                        <th>Failed events</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    {data.users.map((user) => <tr key={user.user_id}>
+                        <td>{user.user_id}</td>
+                        <td>{user.email ?? "-"}</td>
+                        <td>{user.sources.join(", ")}</td>
+                        <td>{user.expired_sources.join(", ")}</td>
+                        <td>{user.last_synced?.toLocaleString() ?? "never"}</td>
+                        <td>{user.last_error ?? "-"}</td>
+                        <td>{user.sync_failures.retrying} retrying, {user.sync_failures.given_up} given up</td>
+                    </tr>)}
+                </tbody>
+            </table>
+            <h3>Merge users</h3>
+            <p>Moves the sources and reservations of a user into another, and deletes it</p>
+            <Form method="post" action="/admin">
+                <input type="hidden" name="intent" value="merge" />
+                <label>
+                    Merge user
+                    <input type="number" name="from" min={1} required />
+                </label>
+                <label>
+                    into
+                    <input type="number" name="into" min={1} required />
+                </label>
+                <button type="submit">Merge</button>
+            </Form>
+            <h3>Allowed emails</h3>
+            <p>Allowed on top of the allowed-emails file</p>
+            <ul>
+                {data.allowed_emails.map((email) => <li key={email}>
+                    <Form method="post" action="/admin">
+                        {email}{" "}
+                        <input type="hidden" name="intent" value="disallow" />
+                        <input type="hidden" name="email" value={email} />
+                        <button type="submit" className="secondary">Remove</button>
+                    </Form>
+                </li>)}
+            </ul>
+            <Form method="post" action="/admin">
+                <input type="email" name="email" required />
+                {result?.error && <p role="alert">{result.error}</p>}
+                <button type="submit">Allow</button>
+            </Form>
+        </div>;
+    } else {
+        return <Navigate to="/" />;
+    }
+}
@@ -0,0 +1,338 @@
+//! Append-only log of the changes made on google calendar by each sync, to
+//! trace and roll back the events a broken crawler made
+
+use anyhow::Context as _;
+use google_calendar3::api::Event;
+use log::error;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use crate::UserId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Action {
+    Insert,
+    Patch,
+    Delete,
+}
+
+/// Days the audit is kept, read from `AUDIT_RETENTION_DAYS`
+const DEFAULT_RETENTION_DAYS: i64 = 90;
+
+pub fn retention_days_from_env() -> anyhow::Result<i64> {
+    retention_days_from_var(std::env::var("AUDIT_RETENTION_DAYS").ok())
+}
+
+fn retention_days_from_var(days: Option<String>) -> anyhow::Result<i64> {
+    let Some(days) = days else {
+        return Ok(DEFAULT_RETENTION_DAYS);
+    };
+    let days = days.parse().context("Invalid AUDIT_RETENTION_DAYS")?;
+    anyhow::ensure!(days > 0, "AUDIT_RETENTION_DAYS must be positive");
+
+    Ok(days)
+}
+
+/// Removes entries older than `days`, the syncs can't be undone anymore
+pub async fn remove_older_than(db: &SqlitePool, days: i64) -> anyhow::Result<u64> {
+    let threshold = chrono::Utc::now().naive_utc() - chrono::Duration::days(days);
+    let result = sqlx::query!(
+        "DELETE FROM `event_audit` WHERE `created_at` < ?",
+        threshold
+    )
+    .execute(db)
+    .await
+    .context("Failed to remove old event audit")?;
+
+    Ok(result.rows_affected())
+}
+
+/// Changed event, `before` is the event as the previous audit entry of it
+/// left in `before_calendar_id`, `None` when it isn't known
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub id: i64,
+    pub action: Action,
+    pub event_id: String,
+    pub calendar_id: String,
+    pub reservation_id: String,
+    pub before: Option<Event>,
+    pub before_calendar_id: Option<String>,
+}
+
+/// Changes made by a sync, or a retry of failed ones
+pub(crate) struct Batch {
+    user_id: UserId,
+    id: String,
+}
+
+impl Batch {
+    pub fn new(user_id: UserId) -> Self {
+        Self {
+            user_id,
+            id: Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// Keeps the change made on google calendar. Only logged on failure not
+    /// to fail the sync of the event which is already changed.
+    pub async fn record(
+        &self,
+        db: &SqlitePool,
+        action: Action,
+        event_id: &str,
+        calendar_id: &str,
+        reservation_id: &str,
+        after: Option<&Event>,
+    ) {
+        if let Err(e) = self
+            .try_record(db, action, event_id, calendar_id, reservation_id, after)
+            .await
+        {
+            error!("Failed to record {action:?} of event {event_id} - {e:?}");
+        }
+    }
+
+    async fn try_record(
+        &self,
+        db: &SqlitePool,
+        action: Action,
+        event_id: &str,
+        calendar_id: &str,
+        reservation_id: &str,
+        after: Option<&Event>,
+    ) -> anyhow::Result<()> {
+        let after = after.map(serde_json::to_string).transpose()?;
+        let now = chrono::Utc::now().naive_utc();
+        sqlx::query!(
+            "INSERT INTO `event_audit`
+                (`user_id`, `batch`, `action`, `event_id`, `calendar_id`, `reservation_id`,
+                `before`, `before_calendar_id`, `after`, `created_at`)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, (
+                SELECT `after` FROM `event_audit`
+                WHERE `user_id` = ?1 AND `event_id` = ?4
+                ORDER BY `id` DESC LIMIT 1
+            ), (
+                SELECT `calendar_id` FROM `event_audit`
+                WHERE `user_id` = ?1 AND `event_id` = ?4
+                ORDER BY `id` DESC LIMIT 1
+            ), ?7, ?8)",
+            self.user_id,
+            self.id,
+            action,
+            event_id,
+            calendar_id,
+            reservation_id,
+            after,
+            now
+        )
+        .execute(db)
+        .await
+        .context("Failed to insert event audit")?;
+
+        Ok(())
+    }
+}
+
+/// Changes of the latest sync which is not undone yet, shown on the main page
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct BatchSummary {
+    pub synced_at: chrono::DateTime<chrono::Utc>,
+    pub inserted: i64,
+    pub patched: i64,
+    pub deleted: i64,
+}
+
+pub async fn get_last_batch(
+    db: &SqlitePool,
+    user_id: UserId,
+) -> anyhow::Result<Option<BatchSummary>> {
+    let Some(batch) = last_batch_id(db, user_id).await? else {
+        return Ok(None);
+    };
+    let summary = sqlx::query!(
+        r#"SELECT
+            MAX(`created_at`) as `synced_at!: chrono::NaiveDateTime`,
+            COUNT(*) FILTER (WHERE `action` = 'insert') as `inserted!: i64`,
+            COUNT(*) FILTER (WHERE `action` = 'patch') as `patched!: i64`,
+            COUNT(*) FILTER (WHERE `action` = 'delete') as `deleted!: i64`
+        FROM `event_audit` WHERE `user_id` = ? AND `batch` = ?"#,
+        user_id,
+        batch
+    )
+    .fetch_one(db)
+    .await
+    .context("Failed to summarize last sync")?;
+
+    Ok(Some(BatchSummary {
+        synced_at: summary.synced_at.and_utc(),
+        inserted: summary.inserted,
+        patched: summary.patched,
+        deleted: summary.deleted,
+    }))
+}
+
+async fn last_batch_id(db: &SqlitePool, user_id: UserId) -> anyhow::Result<Option<String>> {
+    sqlx::query_scalar!(
+        "SELECT `batch` as `batch!` FROM `event_audit`
+        WHERE `user_id` = ? AND `undone_at` IS NULL
+        ORDER BY `id` DESC LIMIT 1",
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .context("Failed to get last sync")
+}
+
+/// Changes of the latest sync not undone yet, the latest change first to be
+/// reverted in order
+pub(crate) async fn last_batch(db: &SqlitePool, user_id: UserId) -> anyhow::Result<Vec<Entry>> {
+    let Some(batch) = last_batch_id(db, user_id).await? else {
+        return Ok(Vec::new());
+    };
+    sqlx::query!(
+        r#"SELECT
+            `id` as `id!`, `action` as `action: Action`, `event_id`, `calendar_id`, `reservation_id`,
+            `before`, `before_calendar_id`
+        FROM `event_audit` WHERE `user_id` = ? AND `batch` = ?
+        ORDER BY `id` DESC"#,
+        user_id,
+        batch
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to get changes of last sync")?
+    .into_iter()
+    .map(|row| {
+        Ok(Entry {
+            id: row.id,
+            action: row.action,
+            event_id: row.event_id,
+            calendar_id: row.calendar_id,
+            reservation_id: row.reservation_id,
+            before: row
+                .before
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()
+                .context("Invalid event in audit")?,
+            before_calendar_id: row.before_calendar_id,
+        })
+    })
+    .collect()
+}
+
+pub(crate) async fn mark_undone(db: &SqlitePool, entry_id: i64) -> anyhow::Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    sqlx::query!(
+        "UPDATE `event_audit` SET `undone_at` = ? WHERE `id` = ?",
+        now,
+        entry_id
+    )
+    .execute(db)
+    .await
+    .context("Failed to mark event audit undone")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, USER_ID};
+
+    fn event(summary: &str) -> Event {
+        Event {
+            id: Some("event".to_string()),
+            summary: Some(summary.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn changes_of_last_sync_are_kept_with_previous_event() {
+        let db = testing::db().await;
+        assert_eq!(get_last_batch(&db, USER_ID).await.unwrap(), None);
+
+        let first = Batch::new(USER_ID);
+        first
+            .record(
+                &db,
+                Action::Insert,
+                "event",
+                "calendar",
+                "cgv/1",
+                Some(&event("old")),
+            )
+            .await;
+        let second = Batch::new(USER_ID);
+        second
+            .record(
+                &db,
+                Action::Patch,
+                "event",
+                "other calendar",
+                "cgv/1",
+                Some(&event("new")),
+            )
+            .await;
+        second
+            .record(&db, Action::Delete, "other", "calendar", "cgv/2", None)
+            .await;
+
+        let summary = get_last_batch(&db, USER_ID).await.unwrap().unwrap();
+        assert_eq!(
+            (summary.inserted, summary.patched, summary.deleted),
+            (0, 1, 1)
+        );
+        let entries = last_batch(&db, USER_ID).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, Action::Delete);
+        assert!(entries[0].before.is_none());
+        assert_eq!(entries[1].action, Action::Patch);
+        assert_eq!(
+            entries[1].before.as_ref().unwrap().summary.as_deref(),
+            Some("old")
+        );
+        assert_eq!(entries[1].calendar_id, "other calendar");
+        assert_eq!(entries[1].before_calendar_id.as_deref(), Some("calendar"));
+
+        for entry in entries {
+            mark_undone(&db, entry.id).await.unwrap();
+        }
+        let summary = get_last_batch(&db, USER_ID).await.unwrap().unwrap();
+        assert_eq!(
+            (summary.inserted, summary.patched, summary.deleted),
+            (1, 0, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn old_entries_are_removed() {
+        let db = testing::db().await;
+        Batch::new(USER_ID)
+            .record(&db, Action::Insert, "event", "calendar", "cgv/1", None)
+            .await;
+        sqlx::query!("UPDATE `event_audit` SET `created_at` = datetime('now', '-91 days')")
+            .execute(&db)
+            .await
+            .unwrap();
+        Batch::new(USER_ID)
+            .record(&db, Action::Insert, "other", "calendar", "cgv/2", None)
+            .await;
+
+        assert_eq!(remove_older_than(&db, 90).await.unwrap(), 1);
+        let entries = last_batch(&db, USER_ID).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].event_id, "other");
+    }
+
+    #[test]
+    fn retention_is_90_days_by_default() {
+        assert_eq!(retention_days_from_var(None).unwrap(), 90);
+        assert_eq!(retention_days_from_var(Some("30".to_string())).unwrap(), 30);
+        assert!(retention_days_from_var(Some("0".to_string())).is_err());
+    }
+}
@@ -5,12 +5,14 @@ use anyhow::Context;
 use axum::{async_trait, Router};
 use chrono::Datelike;
 use futures::StreamExt;
-use log::info;
 use reqwest::cookie::CookieStore;
 use serde::{Deserialize, Serialize};
 use sqlx::{Row, SqlitePool};
 
-use crate::{date_time_to_utc, url, CalendarEvent, UserId};
+use crate::{
+    date_time_to_utc, kst, reservation::ID_CHUNK_SIZE, title::Title, url, CalendarEvent,
+    CrawlStats, UserId, UserImpl as _,
+};
 
 #[derive(Debug, Deserialize)]
 struct ReservationResponse {
@@ -49,6 +51,48 @@ struct Reservation {
     operator_name: String,
 }
 
+impl Reservation {
+    fn cancelled(&self) -> bool {
+        self.all_seat_status == "2"
+    }
+
+    /// Number of seats, which is the party size of the booking
+    fn party_size(&self) -> Option<u32> {
+        self.total_seat_count.trim().parse().ok()
+    }
+
+    /// The seat count is kept in the detail as it is when it isn't a number,
+    /// not to drop what the source tells
+    fn detail(&self) -> String {
+        match self.party_size() {
+            Some(_) => format!("회사: {}", self.operator_name),
+            None => format!(
+                "회사: {}\n좌석번호: {}",
+                self.operator_name, self.total_seat_count
+            ),
+        }
+    }
+
+    /// Departure and arrival time in UTC, `distance_time` is the duration of
+    /// the line in minutes
+    fn schedule_in_utc(
+        &self,
+        distance_time: u64,
+    ) -> anyhow::Result<(chrono::NaiveDateTime, chrono::NaiveDateTime)> {
+        let date = chrono::NaiveDate::parse_from_str(&self.departure_date, "%Y%m%d")
+            .context("Failed to parse departure date")?;
+        let time = chrono::NaiveTime::parse_from_str(&self.departure_time, "%H%M")
+            .context("Failed to parse departure time")?;
+        let (date, time) = date_time_to_utc(date, time, kst());
+        let departure = chrono::NaiveDateTime::new(date, time);
+
+        Ok((
+            departure,
+            departure + chrono::Duration::minutes(distance_time as _),
+        ))
+    }
+}
+
 #[derive(Deserialize)]
 struct LineInfoResponse {
     list: Vec<LineInfo>,
@@ -146,38 +190,134 @@ const REQUIRED_STRING_FIELDS: &[&str] = &[
     "tokenId",
 ];
 
-#[async_trait]
-impl crate::UserImpl for BustagoUser {
-    type Detail = BustagoUserDetail;
+/// `v_dateGb` of the reservation list, which date `fromDate` ~ `toDate` is compared with
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+#[allow(dead_code)]
+enum DateMode {
+    /// date the reservation is made
+    Reservation = 1,
+    /// departure date of the bus
+    Departure = 2,
+}
 
-    const PING_INTERVAL: Option<std::time::Duration> =
-        Some(std::time::Duration::from_secs(10 * 60));
+/// Query by departure so every upcoming trip is found regardless of when it is booked
+const DATE_MODE: DateMode = DateMode::Departure;
+/// Tickets are sold up to a month before the departure
+const SEARCH_DAYS: i64 = 31;
 
-    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<bool> {
-        let date_begin = chrono::Utc::now()
-            .with_timezone(&chrono::FixedOffset::east_opt(9).unwrap())
-            .date_naive();
-        let date_end = date_begin + chrono::Duration::days(7);
-        let jar = self.to_cookie_jar();
-        let reservations_url = url!("https://www.bustago.or.kr/newweb/kr/reserve/reservejson.do");
-        let client = reqwest::Client::new();
-        let user_number = self.user_number.clone();
-        let mut request = serde_json::json!({
-            "fromDate": to_numeric_date(date_begin),
-            "toDate": to_numeric_date(date_end),
-            "v_dateGb": 2,
-            "v_status": 0,
-            "page": 1,
-            "userNumber": user_number,
-        });
-        unsafe { request.as_object_mut().unwrap_unchecked() }.extend(
+fn reservations_request(
+    from: chrono::NaiveDate,
+    to: chrono::NaiveDate,
+    user_number: &str,
+) -> serde_json::Value {
+    let mut request = serde_json::json!({
+        "fromDate": to_numeric_date(from),
+        "toDate": to_numeric_date(to),
+        "v_dateGb": DATE_MODE as u8,
+        "v_status": 0,
+        "page": 1,
+        "userNumber": user_number,
+    });
+    if let serde_json::Value::Object(fields) = &mut request {
+        fields.extend(
             REQUIRED_STRING_FIELDS
                 .iter()
                 .map(|key| (key.to_string(), serde_json::Value::String("".to_string()))),
         );
+    }
+
+    request
+}
+
+/// Event of the reservation, with its arrival from the line of it. `request`
+/// is the one the reservation is listed with.
+async fn line_event(
+    client: &reqwest::Client,
+    jar: &reqwest::cookie::Jar,
+    request: &mut serde_json::Value,
+    reservation: &Reservation,
+    id: String,
+) -> anyhow::Result<CalendarEvent> {
+    request["routecode"] = serde_json::Value::String(reservation.route_code.clone());
+    request["sterCode"] = serde_json::Value::String(reservation.departure_terminal_code.clone());
+    request["eterCode"] = serde_json::Value::String(reservation.arrival_terminal_code.clone());
+    request["sterName"] = serde_json::Value::String(reservation.departure_terminal_name.clone());
+    request["eterName"] = serde_json::Value::String(reservation.arrival_terminal_name.clone());
+    request["reserveNo"] = serde_json::Value::String(reservation.reservation_number.clone());
+    request["sdate"] = serde_json::Value::String(reservation.departure_date.clone());
+    request["totalSeat"] = serde_json::Value::String(reservation.total_seat_count.clone());
+    request["startTime"] = serde_json::Value::String(reservation.departure_time.clone());
+    request["reserveTime"] = serde_json::Value::String(reservation.reservation_date.clone());
+    request["stime"] = serde_json::Value::String(reservation.departure_time.clone());
+    request["appv_no"] = serde_json::Value::String(reservation.approval_number.clone());
+    request["org_reserve_no"] = serde_json::Value::String(reservation.reservation_number.clone());
+    request["org_reserve_time"] = serde_json::Value::String(reservation.reservation_date.clone());
+    request["reserve_cd"] = serde_json::Value::String(reservation.reservation_number.clone());
+    request["card_No"] = serde_json::Value::String(reservation.card_number.clone());
+    request["ticket_no"] = serde_json::Value::String(reservation.reservation_number.clone());
+    request["cardNumber"] = serde_json::Value::String(reservation.card_number.clone());
+    request["page"] = serde_json::Value::Number(1.into());
+
+    let line_info_url = url!("https://www.bustago.or.kr/newweb/kr/reserve/reserveline.do");
+    let req = client
+        .post(line_info_url.as_ref())
+        .headers(crate::http::headers("bustago"))
+        .header(
+            reqwest::header::REFERER,
+            "https://www.bustago.or.kr/newweb/kr/reserve/reservelist.do",
+        )
+        .header(reqwest::header::COOKIE, jar.cookies(line_info_url).unwrap())
+        .form(&*request)
+        .build()?;
+    let res: LineInfoResponse = crate::dump::parse_json(
+        "bustago-reserveline",
+        crate::http::execute(client, req)
+            .await
+            .context("Failed to fetch reserveline")?,
+    )
+    .await?;
+
+    let line_info = res
+        .list
+        .last()
+        .ok_or_else(|| anyhow::anyhow!("No line info of {}", reservation.reservation_number))?;
+
+    let (departure, arrival) = reservation.schedule_in_utc(line_info.distance_time)?;
+
+    CalendarEvent::builder_of(
+        id,
+        Title::Bus {
+            express: false,
+            departure: reservation.departure_terminal_name.clone(),
+            arrival: reservation.arrival_terminal_name.clone(),
+        },
+        departure.date(),
+    )
+    .detail(reservation.detail())
+    .party_size(reservation.party_size())
+    .invalid(reservation.cancelled())
+    .time_begin(departure.time())
+    .end(arrival.date(), arrival.time())
+    .build()
+}
+
+impl BustagoUser {
+    /// Reservations departing in `from` ~ `to`, with the request to look up
+    /// their lines with
+    async fn list_reservations(
+        &self,
+        client: &reqwest::Client,
+        jar: &reqwest::cookie::Jar,
+        from: chrono::NaiveDate,
+        to: chrono::NaiveDate,
+    ) -> anyhow::Result<(serde_json::Value, Vec<Reservation>)> {
+        let reservations_url = url!("https://www.bustago.or.kr/newweb/kr/reserve/reservejson.do");
+        let request = reservations_request(from, to, &self.user_number);
 
         let req = client
             .post(reservations_url.as_ref())
+            .headers(crate::http::headers("bustago"))
             .header(
                 reqwest::header::REFERER,
                 "https://www.bustago.or.kr/newweb/kr/reserve/reservelist.do",
@@ -188,50 +328,76 @@ impl crate::UserImpl for BustagoUser {
             )
             .form(&request)
             .build()?;
-        let res: ReservationResponse = client
-            .execute(req)
-            .await
-            .context("Failed to fetch reservejson")?
-            .json()
-            .await
-            .context("Failed to parse reservejson")?;
-        if res.items.is_empty() {
-            return Ok(false);
+        let res: ReservationResponse = crate::dump::parse_json(
+            "bustago-reservejson",
+            crate::http::execute(client, req)
+                .await
+                .context("Failed to fetch reservejson")?,
+        )
+        .await?;
+
+        Ok((request, res.items))
+    }
+
+    /// Fetches reservations departing in `from` ~ `to`
+    async fn fetch_between(
+        &self,
+        db: SqlitePool,
+        from: chrono::NaiveDate,
+        to: chrono::NaiveDate,
+    ) -> anyhow::Result<CrawlStats> {
+        let jar = self.to_cookie_jar();
+        let client = crate::http::client();
+        let (mut request, items) = self.list_reservations(&client, &jar, from, to).await?;
+        if items.is_empty() {
+            return Ok(CrawlStats::default());
         }
 
-        let ids: Vec<_> = res
-            .items
+        let ids: Vec<_> = items
             .iter()
-            .map(|reservation| format!("bustago/{}", reservation.reservation_number))
+            .map(|reservation| {
+                format!(
+                    "{}{}",
+                    BustagoUser::SOURCE_PREFIX,
+                    reservation.reservation_number
+                )
+            })
             .collect();
-        let saved_reservations: HashMap<_, _> = sqlx::QueryBuilder::new(
-            "
-            SELECT `id`, `invalid`
-            FROM `reservation`
-            WHERE `user_id` =
-        ",
-        )
-        .push_bind(self.user_id)
-        .push("AND `id` in ")
-        .push_tuples(&ids, |mut builder, item| {
-            builder.push_bind(item);
-        })
-        .build()
-        .fetch_all(&db)
-        .await
-        .context("Failed to get saved reservations")?
-        .into_iter()
-        .map(|item| {
-            (
-                item.get::<String, _>(0).split_off("bustago/".len()),
-                item.get::<bool, _>(1),
-            )
-        })
-        .collect();
+        let mut saved_reservations = HashMap::new();
+        for ids in ids.chunks(ID_CHUNK_SIZE) {
+            saved_reservations.extend(
+                sqlx::QueryBuilder::new(
+                    "
+                    SELECT `id`, `invalid`
+                    FROM `reservation`
+                    WHERE `user_id` =
+                ",
+                )
+                .push_bind(self.user_id)
+                .push("AND `id` in ")
+                .push_tuples(ids, |mut builder, item| {
+                    builder.push_bind(item);
+                })
+                .build()
+                .fetch_all(&db)
+                .await
+                .context("Failed to get saved reservations")?
+                .into_iter()
+                .map(|item| {
+                    (
+                        item.get::<String, _>(0)
+                            .split_off(BustagoUser::SOURCE_PREFIX.len()),
+                        item.get::<bool, _>(1),
+                    )
+                }),
+            );
+        }
 
+        let fetched = ids.len() as u64;
+        let mut cancelled = 0;
         let mut new_reservations = Vec::new();
-        for (reservation, id) in res.items.into_iter().zip(ids.into_iter()) {
-            let current_invalid = reservation.all_seat_status == "2";
+        for (reservation, id) in items.into_iter().zip(ids.iter().cloned()) {
+            let current_invalid = reservation.cancelled();
             if let Some(invalid) = saved_reservations.get(&reservation.reservation_number) {
                 if current_invalid {
                     if !invalid {
@@ -247,114 +413,97 @@ impl crate::UserImpl for BustagoUser {
                         .execute(&db)
                         .await
                         .context("Failed to set invalid")?;
+                        cancelled += 1;
                     }
                 }
                 continue;
             }
 
-            request["routecode"] = serde_json::Value::String(reservation.route_code.clone());
-            request["sterCode"] =
-                serde_json::Value::String(reservation.departure_terminal_code.clone());
-            request["eterCode"] =
-                serde_json::Value::String(reservation.arrival_terminal_code.clone());
-            request["sterName"] =
-                serde_json::Value::String(reservation.departure_terminal_name.clone());
-            request["eterName"] =
-                serde_json::Value::String(reservation.arrival_terminal_name.clone());
-            request["reserveNo"] =
-                serde_json::Value::String(reservation.reservation_number.clone());
-            request["sdate"] = serde_json::Value::String(reservation.departure_date.clone());
-            request["totalSeat"] = serde_json::Value::String(reservation.total_seat_count.clone());
-            request["startTime"] = serde_json::Value::String(reservation.departure_time.clone());
-            request["reserveTime"] =
-                serde_json::Value::String(reservation.reservation_date.clone());
-            request["stime"] = serde_json::Value::String(reservation.departure_time.clone());
-            request["appv_no"] = serde_json::Value::String(reservation.approval_number.clone());
-            request["org_reserve_no"] =
-                serde_json::Value::String(reservation.reservation_number.clone());
-            request["org_reserve_time"] =
-                serde_json::Value::String(reservation.reservation_date.clone());
-            request["reserve_cd"] =
-                serde_json::Value::String(reservation.reservation_number.clone());
-            request["card_No"] = serde_json::Value::String(reservation.card_number.clone());
-            request["ticket_no"] =
-                serde_json::Value::String(reservation.reservation_number.clone());
-            request["cardNumber"] = serde_json::Value::String(reservation.card_number.clone());
-            request["page"] = serde_json::Value::Number(1.into());
-
-            let line_info_url = url!("https://www.bustago.or.kr/newweb/kr/reserve/reserveline.do");
-            let req = client
-                .post(line_info_url.as_ref())
-                .header(
-                    reqwest::header::REFERER,
-                    "https://www.bustago.or.kr/newweb/kr/reserve/reservelist.do",
-                )
-                .header(reqwest::header::COOKIE, jar.cookies(line_info_url).unwrap())
-                .form(&request)
-                .build()?;
-            let res: LineInfoResponse = client
-                .execute(req)
-                .await
-                .context("Failed to fetch reserveline")?
-                .json()
-                .await
-                .context("Failed to parse reserveline")?;
+            new_reservations.push(line_event(&client, &jar, &mut request, &reservation, id).await?);
+        }
+
+        let stats =
+            CalendarEvent::upsert_events_to_db(self.user_id, &db, new_reservations.iter()).await?;
+        CalendarEvent::mark_seen(self.user_id, &db, &ids).await?;
+
+        // saved ones are not fetched again except their status
+        Ok(CrawlStats {
+            fetched,
+            cancelled,
+            ..stats
+        })
+    }
+}
+
+#[async_trait]
+impl crate::UserImpl for BustagoUser {
+    type Detail = BustagoUserDetail;
+    const SOURCE: &'static str = "bustago";
+    const SOURCE_PREFIX: &'static str = "bustago/";
+
+    const PING_INTERVAL: Option<std::time::Duration> =
+        Some(std::time::Duration::from_secs(10 * 60));
+    const QUERIES_BY_DATE: bool = true;
+
+    fn user_id(&self) -> UserId {
+        self.user_id
+    }
 
-            let line_info = res.list.last().unwrap();
+    fn all_users(db: &SqlitePool) -> futures::stream::BoxStream<'_, anyhow::Result<Self>> {
+        Self::all(db).boxed()
+    }
 
-            let date_begin =
-                chrono::NaiveDate::parse_from_str(&reservation.departure_date, "%Y%m%d").unwrap();
-            let time_begin =
-                chrono::NaiveTime::parse_from_str(&reservation.departure_time, "%H%M").unwrap();
+    async fn scrape(&self, _db: &SqlitePool) -> anyhow::Result<Vec<CalendarEvent>> {
+        let today = chrono::Utc::now().with_timezone(&kst()).date_naive();
+        let jar = self.to_cookie_jar();
+        let client = crate::http::client();
+        let (mut request, items) = self
+            .list_reservations(
+                &client,
+                &jar,
+                today,
+                today + chrono::Duration::days(SEARCH_DAYS),
+            )
+            .await?;
 
-            let (date_begin, time_begin) = date_time_to_utc(
-                date_begin,
-                time_begin,
-                chrono::FixedOffset::east_opt(9).unwrap(),
+        let mut events = Vec::new();
+        for reservation in &items {
+            let id = format!(
+                "{}{}",
+                BustagoUser::SOURCE_PREFIX,
+                reservation.reservation_number
             );
-            let mut dt = chrono::NaiveDateTime::new(date_begin, time_begin);
-            dt += std::time::Duration::from_secs(line_info.distance_time * 60);
-
-            new_reservations.push(CalendarEvent {
-                id,
-                title: format!(
-                    "{}발 {}행 시외버스",
-                    reservation.departure_terminal_name, reservation.arrival_terminal_name
-                ),
-                detail: format!(
-                    "회사: {}\n좌석번호: {}",
-                    reservation.operator_name, reservation.total_seat_count
-                ),
-                invalid: current_invalid,
-                date_begin,
-                time_begin: Some(time_begin),
-                date_end: Some(dt.date()),
-                time_end: Some(dt.time()),
-                location: None,
-                url: None,
-            });
+            events.push(line_event(&client, &jar, &mut request, reservation, id).await?);
         }
+        Ok(events)
+    }
 
-        let updated_item_count = if !new_reservations.is_empty() {
-            CalendarEvent::upsert_events_to_db(self.user_id, &db, new_reservations.iter()).await?
-        } else {
-            0
-        };
-        info!("updated item count: {updated_item_count}",);
+    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<CrawlStats> {
+        let today = chrono::Utc::now().with_timezone(&kst()).date_naive();
+        self.fetch_between(db, today, today + chrono::Duration::days(SEARCH_DAYS))
+            .await
+    }
 
-        Ok(updated_item_count > 0)
+    async fn fetch_history(&self, db: SqlitePool, days: i64) -> anyhow::Result<CrawlStats> {
+        let today = chrono::Utc::now().with_timezone(&kst()).date_naive();
+        self.fetch_between(
+            db,
+            today - chrono::Duration::days(days),
+            today + chrono::Duration::days(SEARCH_DAYS),
+        )
+        .await
     }
 
     async fn ping(&self) -> anyhow::Result<()> {
         let jar = self.to_cookie_jar();
         let url = url!("https://www.bustago.or.kr/newweb/kr/mypage/myPage.do");
-        let client = reqwest::Client::new();
+        let client = crate::http::client();
         let req = client
             .post(url.as_ref())
+            .headers(crate::http::headers("bustago"))
             .header(reqwest::header::COOKIE, jar.cookies(url).unwrap())
             .build()?;
-        client
-            .execute(req)
+        crate::http::execute(&client, req)
             .await
             .context("Error occurred while sending ping")?;
 
@@ -364,13 +513,22 @@ impl crate::UserImpl for BustagoUser {
     async fn from_user_id(db: SqlitePool, user_id: UserId) -> anyhow::Result<Option<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT `user_id` as `user_id: UserId`, `jsessionid`, `user_number` FROM `bustago_user`"
+            "SELECT `user_id` as `user_id: UserId`, `jsessionid`, `user_number` FROM `bustago_user` WHERE `user_id` = ?",
+            user_id
         )
         .fetch_optional(&db)
         .await
         .with_context(|| format!("Failed to get bustago_user of {user_id:?}"))
     }
 
+    async fn delete(db: &mut sqlx::SqliteConnection, user_id: UserId) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM `bustago_user` WHERE `user_id` = ?", user_id)
+            .execute(db)
+            .await
+            .context("Failed to delete bustago user")
+            .map(|_| ())
+    }
+
     async fn update_session(&self, db: SqlitePool) -> anyhow::Result<()> {
         sqlx::query!(
             "INSERT INTO `bustago_user` (`jsessionid`, `user_number`, `user_id`) VALUES (?, ?, ?)
@@ -390,3 +548,168 @@ impl crate::UserImpl for BustagoUser {
 pub fn web_router() -> Router {
     crate::user_web_router::<BustagoUser>()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn from_user_id_returns_session_of_the_user() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        for (user_id, jsessionid, user_number) in [(1, "first", "0001"), (2, "second", "0002")] {
+            BustagoUser {
+                user_id: UserId(user_id),
+                jsessionid: jsessionid.to_string(),
+                user_number: user_number.to_string(),
+            }
+            .update_session(db.clone())
+            .await
+            .unwrap();
+        }
+
+        let user = BustagoUser::from_user_id(db.clone(), UserId(2))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.user_id, UserId(2));
+        assert_eq!(user.jsessionid, "second");
+        assert_eq!(user.user_number, "0002");
+        assert!(BustagoUser::from_user_id(db, UserId(3))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn saved_reservations_are_marked_seen() {
+        let db = crate::testing::db().await;
+        for id in ["bustago/R202405010001", "bustago/R202405010002"] {
+            sqlx::query(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `invalid`, `updated_at`, `last_seen_at`)
+                VALUES (?, ?, '동서울 → 강릉', '', '2024-05-01', FALSE, '2024-04-20 00:00:00', '2024-04-20 00:00:00')",
+            )
+            .bind(id)
+            .bind(crate::testing::USER_ID)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        let user = BustagoUser {
+            user_id: crate::testing::USER_ID,
+            jsessionid: "jsessionid".to_string(),
+            user_number: "0001".to_string(),
+        };
+
+        // only listed, the details of the saved ones are not fetched again
+        let stats = crate::vcr::Cassette::load("tests/fixtures/cassettes/bustago_saved.json")
+            .unwrap()
+            .scope(crate::UserImpl::fetch(&user, db.clone()))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((stats.fetched, stats.new, stats.cancelled), (2, 0, 1));
+
+        let last_seen: Vec<chrono::NaiveDateTime> =
+            sqlx::query_scalar("SELECT `last_seen_at` FROM `reservation`")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(last_seen.len(), 2);
+        let today = chrono::Utc::now().date_naive();
+        assert!(last_seen
+            .iter()
+            .all(|last_seen| last_seen.date() >= today - chrono::Duration::days(1)));
+    }
+
+    #[test]
+    fn parse_reservations_fixture() {
+        let res: ReservationResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/bustago_reservations.json"))
+                .unwrap();
+
+        assert_eq!(res.items.len(), 2);
+        let reservation = &res.items[0];
+        assert_eq!(reservation.reservation_number, "R202405010001");
+        assert_eq!(reservation.departure_terminal_name, "동서울");
+        assert_eq!(reservation.arrival_terminal_name, "강릉");
+        assert_eq!(reservation.departure_date, "20240501");
+        assert_eq!(reservation.departure_time, "0830");
+        assert_eq!(reservation.all_seat_status, "1");
+        assert_eq!(res.items[1].all_seat_status, "2");
+    }
+
+    #[test]
+    fn seat_count_is_party_size() {
+        let res: ReservationResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/bustago_reservations.json"))
+                .unwrap();
+        let mut reservation = res.items.into_iter().next().unwrap();
+        let operator = reservation.operator_name.clone();
+
+        reservation.total_seat_count = "2".to_string();
+        assert_eq!(reservation.party_size(), Some(2));
+        assert_eq!(reservation.detail(), format!("회사: {operator}"));
+
+        reservation.total_seat_count = "2석".to_string();
+        assert_eq!(reservation.party_size(), None);
+        assert_eq!(
+            reservation.detail(),
+            format!("회사: {operator}\n좌석번호: 2석")
+        );
+    }
+
+    #[test]
+    fn parse_line_info_fixture() {
+        let res: LineInfoResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/bustago_line.json")).unwrap();
+
+        assert_eq!(res.list.last().unwrap().distance_time, 150);
+    }
+
+    #[test]
+    fn reservations_are_queried_by_departure_date() {
+        let request = reservations_request(
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap(),
+            chrono::NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(),
+            "12345",
+        );
+
+        assert_eq!(request["v_dateGb"], 2);
+        assert_eq!(request["fromDate"], 20240501);
+        assert_eq!(request["toDate"], 20240601);
+        assert_eq!(request["userNumber"], "12345");
+        assert_eq!(request["tokenId"], "");
+    }
+
+    #[test]
+    fn schedule_is_converted_to_utc() {
+        let res: ReservationResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/bustago_reservations.json"))
+                .unwrap();
+        let date_time = |day, hour, minute| {
+            chrono::NaiveDate::from_ymd_opt(2024, 4, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap()
+        };
+
+        // 08:30 KST departs on the previous day in UTC and arrives on the same day
+        let (departure, arrival) = res.items[0].schedule_in_utc(150).unwrap();
+        assert_eq!(departure, date_time(30, 23, 30));
+        assert_eq!(
+            arrival,
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 1)
+                .unwrap()
+                .and_hms_opt(2, 0, 0)
+                .unwrap()
+        );
+
+        // 00:30 KST
+        let mut reservation = res.items.into_iter().next().unwrap();
+        reservation.departure_time = "0030".to_string();
+        let (departure, arrival) = reservation.schedule_in_utc(150).unwrap();
+        assert_eq!(departure, date_time(30, 15, 30));
+        assert_eq!(arrival, date_time(30, 18, 0));
+    }
+}
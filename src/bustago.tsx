@@ -1,6 +1,10 @@
 import React from "react";
-import { ActionFunctionArgs, Form, Navigate, Params, useLoaderData } from "react-router-dom";
-import { AsyncReturnType, formDataToJsonString } from './utils';
+import { ActionFunctionArgs, Form, Navigate, Params, useActionData, useLoaderData } from "react-router-dom";
+import { AsyncReturnType, formDataToJsonString, postJson, request as sendRequest } from './utils';
+import { ImportHistory } from './import_history';
+import { RemoveSource } from './remove_source';
+import { preview, PreviewButton, PreviewEvents, PreviewResult } from './preview';
+import { SessionInput } from './session_input';
 
 export async function loader() {
     const resp = await fetch("/bustago/user", {
@@ -10,7 +14,8 @@ export async function loader() {
     if (resp.ok) {
         const parsed = await resp.json();
         return {
-            jsessionid: parsed.jsessionid as string,
+            saved: parsed.saved as string[],
+            required: parsed.required as string[],
             user_number: parsed.user_number as string,
         }
     } else {
@@ -20,27 +25,35 @@ export async function loader() {
 
 export async function action({ request }: ActionFunctionArgs) {
     const formData = await request.formData();
-    return await fetch("/bustago/user", {
-        headers: {
-            'Content-Type': 'application/json'
-        },
-        method: "post",
-        body: formDataToJsonString(formData)
-    });
+    if (formData.get("intent") === "remove") {
+        return await sendRequest("/bustago/user", { method: "delete" });
+    }
+    if (formData.get("intent") === "history") {
+        return await sendRequest("/bustago/user/history", { method: "post" });
+    }
+    if (formData.get("intent") === "preview") {
+        return await preview("/bustago/user/preview", formDataToJsonString(formData));
+    }
+    return await postJson("/bustago/user", formDataToJsonString(formData));
 }
 
 export function Component() {
     const data = useLoaderData() as AsyncReturnType<typeof loader>;
+    const result = useActionData() as PreviewResult | undefined;
 
     if (data !== null) {
         return <div>
             <Form method="post" action="/bustago">
-                <label htmlFor="jsessionid">jsessionid</label>
-                <input type="text" name="jsessionid" defaultValue={data.jsessionid} />
+                <SessionInput name="jsessionid" label="jsessionid" saved={data.saved} required={data.required} />
                 <label htmlFor="user_number">user_number</label>
                 <input type="text" name="user_number" defaultValue={data.user_number} />
+                {result?.error && <p role="alert">{result.error}</p>}
                 <button type="submit">Update</button>
+                <PreviewButton />
             </Form>
+            <PreviewEvents result={result} />
+            <ImportHistory action="/bustago" />
+            <RemoveSource action="/bustago" name="Bustago" />
         </div>;
     } else {
         return <Navigate to="/" />;
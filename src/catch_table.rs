@@ -1,12 +1,58 @@
 use anyhow::Context;
 use axum::{async_trait, Router};
 use futures::StreamExt;
-use log::info;
 use reqwest::cookie::CookieStore;
 use serde::Deserialize;
 use sqlx::SqlitePool;
 
-use crate::{url, CalendarEvent, UserId};
+use crate::{
+    title::Title, url, user::SessionExpired, CalendarEvent, CrawlStats, UserId, UserImpl as _,
+};
+
+/// Envelope of all responses, `resultCode` is `"0000"` on success
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResultEnvelope {
+    result_code: Option<String>,
+    result_message: Option<String>,
+}
+
+const SUCCESS_CODE: &str = "0000";
+/// Result codes of the error envelope asking to login, `4010` and alike
+const AUTH_FAILURE_CODE_PREFIX: &str = "401";
+const SESSION_EXPIRED: &str = "CatchTable session expired, re-paste x-ct-a";
+
+/// With an invalid cookie, the api answers the login page or an error
+/// envelope instead of the data, which is not parsed as the response
+fn check_response(response: &crate::dump::RawResponse) -> anyhow::Result<()> {
+    if matches!(
+        response.status,
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN
+    ) {
+        return Err(SessionExpired(format!("{SESSION_EXPIRED} - {}", response.status)).into());
+    }
+    if response.body.trim_ascii_start().starts_with(b"<") {
+        return Err(SessionExpired(format!("{SESSION_EXPIRED} - login page is returned")).into());
+    }
+    if let Ok(ResultEnvelope {
+        result_code: Some(code),
+        result_message,
+    }) = serde_json::from_slice(&response.body)
+    {
+        let message = result_message.unwrap_or_default();
+        if code.starts_with(AUTH_FAILURE_CODE_PREFIX) {
+            return Err(SessionExpired(format!("{SESSION_EXPIRED} - {code} {message}")).into());
+        }
+        if code != SUCCESS_CODE {
+            anyhow::bail!("CatchTable responded {code} {message}");
+        }
+    }
+    if !response.status.is_success() {
+        anyhow::bail!("CatchTable responded {}", response.status);
+    }
+
+    Ok(())
+}
 
 #[derive(Debug, Deserialize)]
 struct ReservationsResponse {
@@ -22,8 +68,27 @@ struct ReservationsData {
 #[serde(rename_all = "UPPERCASE")]
 #[serde(tag = "reservationType")]
 enum Reservation {
-    Waiting,
+    Waiting(Waiting),
     Dining(Dining),
+    /// e.g. pickup orders, which are not placed on calendar
+    #[serde(other)]
+    Other,
+}
+
+/// `reservationStatus`, not given by some responses for active ones
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum ReservationStatus {
+    #[default]
+    Reserved,
+    Visited,
+    #[serde(alias = "CANCELED", alias = "CANCEL")]
+    Cancelled,
+    /// Kept as it is, the reservation was made for the time
+    #[serde(alias = "NOSHOW")]
+    NoShow,
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, Deserialize)]
@@ -32,11 +97,26 @@ struct ReservationCommon {
     // unique id
     // https://ct-api.catchtable.co.kr/api/v3/reservation/detail?reservationRef=<reservation_ref>
     reservation_ref: String,
+    #[serde(default)]
+    reservation_status: ReservationStatus,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Waiting {
+    #[serde(flatten)]
+    common: ReservationCommon,
+    #[serde(default)]
+    waiting: Option<WaitingDetail>,
+    shop: Option<Shop>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct Waiting {}
+struct WaitingDetail {
+    /// expected time to be seated, not given until the shop estimates it
+    estimated_entry_date_time: Option<u64>,
+}
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -51,6 +131,8 @@ struct Dining {
 #[serde(rename_all = "camelCase")]
 struct DiningDetail {
     visit_date_time: u64,
+    #[serde(default)]
+    person_count: Option<u32>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -62,47 +144,73 @@ struct Shop {
     food_kind: Option<String>,
 }
 
+impl Shop {
+    fn detail(self) -> String {
+        itertools::join(
+            self.land_name.into_iter().chain(self.food_kind.into_iter()),
+            " - ",
+        )
+    }
+}
+
+fn from_timestamp(timestamp: u64) -> anyhow::Result<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::from_timestamp_millis(timestamp as i64)
+        .context("Failed to convert from timestamp")
+}
+
+fn reservation_url(reservation_ref: &str) -> String {
+    format!("https://ct-api.catchtable.co.kr/api/v3/reservation/detail?reservationRef={reservation_ref}")
+}
+
 impl TryFrom<Reservation> for Option<CalendarEvent> {
     type Error = anyhow::Error;
 
     fn try_from(value: Reservation) -> Result<Self, Self::Error> {
-        let Reservation::Dining(dining) = value else {
-            return Ok(None);
+        let (common, shop, date_time, party_size, tentative) = match value {
+            Reservation::Dining(dining) => (
+                dining.common,
+                dining.shop,
+                dining.dining.visit_date_time,
+                dining.dining.person_count,
+                false,
+            ),
+            Reservation::Waiting(Waiting {
+                common,
+                waiting:
+                    Some(WaitingDetail {
+                        estimated_entry_date_time: Some(date_time),
+                    }),
+                shop: Some(shop),
+            }) => (common, shop, date_time, None, true),
+            // could not be placed on calendar without the time
+            Reservation::Waiting(_) | Reservation::Other => return Ok(None),
         };
 
-        let id = format!("catch_table/{}", dining.common.reservation_ref);
-        let title = dining.shop.shop_name;
-        let location = dining.shop.shop_address;
-        let detail = itertools::join(
-            dining
-                .shop
-                .land_name
-                .into_iter()
-                .chain(dining.shop.food_kind.into_iter()),
-            " - ",
-        );
-        let date_time =
-            chrono::NaiveDateTime::from_timestamp_millis(dining.dining.visit_date_time as i64)
-                .context("Failed to convert from timestamp")?;
-        let date_begin = date_time.date();
-        let time_begin = date_time.time();
-        let url = format!(
-            "https://ct-api.catchtable.co.kr/api/v3/reservation/detail?reservationRef={}",
-            dining.common.reservation_ref
+        let id = format!(
+            "{}{}",
+            CatchTableUser::SOURCE_PREFIX,
+            common.reservation_ref
         );
+        let location = shop.shop_address.clone();
+        let date_time = from_timestamp(date_time)?;
+        let place = shop.shop_name.clone();
+        let title = if tentative {
+            Title::Waiting { place }
+        } else {
+            Title::Dining { place }
+        };
+        let builder = CalendarEvent::builder_of(id, title, date_time.date());
 
-        Ok(Some(CalendarEvent {
-            id,
-            title,
-            detail,
-            invalid: false,
-            date_begin,
-            time_begin: Some(time_begin),
-            date_end: None,
-            time_end: None,
-            location: Some(location),
-            url: Some(url),
-        }))
+        builder
+            .detail(shop.detail())
+            .invalid(common.reservation_status == ReservationStatus::Cancelled)
+            .time_begin(date_time.time())
+            .location(location)
+            .url(reservation_url(&common.reservation_ref))
+            .tentative(tentative)
+            .party_size(party_size)
+            .build()
+            .map(Some)
     }
 }
 
@@ -129,63 +237,87 @@ impl CatchTableUser {
 #[async_trait]
 impl crate::UserImpl for CatchTableUser {
     type Detail = CatchTableUserDetail;
+    const SOURCE: &'static str = "catch_table";
+    const SOURCE_PREFIX: &'static str = "catch_table/";
 
     const PING_INTERVAL: Option<std::time::Duration> =
         Some(std::time::Duration::from_secs(10 * 60));
 
-    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<bool> {
+    fn user_id(&self) -> UserId {
+        self.user_id
+    }
+
+    fn all_users(db: &SqlitePool) -> futures::stream::BoxStream<'_, anyhow::Result<Self>> {
+        Self::all(db).boxed()
+    }
+
+    async fn scrape(&self, _db: &SqlitePool) -> anyhow::Result<Vec<CalendarEvent>> {
         let jar = self.to_cookie_jar();
         let planned_url = url!("https://ct-api.catchtable.co.kr/api/v4/user/reservations/_list?statusGroup=PLANNED&sortCode=DESC&size=10");
-        let client = reqwest::Client::new();
+        let client = crate::http::client();
         let req = client
             .get(planned_url.as_ref())
+            .headers(crate::http::headers("catch_table"))
             .header(reqwest::header::COOKIE, jar.cookies(planned_url).unwrap())
             .build()?;
-        let res: ReservationsResponse = client.execute(req).await?.json().await?;
-        if res.data.items.is_empty() {
-            return Ok(false);
-        }
+        let res = crate::dump::RawResponse::read(crate::http::execute(&client, req).await?).await?;
+        check_response(&res)?;
+        let res: ReservationsResponse = res.parse_json("catch_table")?;
 
-        let reservations = res
-            .data
+        res.data
             .items
             .into_iter()
             .filter_map(|item| <Option<CalendarEvent>>::try_from(item).transpose())
-            .collect::<Result<Vec<_>, _>>()?;
+            .collect()
+    }
 
-        let updated_item_count =
-            CalendarEvent::upsert_events_to_db(self.user_id, &db, reservations.iter()).await?;
-        info!("updated item count: {updated_item_count}",);
+    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<CrawlStats> {
+        let reservations = self.scrape(&db).await?;
+        if reservations.is_empty() {
+            return Ok(CrawlStats::default());
+        }
 
-        Ok(updated_item_count > 0)
+        CalendarEvent::upsert_events_to_db(self.user_id, &db, reservations.iter()).await
     }
 
     async fn ping(&self) -> anyhow::Result<()> {
         let jar = self.to_cookie_jar();
         let url = url!("https://ct-api.catchtable.co.kr/api/v3/user/lastLoginTime");
-        let client = reqwest::Client::new();
+        let client = crate::http::client();
         let req = client
             .post(url.as_ref())
+            .headers(crate::http::headers("catch_table"))
             .header(reqwest::header::COOKIE, jar.cookies(url).unwrap())
             .build()?;
-        client
-            .execute(req)
+        let res = crate::http::execute(&client, req)
             .await
             .context("Error occurred while sending ping")?;
 
-        Ok(())
+        check_response(&crate::dump::RawResponse::read(res).await?)
     }
 
     async fn from_user_id(db: SqlitePool, user_id: UserId) -> anyhow::Result<Option<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT `user_id` as `user_id: UserId`, `jsessionid` FROM `catch_table_user`"
+            "SELECT `user_id` as `user_id: UserId`, `jsessionid` FROM `catch_table_user` WHERE `user_id` = ?",
+            user_id
         )
         .fetch_optional(&db)
         .await
         .with_context(|| format!("Failed to get catch_table_user of {user_id:?}"))
     }
 
+    async fn delete(db: &mut sqlx::SqliteConnection, user_id: UserId) -> anyhow::Result<()> {
+        sqlx::query!(
+            "DELETE FROM `catch_table_user` WHERE `user_id` = ?",
+            user_id
+        )
+        .execute(db)
+        .await
+        .context("Failed to delete catch table user")
+        .map(|_| ())
+    }
+
     async fn update_session(&self, db: SqlitePool) -> anyhow::Result<()> {
         sqlx::query!(
             "INSERT INTO `catch_table_user` (`jsessionid`, `user_id`) VALUES (?, ?)
@@ -204,3 +336,123 @@ impl crate::UserImpl for CatchTableUser {
 pub fn web_router() -> Router {
     crate::user_web_router::<CatchTableUser>()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelled_dining_is_invalid() {
+        let res: ReservationsResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/catch_table_statuses.json"))
+                .unwrap();
+
+        let events = res
+            .data
+            .items
+            .into_iter()
+            .filter_map(|item| <Option<CalendarEvent>>::try_from(item).transpose())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| (event.id.as_str(), event.invalid))
+                .collect::<Vec<_>>(),
+            [
+                ("catch_table/CT-RESV-0002", false),
+                ("catch_table/CT-RESV-0003", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_reservations_fixture() {
+        let res: ReservationsResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/catch_table.json")).unwrap();
+        assert_eq!(res.data.items.len(), 3);
+
+        let events = res
+            .data
+            .items
+            .into_iter()
+            .filter_map(|item| <Option<CalendarEvent>>::try_from(item).transpose())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(events.len(), 2);
+        let event = &events[0];
+        assert_eq!(event.id, "catch_table/CT-RESV-0001");
+        assert_eq!(event.title, "테스트 오마카세");
+        assert_eq!(
+            event.title_with(crate::title::Locale::En, Some("🍣 {place}")),
+            "🍣 테스트 오마카세"
+        );
+        assert_eq!(event.detail, "압구정 - 스시");
+        assert_eq!(event.party_size, Some(2));
+        assert!(!event.invalid);
+        assert_eq!(
+            event.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+        );
+        assert_eq!(event.time_begin, chrono::NaiveTime::from_hms_opt(10, 30, 0));
+        assert_eq!(event.location.as_deref(), Some("서울 강남구 도산대로 1"));
+        assert!(!event.tentative);
+
+        let waiting = &events[1];
+        assert_eq!(waiting.id, "catch_table/CT-WAIT-0002");
+        assert_eq!(waiting.title, "[대기] 테스트 라멘");
+        assert_eq!(waiting.detail, "성수");
+        assert!(waiting.tentative);
+        assert_eq!(
+            waiting.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+        );
+        assert_eq!(waiting.time_begin, chrono::NaiveTime::from_hms_opt(3, 0, 0));
+    }
+    #[test]
+    fn expired_session_is_reported() {
+        let response = |status, body: &'static str| crate::dump::RawResponse {
+            status,
+            headers: Default::default(),
+            body: body.into(),
+        };
+
+        let expired = check_response(&response(
+            reqwest::StatusCode::OK,
+            include_str!("../tests/fixtures/catch_table_expired.json"),
+        ))
+        .unwrap_err()
+        .to_string();
+        assert!(expired.starts_with(SESSION_EXPIRED));
+        assert!(expired.contains("로그인이 필요합니다."));
+
+        let login_page = check_response(&response(
+            reqwest::StatusCode::OK,
+            "\n<!DOCTYPE html><html><title>로그인</title></html>",
+        ))
+        .unwrap_err();
+        assert!(login_page.to_string().starts_with(SESSION_EXPIRED));
+
+        let unauthorized =
+            check_response(&response(reqwest::StatusCode::UNAUTHORIZED, "")).unwrap_err();
+        assert!(unauthorized.to_string().starts_with(SESSION_EXPIRED));
+        assert!(unauthorized.is::<SessionExpired>());
+
+        // failures not about the session
+        let failure = check_response(&response(
+            reqwest::StatusCode::OK,
+            r#"{"resultCode": "5000", "resultMessage": "일시적인 오류입니다."}"#,
+        ))
+        .unwrap_err();
+        assert!(!failure.is::<SessionExpired>());
+        assert!(failure.to_string().contains("5000"));
+
+        check_response(&response(
+            reqwest::StatusCode::OK,
+            include_str!("../tests/fixtures/catch_table.json"),
+        ))
+        .unwrap();
+    }
+}
@@ -4,10 +4,10 @@ use std::str::FromStr;
 use anyhow::Context as _;
 use axum::{async_trait, Router};
 use chrono::Datelike;
-use futures::StreamExt;
+use futures::{StreamExt, TryStreamExt as _};
 use hyper::StatusCode;
 use itertools::Itertools as _;
-use log::info;
+use log::{info, warn};
 use reqwest::{
     cookie::{CookieStore as _, Jar},
     Client,
@@ -16,7 +16,15 @@ use scraper::Html;
 use sqlx::SqlitePool;
 use std::fmt::Write;
 
-use crate::{date_time_to_utc, regex, selector, url, CalendarEvent, UserId};
+use crate::{
+    date_time_to_utc, kst, regex,
+    reservation::linked_data_events,
+    selector,
+    title::{Chain, Title},
+    url,
+    user::SessionExpired,
+    CalendarEvent, CrawlStats, UserId, UserImpl as _,
+};
 
 #[derive(serde::Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -30,6 +38,68 @@ struct ReservationListResponse {
     pub data: ReservationListResponseData,
 }
 
+/// Reservations per page of the list
+const SHOW_COUNT: usize = 10;
+/// Not to loop forever when the list doesn't end
+const MAX_PAGES: u32 = 10;
+/// Reservations made in these days are fetched on each crawl
+const RECENT_DAYS: i64 = 7;
+
+fn parse_list_ids(html: &str) -> Vec<String> {
+    regex!("javascript:fnReservDetail\\('([^']+)'\\)")
+        .captures_iter(html)
+        .filter_map(|capture| {
+            capture
+                .get(1)
+                .map(|i| format!("{}{}", CgvUser::SOURCE_PREFIX, i.as_str()))
+        })
+        .collect()
+}
+
+/// Reservation ids of all pages, `fetch_page` gives the list html of the page
+/// starting from 1. The last page has less than `SHOW_COUNT` reservations.
+async fn collect_ids<F, Fut>(mut fetch_page: F) -> anyhow::Result<Vec<String>>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<Option<String>>>,
+{
+    let mut ids = Vec::new();
+    for page in 1..=MAX_PAGES {
+        let Some(html) = fetch_page(page).await? else {
+            break;
+        };
+        let page_ids = parse_list_ids(&html);
+        let is_last = page_ids.len() < SHOW_COUNT;
+        ids.extend(page_ids);
+        if is_last {
+            break;
+        }
+        if page == MAX_PAGES {
+            warn!("Reservations after page {MAX_PAGES} are ignored");
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Details of `ids`, fetched up to `concurrency` at once. Stops on the first
+/// failure. The order of the details is not kept.
+async fn fetch_details<F, Fut>(
+    ids: impl IntoIterator<Item = String>,
+    concurrency: usize,
+    fetch: F,
+) -> anyhow::Result<Vec<CalendarEvent>>
+where
+    F: FnMut(String) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<CalendarEvent>>,
+{
+    futures::stream::iter(ids)
+        .map(fetch)
+        .buffer_unordered(concurrency.max(1))
+        .try_collect()
+        .await
+}
+
 crate::define_user_data! {
     #[table_name = "cgv"]
     #[base_url = "https://m.cgv.co.kr/"]
@@ -58,28 +128,78 @@ async fn fetch_detail(
     id: &str,
     year: i32,
 ) -> anyhow::Result<CalendarEvent> {
-    // remove prefix
-    let cgv_id = &id[4..];
+    let cgv_id = id
+        .strip_prefix(CgvUser::SOURCE_PREFIX)
+        .ok_or_else(|| anyhow::anyhow!("Not a CGV reservation - {id}"))?;
     info!("Crawl detail for {cgv_id}");
-    let detail_url = reqwest::Url::from_str(&format!(
-        "https://m.cgv.co.kr/WebApp/MyCgvV5/reservationDetail.aspx?bookingnumber={cgv_id}"
-    ))?;
+    let url = reqwest::Url::from_str(&detail_url(cgv_id))?;
 
-    let cookie = jar.cookies(&detail_url).unwrap();
+    let cookie = jar.cookies(&url).unwrap();
     let req = client
-        .get(detail_url)
+        .get(url)
+        .headers(crate::http::headers("cgv"))
         .header(reqwest::header::COOKIE, cookie)
         .build()?;
-    let res = client.execute(req).await?.bytes().await?;
+    let res = crate::http::execute(client, req).await?.bytes().await?;
     let html = std::str::from_utf8(&res)?;
-    let fragment = Html::parse_fragment(&html);
-    let movie_title = fragment
-        .select(selector!(".movie-tit"))
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("Could not find title"))?
-        .text()
-        .map(|t| t.to_string())
-        .join("");
+
+    parse_detail(html, id, year)
+}
+
+fn detail_url(cgv_id: &str) -> String {
+    format!("https://m.cgv.co.kr/WebApp/MyCgvV5/reservationDetail.aspx?bookingnumber={cgv_id}")
+}
+
+/// Detail from the JSON-LD of the page, when the markup is changed
+fn parse_linked_data_detail(fragment: &Html, id: &str) -> anyhow::Result<CalendarEvent> {
+    let cgv_id = id.strip_prefix(CgvUser::SOURCE_PREFIX).unwrap_or(id);
+    let (event, start) = linked_data_events(fragment)
+        .into_iter()
+        .filter(|event| {
+            event
+                .reservation_number
+                .as_deref()
+                .is_none_or(|number| number == cgv_id)
+        })
+        .find_map(|event| event.start.map(|start| (event, start)))
+        .ok_or_else(|| anyhow::anyhow!("Could not find title nor JSON-LD"))?;
+    let movie_title = event
+        .name
+        .ok_or_else(|| anyhow::anyhow!("Could not find title in JSON-LD"))?;
+
+    let builder = match &event.location {
+        Some(theater) => CalendarEvent::builder_of(
+            id,
+            Title::Movie {
+                movie: movie_title,
+                chain: Chain::Cgv,
+                theater: theater.clone(),
+            },
+            start.date(),
+        ),
+        None => CalendarEvent::builder(id, movie_title, start.date()),
+    };
+
+    builder
+        .invalid(event.cancelled)
+        .time_begin(start.time())
+        .end(
+            event.end.map(|end| end.date()),
+            event.end.map(|end| end.time()),
+        )
+        .location(event.location)
+        .url(event.url.unwrap_or_else(|| detail_url(cgv_id)))
+        .build()
+}
+
+fn parse_detail(html: &str, id: &str, year: i32) -> anyhow::Result<CalendarEvent> {
+    // remove prefix
+    let cgv_id = &id[4..];
+    let fragment = Html::parse_fragment(html);
+    let Some(movie_title) = fragment.select(selector!(".movie-tit")).next() else {
+        return parse_linked_data_detail(&fragment, id);
+    };
+    let movie_title = movie_title.text().map(|t| t.to_string()).join("");
 
     let date_time_element = fragment
         .select(selector!(".date-n-runningtime"))
@@ -88,6 +208,8 @@ async fn fetch_detail(
     let mut date = None;
     let mut time_begin = None;
     let mut time_end = None;
+    // late night showtimes are written as hours past 24 on the previous date
+    let mut begin_next_day = false;
     for div in date_time_element.select(selector!("div")) {
         let key = div
             .select(selector!(".inner-tit"))
@@ -103,10 +225,11 @@ async fn fetch_detail(
             "상영일" => {
                 let s = value.context("date content")?.text().join("");
                 if let Some(c) = regex!("(\\d+)/(\\d+)").captures(&s) {
-                    let (month, day) =
-                        unsafe { (c.get(1).unwrap_unchecked(), c.get(2).unwrap_unchecked()) };
-                    let month: u32 = unsafe { month.as_str().parse().unwrap_unchecked() };
-                    let day: u32 = unsafe { day.as_str().parse().unwrap_unchecked() };
+                    let number = |i: usize| -> anyhow::Result<u32> {
+                        c[i].parse()
+                            .with_context(|| format!("Invalid number in date - {s}"))
+                    };
+                    let (month, day) = (number(1)?, number(2)?);
 
                     date = Some(
                         chrono::NaiveDate::from_ymd_opt(year, month, day).ok_or_else(|| {
@@ -118,28 +241,25 @@ async fn fetch_detail(
             "상영시간" => {
                 let s = value.context("time content")?.text().join("");
                 if let Some(c) = regex!("(\\d+):(\\d+)\\s*~\\s*(\\d+):(\\d+)").captures(&s) {
-                    let (begin_hour, begin_minute, end_hour, end_minute) = unsafe {
-                        (
-                            c.get(1).unwrap_unchecked(),
-                            c.get(2).unwrap_unchecked(),
-                            c.get(3).unwrap_unchecked(),
-                            c.get(4).unwrap_unchecked(),
-                        )
+                    let number = |i: usize| -> anyhow::Result<u32> {
+                        c[i].parse()
+                            .with_context(|| format!("Invalid number in time - {s}"))
                     };
-                    let begin_hour: u32 = unsafe { begin_hour.as_str().parse().unwrap_unchecked() };
-                    let begin_minute: u32 =
-                        unsafe { begin_minute.as_str().parse().unwrap_unchecked() };
-                    let end_hour: u32 = unsafe { end_hour.as_str().parse().unwrap_unchecked() };
-                    let end_minute: u32 = unsafe { end_minute.as_str().parse().unwrap_unchecked() };
+                    let (begin_hour, begin_minute, end_hour, end_minute) =
+                        (number(1)?, number(2)?, number(3)?, number(4)?);
 
+                    begin_next_day = begin_hour >= 24;
                     time_begin = Some(
-                        chrono::NaiveTime::from_hms_opt(begin_hour, begin_minute, 0).ok_or_else(
-                            || {
-                                anyhow::anyhow!(
-                                    "Failed to convert begin time - {begin_hour}:{begin_minute} - {s}"
-                                )
-                            },
-                        )?,
+                        chrono::NaiveTime::from_hms_opt(
+                            begin_hour.checked_sub(24).unwrap_or(begin_hour),
+                            begin_minute,
+                            0,
+                        )
+                        .ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "Failed to convert begin time - {begin_hour}:{begin_minute} - {s}"
+                            )
+                        })?,
                     );
                     time_end = Some(
                         chrono::NaiveTime::from_hms_opt(
@@ -161,25 +281,22 @@ async fn fetch_detail(
     let (Some(date), Some(time_begin), Some(time_end)) = (date, time_begin, time_end) else {
         return Err(anyhow::anyhow!("Could not find date or time element"));
     };
-    let (date_begin, date_end) = if time_end > time_begin {
-        (date, date)
+    let next_day_of = |date: chrono::NaiveDate| {
+        date.succ_opt()
+            .ok_or_else(|| anyhow::anyhow!("Could not get next day of {:?}", date))
+    };
+    let date_begin = if begin_next_day {
+        next_day_of(date)?
     } else {
-        (
-            date,
-            date.succ_opt()
-                .ok_or_else(|| anyhow::anyhow!("Could not get next day of {:?}", date))?,
-        )
+        date
     };
-    let (date_begin, time_begin) = date_time_to_utc(
-        date_begin,
-        time_begin,
-        chrono::FixedOffset::east_opt(9 * 60 * 60).unwrap(),
-    );
-    let (date_end, time_end) = date_time_to_utc(
-        date_end,
-        time_end,
-        chrono::FixedOffset::east_opt(9 * 60 * 60).unwrap(),
-    );
+    let date_end = if time_end > time_begin {
+        date_begin
+    } else {
+        next_day_of(date_begin)?
+    };
+    let (date_begin, time_begin) = date_time_to_utc(date_begin, time_begin, kst());
+    let (date_end, time_end) = date_time_to_utc(date_end, time_end, kst());
 
     let ticket_detail_element = fragment
         .select(selector!(".ticket-detail"))
@@ -223,101 +340,154 @@ async fn fetch_detail(
         writeln!(detail, "좌석: {}", seat)?;
     }
 
-    let url =
-        format!("https://m.cgv.co.kr/WebApp/MyCgvV5/reservationDetail.aspx?bookingnumber={cgv_id}");
-
-    Ok(CalendarEvent {
-        id: id.to_string(),
-        title: format!("{movie_title} - {theater}"),
-        detail,
-        invalid: false,
-        date_begin,
-        time_begin: Some(time_begin),
-        date_end: Some(date_end),
-        time_end: Some(time_end),
-        location: Some(theater),
-        url: Some(url),
-    })
-}
+    let url = detail_url(cgv_id);
 
-#[async_trait]
-impl crate::UserImpl for CgvUser {
-    type Detail = CgvUserDetail;
+    let title = Title::Movie {
+        movie: movie_title,
+        chain: Chain::Cgv,
+        theater: theater.clone(),
+    };
 
-    const PING_INTERVAL: Option<std::time::Duration> =
-        Some(std::time::Duration::from_secs(29 * 60));
+    CalendarEvent::builder_of(id, title, date_begin)
+        .detail(detail)
+        .time_begin(time_begin)
+        .end(date_end, time_end)
+        .location(theater)
+        .url(url)
+        .build()
+}
 
-    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<bool> {
-        let jar = self.to_cookie_jar();
+impl CgvUser {
+    /// Ids of reservations made in the last `days`
+    async fn list_ids(&self, client: &Client, jar: &Jar, days: i64) -> anyhow::Result<Vec<String>> {
         let reservation_list_page_url =
             url!("https://m.cgv.co.kr/WebApp/MyCgvV5/paymentList.aspx/GetReservationListPaging");
-        let client = reqwest::Client::new();
-        let now_in_utc9 = chrono::Local::now()
-            .with_timezone(&unsafe { chrono::FixedOffset::east_opt(9).unwrap_unchecked() });
+        let now_in_utc9 = chrono::Local::now().with_timezone(&kst());
         let end_dt = now_in_utc9.format("%Y-%m-%d").to_string();
-        let start_dt = (now_in_utc9 - chrono::Duration::days(7))
+        let start_dt = (now_in_utc9 - chrono::Duration::days(days))
             .format("%Y-%m-%d")
             .to_string();
-        let request_data = serde_json::to_string(&serde_json::json!({
-            "UserId": "",
-            "Ssn": "",
-            "AppType": "",
-            "RegistSite": "",
-            "BookingStateCd": "A",
-            "SortCd": "R",
-            "SelectStartDT": start_dt,
-            "SelectEndDT": end_dt,
-            "ShowCnt": 10,
-            "NowPage":1
-        }))
-        .unwrap();
-        let req = client
-            .post(reservation_list_page_url.as_ref())
-            .header(
-                reqwest::header::COOKIE,
-                jar.cookies(reservation_list_page_url).unwrap(),
-            )
-            .json(&serde_json::json!({ "requestData": request_data }))
-            .build()?;
-        let res: ReservationListResponse = client.execute(req).await?.json().await?;
-        let Some(html) = res.data.reservation_list_html else {
-            return Ok(false);
-        };
-
-        let item_regex = regex!("javascript:fnReservDetail\\('([^']+)'\\)");
-        let captures = item_regex.captures_iter(&html);
+        collect_ids(|page| {
+            let request_data = serde_json::to_string(&serde_json::json!({
+                "UserId": "",
+                "Ssn": "",
+                "AppType": "",
+                "RegistSite": "",
+                "BookingStateCd": "A",
+                "SortCd": "R",
+                "SelectStartDT": start_dt,
+                "SelectEndDT": end_dt,
+                "ShowCnt": SHOW_COUNT,
+                "NowPage": page
+            }))
+            .unwrap();
+            let req = client
+                .post(reservation_list_page_url.as_ref())
+                .headers(crate::http::headers("cgv"))
+                .header(
+                    reqwest::header::COOKIE,
+                    jar.cookies(reservation_list_page_url).unwrap(),
+                )
+                .json(&serde_json::json!({ "requestData": request_data }))
+                .build();
+            async move {
+                let res: ReservationListResponse =
+                    crate::dump::parse_json("cgv", crate::http::execute(client, req?).await?)
+                        .await?;
+                Ok(res.data.reservation_list_html)
+            }
+        })
+        .await
+    }
 
-        let ids = captures
-            .filter_map(|capture| capture.get(1).map(|i| format!("cgv/{}", i.as_str())))
-            .collect::<Vec<_>>();
+    /// Fetches reservations made in the last `days`
+    async fn fetch_since(&self, db: SqlitePool, days: i64) -> anyhow::Result<CrawlStats> {
+        let jar = self.to_cookie_jar();
+        let client = crate::http::client();
+        let ids = self.list_ids(&client, &jar, days).await?;
+        if ids.is_empty() {
+            return Ok(CrawlStats::default());
+        }
 
         let new_ids = CalendarEvent::filter_ids(self.user_id, &db, &ids).await?;
-        let mut reservations = Vec::with_capacity(new_ids.len());
-        for id in new_ids {
-            let reservation = fetch_detail(&client, &jar, id, now_in_utc9.year()).await?;
-            reservations.push(reservation);
-        }
+        let year = chrono::Local::now().with_timezone(&kst()).year();
+        let (client, jar) = (&client, &jar);
+        let reservations = fetch_details(
+            new_ids.into_iter().map(str::to_string),
+            crate::http::detail_concurrency(),
+            |id| async move { fetch_detail(client, jar, &id, year).await },
+        )
+        .await?;
 
-        let updated_item_count = if reservations.is_empty() {
-            0
-        } else {
-            CalendarEvent::upsert_events_to_db(self.user_id, &db, reservations.iter()).await?
-        };
-        info!("updated item count: {updated_item_count}",);
+        let stats =
+            CalendarEvent::upsert_events_to_db(self.user_id, &db, reservations.iter()).await?;
+        CalendarEvent::mark_seen(self.user_id, &db, &ids).await?;
 
-        Ok(updated_item_count > 0)
+        // details are fetched only for new ones
+        Ok(CrawlStats {
+            fetched: ids.len() as u64,
+            ..stats
+        })
+    }
+}
+
+#[async_trait]
+impl crate::UserImpl for CgvUser {
+    type Detail = CgvUserDetail;
+    const SOURCE: &'static str = "cgv";
+    const SOURCE_PREFIX: &'static str = "cgv/";
+
+    const PING_INTERVAL: Option<std::time::Duration> =
+        Some(std::time::Duration::from_secs(29 * 60));
+    const QUERIES_BY_DATE: bool = true;
+
+    fn user_id(&self) -> UserId {
+        self.user_id
+    }
+
+    fn all_users(db: &SqlitePool) -> futures::stream::BoxStream<'_, anyhow::Result<Self>> {
+        Self::all(db).boxed()
+    }
+
+    async fn scrape(&self, _db: &SqlitePool) -> anyhow::Result<Vec<CalendarEvent>> {
+        let jar = self.to_cookie_jar();
+        let client = crate::http::client();
+        let ids = self.list_ids(&client, &jar, RECENT_DAYS).await?;
+        let year = chrono::Local::now().with_timezone(&kst()).year();
+        let (client, jar) = (&client, &jar);
+        fetch_details(ids, crate::http::detail_concurrency(), |id| async move {
+            fetch_detail(client, jar, &id, year).await
+        })
+        .await
+    }
+
+    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<CrawlStats> {
+        self.fetch_since(db, RECENT_DAYS).await
+    }
+
+    async fn fetch_history(&self, db: SqlitePool, days: i64) -> anyhow::Result<CrawlStats> {
+        self.fetch_since(db, days).await
     }
 
     async fn from_user_id(db: SqlitePool, user_id: UserId) -> anyhow::Result<Option<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT `user_id` as `user_id: UserId`, `webauth`, `aspxauth` FROM `cgv_user`"
+            "SELECT `user_id` as `user_id: UserId`, `webauth`, `aspxauth` FROM `cgv_user` WHERE `user_id` = ?",
+            user_id
         )
         .fetch_optional(&db)
         .await
         .with_context(|| format!("Failed to get cgv_user of {user_id:?}"))
     }
 
+    async fn delete(db: &mut sqlx::SqliteConnection, user_id: UserId) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM `cgv_user` WHERE `user_id` = ?", user_id)
+            .execute(db)
+            .await
+            .context("Failed to delete cgv user")
+            .map(|_| ())
+    }
+
     async fn update_session(&self, db: SqlitePool) -> anyhow::Result<()> {
         sqlx::query!(
             "INSERT INTO `cgv_user` (`webauth`, `aspxauth`, `user_id`) VALUES (?, ?, ?)
@@ -336,18 +506,20 @@ impl crate::UserImpl for CgvUser {
     async fn ping(&self) -> anyhow::Result<()> {
         let jar = self.to_cookie_jar();
         let planned_url = url!("https://m.cgv.co.kr/");
-        let client = reqwest::Client::new();
+        let client = crate::http::client();
         let req = client
             .post(planned_url.as_ref())
+            .headers(crate::http::headers("cgv"))
             .header(reqwest::header::COOKIE, jar.cookies(planned_url).unwrap())
             .build()?;
 
-        let res = client.execute(req).await?;
+        let res = crate::http::execute(&client, req).await?;
 
+        if res.status().is_client_error() {
+            return Err(SessionExpired(format!("Session is expired - {}", res.status())).into());
+        }
         if res.status() != StatusCode::OK {
-            return Err(anyhow::anyhow!(
-                "Failed to fetch data. Session could be expired"
-            ));
+            anyhow::bail!("Failed to ping - {}", res.status());
         }
 
         Ok(())
@@ -357,3 +529,198 @@ impl crate::UserImpl for CgvUser {
 pub fn web_router() -> Router {
     crate::user_web_router::<CgvUser>()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reservation_list_fixture() {
+        let res: ReservationListResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/cgv_list.json")).unwrap();
+        let html = res.data.reservation_list_html.unwrap();
+
+        assert_eq!(
+            parse_list_ids(&html),
+            ["cgv/0001234567890", "cgv/0001234567891"]
+        );
+    }
+
+    #[tokio::test]
+    async fn reservations_of_all_pages_are_collected() {
+        let mut requested = Vec::new();
+        let ids = collect_ids(|page| {
+            requested.push(page);
+            let fixture = match page {
+                1 => include_str!("../tests/fixtures/cgv_list_full.json"),
+                2 => include_str!("../tests/fixtures/cgv_list.json"),
+                _ => panic!("page {page} should not be requested"),
+            };
+            async move {
+                let res: ReservationListResponse = serde_json::from_str(fixture)?;
+                Ok(res.data.reservation_list_html)
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(requested, [1, 2]);
+        assert_eq!(ids.len(), SHOW_COUNT + 2);
+        assert_eq!(ids[0], "cgv/0001234567800");
+        assert_eq!(ids[SHOW_COUNT], "cgv/0001234567890");
+    }
+
+    #[tokio::test]
+    async fn details_are_fetched_concurrently() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let ids = (0..10).map(|i| format!("cgv/{i}")).collect::<Vec<_>>();
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let fetch = |id: String| {
+            let (running, max_running) = (&running, &max_running);
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                anyhow::ensure!(id != "cgv/broken", "Failed to fetch {id}");
+                parse_detail(include_str!("../tests/fixtures/cgv_detail.html"), &id, 2024)
+            }
+        };
+
+        let mut details = fetch_details(ids.clone(), 3, fetch)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| event.id)
+            .collect::<Vec<_>>();
+        details.sort_by_key(|id| id[4..].parse::<u32>().unwrap());
+        assert_eq!(details, ids);
+        assert_eq!(max_running.load(Ordering::SeqCst), 3);
+
+        assert!(
+            fetch_details(["cgv/1", "cgv/broken", "cgv/2"].map(String::from), 3, fetch)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_replays_recorded_session() {
+        let db = crate::testing::db().await;
+        let user = CgvUser {
+            user_id: crate::testing::USER_ID,
+            webauth: "webauth".to_string(),
+            aspxauth: "aspxauth".to_string(),
+        };
+
+        let stats = crate::vcr::Cassette::load("tests/fixtures/cassettes/cgv.json")
+            .unwrap()
+            .scope(crate::UserImpl::fetch(&user, db.clone()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!((stats.fetched, stats.new), (2, 2));
+        let ids: Vec<String> = sqlx::query_scalar("SELECT `id` FROM `reservation` ORDER BY `id`")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(ids, ["cgv/0001234567890", "cgv/0001234567891"]);
+    }
+
+    #[test]
+    fn parse_detail_fixture() {
+        let event = parse_detail(
+            include_str!("../tests/fixtures/cgv_detail.html"),
+            "cgv/0001234567890",
+            2024,
+        )
+        .unwrap();
+
+        assert_eq!(event.id, "cgv/0001234567890");
+        assert_eq!(event.title, "테스트 무비 - CGV용산아이파크몰");
+        assert_eq!(
+            event.title_with(crate::title::Locale::En, Some("{movie} @ {theater}")),
+            "테스트 무비 @ CGV용산아이파크몰"
+        );
+        assert_eq!(event.detail, "상영관: IMAX관\n좌석: J열 15\n");
+        assert!(!event.invalid);
+        assert_eq!(
+            event.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+        );
+        assert_eq!(event.time_begin, chrono::NaiveTime::from_hms_opt(14, 40, 0));
+        assert_eq!(event.date_end, chrono::NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert_eq!(event.time_end, chrono::NaiveTime::from_hms_opt(16, 50, 0));
+        assert_eq!(event.location.as_deref(), Some("CGV용산아이파크몰"));
+        assert_eq!(
+            event.url.as_deref(),
+            Some("https://m.cgv.co.kr/WebApp/MyCgvV5/reservationDetail.aspx?bookingnumber=0001234567890")
+        );
+    }
+
+    #[test]
+    fn detail_falls_back_to_linked_data() {
+        let event = parse_detail(
+            include_str!("../tests/fixtures/cgv_detail_ld.html"),
+            "cgv/0001234567890",
+            2024,
+        )
+        .unwrap();
+
+        assert_eq!(event.id, "cgv/0001234567890");
+        assert_eq!(event.title, "테스트 무비 - CGV용산아이파크몰");
+        assert!(!event.invalid);
+        assert_eq!(
+            event.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+        );
+        assert_eq!(event.time_begin, chrono::NaiveTime::from_hms_opt(14, 40, 0));
+        assert_eq!(event.date_end, chrono::NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert_eq!(event.time_end, chrono::NaiveTime::from_hms_opt(16, 50, 0));
+        assert_eq!(event.location.as_deref(), Some("CGV용산아이파크몰"));
+        assert_eq!(
+            event.url.as_deref(),
+            Some("https://m.cgv.co.kr/WebApp/MyCgvV5/reservationDetail.aspx?bookingnumber=0001234567890")
+        );
+    }
+
+    #[test]
+    fn after_midnight_showtime_is_converted_consistently() {
+        let date_time =
+            |date: chrono::NaiveDate, time: Option<chrono::NaiveTime>| date.and_time(time.unwrap());
+        let expected = |day, hour, minute| {
+            chrono::NaiveDate::from_ymd_opt(2024, 4, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap()
+        };
+
+        for (showtime, begin, end) in [
+            // written on the previous date with hours past 24
+            ("24:30 ~ 26:40", expected(29, 15, 30), expected(29, 17, 40)),
+            // written on the showing date
+            ("00:30 ~ 02:40", expected(28, 15, 30), expected(28, 17, 40)),
+            // ends after UTC midnight
+            ("08:00 ~ 10:10", expected(28, 23, 0), expected(29, 1, 10)),
+        ] {
+            let html = include_str!("../tests/fixtures/cgv_detail.html")
+                .replace("05/01(수)", "04/29(월)")
+                .replace("23:40 ~ 25:50", showtime);
+            let event = parse_detail(&html, "cgv/0001234567890", 2024).unwrap();
+
+            assert_eq!(
+                date_time(event.date_begin, event.time_begin),
+                begin,
+                "{showtime}"
+            );
+            assert_eq!(
+                date_time(event.date_end.unwrap(), event.time_end),
+                end,
+                "{showtime}"
+            );
+        }
+    }
+}
@@ -1,6 +1,10 @@
 import React from "react";
-import { ActionFunctionArgs, Form, Navigate, useLoaderData } from "react-router-dom";
-import { AsyncReturnType, formDataToJsonString } from './utils';
+import { ActionFunctionArgs, Form, Navigate, useActionData, useLoaderData } from "react-router-dom";
+import { AsyncReturnType, formDataToJsonString, postJson, request as sendRequest } from './utils';
+import { ImportHistory } from './import_history';
+import { RemoveSource } from './remove_source';
+import { preview, PreviewButton, PreviewEvents, PreviewResult } from './preview';
+import { SessionInput } from './session_input';
 
 export async function loader() {
     const resp = await fetch("/cgv/user", {
@@ -10,8 +14,8 @@ export async function loader() {
     if (resp.ok) {
         const parsed = await resp.json();
         return {
-            webauth: parsed.webauth as string,
-            aspxauth: parsed.aspxauth as string,
+            saved: parsed.saved as string[],
+            required: parsed.required as string[],
         }
     } else {
         return null;
@@ -20,27 +24,34 @@ export async function loader() {
 
 export async function action({ request }: ActionFunctionArgs) {
     const formData = await request.formData();
-    return await fetch("/cgv/user", {
-        headers: {
-            'Content-Type': 'application/json'
-        },
-        method: "post",
-        body: formDataToJsonString(formData)
-    });
+    if (formData.get("intent") === "remove") {
+        return await sendRequest("/cgv/user", { method: "delete" });
+    }
+    if (formData.get("intent") === "history") {
+        return await sendRequest("/cgv/user/history", { method: "post" });
+    }
+    if (formData.get("intent") === "preview") {
+        return await preview("/cgv/user/preview", formDataToJsonString(formData));
+    }
+    return await postJson("/cgv/user", formDataToJsonString(formData));
 }
 
 export function Component() {
     const data = useLoaderData() as AsyncReturnType<typeof loader>;
+    const result = useActionData() as PreviewResult | undefined;
 
     if (data !== null) {
         return <div>
             <Form method="post" action="/cgv">
-                <label htmlFor="webauth">WEBAUTH</label>
-                <input type="text" name="webauth" defaultValue={data.webauth} />
-                <label htmlFor="aspxauth">.ASPXAUTH</label>
-                <input type="text" name="aspxauth" defaultValue={data.aspxauth} />
+                <SessionInput name="webauth" label="WEBAUTH" saved={data.saved} required={data.required} />
+                <SessionInput name="aspxauth" label=".ASPXAUTH" saved={data.saved} required={data.required} />
+                {result?.error && <p role="alert">{result.error}</p>}
                 <button type="submit">Update</button>
+                <PreviewButton />
             </Form>
+            <PreviewEvents result={result} />
+            <ImportHistory action="/cgv" />
+            <RemoveSource action="/cgv" name="cgv" />
         </div>;
     } else {
         return <Navigate to="/" />;
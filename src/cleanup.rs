@@ -0,0 +1,82 @@
+//! Removal of reservations which are long over and not listed by the sources
+//! anymore, with their google events
+
+use std::collections::BTreeMap;
+
+use anyhow::Context as _;
+#[cfg(not(feature = "crawl_test"))]
+use log::error;
+use log::info;
+use sqlx::SqlitePool;
+
+use crate::CalendarEvent;
+
+/// Days after which stale reservations are removed, read from
+/// `RESERVATION_CLEANUP_DAYS`
+#[derive(Debug, PartialEq)]
+pub struct CleanupConfig {
+    pub days: i64,
+}
+
+impl CleanupConfig {
+    /// `None` to keep reservations forever when it is not set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Option<Self>> {
+        let Some(days) = var("RESERVATION_CLEANUP_DAYS") else {
+            return Ok(None);
+        };
+        let days = days.parse().context("Invalid RESERVATION_CLEANUP_DAYS")?;
+        anyhow::ensure!(days > 0, "RESERVATION_CLEANUP_DAYS must be positive");
+
+        Ok(Some(Self { days }))
+    }
+
+    /// Removes reservations over and not seen for `days`. Google events are
+    /// deleted first, the reservations of a user are kept to retry next time
+    /// when it fails.
+    pub async fn run(&self, db: &SqlitePool) -> anyhow::Result<u64> {
+        let stale = CalendarEvent::stale(db, self.days, chrono::Utc::now().naive_utc()).await?;
+        let mut ids_of = BTreeMap::<_, Vec<_>>::new();
+        for (user_id, id) in stale {
+            ids_of.entry(user_id).or_default().push(id);
+        }
+
+        let mut removed = 0;
+        for (user_id, ids) in ids_of {
+            #[cfg(not(feature = "crawl_test"))]
+            if let Some(google_user) =
+                crate::google_calendar::GoogleUser::from_user_id(db, user_id).await?
+            {
+                if let Err(e) = google_user.delete_events_of_reservations(db, &ids).await {
+                    error!("Failed to delete google events of stale reservations of {user_id:?} - {e:?}");
+                    continue;
+                }
+            }
+
+            let deleted = CalendarEvent::delete_ids(user_id, db, &ids).await?;
+            info!("{deleted} stale reservations of {user_id:?} are removed");
+            removed += deleted;
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleanup_is_opt_in() {
+        assert_eq!(CleanupConfig::from_vars(|_| None).unwrap(), None);
+        assert_eq!(
+            CleanupConfig::from_vars(|_| Some("90".to_string())).unwrap(),
+            Some(CleanupConfig { days: 90 })
+        );
+        assert!(CleanupConfig::from_vars(|_| Some("0".to_string())).is_err());
+        assert!(CleanupConfig::from_vars(|_| Some("soon".to_string())).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use std::{fmt::Write as _, future::Future, io::Write as _};
+
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::UserId;
+
+tokio::task_local! {
+    static CORRELATION_ID: Uuid;
+    static CRAWL: Crawl;
+}
+
+/// Source and user being crawled
+#[derive(Debug, Clone, Copy)]
+struct Crawl {
+    source: &'static str,
+    user_id: UserId,
+}
+
+/// Runs `f` with `id`, logs written while it runs are tagged with the id to
+/// trace a chain of fetches and sync
+pub async fn scope<F: Future>(id: Uuid, f: F) -> F::Output {
+    CORRELATION_ID.scope(id, f).await
+}
+
+pub fn current() -> Option<Uuid> {
+    CORRELATION_ID.try_with(|id| *id).ok()
+}
+
+/// Runs the crawl `f` of the user, logs written while it runs are also tagged
+/// with the source and the user, to tell crawls running at once apart
+pub async fn crawl_scope<F: Future>(source: &'static str, user_id: UserId, f: F) -> F::Output {
+    CRAWL.scope(Crawl { source, user_id }, f).await
+}
+
+fn current_crawl() -> Option<Crawl> {
+    CRAWL.try_with(|crawl| *crawl).ok()
+}
+
+/// `tokio::spawn` keeping the correlation id and the crawl of the current task
+pub fn spawn<F>(f: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let crawl = current_crawl();
+    let f = async move {
+        match crawl {
+            Some(crawl) => CRAWL.scope(crawl, f).await,
+            None => f.await,
+        }
+    };
+    match current() {
+        Some(id) => tokio::spawn(CORRELATION_ID.scope(id, f)),
+        None => tokio::spawn(f),
+    }
+}
+
+fn tag() -> String {
+    let mut tag = current().map(|id| format!(" [{id}]")).unwrap_or_default();
+    if let Some(Crawl { source, user_id }) = current_crawl() {
+        write!(tag, " [{source} user={}]", user_id.0).unwrap();
+    }
+    tag
+}
+
+/// `env_logger` with the correlation id after the target
+pub fn init_logger() {
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "[{} {:<5} {}]{} {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                tag(),
+                record.args()
+            )
+        })
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn sync() -> String {
+        // as deep as google calendar sync in the chain
+        tokio::task::yield_now().await;
+        tag()
+    }
+
+    #[tokio::test]
+    async fn id_propagates_into_sync_logs() {
+        let id = Uuid::new_v4();
+
+        assert_eq!(scope(id, sync()).await, format!(" [{id}]"));
+        assert_eq!(
+            scope(id, async { spawn(sync()).await.unwrap() }).await,
+            format!(" [{id}]")
+        );
+        assert_eq!(sync().await, "");
+    }
+
+    #[tokio::test]
+    async fn crawl_is_tagged_after_id() {
+        let id = Uuid::new_v4();
+        let crawl = crawl_scope("cgv", UserId(1), async { spawn(sync()).await.unwrap() });
+
+        assert_eq!(scope(id, crawl).await, format!(" [{id}] [cgv user=1]"));
+        assert_eq!(
+            crawl_scope("kobus", UserId(2), sync()).await,
+            " [kobus user=2]"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::{str::FromStr as _, time::Duration};
+
+use anyhow::Context as _;
+use log::info;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+    SqlitePool,
+};
+
+const DEFAULT_URL: &str = "sqlite://db.db";
+const DEFAULT_MAX_CONNECTIONS: u32 = 10;
+const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where and how to connect the DB, read from `DATABASE_URL`,
+/// `DATABASE_MAX_CONNECTIONS` and `DATABASE_BUSY_TIMEOUT` in seconds
+#[derive(Debug, PartialEq)]
+pub struct DbConfig {
+    pub url: String,
+    pub max_connections: u32,
+    /// How long a connection waits for the lock held by another one, e.g. the
+    /// scheduler writing crawled reservations, before "database is locked"
+    pub busy_timeout: Duration,
+}
+
+impl DbConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let max_connections = var("DATABASE_MAX_CONNECTIONS")
+            .map(|value| value.parse())
+            .transpose()
+            .context("Invalid DATABASE_MAX_CONNECTIONS")?
+            .unwrap_or(DEFAULT_MAX_CONNECTIONS);
+        anyhow::ensure!(
+            max_connections > 0,
+            "DATABASE_MAX_CONNECTIONS must be positive"
+        );
+        let busy_timeout = var("DATABASE_BUSY_TIMEOUT")
+            .map(|value| value.parse().map(Duration::from_secs))
+            .transpose()
+            .context("Invalid DATABASE_BUSY_TIMEOUT")?
+            .unwrap_or(DEFAULT_BUSY_TIMEOUT);
+
+        Ok(Self {
+            url: var("DATABASE_URL").unwrap_or_else(|| DEFAULT_URL.to_string()),
+            max_connections,
+            busy_timeout,
+        })
+    }
+
+    /// Connection options with the pragmas below on top of the defaults of sqlx
+    /// (`foreign_keys = ON`)
+    /// - `journal_mode = WAL`: web requests read while the scheduler writes
+    /// - `synchronous = NORMAL`: durable enough with WAL, without syncing on
+    ///   each commit
+    fn connect_options(&self) -> anyhow::Result<SqliteConnectOptions> {
+        Ok(SqliteConnectOptions::from_str(&self.url)
+            .with_context(|| format!("Invalid DATABASE_URL {}", self.url))?
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(self.busy_timeout))
+    }
+
+    pub async fn connect(&self) -> anyhow::Result<SqlitePool> {
+        info!(
+            "Connect to {} with {} connections",
+            self.url, self.max_connections
+        );
+        SqlitePoolOptions::new()
+            .max_connections(self.max_connections)
+            .connect_with(self.connect_options()?)
+            .await
+            .with_context(|| format!("Failed to connect to {}", self.url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_falls_back_to_defaults() {
+        assert_eq!(
+            DbConfig::from_vars(|_| None).unwrap(),
+            DbConfig {
+                url: DEFAULT_URL.to_string(),
+                max_connections: DEFAULT_MAX_CONNECTIONS,
+                busy_timeout: DEFAULT_BUSY_TIMEOUT,
+            }
+        );
+
+        let config = DbConfig::from_vars(|name| match name {
+            "DATABASE_URL" => Some("sqlite:///data/calendar-hub.db".to_string()),
+            "DATABASE_MAX_CONNECTIONS" => Some("4".to_string()),
+            "DATABASE_BUSY_TIMEOUT" => Some("30".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(config.url, "sqlite:///data/calendar-hub.db");
+        assert_eq!(config.max_connections, 4);
+        assert_eq!(config.busy_timeout, Duration::from_secs(30));
+
+        assert!(DbConfig::from_vars(|name| {
+            (name == "DATABASE_MAX_CONNECTIONS").then(|| "0".to_string())
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn connection_uses_wal() {
+        let dir = std::env::temp_dir().join(format!("calendar-hub-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = DbConfig {
+            url: format!("sqlite://{}?mode=rwc", dir.join("db.db").display()),
+            max_connections: 2,
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
+        };
+
+        let db = config.connect().await.unwrap();
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        db.close().await;
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
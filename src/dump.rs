@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use log::{error, info};
+use reqwest::header::HeaderMap;
+
+const DUMP_DIRECTORY: &str = "debug_dumps";
+pub(crate) const REDACTED_HEADERS: &[reqwest::header::HeaderName] = &[
+    reqwest::header::SET_COOKIE,
+    reqwest::header::COOKIE,
+    reqwest::header::AUTHORIZATION,
+    reqwest::header::PROXY_AUTHORIZATION,
+];
+
+fn is_enabled() -> bool {
+    matches!(std::env::var("DUMP_RESPONSES").as_deref(), Ok("1"))
+}
+
+fn format_dump(status: reqwest::StatusCode, headers: &HeaderMap, body: &[u8]) -> String {
+    let mut dump = format!("{status}\n");
+    for (name, value) in headers {
+        let value = if REDACTED_HEADERS.contains(name) {
+            "<redacted>"
+        } else {
+            value.to_str().unwrap_or("<binary>")
+        };
+        dump.push_str(&format!("{name}: {value}\n"));
+    }
+    dump.push('\n');
+    dump.push_str(&String::from_utf8_lossy(body));
+
+    dump
+}
+
+fn dump(
+    source: &str,
+    status: reqwest::StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(DUMP_DIRECTORY)
+        .with_context(|| format!("Failed to create {DUMP_DIRECTORY}"))?;
+    let path = PathBuf::from(DUMP_DIRECTORY).join(format!(
+        "{source}-{}.txt",
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f")
+    ));
+    std::fs::write(&path, format_dump(status, headers, body))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("Response dumped to {}", path.display());
+
+    Ok(path)
+}
+
+/// Status, headers and body of a response, read to inspect the body before
+/// parsing it
+pub(crate) struct RawResponse {
+    pub status: reqwest::StatusCode,
+    pub headers: HeaderMap,
+    pub body: hyper::body::Bytes,
+}
+
+impl RawResponse {
+    pub async fn read(res: reqwest::Response) -> anyhow::Result<Self> {
+        Ok(Self {
+            status: res.status(),
+            headers: res.headers().clone(),
+            body: res.bytes().await.context("Failed to read response")?,
+        })
+    }
+
+    /// Parses JSON body. When it fails and `DUMP_RESPONSES=1` is set, the raw
+    /// response is written under `debug_dumps/` with credential headers
+    /// redacted, and the path is put in the error instead of the body.
+    pub fn parse_json<T: serde::de::DeserializeOwned>(&self, source: &str) -> anyhow::Result<T> {
+        serde_json::from_slice(&self.body).map_err(|e| {
+            let e = anyhow::Error::new(e).context(format!("Failed to parse {source} response"));
+            if !is_enabled() {
+                return e;
+            }
+            match dump(source, self.status, &self.headers, &self.body) {
+                Ok(path) => e.context(format!("Raw response is dumped to {}", path.display())),
+                Err(dump_error) => {
+                    error!("Failed to dump response - {dump_error:?}");
+                    e
+                }
+            }
+        })
+    }
+}
+
+/// Parses JSON body of the response, see [`RawResponse::parse_json`]
+pub(crate) async fn parse_json<T: serde::de::DeserializeOwned>(
+    source: &str,
+    res: reqwest::Response,
+) -> anyhow::Result<T> {
+    RawResponse::read(res).await?.parse_json(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn dump_redacts_credential_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            reqwest::header::SET_COOKIE,
+            HeaderValue::from_static("JSESSIONID=secret; Path=/"),
+        );
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer secret"),
+        );
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            HeaderValue::from_static("text/html"),
+        );
+
+        let dump = format_dump(reqwest::StatusCode::OK, &headers, b"<html></html>");
+        assert!(!dump.contains("secret"));
+        assert!(dump.contains("set-cookie: <redacted>"));
+        assert!(dump.contains("content-type: text/html"));
+        assert!(dump.ends_with("\n\n<html></html>"));
+    }
+}
@@ -0,0 +1,258 @@
+use anyhow::Context as _;
+use axum::{
+    extract::Query,
+    response::{IntoResponse as _, Response},
+    routing::get,
+    Extension, Router,
+};
+use axum_sessions::extractors::ReadableSession;
+use futures::TryStreamExt as _;
+use hyper::{header, StatusCode};
+use log::{debug, error};
+use sqlx::SqlitePool;
+
+use crate::{date_time_from_utc, date_time_to_utc, kst, UserId};
+
+const COLUMNS: &[&str] = &[
+    "id",
+    "title",
+    "detail",
+    "date_begin",
+    "time_begin",
+    "date_end",
+    "time_end",
+    "location",
+    "url",
+    "invalid",
+];
+
+/// Range of the begin date of reservations in KST, both inclusive
+#[derive(Debug, Default, serde::Deserialize)]
+struct DateRange {
+    from: Option<chrono::NaiveDate>,
+    to: Option<chrono::NaiveDate>,
+}
+
+/// Date and time in KST, like the sources show them
+pub(crate) fn local_date_time(
+    date: Option<chrono::NaiveDate>,
+    time: Option<chrono::NaiveTime>,
+) -> (String, String) {
+    match (date, time) {
+        (Some(date), Some(time)) => {
+            let (date, time) = date_time_from_utc(date, time, &kst());
+            (date.to_string(), time.format("%H:%M").to_string())
+        }
+        (date, _) => (
+            date.map(|date| date.to_string()).unwrap_or_default(),
+            String::new(),
+        ),
+    }
+}
+
+/// UTC date and time the KST `date` begins at, to compare with the stored
+/// begin of reservations with a time. Reservations without a time keep their
+/// date as it is.
+pub(crate) fn utc_start_of(date: chrono::NaiveDate) -> chrono::NaiveDateTime {
+    let (date, time) = date_time_to_utc(date, chrono::NaiveTime::MIN, kst());
+    date.and_time(time)
+}
+
+/// [`utc_start_of`] the day after `date`, as the exclusive end of the range
+/// to `date`. `None` when there is no next day.
+pub(crate) fn utc_end_of(date: chrono::NaiveDate) -> Option<chrono::NaiveDateTime> {
+    date.succ_opt().map(utc_start_of)
+}
+
+/// Cell as text, spreadsheets run the ones starting like a formula
+fn escape_formula(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{field}").into()
+    } else {
+        field.into()
+    }
+}
+
+fn csv_line<'a>(fields: impl IntoIterator<Item = &'a str>) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    for field in fields {
+        writer.write_field(escape_formula(field).as_bytes())?;
+    }
+    writer.write_record(None::<&[u8]>)?;
+    Ok(writer.into_inner()?)
+}
+
+/// Writes reservations of the user as CSV into `sender` row by row, not to
+/// keep all of them in memory
+async fn write_csv(
+    db: &SqlitePool,
+    user_id: UserId,
+    range: &DateRange,
+    sender: &mut hyper::body::Sender,
+) -> anyhow::Result<()> {
+    sender
+        .send_data(csv_line(COLUMNS.iter().copied())?.into())
+        .await?;
+
+    let from_utc = range.from.map(utc_start_of);
+    let to_utc = range.to.and_then(utc_end_of);
+    let mut rows = sqlx::query!(
+        r#"SELECT
+            `id`, `title`, `detail`,
+            `date_begin` as `date_begin: chrono::NaiveDate`,
+            `time_begin` as `time_begin: chrono::NaiveTime`,
+            `date_end` as `date_end: chrono::NaiveDate`,
+            `time_end` as `time_end: chrono::NaiveTime`,
+            `location`, `url`, `invalid`
+        FROM `reservation`
+        WHERE `user_id` = ?1
+            AND (?2 IS NULL OR CASE WHEN `time_begin` IS NULL THEN `date_begin` >= ?2
+                ELSE `date_begin` || ' ' || `time_begin` >= ?3 END)
+            AND (?4 IS NULL OR CASE WHEN `time_begin` IS NULL THEN `date_begin` <= ?4
+                ELSE ?5 IS NULL OR `date_begin` || ' ' || `time_begin` < ?5 END)
+        ORDER BY `date_begin`, `time_begin`, `id`"#,
+        user_id,
+        range.from,
+        from_utc,
+        range.to,
+        to_utc
+    )
+    .fetch(db);
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .context("Failed to get reservations")?
+    {
+        let (date_begin, time_begin) = local_date_time(Some(row.date_begin), row.time_begin);
+        let (date_end, time_end) = local_date_time(row.date_end, row.time_end);
+        let line = csv_line([
+            row.id.as_str(),
+            &row.title,
+            &row.detail,
+            &date_begin,
+            &time_begin,
+            &date_end,
+            &time_end,
+            row.location.as_deref().unwrap_or_default(),
+            row.url.as_deref().unwrap_or_default(),
+            if row.invalid { "true" } else { "false" },
+        ])?;
+        sender.send_data(line.into()).await?;
+    }
+
+    Ok(())
+}
+
+async fn export_reservations(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Query(range): Query<DateRange>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    let (mut sender, body) = hyper::Body::channel();
+    tokio::spawn(async move {
+        if let Err(e) = write_csv(&db, user_id, &range, &mut sender).await {
+            error!("Error occurred while export reservations - {e:?}");
+            sender.abort();
+        }
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"reservations.csv\"",
+            ),
+        ],
+        axum::body::boxed(body),
+    )
+        .into_response()
+}
+
+pub fn web_router() -> Router {
+    Router::new().route("/reservations.csv", get(export_reservations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reservations_of_user_are_written_in_range() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        for (id, user_id, date_begin, time_begin) in [
+            ("cgv/1", 1, "2024-05-01", Some("14:40:00")),
+            ("naver/1", 1, "2024-06-01", None),
+            // 2024-06-01 00:10 KST
+            ("cgv/3", 1, "2024-05-31", Some("15:10:00")),
+            ("cgv/2", 2, "2024-05-01", None),
+        ] {
+            sqlx::query(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `time_begin`, `invalid`, `updated_at`)
+                VALUES (?, ?, 'title, with comma', '', ?, ?, FALSE, '2024-04-01 00:00:00')",
+            )
+            .bind(id)
+            .bind(user_id)
+            .bind(date_begin)
+            .bind(time_begin)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        let export = |range: DateRange| {
+            let db = db.clone();
+            async move {
+                let (mut sender, body) = hyper::Body::channel();
+                let written =
+                    tokio::spawn(
+                        async move { write_csv(&db, UserId(1), &range, &mut sender).await },
+                    );
+                let body = hyper::body::to_bytes(body).await.unwrap();
+                written.await.unwrap().unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        assert_eq!(
+            export(DateRange::default()).await,
+            "id,title,detail,date_begin,time_begin,date_end,time_end,location,url,invalid\n\
+            cgv/1,\"title, with comma\",,2024-05-01,23:40,,,,,false\n\
+            cgv/3,\"title, with comma\",,2024-06-01,00:10,,,,,false\n\
+            naver/1,\"title, with comma\",,2024-06-01,,,,,,false\n"
+        );
+        let june = export(DateRange {
+            from: chrono::NaiveDate::from_ymd_opt(2024, 6, 1),
+            to: None,
+        })
+        .await;
+        assert_eq!(june.lines().skip(1).count(), 2);
+        assert!(june.contains("cgv/3"));
+        assert!(june.contains("naver/1"));
+        let may = export(DateRange {
+            from: None,
+            to: chrono::NaiveDate::from_ymd_opt(2024, 5, 31),
+        })
+        .await;
+        assert_eq!(may.lines().skip(1).count(), 1);
+        assert!(may.contains("cgv/1"));
+    }
+
+    #[test]
+    fn formulas_are_written_as_text() {
+        assert_eq!(
+            String::from_utf8(
+                csv_line(["=HYPERLINK(\"x\")", "-1", "@SUM", "+1", "영화 - 1"]).unwrap()
+            )
+            .unwrap(),
+            "\"'=HYPERLINK(\"\"x\"\")\",'-1,'@SUM,'+1,영화 - 1\n"
+        );
+    }
+}
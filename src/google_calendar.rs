@@ -18,7 +18,7 @@ use axum_sessions::extractors::{ReadableSession, WritableSession};
 use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use hyper::StatusCode;
 use jwt::VerifyingAlgorithm;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use notify::{event::ModifyKind, EventKind, RecommendedWatcher};
 use rsa::{pkcs8::AssociatedOid, Pkcs1v15Sign, RsaPublicKey};
 use sha2::Digest;
@@ -26,7 +26,10 @@ use sqlx::{Row, SqlitePool};
 
 use anyhow::Context;
 use google_calendar3::{
-    api::{AclRule, AclRuleScope, Calendar, Event, EventDateTime},
+    api::{
+        AclRule, AclRuleScope, Calendar, Event, EventDateTime, EventExtendedProperties,
+        EventReminder, EventReminders, EventSource,
+    },
     hyper, hyper_rustls,
     oauth2::{self, authenticator_delegate::InstalledFlowDelegate},
     CalendarHub,
@@ -34,16 +37,31 @@ use google_calendar3::{
 use tokio::sync::{oneshot, Mutex, RwLock};
 use uuid::Uuid;
 
-use crate::{CalendarEvent, UserId};
+use crate::{
+    audit::{self, Action},
+    reservation::ID_CHUNK_SIZE,
+    setting::{CancelledEventMode, SourceSetting, Transparency},
+    title::Locale,
+    CalendarEvent, UserId,
+};
+
+/// Time zone to show the events in, events are stored in UTC
+const DEFAULT_EVENT_TIME_ZONE: &str = "Asia/Seoul";
+const DEFAULT_CALENDAR_NAME: &str = "Calendar hub";
+/// Shared extended property of the events holding the party size, for other
+/// apps reading the calendar
+const PARTY_SIZE_PROPERTY: &str = "partySize";
+/// Failed events are retried up to this, including the first try
+pub const MAX_SYNC_ATTEMPTS: i64 = 5;
 
 trait IntoGoogleEventDateTime {
-    fn into_google(self) -> EventDateTime;
+    fn into_google(self, time_zone: &str) -> EventDateTime;
 }
 
 impl IntoGoogleEventDateTime for Option<(NaiveDate, Option<NaiveTime>)> {
-    fn into_google(self) -> EventDateTime {
+    fn into_google(self, time_zone: &str) -> EventDateTime {
         match self {
-            Some(val) => val.into_google(),
+            Some(val) => val.into_google(time_zone),
             None => EventDateTime {
                 date: None,
                 date_time: None,
@@ -54,7 +72,7 @@ impl IntoGoogleEventDateTime for Option<(NaiveDate, Option<NaiveTime>)> {
 }
 
 impl IntoGoogleEventDateTime for (NaiveDate, Option<NaiveTime>) {
-    fn into_google(self) -> EventDateTime {
+    fn into_google(self, time_zone: &str) -> EventDateTime {
         if let Some(time) = self.1 {
             EventDateTime {
                 date_time: Some(
@@ -63,39 +81,118 @@ impl IntoGoogleEventDateTime for (NaiveDate, Option<NaiveTime>) {
                         .unwrap(),
                 ),
                 date: None,
-                time_zone: Some("GMT+00:00".to_string()),
+                time_zone: Some(time_zone.to_string()),
             }
         } else {
             EventDateTime {
                 date: Some(self.0),
                 date_time: None,
-                time_zone: Some("GMT+00:00".to_string()),
+                time_zone: Some(time_zone.to_string()),
             }
         }
     }
 }
 
-impl From<CalendarEvent> for Event {
-    fn from(event: CalendarEvent) -> Event {
-        let start = (event.date_begin, event.time_begin).into_google();
-        Event {
-            description: Some(
-                event
-                    .url
-                    .map(|url| format!("{}\n{}", event.detail, url))
-                    .unwrap_or(event.detail),
-            ),
-            end: Some(
-                event
-                    .date_end
-                    .map(|date| (date, event.time_end).into_google())
-                    .unwrap_or_else(|| start.clone()),
-            ),
-            start: Some(start),
-            summary: Some(event.title),
-            location: event.location,
+/// Detail of the event with the party size, then the link to the reservation
+/// separated by a blank line, both in `locale`
+fn describe(detail: &str, party_size: Option<u32>, locale: Locale, url: Option<&str>) -> String {
+    let detail = detail.trim_end();
+    let detail = match party_size {
+        Some(party_size) if detail.is_empty() => locale.party_size(party_size),
+        Some(party_size) => format!("{detail}\n{}", locale.party_size(party_size)),
+        None => detail.to_string(),
+    };
+    match url {
+        Some(url) if detail.is_empty() => locale.reservation_link(url),
+        Some(url) => format!("{detail}\n\n{}", locale.reservation_link(url)),
+        None => detail.to_string(),
+    }
+}
+
+fn to_google_event(
+    event: CalendarEvent,
+    setting: &SourceSetting,
+    locale: Locale,
+    cancelled_event_mode: CancelledEventMode,
+    time_zone: &str,
+) -> Event {
+    let event = event.with_default_end(setting.default_duration);
+    let cancelled = event.invalid && cancelled_event_mode == CancelledEventMode::MarkCancelled;
+    let summary = event.title_with(locale, setting.title_template.as_deref());
+    let summary = if cancelled {
+        format!("[취소] {summary}")
+    } else {
+        summary
+    };
+    let start = (event.date_begin, event.time_begin).into_google(time_zone);
+    let color_id = setting.color_of(&event);
+    let reminders = EventReminders {
+        overrides: setting.reminders.as_ref().map(|reminders| {
+            reminders
+                .iter()
+                .map(|&minutes| EventReminder {
+                    method: Some("popup".to_string()),
+                    minutes: Some(minutes as _),
+                })
+                .collect()
+        }),
+        use_default: Some(setting.reminders.is_none()),
+    };
+    Event {
+        description: Some(describe(
+            &event.detail,
+            event.party_size,
+            locale,
+            event.url.as_deref(),
+        )),
+        // google only accepts http(s) links as the source
+        source: event
+            .url
+            .filter(|url| url.starts_with("https://") || url.starts_with("http://"))
+            .map(|url| EventSource {
+                title: Some(summary.clone()),
+                url: Some(url),
+            }),
+        end: Some(
+            event
+                .date_end
+                .map(|date| (date, event.time_end).into_google(time_zone))
+                .unwrap_or_else(|| start.clone()),
+        ),
+        start: Some(start),
+        summary: Some(summary),
+        location: event.location,
+        reminders: Some(reminders),
+        color_id,
+        recurrence: event.recurrence,
+        // always set, as patches leave the fields out of the event as they are
+        status: Some(
+            if cancelled {
+                "cancelled"
+            } else if event.tentative {
+                "tentative"
+            } else {
+                "confirmed"
+            }
+            .to_string(),
+        ),
+        // tentative events don't block the time
+        transparency: Some(
+            if event.tentative || setting.transparency == Transparency::Transparent {
+                "transparent"
+            } else {
+                "opaque"
+            }
+            .to_string(),
+        ),
+        extended_properties: event.party_size.map(|party_size| EventExtendedProperties {
+            shared: Some(HashMap::from([(
+                PARTY_SIZE_PROPERTY.to_string(),
+                party_size.to_string(),
+            )])),
             ..Default::default()
-        }
+        }),
+        ..Default::default()
     }
 }
 
@@ -107,6 +204,18 @@ const CALENDAR_SCOPE: &[&str] = &[
     "email",
 ];
 
+/// Scopes of `CALENDAR_SCOPE` which are not in the space separated `granted`
+/// scopes. Users can uncheck the calendar permissions on the consent screen,
+/// while `openid` and `email` are always granted under other names.
+fn missing_scopes(granted: &str) -> Vec<&'static str> {
+    let granted = granted.split_whitespace().collect::<HashSet<_>>();
+    CALENDAR_SCOPE
+        .iter()
+        .copied()
+        .filter(|scope| scope.starts_with("https://") && !granted.contains(scope))
+        .collect()
+}
+
 #[repr(transparent)]
 #[derive(Debug, Clone)]
 struct LoginCallbackCode(String);
@@ -123,6 +232,42 @@ type LoginContextMap = HashMap<
     ),
 >;
 
+/// Source of login context ids, replaceable to drive the login flow with a
+/// known `state` in tests
+trait IdSource: Send + Sync {
+    fn new_id(&self) -> Uuid;
+}
+
+struct RandomId;
+
+impl IdSource for RandomId {
+    fn new_id(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// Registers a new login context. The login flow receives the auth code with
+/// the receiver and answers the logged in user with the sender.
+async fn register_login(
+    contexts: &Mutex<LoginContextMap>,
+    ids: &dyn IdSource,
+) -> (
+    Uuid,
+    oneshot::Receiver<LoginCallbackCode>,
+    oneshot::Sender<Option<UserId>>,
+) {
+    let (code_sender, code_receiver) = oneshot::channel();
+    let (user_id_sender, user_id_receiver) = oneshot::channel();
+
+    let id = ids.new_id();
+    contexts
+        .lock()
+        .await
+        .insert(id, (code_sender, user_id_receiver));
+
+    (id, code_receiver, user_id_sender)
+}
+
 struct LoginDelegate {
     channels: Mutex<
         Option<(
@@ -221,86 +366,237 @@ impl VerifyingAlgorithm for RsaVerifying {
     }
 }
 
+/// Parses the file at `path`, or the content of `env` when the file is absent
+/// for deployments providing secrets with environment variables
+async fn read_json_file_or_env<T>(
+    path: impl AsRef<Path>,
+    env: &str,
+    parse: impl FnOnce(Vec<u8>) -> std::io::Result<T>,
+) -> anyhow::Result<T> {
+    let path = path.as_ref();
+    let json = match tokio::fs::read(path).await {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => match std::env::var(env) {
+            Ok(json) => {
+                return parse(json.into_bytes()).with_context(|| format!("Invalid JSON in {env}"))
+            }
+            Err(_) => anyhow::bail!("Neither {} nor {env} is set", path.display()),
+        },
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    parse(json).with_context(|| format!("Invalid JSON in {}", path.display()))
+}
+
 pub struct Config {
     secret: oauth2::ApplicationSecret,
     url_prefix: String,
-    google_key_store: BTreeMap<String, RsaVerifying>,
+    google_key_store: RwLock<BTreeMap<String, RsaVerifying>>,
     service_account: google_calendar3::oauth2::ServiceAccountKey,
     allowed_emails: AllowedEmails,
+    reconcile_renamed_reservations: bool,
+    /// Events edited on google calendar after the hub wrote them are not
+    /// patched, at the cost of getting each event before patching it
+    keep_manual_edits: bool,
+    event_time_zone: String,
+    /// Name of the calendars created on login
+    calendar_name: String,
+    admin_emails: HashSet<String>,
     _watcher: RecommendedWatcher,
 }
 
+/// IANA name of the time zone, checked to be known
+fn event_time_zone_from_var(time_zone: Option<String>) -> anyhow::Result<String> {
+    let Some(time_zone) = time_zone else {
+        return Ok(DEFAULT_EVENT_TIME_ZONE.to_string());
+    };
+    time_zone
+        .parse::<chrono_tz::Tz>()
+        .map_err(|e| anyhow::anyhow!("Invalid EVENT_TIME_ZONE {time_zone:?} - {e}"))?;
+
+    Ok(time_zone)
+}
+
 static SHARED_CONFIG: once_cell::sync::OnceCell<Arc<Config>> = once_cell::sync::OnceCell::new();
 
 impl Config {
     pub async fn init(url_prefix: String) -> anyhow::Result<()> {
-        let secret = google_calendar3::oauth2::read_application_secret("google.json").await?;
-        let google_key_store = fetch_google_key_store().await?;
+        // every event would be rejected by google with an unknown time zone
+        let event_time_zone = event_time_zone_from_var(std::env::var("EVENT_TIME_ZONE").ok())?;
+        let secret = read_json_file_or_env(
+            "google.json",
+            "GOOGLE_APP_SECRET_JSON",
+            oauth2::parse_application_secret,
+        )
+        .await
+        .context("Failed to load google application secret")?;
+        let google_key_store = fetch_google_key_store(GOOGLE_CERTS_URL).await?;
         let (allowed_emails, watcher) = AllowedEmails::new("allowed-emails").await?;
-        let service_account =
-            google_calendar3::oauth2::read_service_account_key("service_account.json").await?;
+        let service_account = read_json_file_or_env(
+            "service_account.json",
+            "GOOGLE_SERVICE_ACCOUNT_JSON",
+            oauth2::parse_service_account_key,
+        )
+        .await
+        .context("Failed to load google service account key")?;
 
         SHARED_CONFIG
             .set(Arc::new(Self {
                 secret,
                 url_prefix,
-                google_key_store: google_key_store,
+                google_key_store: RwLock::new(google_key_store),
                 service_account,
                 allowed_emails,
+                reconcile_renamed_reservations: std::env::var("RECONCILE_RENAMED_RESERVATIONS")
+                    .map(|value| value == "1" || value == "true")
+                    .unwrap_or(false),
+                keep_manual_edits: std::env::var("KEEP_MANUAL_EDITS")
+                    .map(|value| value == "1" || value == "true")
+                    .unwrap_or(false),
+                event_time_zone,
+                calendar_name: std::env::var("CALENDAR_NAME")
+                    .ok()
+                    .filter(|name| !name.trim().is_empty())
+                    .unwrap_or_else(|| DEFAULT_CALENDAR_NAME.to_string()),
+                admin_emails: std::env::var("ADMIN_EMAILS")
+                    .map(|emails| {
+                        emails
+                            .split(',')
+                            .map(|email| email.trim().to_string())
+                            .filter(|email| !email.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
                 _watcher: watcher,
             }))
             .map_err(|_| anyhow::anyhow!("Config init should be called only once"))
     }
 
+    /// Emails allowed to login are listed in the `allowed-emails` file or in
+    /// the `allowed_email` table
+    pub async fn is_allowed(&self, db: &SqlitePool, email: &str) -> anyhow::Result<bool> {
+        if self.allowed_emails.as_ref().read().await.contains(email) {
+            return Ok(true);
+        }
+        crate::admin::is_allowed_email(db, email).await
+    }
+
+    /// Admins are listed in `ADMIN_EMAILS` and still allowed to login
+    pub async fn is_admin(&self, db: &SqlitePool, email: &str) -> anyhow::Result<bool> {
+        Ok(self.admin_emails.contains(email) && self.is_allowed(db, email).await?)
+    }
+
     pub fn get() -> Arc<Self> {
         SHARED_CONFIG
             .get()
             .expect("google config is not initialized yet")
             .clone()
     }
-}
 
-async fn fetch_google_key_store() -> anyhow::Result<BTreeMap<String, RsaVerifying>> {
-    #[derive(serde::Deserialize)]
-    struct Key {
-        n: String,
-        e: String,
-        kid: String,
-        alg: String,
+    /// Keys to verify id tokens on login are loaded
+    pub async fn has_google_keys(&self) -> bool {
+        !self.google_key_store.read().await.is_empty()
     }
-    #[derive(serde::Deserialize)]
-    struct R {
-        keys: Vec<Key>,
+
+    /// Replaces google's public keys used to verify id tokens with freshly fetched ones
+    pub async fn refresh_google_key_store(&self) -> anyhow::Result<()> {
+        refresh_key_store(&self.google_key_store, GOOGLE_CERTS_URL).await
     }
-    let resp: R = reqwest::get("https://www.googleapis.com/oauth2/v3/certs")
-        .await?
-        .json()
-        .await?;
 
+    async fn verify_id_token(
+        &self,
+        id_token: &str,
+    ) -> anyhow::Result<BTreeMap<String, serde_json::Value>> {
+        use jwt::VerifyWithStore;
+
+        let result = id_token.verify_with_store(&*self.google_key_store.read().await);
+        match result {
+            // keys could be rotated after the last refresh
+            Err(jwt::Error::NoKeyWithKeyId(key_id)) => {
+                info!("Unknown key id({key_id}) - refresh google key store");
+                self.refresh_google_key_store().await?;
+                id_token
+                    .verify_with_store(&*self.google_key_store.read().await)
+                    .context("jwt verification failed")
+            }
+            result => result.context("jwt verification failed"),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct GoogleKey {
+    n: String,
+    e: String,
+    kid: String,
+    alg: String,
+}
+
+impl GoogleKey {
+    fn to_verifying(&self) -> anyhow::Result<RsaVerifying> {
+        let algorithm = match self.alg.as_str() {
+            "RS256" => RsAlgorithm::Rs256,
+            "RS384" => RsAlgorithm::Rs384,
+            "RS512" => RsAlgorithm::Rs512,
+            alg => anyhow::bail!("Invalid algorithm type - {alg}"),
+        };
+        let n = base64_url::decode(&self.n).context("Invalid modulus")?;
+        let e = base64_url::decode(&self.e).context("Invalid exponent")?;
+        let key = RsaPublicKey::new(
+            rsa::BigUint::from_bytes_be(&n),
+            rsa::BigUint::from_bytes_be(&e),
+        )?;
+
+        Ok(RsaVerifying(key, algorithm))
+    }
+}
+
+/// Builds the key store skipping malformed keys, so a single bad key does not
+/// break verification of the tokens signed by the others.
+fn parse_google_key_store(keys: Vec<GoogleKey>) -> anyhow::Result<BTreeMap<String, RsaVerifying>> {
     let mut ret = BTreeMap::new();
 
-    for key in resp.keys {
-        ret.insert(
-            key.kid.to_string(),
-            RsaVerifying(
-                rsa::RsaPublicKey::new(
-                    rsa::BigUint::from_bytes_be(&base64_url::decode(&key.n).unwrap()),
-                    rsa::BigUint::from_bytes_be(&base64_url::decode(&key.e).unwrap()),
-                )
-                .unwrap(),
-                match key.alg.as_str() {
-                    "RS256" => RsAlgorithm::Rs256,
-                    "RS384" => RsAlgorithm::Rs384,
-                    "RS512" => RsAlgorithm::Rs512,
-                    alg => unreachable!("Invalid algorithm type - {alg}"),
-                },
-            ),
-        );
+    for key in keys {
+        match key.to_verifying() {
+            Ok(verifying) => {
+                ret.insert(key.kid, verifying);
+            }
+            Err(e) => warn!("Skip malformed google key {} - {e:?}", key.kid),
+        }
     }
 
+    anyhow::ensure!(!ret.is_empty(), "No valid google key");
+
     Ok(ret)
 }
 
+const GOOGLE_CERTS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+
+async fn fetch_google_key_store(certs_url: &str) -> anyhow::Result<BTreeMap<String, RsaVerifying>> {
+    #[derive(serde::Deserialize)]
+    struct R {
+        keys: Vec<GoogleKey>,
+    }
+    let resp: R = reqwest::get(certs_url).await?.json().await?;
+
+    parse_google_key_store(resp.keys)
+}
+
+/// Replaces the keys with the ones fetched from `certs_url`, kept as they are
+/// when it fails
+async fn refresh_key_store(
+    store: &RwLock<BTreeMap<String, RsaVerifying>>,
+    certs_url: &str,
+) -> anyhow::Result<()> {
+    let google_key_store = fetch_google_key_store(certs_url).await?;
+    info!(
+        "google key store refreshed - {} keys",
+        google_key_store.len()
+    );
+    *store.write().await = google_key_store;
+
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 #[repr(transparent)]
 struct AllowedEmails(Arc<RwLock<HashSet<String>>>);
@@ -315,15 +611,37 @@ impl AllowedEmails {
     async fn new(
         path: impl AsRef<Path> + Send + Clone + 'static,
     ) -> anyhow::Result<(AllowedEmails, RecommendedWatcher)> {
-        let ret = tokio::task::block_in_place(|| Self::read_from_file(path.as_ref()))?;
+        // the file is optional when the emails are allowed in the DB
+        let ret = if path.as_ref().exists() {
+            tokio::task::block_in_place(|| Self::read_from_file(path.as_ref()))?
+        } else {
+            info!("allowed-emails is not found, only the DB is used until it is created");
+            HashSet::new()
+        };
         let ret = Arc::new(RwLock::new(ret));
 
         let data = ret.clone();
+        let file_name = path
+            .as_ref()
+            .file_name()
+            .context("allowed-emails path should point to a file")?
+            .to_os_string();
         let mut watcher = {
             let path = path.clone();
             notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
                 if let Ok(res) = res {
-                    if let EventKind::Modify(ModifyKind::Data(_)) = res.kind {
+                    // editors and `mv` based atomic writes replace the file instead of modifying it
+                    if let EventKind::Create(_)
+                    | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_)) = res.kind
+                    {
+                        if !res
+                            .paths
+                            .iter()
+                            .any(|changed| changed.file_name() == Some(file_name.as_os_str()))
+                            || !path.as_ref().is_file()
+                        {
+                            return;
+                        }
                         match Self::read_from_file(path.as_ref()) {
                             Ok(value) => {
                                 info!("allowed-emails {} items reloaded", value.len());
@@ -335,8 +653,14 @@ impl AllowedEmails {
                 }
             })?
         };
+        // watch the containing directory rather than the file itself,
+        // so the watch keeps working after the file is replaced by a new inode
+        let directory = match path.as_ref().parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
         use notify::{RecursiveMode, Watcher};
-        watcher.watch(path.as_ref(), RecursiveMode::NonRecursive)?;
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
 
         Ok((Self(ret), watcher))
     }
@@ -364,6 +688,7 @@ async fn begin_login(
     session: ReadableSession,
     Extension(db): Extension<SqlitePool>,
     Extension(contexts): Extension<Arc<Mutex<LoginContextMap>>>,
+    Extension(ids): Extension<Arc<dyn IdSource>>,
 ) -> Response {
     if let Some(session) = session.get::<UserId>("user_id") {
         debug!("Already logged in redirect to main - {session:?}");
@@ -371,14 +696,7 @@ async fn begin_login(
     }
 
     let (url_sender, url_receiver) = oneshot::channel();
-    let (code_sender, code_receiver) = oneshot::channel();
-    let (user_id_sender, user_id_receiver) = oneshot::channel();
-
-    let id = Uuid::new_v4();
-    contexts
-        .lock()
-        .await
-        .insert(id, (code_sender, user_id_receiver));
+    let (id, code_receiver, user_id_sender) = register_login(&contexts, ids.as_ref()).await;
 
     tokio::spawn(async move {
         let config = Config::get();
@@ -398,13 +716,14 @@ async fn begin_login(
         .unwrap();
 
         let (subject, email) = {
-            use jwt::VerifyWithStore;
-
-            let id_token = auth.id_token(CALENDAR_SCOPE).await.unwrap().unwrap();
-            let mut claims: BTreeMap<String, serde_json::Value> = id_token
-                .verify_with_store(&config.google_key_store)
-                .context("jwt verification failed")
-                .unwrap();
+            let id_token = match auth.id_token(CALENDAR_SCOPE).await {
+                Ok(id_token) => id_token.context("No id token received").unwrap(),
+                Err(e) => {
+                    debug!("Login is not completed - {e:?}");
+                    return;
+                }
+            };
+            let mut claims = config.verify_id_token(&id_token).await.unwrap();
             (
                 claims
                     .remove("sub")
@@ -427,14 +746,11 @@ async fn begin_login(
             .context("received email in claims is not string")
             .unwrap();
 
-        if config
-            .allowed_emails
-            .as_ref()
-            .read()
-            .await
-            .get(email)
-            .is_none()
-        {
+        let allowed = config.is_allowed(&db, email).await.unwrap_or_else(|e| {
+            error!("Failed to check allowed email - {e:?}");
+            false
+        });
+        if !allowed {
             user_id_sender.send(None).unwrap();
             return;
         }
@@ -446,44 +762,40 @@ async fn begin_login(
             .unwrap();
 
         let user_info = sqlx::query!(
-            "SELECT `user_id` as `user_id:UserId`, `calendar_id`, `acl_id` FROM `google_user` WHERE `subject` = ?",
+            "SELECT `user_id` as `user_id:UserId`, `calendar_id`, `acl_id`, `service_account_email`, `calendar_revoked` FROM `google_user` WHERE `subject` = ?",
             subject
         )
         .fetch_optional(&db)
         .await
         .context("Failed to query logged in user")
         .unwrap()
-        .map(|record| (record.user_id, record.calendar_id, record.acl_id));
-
-        let calendar_hub = CalendarHub::new(
-            hyper::Client::builder().build(
-                hyper_rustls::HttpsConnectorBuilder::new()
-                    .with_native_roots()
-                    .https_or_http()
-                    .enable_http1()
-                    .enable_http2()
-                    .build(),
-            ),
-            auth,
-        );
+        .map(|record| {
+            let acl_id = acl_to_reuse(
+                record.acl_id,
+                record.service_account_email.as_deref(),
+                &config.service_account.client_email,
+            );
+            // revoked calendar is not shared to the service account anymore
+            // or deleted, a new one is created instead
+            let calendar_id = (!record.calendar_revoked).then_some(record.calendar_id);
+            (record.user_id, calendar_id, acl_id)
+        });
+
+        let calendar_hub = hub_with(auth);
 
         // validate calendar_id & acl_id, make sure user_id is valid
         let (user_id, calendar_id, acl_id) = if let Some((user_id, calendar_id, acl_id)) = user_info
         {
-            if let Err(e) = calendar_hub.calendars().get(&calendar_id).doit().await {
-                info!("Saved calendar_id({calendar_id}) is invalid - {e:?}");
-                (user_id, None, None)
-            } else if let Some(acl_id) = acl_id {
-                let acl_id =
-                    if let Err(e) = calendar_hub.acl().get(&calendar_id, &acl_id).doit().await {
-                        info!("Saved acl_id is invalid - {e:?}");
-                        None
-                    } else {
-                        Some(acl_id)
-                    };
-                (user_id, Some(calendar_id), acl_id)
+            if let Some(calendar_id) = calendar_id {
+                if let Err(e) = calendar_hub.calendars().get(&calendar_id).doit().await {
+                    info!("Saved calendar_id({calendar_id}) is invalid - {e:?}");
+                    (user_id, None, None)
+                } else {
+                    (user_id, Some(calendar_id), acl_id)
+                }
             } else {
-                (user_id, Some(calendar_id), None)
+                info!("Saved calendar of {user_id:?} is revoked");
+                (user_id, None, None)
             }
         } else {
             let user_id = UserId(
@@ -505,7 +817,7 @@ async fn begin_login(
                 calendar_hub
                     .calendars()
                     .insert(Calendar {
-                        summary: Some("Calendar hub".to_string()),
+                        summary: Some(config.calendar_name.clone()),
                         ..Default::default()
                     })
                     .doit()
@@ -518,33 +830,14 @@ async fn begin_login(
             }
         };
 
-        let acl_id = match acl_id {
-            Some(id) => id,
-            None => {
-                info!("Share calendar {calendar_id} to service account");
-                calendar_hub
-                    .acl()
-                    .insert(
-                        AclRule {
-                            etag: None,
-                            id: None,
-                            kind: None,
-                            role: Some("writer".to_string()),
-                            scope: Some(AclRuleScope {
-                                type_: Some("user".to_string()),
-                                value: Some(config.service_account.client_email.clone()),
-                            }),
-                        },
-                        &calendar_id,
-                    )
-                    .doit()
-                    .await
-                    .unwrap()
-                    .1
-                    .id
-                    .expect("Id of AclRule in Response should be set")
-            }
-        };
+        let acl_id = share_calendar(
+            &calendar_hub,
+            &calendar_id,
+            acl_id,
+            &config.service_account.client_email,
+        )
+        .await
+        .unwrap();
 
         let minimum_date_time = chrono::DateTime::<chrono::Utc>::from_naive_utc_and_offset(
             chrono::NaiveDateTime::MIN,
@@ -552,16 +845,20 @@ async fn begin_login(
         );
         sqlx::query!(
             r#"INSERT INTO `google_user`
-            (`user_id`, `calendar_id`, `acl_id`, `last_synced`, `subject`)
+            (`user_id`, `calendar_id`, `acl_id`, `last_synced`, `subject`, `service_account_email`, `email`)
             VALUES
-            (?, ?, ?, ?, ?)
+            (?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT DO UPDATE SET
-            `calendar_id`=`excluded`.`calendar_id`, `acl_id`=`excluded`.`acl_id`"#,
+            `calendar_id`=`excluded`.`calendar_id`, `acl_id`=`excluded`.`acl_id`,
+            `service_account_email`=`excluded`.`service_account_email`, `email`=`excluded`.`email`,
+            `calendar_revoked`=FALSE"#,
             user_id,
             calendar_id,
             acl_id,
             minimum_date_time,
-            subject
+            subject,
+            config.service_account.client_email,
+            email
         )
         .execute(&db)
         .await
@@ -582,7 +879,6 @@ async fn begin_login(
 struct LoginCallbackQuery {
     state: Uuid,
     code: String,
-    #[allow(dead_code)]
     scope: String,
 }
 
@@ -592,6 +888,12 @@ async fn login_callback(
     Query(query): Query<LoginCallbackQuery>,
 ) -> Response {
     if let Some((code_sender, user_id_receiver)) = contexts.lock().await.remove(&query.state) {
+        let missing = missing_scopes(&query.scope);
+        if !missing.is_empty() {
+            // dropping the sender stops the login
+            debug!("Required scopes are not granted - {missing:?}");
+            return Redirect::to("/permissions-required").into_response();
+        }
         code_sender
             .send(LoginCallbackCode(query.code))
             .map_err(|e| format!("Failed to send auth code - {e:?}"))
@@ -624,14 +926,98 @@ pub fn web_router<S: Sync + Send + Clone + 'static, B: HttpBody + Send + 'static
         .route("/login", get(begin_login))
         .route("/callback", get(login_callback))
         .layer(Extension(login_contexts))
+        .layer(Extension(Arc::new(RandomId) as Arc<dyn IdSource>))
+}
+
+/// Saved ACL is for the service account at that time. When the service account
+/// is replaced, the calendar has to be shared again to the new one.
+/// Unknown `shared_to` is regarded as the current one.
+fn is_shared_to_other(shared_to: Option<&str>, service_account_email: &str) -> bool {
+    shared_to.is_some_and(|shared_to| shared_to != service_account_email)
+}
+
+fn acl_to_reuse(
+    acl_id: Option<String>,
+    shared_to: Option<&str>,
+    service_account_email: &str,
+) -> Option<String> {
+    if is_shared_to_other(shared_to, service_account_email) {
+        info!("Service account is changed from {shared_to:?}, share calendar again");
+        None
+    } else {
+        acl_id
+    }
+}
+
+/// Id of the ACL rule sharing the calendar to the service account. The saved
+/// `acl_id` is reused while it is valid, otherwise the calendar is shared again.
+async fn share_calendar(
+    hub: &Hub,
+    calendar_id: &str,
+    acl_id: Option<String>,
+    service_account_email: &str,
+) -> anyhow::Result<String> {
+    if let Some(acl_id) = acl_id {
+        match hub.acl().get(calendar_id, &acl_id).doit().await {
+            Ok(_) => return Ok(acl_id),
+            Err(e) => info!("Saved acl_id is invalid - {e:?}"),
+        }
+    }
+
+    info!("Share calendar {calendar_id} to service account");
+    hub.acl()
+        .insert(
+            AclRule {
+                etag: None,
+                id: None,
+                kind: None,
+                role: Some("writer".to_string()),
+                scope: Some(AclRuleScope {
+                    type_: Some("user".to_string()),
+                    value: Some(service_account_email.to_string()),
+                }),
+            },
+            calendar_id,
+        )
+        .doit()
+        .await
+        .context("Failed to share calendar to service account")?
+        .1
+        .id
+        .context("Id of AclRule in Response should be set")
 }
 
 pub struct GoogleUser {
     user_id: UserId,
     calendar_id: String,
     last_synced: NaiveDateTime,
+    service_account_email: Option<String>,
+    /// Language of the titles of the events
+    locale: Locale,
+    cancelled_event_mode: CancelledEventMode,
+    /// Reservations already over are not inserted
+    only_future_events: bool,
+    /// The calendar is deleted or not shared to the service account anymore
+    calendar_revoked: bool,
+}
+
+/// Sync failed because the calendar of the user is deleted or not shared to
+/// the service account anymore. A new calendar is created on the next login.
+#[derive(Debug)]
+pub struct CalendarRevoked(pub UserId);
+
+impl std::fmt::Display for CalendarRevoked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Calendar of {:?} is not accessible, it has to login again to reconnect the calendar",
+            self.0
+        )
+    }
 }
 
+impl std::error::Error for CalendarRevoked {}
+
 impl GoogleUser {
     pub async fn from_user_id(db: &SqlitePool, user_id: UserId) -> anyhow::Result<Option<Self>> {
         sqlx::query_as!(
@@ -639,7 +1025,12 @@ impl GoogleUser {
             r#"SELECT
                 `user_id` as `user_id: UserId`,
                 `calendar_id`,
-                `last_synced`
+                `last_synced`,
+                `service_account_email`,
+                `locale` as `locale: Locale`,
+                `cancelled_event_mode` as `cancelled_event_mode: CancelledEventMode`,
+                `only_future_events`,
+                `calendar_revoked`
             FROM `google_user`
             WHERE `user_id` = ?"#,
             user_id.0
@@ -649,9 +1040,55 @@ impl GoogleUser {
         .with_context(|| format!("Failed to get google_user for user_id {user_id:?}"))
     }
 
+    /// The calendar is not shared to the current service account, the user has
+    /// to login again to share it
+    pub fn reshare_required(&self) -> bool {
+        is_shared_to_other(
+            self.service_account_email.as_deref(),
+            &Config::get().service_account.client_email,
+        )
+    }
+
+    /// The calendar is deleted or unshared, the user has to login again to
+    /// create a new one
+    pub fn calendar_revoked(&self) -> bool {
+        self.calendar_revoked
+    }
+
+    /// Syncs and keeps the error of it for the admin page
     pub async fn sync(&self, db: &SqlitePool) -> anyhow::Result<()> {
-        let mut reservations: HashMap<_, _> = sqlx::query_as!(
-            CalendarEvent,
+        let result = self.sync_reservations(db).await;
+        let last_error = result.as_ref().err().map(|e| format!("{e:#}"));
+        sqlx::query!(
+            "UPDATE `google_user` SET `last_error` = ? WHERE `user_id` = ?",
+            last_error,
+            self.user_id
+        )
+        .execute(db)
+        .await
+        .context("Failed to update last error of sync")?;
+
+        result
+    }
+
+    /// Sync is stopped until the user logs in again
+    fn ensure_syncable(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.reshare_required(),
+            "Service account is changed, {:?} has to login again to share the calendar",
+            self.user_id
+        );
+        if self.calendar_revoked {
+            return Err(CalendarRevoked(self.user_id).into());
+        }
+
+        Ok(())
+    }
+
+    async fn sync_reservations(&self, db: &SqlitePool) -> anyhow::Result<()> {
+        self.ensure_syncable()?;
+
+        let mut reservations: HashMap<_, _> = sqlx::query!(
             r#"SELECT
                 `id`, `title`, `detail`,
                 `date_begin` as `date_begin: chrono::NaiveDate`,
@@ -660,7 +1097,11 @@ impl GoogleUser {
                 `time_end` as `time_end: chrono::NaiveTime`,
                 `invalid`,
                 `location`,
-                `url`
+                `url`,
+                `recurrence`,
+                `tentative`,
+                `title_parts`,
+                `party_size` as `party_size: u32`
             FROM `reservation`
             WHERE `user_id` = ? AND `updated_at` > ?"#,
             self.user_id,
@@ -670,127 +1111,2893 @@ impl GoogleUser {
         .await
         .context("Failed to collect reservation data to update")?
         .into_iter()
-        .map(|item| (item.id.clone(), item))
+        .map(|item| {
+            let reservation = CalendarEvent {
+                id: item.id,
+                title: item.title,
+                detail: item.detail,
+                invalid: item.invalid,
+                date_begin: item.date_begin,
+                time_begin: item.time_begin,
+                date_end: item.date_end,
+                time_end: item.time_end,
+                location: item.location,
+                url: item.url,
+                recurrence: CalendarEvent::recurrence_from_db(item.recurrence),
+                tentative: item.tentative,
+                title_parts: CalendarEvent::title_parts_from_db(item.title_parts),
+                party_size: item.party_size,
+            };
+            (reservation.id.clone(), reservation)
+        })
         .collect();
 
-        let config = Config::get();
+        let hub = service_account_hub(&Config::get()).await?;
+        self.check_calendar(&hub, db).await?;
 
-        let auth = oauth2::ServiceAccountAuthenticator::builder(config.service_account.clone())
-            .build()
-            .await?;
+        match self.take_externally_deleted(&hub, db).await {
+            Ok(restored) => reservations.extend(
+                restored
+                    .into_iter()
+                    .map(|reservation| (reservation.id.clone(), reservation)),
+            ),
+            Err(e) => error!("Failed to check externally deleted events - {e:?}"),
+        }
 
-        if !reservations.is_empty() {
-            let hub = CalendarHub::new(
-                hyper::Client::builder().build(
-                    hyper_rustls::HttpsConnectorBuilder::new()
-                        .with_native_roots()
-                        .https_or_http()
-                        .enable_http1()
-                        .enable_http2()
-                        .build(),
-                ),
-                auth,
-            );
+        self.apply(&hub, db, reservations).await?;
 
-            let google_events = sqlx::QueryBuilder::new(
-                "SELECT `event_id`, `reservation_id` FROM `google_event` WHERE `user_id` = ",
-            )
-            .push_bind(self.user_id)
-            .push("AND `reservation_id` in ")
-            .push_tuples(reservations.keys(), |mut builder, item| {
-                builder.push_bind(item);
-            })
-            .build()
-            .fetch_all(db)
-            .await
-            .context("Failed to get saved google events")?;
-
-            for google_event in google_events {
-                let event_id: String = google_event.get_unchecked(0);
-                let reservation_id: String = google_event.get_unchecked(1);
-                if let Some(reservation) = reservations.remove(&reservation_id) {
-                    if reservation.invalid {
-                        if let Err(_e) = hub
-                            .events()
-                            .delete(&self.calendar_id, &event_id)
-                            .doit()
-                            .await
-                        {
-                            // TODO: handle error
-                        }
-                    } else if let Err(_e) = hub
-                        .events()
-                        .patch(reservation.into(), &self.calendar_id, &event_id)
-                        .doit()
-                        .await
-                    {
-                        // TODO: handle error
-                    }
-                }
-            }
+        update_last_synced(db, self.user_id, chrono::Utc::now().naive_utc()).await
+    }
 
-            if !reservations.is_empty() {
-                let mut builder = sqlx::QueryBuilder::new(
-                    "INSERT INTO `google_event` (`event_id`, `user_id`, `reservation_id`)",
-                );
-                let mut new_events = Vec::new();
-                for (_, reservation) in reservations.into_iter() {
-                    if reservation.invalid {
-                        continue;
-                    }
+    /// Syncs again only the reservations which failed on the previous syncs,
+    /// except the ones given up after [`MAX_SYNC_ATTEMPTS`]
+    pub async fn retry_failed(&self, db: &SqlitePool) -> anyhow::Result<usize> {
+        self.ensure_syncable()?;
 
-                    let reservation_id = reservation.id.clone();
+        let reservations = self.failed_reservations(db).await?;
+        if reservations.is_empty() {
+            return Ok(0);
+        }
 
-                    match hub
-                        .events()
-                        .insert(reservation.into(), &self.calendar_id)
-                        .doit()
-                        .await
-                    {
-                        Ok((_, e)) => {
-                            new_events.push((e.id.unwrap(), reservation_id));
-                        }
-                        Err(e) => error!("Failed to insert event - {e:?}"),
-                    }
-                }
+        let retried = reservations.len();
+        let hub = service_account_hub(&Config::get()).await?;
+        self.check_calendar(&hub, db).await?;
+        self.apply(&hub, db, reservations).await?;
 
-                if !new_events.is_empty() {
-                    builder.push_values(new_events, |mut b, r| {
-                        b.push_bind(r.0).push_bind(self.user_id).push_bind(r.1);
-                    });
-                    builder
-                        .build()
-                        .execute(db)
-                        .await
-                        .context("Failed to insert newly created events")?;
-                }
-            }
-        }
+        Ok(retried)
+    }
 
-        let now = chrono::Utc::now().naive_utc();
-        sqlx::query!(
-            "UPDATE `google_user` SET `last_synced` = ? WHERE `user_id` = ?",
-            now,
-            self.user_id
+    /// Reservations failed to sync and not given up yet
+    async fn failed_reservations(
+        &self,
+        db: &SqlitePool,
+    ) -> anyhow::Result<HashMap<String, CalendarEvent>> {
+        let reservations = sqlx::query!(
+            r#"SELECT
+                `id`, `title`, `detail`,
+                `date_begin` as `date_begin: chrono::NaiveDate`,
+                `time_begin` as `time_begin: chrono::NaiveTime`,
+                `date_end` as `date_end: chrono::NaiveDate`,
+                `time_end` as `time_end: chrono::NaiveTime`,
+                `invalid`,
+                `location`,
+                `url`,
+                `recurrence`,
+                `tentative`,
+                `title_parts`,
+                `party_size` as `party_size: u32`
+            FROM `reservation`
+            INNER JOIN `sync_failure`
+                ON `sync_failure`.`user_id` = `reservation`.`user_id`
+                AND `sync_failure`.`reservation_id` = `reservation`.`id`
+            WHERE `reservation`.`user_id` = ? AND `attempts` < ?"#,
+            self.user_id,
+            MAX_SYNC_ATTEMPTS
         )
-        .execute(db)
+        .fetch_all(db)
         .await
-        .unwrap();
+        .context("Failed to collect reservations failed to sync")?
+        .into_iter()
+        .map(|item| {
+            let reservation = CalendarEvent {
+                id: item.id,
+                title: item.title,
+                detail: item.detail,
+                invalid: item.invalid,
+                date_begin: item.date_begin,
+                time_begin: item.time_begin,
+                date_end: item.date_end,
+                time_end: item.time_end,
+                location: item.location,
+                url: item.url,
+                recurrence: CalendarEvent::recurrence_from_db(item.recurrence),
+                tentative: item.tentative,
+                title_parts: CalendarEvent::title_parts_from_db(item.title_parts),
+                party_size: item.party_size,
+            };
+            (reservation.id.clone(), reservation)
+        })
+        .collect();
 
-        Ok(())
+        Ok(reservations)
     }
-}
 
-pub async fn get_last_synced(
-    db: SqlitePool,
-    user_id: UserId,
-) -> anyhow::Result<chrono::DateTime<chrono::Utc>> {
-    sqlx::query!(
-        "SELECT `last_synced` FROM `google_user` WHERE `user_id` = ?",
-        user_id
-    )
-    .fetch_one(&db)
-    .await
-    .context("Failed to get last_synced for ({user_id:?}) from DB")
-    .map(|row| chrono::DateTime::from_naive_utc_and_offset(row.last_synced, chrono::Utc))
+    /// Reverts the changes made by the latest sync which is not undone yet,
+    /// the latest change first. Patches and deletes of events which were never
+    /// changed since the audit started can't be restored and are skipped, as
+    /// well as events deleted on google calendar since.
+    pub async fn undo_last_sync(&self, db: &SqlitePool) -> anyhow::Result<Undone> {
+        self.ensure_syncable()?;
+
+        let entries = audit::last_batch(db, self.user_id).await?;
+        if entries.is_empty() {
+            return Ok(Undone::default());
+        }
+
+        let hub = service_account_hub(&Config::get()).await?;
+        let undone = self.undo_on(&hub, db, entries).await?;
+        info!(
+            "{} changes of the last sync are reverted for {:?}, {} skipped",
+            undone.reverted, self.user_id, undone.skipped
+        );
+
+        Ok(undone)
+    }
+
+    async fn undo_on(
+        &self,
+        hub: &Hub,
+        db: &SqlitePool,
+        entries: Vec<audit::Entry>,
+    ) -> anyhow::Result<Undone> {
+        let mut undone = Undone::default();
+        for entry in entries {
+            if self.revert(hub, db, &entry).await? {
+                undone.reverted += 1;
+            } else {
+                undone.skipped += 1;
+            }
+            audit::mark_undone(db, entry.id).await?;
+        }
+
+        Ok(undone)
+    }
+
+    /// Returns whether the change could be reverted
+    async fn revert(
+        &self,
+        hub: &Hub,
+        db: &SqlitePool,
+        entry: &audit::Entry,
+    ) -> anyhow::Result<bool> {
+        match self.try_revert(hub, db, entry).await {
+            Err(e)
+                if e.downcast_ref::<google_calendar3::Error>()
+                    .is_some_and(is_gone) =>
+            {
+                debug!("Event {} is deleted, can't revert - {e:?}", entry.event_id);
+                Ok(false)
+            }
+            result => result,
+        }
+    }
+
+    async fn try_revert(
+        &self,
+        hub: &Hub,
+        db: &SqlitePool,
+        entry: &audit::Entry,
+    ) -> anyhow::Result<bool> {
+        match (entry.action, &entry.before) {
+            (Action::Insert, _) => {
+                match hub
+                    .events()
+                    .delete(&entry.calendar_id, &entry.event_id)
+                    .doit()
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(e) if is_gone(&e) => debug!("Event {} is already deleted", entry.event_id),
+                    Err(e) => return Err(e).context("Failed to delete inserted event"),
+                }
+                sqlx::query!(
+                    "DELETE FROM `google_event` WHERE `user_id` = ? AND `event_id` = ?",
+                    self.user_id,
+                    entry.event_id
+                )
+                .execute(db)
+                .await
+                .context("Failed to delete mapping of inserted event")?;
+            }
+            (Action::Patch, Some(before)) => {
+                // the patch moved the event when the calendar of the source was changed
+                let calendar_id = entry
+                    .before_calendar_id
+                    .as_deref()
+                    .unwrap_or(&entry.calendar_id);
+                if calendar_id != entry.calendar_id {
+                    hub.events()
+                        .move_(&entry.calendar_id, &entry.event_id, calendar_id)
+                        .doit()
+                        .await
+                        .context("Failed to move patched event back")?;
+                    let mapped_calendar_id =
+                        (calendar_id != self.calendar_id).then_some(calendar_id);
+                    sqlx::query!(
+                        "UPDATE `google_event` SET `calendar_id` = ? WHERE `user_id` = ? AND `event_id` = ?",
+                        mapped_calendar_id,
+                        self.user_id,
+                        entry.event_id
+                    )
+                    .execute(db)
+                    .await
+                    .context("Failed to update calendar of moved back event")?;
+                }
+                let (_, restored) = hub
+                    .events()
+                    .patch(restorable(before.clone()), calendar_id, &entry.event_id)
+                    .doit()
+                    .await
+                    .context("Failed to restore patched event")?;
+                self.record_written(db, &entry.event_id, &restored).await?;
+            }
+            (Action::Delete, Some(before)) => {
+                // deleted events are kept as cancelled, and confirmed again
+                let event = Event {
+                    status: Some("confirmed".to_string()),
+                    ..restorable(before.clone())
+                };
+                let (_, restored) = hub
+                    .events()
+                    .patch(event, &entry.calendar_id, &entry.event_id)
+                    .doit()
+                    .await
+                    .context("Failed to restore deleted event")?;
+                let calendar_id =
+                    (entry.calendar_id != self.calendar_id).then_some(&entry.calendar_id);
+                sqlx::query!(
+                    "INSERT INTO `google_event` (`event_id`, `user_id`, `reservation_id`, `calendar_id`)
+                    VALUES (?, ?, ?, ?) ON CONFLICT DO NOTHING",
+                    entry.event_id,
+                    self.user_id,
+                    entry.reservation_id,
+                    calendar_id
+                )
+                .execute(db)
+                .await
+                .context("Failed to restore mapping of deleted event")?;
+                self.record_written(db, &entry.event_id, &restored).await?;
+            }
+            (Action::Patch | Action::Delete, None) => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Creates, patches or deletes google events of the reservations. Failures
+    /// of each event are kept in `sync_failure` to retry, instead of failing
+    /// the whole sync.
+    async fn apply(
+        &self,
+        hub: &Hub,
+        db: &SqlitePool,
+        mut reservations: HashMap<String, CalendarEvent>,
+    ) -> anyhow::Result<()> {
+        if reservations.is_empty() {
+            return Ok(());
+        }
+
+        let settings = SourceSetting::all_or_default_of(db, self.user_id).await?;
+        let default_setting = SourceSetting::default();
+        let setting_of = |reservation: &CalendarEvent| {
+            settings
+                .get(reservation.source_prefix())
+                .unwrap_or(&default_setting)
+        };
+        let config = Config::get();
+        let batch = audit::Batch::new(self.user_id);
+
+        // before the events of the old reservations are deleted as cancelled
+        if config.reconcile_renamed_reservations {
+            for (event_id, calendar_id, reservation) in
+                self.take_renamed(db, &mut reservations).await?
+            {
+                let reservation_id = reservation.id.clone();
+                let result = self
+                    .update_event(
+                        hub,
+                        db,
+                        &batch,
+                        &event_id,
+                        calendar_id.as_deref(),
+                        reservation,
+                        setting_of,
+                    )
+                    .await;
+                self.account(db, &reservation_id, result).await;
+            }
+        }
+
+        let reservation_ids = reservations.keys().cloned().collect::<Vec<_>>();
+        let mut google_events = Vec::new();
+        for reservation_ids in reservation_ids.chunks(ID_CHUNK_SIZE) {
+            google_events.extend(
+                sqlx::QueryBuilder::new(
+                    "SELECT `event_id`, `reservation_id`, `calendar_id` FROM `google_event` WHERE `user_id` = ",
+                )
+                .push_bind(self.user_id)
+                .push("AND `reservation_id` in ")
+                .push_tuples(reservation_ids, |mut builder, item| {
+                    builder.push_bind(item);
+                })
+                .build()
+                .fetch_all(db)
+                .await
+                .context("Failed to get saved google events")?,
+            );
+        }
+
+        for google_event in google_events {
+            let event_id: String = google_event.get_unchecked(0);
+            let reservation_id: String = google_event.get_unchecked(1);
+            let calendar_id: Option<String> = google_event.get_unchecked(2);
+            if let Some(reservation) = reservations.remove(&reservation_id) {
+                let result = if reservation.invalid
+                    && self.cancelled_event_mode == CancelledEventMode::Delete
+                {
+                    let calendar_id = calendar_id.as_deref().unwrap_or(&self.calendar_id);
+                    match hub.events().delete(calendar_id, &event_id).doit().await {
+                        Ok(_) => {
+                            batch
+                                .record(
+                                    db,
+                                    Action::Delete,
+                                    &event_id,
+                                    calendar_id,
+                                    &reservation_id,
+                                    None,
+                                )
+                                .await;
+                            Ok(())
+                        }
+                        Err(e) if is_gone(&e) => Ok(()),
+                        Err(e) => Err(anyhow::Error::from(e).context("Failed to delete event")),
+                    }
+                } else {
+                    self.update_event(
+                        hub,
+                        db,
+                        &batch,
+                        &event_id,
+                        calendar_id.as_deref(),
+                        reservation,
+                        setting_of,
+                    )
+                    .await
+                };
+                self.account(db, &reservation_id, result).await;
+            }
+        }
+
+        if !reservations.is_empty() {
+            let mut new_events = Vec::new();
+            let today = Utc::now().date_naive();
+            for (_, reservation) in reservations.into_iter() {
+                if !is_to_insert(&reservation, self.only_future_events, today) {
+                    continue;
+                }
+
+                let reservation_id = reservation.id.clone();
+                let setting = setting_of(&reservation);
+                let calendar_id = self.calendar_of(setting);
+
+                let event = to_google_event(
+                    reservation,
+                    setting,
+                    self.locale,
+                    self.cancelled_event_mode,
+                    &config.event_time_zone,
+                );
+                let result = match self
+                    .import_event(hub, calendar_id, &reservation_id, event)
+                    .await
+                {
+                    Ok((event_id, e)) => {
+                        batch
+                            .record(
+                                db,
+                                Action::Insert,
+                                &event_id,
+                                calendar_id,
+                                &reservation_id,
+                                Some(&e),
+                            )
+                            .await;
+                        new_events.push((
+                            event_id,
+                            reservation_id.clone(),
+                            setting.calendar_id.clone(),
+                            e.updated.map(|updated| updated.naive_utc()),
+                        ));
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                };
+                self.account(db, &reservation_id, result).await;
+            }
+
+            // 5 variables are bound for each row
+            for new_events in new_events.chunks(ID_CHUNK_SIZE / 5) {
+                let mut builder = sqlx::QueryBuilder::new(
+                    "INSERT INTO `google_event` (`event_id`, `user_id`, `reservation_id`, `calendar_id`, `written_at`)",
+                );
+                builder.push_values(new_events, |mut b, r| {
+                    b.push_bind(&r.0)
+                        .push_bind(self.user_id)
+                        .push_bind(&r.1)
+                        .push_bind(&r.2)
+                        .push_bind(r.3);
+                });
+                // the event imported again may be mapped already
+                builder
+                    .push(
+                        " ON CONFLICT DO UPDATE SET
+                        `event_id`=`excluded`.`event_id`, `calendar_id`=`excluded`.`calendar_id`,
+                        `written_at`=`excluded`.`written_at`",
+                    )
+                    .build()
+                    .execute(db)
+                    .await
+                    .context("Failed to insert newly created events")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the failure of the reservation to retry, or forgets the previous
+    /// one when it is synced
+    async fn account(&self, db: &SqlitePool, reservation_id: &str, result: anyhow::Result<()>) {
+        let recorded = match result {
+            Ok(()) => {
+                sqlx::query!(
+                    "DELETE FROM `sync_failure` WHERE `user_id` = ? AND `reservation_id` = ?",
+                    self.user_id,
+                    reservation_id
+                )
+                .execute(db)
+                .await
+            }
+            Err(e) => {
+                error!("Failed to sync {reservation_id} - {e:?}");
+                let last_error = format!("{e:#}");
+                let now = chrono::Utc::now().naive_utc();
+                sqlx::query!(
+                    r#"INSERT INTO `sync_failure` (`user_id`, `reservation_id`, `last_error`, `attempts`, `failed_at`)
+                    VALUES (?, ?, ?, 1, ?)
+                    ON CONFLICT DO UPDATE SET
+                    `last_error`=`excluded`.`last_error`, `attempts`=`attempts` + 1,
+                    `failed_at`=`excluded`.`failed_at`"#,
+                    self.user_id,
+                    reservation_id,
+                    last_error,
+                    now
+                )
+                .execute(db)
+                .await
+            }
+        };
+        if let Err(e) = recorded {
+            error!("Failed to record sync result of {reservation_id} - {e:?}");
+        }
+    }
+}
+
+/// Identifies the event of the reservation on google calendar
+fn i_cal_uid(user_id: UserId, reservation_id: &str) -> String {
+    format!("{reservation_id}.{}@calendar-hub", user_id.0)
+}
+
+/// Reservations without an event yet get one unless cancelled, or over when
+/// only future events are synced. Recurring ones are always inserted for their
+/// upcoming occurrences.
+fn is_to_insert(reservation: &CalendarEvent, only_future_events: bool, today: NaiveDate) -> bool {
+    if reservation.invalid {
+        return false;
+    }
+    !only_future_events
+        || reservation.recurrence.is_some()
+        || reservation.date_end.unwrap_or(reservation.date_begin) >= today
+}
+
+/// Event kept in the audit without the fields google manages, to be patched
+/// over the current one
+fn restorable(event: Event) -> Event {
+    Event {
+        etag: None,
+        sequence: None,
+        created: None,
+        updated: None,
+        ..event
+    }
+}
+
+type Hub = CalendarHub<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>;
+
+/// Hub acting as the service account, which the calendars are shared to
+async fn service_account_hub(config: &Config) -> anyhow::Result<Hub> {
+    let auth = oauth2::ServiceAccountAuthenticator::builder(config.service_account.clone())
+        .build()
+        .await?;
+
+    Ok(hub_with(auth))
+}
+
+fn hub_with(auth: impl google_calendar3::client::GetToken + 'static) -> Hub {
+    CalendarHub::new(
+        hyper::Client::builder().build(
+            hyper_rustls::HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .https_or_http()
+                .enable_http1()
+                .enable_http2()
+                .build(),
+        ),
+        auth,
+    )
+}
+
+/// The event is already deleted, e.g. by the user on google calendar
+fn is_gone(e: &google_calendar3::Error) -> bool {
+    match e {
+        google_calendar3::Error::BadRequest(value) => {
+            matches!(value["error"]["code"].as_u64(), Some(404 | 410))
+        }
+        google_calendar3::Error::Failure(response) => {
+            matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::GONE)
+        }
+        _ => false,
+    }
+}
+
+/// The calendar is deleted, or not shared to the service account anymore.
+/// Google answers 403 also for exceeded rate limits and quota, e.g.
+/// `rateLimitExceeded`, which are told apart by the reason of the error. A 403
+/// without the reason is not taken as revoked.
+fn is_revoked(e: &google_calendar3::Error) -> bool {
+    is_gone(e)
+        || match e {
+            google_calendar3::Error::BadRequest(value) => {
+                value["error"]["code"].as_u64() == Some(403)
+                    && value["error"]["errors"].as_array().is_some_and(|errors| {
+                        errors.iter().any(|error| {
+                            matches!(error["reason"].as_str(), Some("forbidden" | "notFound"))
+                        })
+                    })
+            }
+            _ => false,
+        }
+}
+
+/// Whether the ACL of a calendar lets the user of `email` write events into it
+fn acl_lets_write(rules: &[AclRule], email: &str) -> bool {
+    rules.iter().any(|rule| {
+        matches!(rule.role.as_deref(), Some("owner" | "writer"))
+            && rule.scope.as_ref().is_some_and(|scope| {
+                scope.type_.as_deref() == Some("user")
+                    && scope
+                        .value
+                        .as_deref()
+                        .is_some_and(|value| value.eq_ignore_ascii_case(email))
+            })
+    })
+}
+
+impl GoogleUser {
+    /// Name of the calendar on google calendar, `None` when the service
+    /// account doesn't own it and can't rename it
+    pub async fn calendar_name(&self) -> anyhow::Result<Option<String>> {
+        let hub = service_account_hub(&Config::get()).await?;
+        self.calendar_name_on(&hub).await
+    }
+
+    async fn calendar_name_on(&self, hub: &Hub) -> anyhow::Result<Option<String>> {
+        match hub.calendar_list().get(&self.calendar_id).doit().await {
+            Ok((_, entry)) if entry.access_role.as_deref() == Some("owner") => {
+                Ok(Some(entry.summary.unwrap_or_default()))
+            }
+            Ok(_) => Ok(None),
+            // not in the calendar list of the service account
+            Err(e) if is_gone(&e) => Ok(None),
+            Err(e) => Err(e).context("Failed to get calendar list entry"),
+        }
+    }
+
+    /// Whether the events of a source can be synced into the calendar: the
+    /// calendar of the hub, or one the user owns or can write to. Other
+    /// calendars shared to the service account, e.g. the one of another user,
+    /// are not.
+    pub async fn can_sync_into(&self, db: &SqlitePool, calendar_id: &str) -> anyhow::Result<bool> {
+        if calendar_id == self.calendar_id {
+            return Ok(true);
+        }
+        let Some(email) = get_email(db, self.user_id).await? else {
+            debug!(
+                "Email of {:?} is unknown to check calendar {calendar_id}",
+                self.user_id
+            );
+            return Ok(false);
+        };
+        let hub = service_account_hub(&Config::get()).await?;
+        self.can_sync_into_on(&hub, &email, calendar_id).await
+    }
+
+    async fn can_sync_into_on(
+        &self,
+        hub: &Hub,
+        email: &str,
+        calendar_id: &str,
+    ) -> anyhow::Result<bool> {
+        if calendar_id == self.calendar_id {
+            return Ok(true);
+        }
+
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut request = hub.acl().list(calendar_id);
+            if let Some(page_token) = &page_token {
+                request = request.page_token(page_token);
+            }
+            let acl = match request.doit().await {
+                Ok((_, acl)) => acl,
+                // not shared to the service account, or without the access to the ACL
+                Err(e) if is_revoked(&e) => {
+                    debug!("ACL of calendar {calendar_id} is not accessible - {e:?}");
+                    return Ok(false);
+                }
+                Err(e) => return Err(e).context("Failed to get ACL of calendar"),
+            };
+            if acl_lets_write(acl.items.as_deref().unwrap_or_default(), email) {
+                return Ok(true);
+            }
+            page_token = acl.next_page_token;
+            if page_token.is_none() {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Renames the calendar, which is created with `CALENDAR_NAME`. Only
+    /// the owner can rename a calendar, `false` when the service account is
+    /// just a writer of it.
+    pub async fn rename_calendar(&self, name: &str) -> anyhow::Result<bool> {
+        let hub = service_account_hub(&Config::get()).await?;
+        self.rename_calendar_on(&hub, name).await
+    }
+
+    async fn rename_calendar_on(&self, hub: &Hub, name: &str) -> anyhow::Result<bool> {
+        if self.calendar_name_on(hub).await?.is_none() {
+            debug!(
+                "Calendar {} of {:?} is not owned by the service account",
+                self.calendar_id, self.user_id
+            );
+            return Ok(false);
+        }
+
+        hub.calendars()
+            .patch(
+                Calendar {
+                    summary: Some(name.to_string()),
+                    ..Default::default()
+                },
+                &self.calendar_id,
+            )
+            .doit()
+            .await
+            .context("Failed to rename calendar")?;
+
+        Ok(true)
+    }
+
+    /// Makes sure the service account still has access to the calendar. When
+    /// it is revoked, the events on it are forgotten and the calendar is marked
+    /// to be created again on the next login.
+    async fn check_calendar(&self, hub: &Hub, db: &SqlitePool) -> anyhow::Result<()> {
+        match hub.calendars().get(&self.calendar_id).doit().await {
+            Ok(_) => Ok(()),
+            Err(e) if is_revoked(&e) => {
+                warn!(
+                    "Calendar {} of {:?} is revoked - {e:?}",
+                    self.calendar_id, self.user_id
+                );
+                self.forget_calendar(db).await?;
+                Err(CalendarRevoked(self.user_id).into())
+            }
+            Err(e) => Err(e).context("Failed to get calendar"),
+        }
+    }
+
+    /// Drops the mappings to the events on the revoked calendar, and syncs all
+    /// reservations again into the new calendar after the next login. Events
+    /// on calendars of sources are kept.
+    async fn forget_calendar(&self, db: &SqlitePool) -> anyhow::Result<()> {
+        let mut tx = db.begin().await?;
+        sqlx::query!(
+            "DELETE FROM `google_event` WHERE `user_id` = ? AND (`calendar_id` IS NULL OR `calendar_id` = ?)",
+            self.user_id,
+            self.calendar_id
+        )
+        .execute(&mut tx)
+        .await
+        .context("Failed to delete events of revoked calendar")?;
+        sqlx::query!(
+            "UPDATE `google_user` SET `calendar_revoked` = TRUE, `acl_id` = NULL, `last_synced` = ? WHERE `user_id` = ?",
+            NaiveDateTime::MIN,
+            self.user_id
+        )
+        .execute(&mut tx)
+        .await
+        .context("Failed to mark calendar as revoked")?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Deletes google events of the reservations with `prefix`, with their
+    /// mappings. Stops at the first failure, the rest are kept to retry.
+    pub async fn delete_events_of(&self, db: &SqlitePool, prefix: &str) -> anyhow::Result<u64> {
+        let pattern = format!("{prefix}%");
+        let google_events = sqlx::query!(
+            "SELECT `event_id`, `calendar_id` FROM `google_event` WHERE `user_id` = ? AND `reservation_id` LIKE ?",
+            self.user_id,
+            pattern
+        )
+        .fetch_all(db)
+        .await
+        .context("Failed to get google events to delete")?
+        .into_iter()
+        .map(|row| (row.event_id, row.calendar_id))
+        .collect();
+
+        self.delete_google_events(db, google_events).await
+    }
+
+    /// Deletes google events of the reservations, like [`Self::delete_events_of`]
+    #[cfg(not(feature = "crawl_test"))]
+    pub(crate) async fn delete_events_of_reservations(
+        &self,
+        db: &SqlitePool,
+        reservation_ids: &[impl AsRef<str>],
+    ) -> anyhow::Result<u64> {
+        let mut google_events = Vec::new();
+        for reservation_ids in reservation_ids.chunks(ID_CHUNK_SIZE) {
+            google_events.extend(
+                sqlx::QueryBuilder::new(
+                    "SELECT `event_id`, `calendar_id` FROM `google_event` WHERE `user_id` = ",
+                )
+                .push_bind(self.user_id)
+                .push(" AND `reservation_id` IN ")
+                .push_tuples(reservation_ids, |mut builder, id| {
+                    builder.push_bind(id.as_ref());
+                })
+                .build()
+                .fetch_all(db)
+                .await
+                .context("Failed to get google events to delete")?
+                .into_iter()
+                .map(|row| (row.get_unchecked(0), row.get_unchecked(1))),
+            );
+        }
+
+        self.delete_google_events(db, google_events).await
+    }
+
+    async fn delete_google_events(
+        &self,
+        db: &SqlitePool,
+        google_events: Vec<(String, Option<String>)>,
+    ) -> anyhow::Result<u64> {
+        if google_events.is_empty() {
+            return Ok(0);
+        }
+
+        let hub = service_account_hub(&Config::get()).await?;
+        let mut deleted = 0;
+        for (event_id, calendar_id) in google_events {
+            let calendar_id = calendar_id.as_deref().unwrap_or(&self.calendar_id);
+            match hub.events().delete(calendar_id, &event_id).doit().await {
+                Ok(_) => {}
+                Err(e) if is_gone(&e) => debug!("Event {event_id} is already deleted"),
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to delete event {event_id}"))
+                }
+            }
+            sqlx::query!(
+                "DELETE FROM `google_event` WHERE `user_id` = ? AND `event_id` = ?",
+                self.user_id,
+                event_id
+            )
+            .execute(db)
+            .await
+            .context("Failed to delete mapping of deleted event")?;
+            deleted += 1;
+        }
+
+        Ok(deleted)
+    }
+
+    /// Detects events which were deleted directly on google calendar since the last sync.
+    ///
+    /// Instead of probing every saved event, this lists events of each calendar
+    /// having events of the user updated after `last_synced` including deleted
+    /// ones (`updatedMin` + `showDeleted`). Mappings to cancelled events are
+    /// dropped and still valid reservations are returned, so they are inserted
+    /// again rather than being lost permanently.
+    async fn take_externally_deleted(
+        &self,
+        hub: &Hub,
+        db: &SqlitePool,
+    ) -> anyhow::Result<Vec<CalendarEvent>> {
+        // nothing can be deleted before the first sync
+        if self.last_synced.timestamp() <= 0 {
+            return Ok(Vec::new());
+        }
+
+        let calendar_ids = sqlx::query_scalar!(
+            r#"SELECT DISTINCT IFNULL(`calendar_id`, ?) as `calendar_id!: String`
+            FROM `google_event` WHERE `user_id` = ?"#,
+            self.calendar_id,
+            self.user_id
+        )
+        .fetch_all(db)
+        .await
+        .context("Failed to get calendars of events")?;
+
+        let mut reservation_ids = Vec::new();
+        for calendar_id in calendar_ids {
+            // a calendar which can't be listed doesn't keep the others from being checked
+            match self.take_deleted_mappings_of(hub, db, &calendar_id).await {
+                Ok(ids) => reservation_ids.extend(ids),
+                Err(e) => error!("Failed to check deleted events of {calendar_id} - {e:?}"),
+            }
+        }
+
+        if reservation_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        info!(
+            "{} events are deleted externally for {:?}",
+            reservation_ids.len(),
+            self.user_id
+        );
+
+        let mut rows = Vec::new();
+        for reservation_ids in reservation_ids.chunks(ID_CHUNK_SIZE) {
+            rows.extend(
+                sqlx::QueryBuilder::new(
+                    r#"SELECT
+                        `id`, `title`, `detail`,
+                        `date_begin`, `time_begin`,
+                        `date_end`, `time_end`,
+                        `invalid`,
+                        `location`,
+                        `url`,
+                        `recurrence`,
+                        `tentative`,
+                        `title_parts`,
+                        `party_size`
+                    FROM `reservation`
+                    WHERE `invalid` = FALSE AND `user_id` = "#,
+                )
+                .push_bind(self.user_id)
+                .push(" AND `id` IN ")
+                .push_tuples(reservation_ids, |mut builder, id| {
+                    builder.push_bind(id);
+                })
+                .build()
+                .fetch_all(db)
+                .await
+                .context("Failed to collect externally deleted reservations")?,
+            );
+        }
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(CalendarEvent {
+                    id: row.try_get(0)?,
+                    title: row.try_get(1)?,
+                    detail: row.try_get(2)?,
+                    date_begin: row.try_get(3)?,
+                    time_begin: row.try_get(4)?,
+                    date_end: row.try_get(5)?,
+                    time_end: row.try_get(6)?,
+                    invalid: row.try_get(7)?,
+                    location: row.try_get(8)?,
+                    url: row.try_get(9)?,
+                    recurrence: CalendarEvent::recurrence_from_db(row.try_get(10)?),
+                    tentative: row.try_get(11)?,
+                    title_parts: CalendarEvent::title_parts_from_db(row.try_get(12)?),
+                    party_size: row.try_get(13)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Drops the mappings to the events of the calendar cancelled since the
+    /// last sync, returning the ids of their reservations
+    async fn take_deleted_mappings_of(
+        &self,
+        hub: &Hub,
+        db: &SqlitePool,
+        calendar_id: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        let updated_min = chrono::DateTime::from_naive_utc_and_offset(self.last_synced, Utc);
+        let mut deleted_event_ids = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut call = hub
+                .events()
+                .list(calendar_id)
+                .updated_min(updated_min)
+                .show_deleted(true);
+            if let Some(page_token) = &page_token {
+                call = call.page_token(page_token);
+            }
+            let (_, events) = call.doit().await.context("Failed to list updated events")?;
+
+            deleted_event_ids.extend(
+                events
+                    .items
+                    .into_iter()
+                    .flatten()
+                    .filter(|event| event.status.as_deref() == Some("cancelled"))
+                    .filter_map(|event| event.id),
+            );
+
+            page_token = events.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+
+        let mut reservation_ids = Vec::new();
+        for deleted_event_ids in deleted_event_ids.chunks(ID_CHUNK_SIZE) {
+            reservation_ids.extend(
+                sqlx::QueryBuilder::new("DELETE FROM `google_event` WHERE `user_id` = ")
+                    .push_bind(self.user_id)
+                    .push(" AND IFNULL(`calendar_id`, ")
+                    .push_bind(&self.calendar_id)
+                    .push(") = ")
+                    .push_bind(calendar_id)
+                    .push(" AND `event_id` IN ")
+                    .push_tuples(deleted_event_ids, |mut builder, event_id| {
+                        builder.push_bind(event_id);
+                    })
+                    .push(" RETURNING `reservation_id`")
+                    .build()
+                    .fetch_all(db)
+                    .await
+                    .context("Failed to remove externally deleted events")?
+                    .into_iter()
+                    .map(|row| row.get_unchecked::<String, _>(0)),
+            );
+        }
+
+        Ok(reservation_ids)
+    }
+
+    /// Creates the event of the reservation with an iCalUID of its own. Google
+    /// updates the event of the same iCalUID instead of creating another, so an
+    /// insert which succeeded but wasn't saved doesn't leave a duplicate.
+    async fn import_event(
+        &self,
+        hub: &Hub,
+        calendar_id: &str,
+        reservation_id: &str,
+        event: Event,
+    ) -> anyhow::Result<(String, Event)> {
+        let event = Event {
+            i_cal_uid: Some(i_cal_uid(self.user_id, reservation_id)),
+            ..event
+        };
+        let (_, event) = hub
+            .events()
+            .import(event, calendar_id)
+            .doit()
+            .await
+            .context("Failed to insert event")?;
+        let event_id = event.id.clone().context("Inserted event has no id")?;
+
+        Ok((event_id, event))
+    }
+
+    /// Calendar which the events of the source are synced into
+    fn calendar_of<'a>(&'a self, setting: &'a SourceSetting) -> &'a str {
+        setting.calendar_id.as_deref().unwrap_or(&self.calendar_id)
+    }
+
+    /// Patches the event of the reservation. When the calendar of the source is
+    /// changed after the event is created, the event is moved to it first.
+    /// With `KEEP_MANUAL_EDITS`, the event edited on google calendar is left
+    /// as it is.
+    #[allow(clippy::too_many_arguments)]
+    async fn update_event<'a>(
+        &'a self,
+        hub: &Hub,
+        db: &SqlitePool,
+        batch: &audit::Batch,
+        event_id: &str,
+        calendar_id: Option<&str>,
+        reservation: CalendarEvent,
+        setting_of: impl Fn(&CalendarEvent) -> &'a SourceSetting,
+    ) -> anyhow::Result<()> {
+        let reservation_id = reservation.id.clone();
+        let setting = setting_of(&reservation);
+        let calendar_id = calendar_id.unwrap_or(&self.calendar_id);
+        let target = self.calendar_of(setting);
+        if Config::get().keep_manual_edits
+            && self
+                .edited_on_google(hub, db, calendar_id, event_id)
+                .await?
+        {
+            info!(
+                "Event {event_id} of {reservation_id} is edited on google calendar, skip patching"
+            );
+            return Ok(());
+        }
+        if calendar_id != target {
+            debug!("Move event {event_id} from {calendar_id} to {target}");
+            hub.events()
+                .move_(calendar_id, event_id, target)
+                .doit()
+                .await
+                .context("Failed to move event")?;
+            sqlx::query!(
+                "UPDATE `google_event` SET `calendar_id` = ? WHERE `user_id` = ? AND `event_id` = ?",
+                setting.calendar_id,
+                self.user_id,
+                event_id
+            )
+            .execute(db)
+            .await
+            .context("Failed to update calendar of moved event")?;
+        }
+
+        let (_, patched) = hub
+            .events()
+            .patch(
+                to_google_event(
+                    reservation,
+                    setting,
+                    self.locale,
+                    self.cancelled_event_mode,
+                    &Config::get().event_time_zone,
+                ),
+                target,
+                event_id,
+            )
+            .doit()
+            .await
+            .context("Failed to patch event")?;
+        self.record_written(db, event_id, &patched).await?;
+        batch
+            .record(
+                db,
+                Action::Patch,
+                event_id,
+                target,
+                &reservation_id,
+                Some(&patched),
+            )
+            .await;
+
+        Ok(())
+    }
+
+    /// Whether the event is modified on google calendar after the hub wrote it.
+    /// Events written before the time is recorded are taken as not modified.
+    async fn edited_on_google(
+        &self,
+        hub: &Hub,
+        db: &SqlitePool,
+        calendar_id: &str,
+        event_id: &str,
+    ) -> anyhow::Result<bool> {
+        let written_at = sqlx::query_scalar!(
+            "SELECT `written_at` as `written_at: NaiveDateTime` FROM `google_event`
+            WHERE `user_id` = ? AND `event_id` = ?",
+            self.user_id,
+            event_id
+        )
+        .fetch_optional(db)
+        .await
+        .context("Failed to get written time of event")?
+        .flatten();
+        let Some(written_at) = written_at else {
+            return Ok(false);
+        };
+
+        let (_, event) = hub
+            .events()
+            .get(calendar_id, event_id)
+            .doit()
+            .await
+            .context("Failed to get event")?;
+
+        Ok(event
+            .updated
+            .is_some_and(|updated| updated.naive_utc() > written_at))
+    }
+
+    /// Keeps the time google took as the last write of the event, to tell the
+    /// edits made on google calendar after it
+    async fn record_written(
+        &self,
+        db: &SqlitePool,
+        event_id: &str,
+        event: &Event,
+    ) -> anyhow::Result<()> {
+        let written_at = event.updated.map(|updated| updated.naive_utc());
+        sqlx::query!(
+            "UPDATE `google_event` SET `written_at` = ? WHERE `user_id` = ? AND `event_id` = ?",
+            written_at,
+            self.user_id,
+            event_id
+        )
+        .execute(db)
+        .await
+        .context("Failed to record written time of event")?;
+
+        Ok(())
+    }
+
+    /// Finds reservations which are already on the calendar under an old id and
+    /// the upstream re-issued under a new one. Their google events are moved to
+    /// the new id and the old reservation is invalidated, so the event gets
+    /// patched instead of being duplicated. Only old reservations the source
+    /// doesn't list anymore, invalidated since the last sync or not seen in the
+    /// crawl of the new one, are taken, so identical bookings keep their own
+    /// events.
+    async fn take_renamed(
+        &self,
+        db: &SqlitePool,
+        reservations: &mut HashMap<String, CalendarEvent>,
+    ) -> anyhow::Result<Vec<(String, Option<String>, CalendarEvent)>> {
+        let Some(date_min) = reservations
+            .values()
+            .filter(|reservation| !reservation.invalid)
+            .map(|reservation| reservation.date_begin)
+            .min()
+        else {
+            return Ok(Vec::new());
+        };
+
+        let candidates = sqlx::query!(
+            r#"SELECT
+                `google_event`.`event_id`,
+                `google_event`.`calendar_id`,
+                `id`, `title`, `detail`,
+                `date_begin` as `date_begin: chrono::NaiveDate`,
+                `time_begin` as `time_begin: chrono::NaiveTime`,
+                `date_end` as `date_end: chrono::NaiveDate`,
+                `time_end` as `time_end: chrono::NaiveTime`,
+                `invalid`,
+                `location`,
+                `url`,
+                `recurrence`,
+                `tentative`,
+                `title_parts`,
+                `party_size` as `party_size: u32`,
+                `last_seen_at`
+            FROM `reservation`
+            INNER JOIN `google_event`
+                ON `google_event`.`user_id` = `reservation`.`user_id`
+                AND `google_event`.`reservation_id` = `reservation`.`id`
+            WHERE `reservation`.`user_id` = ? AND `date_begin` >= ?"#,
+            self.user_id,
+            date_min
+        )
+        .fetch_all(db)
+        .await
+        .context("Failed to collect reservations to reconcile")?;
+
+        let mut renamed = Vec::new();
+        for candidate in candidates {
+            let old = CalendarEvent {
+                id: candidate.id,
+                title: candidate.title,
+                detail: candidate.detail,
+                invalid: candidate.invalid,
+                date_begin: candidate.date_begin,
+                time_begin: candidate.time_begin,
+                date_end: candidate.date_end,
+                time_end: candidate.time_end,
+                location: candidate.location,
+                url: candidate.url,
+                recurrence: CalendarEvent::recurrence_from_db(candidate.recurrence),
+                tentative: candidate.tentative,
+                title_parts: CalendarEvent::title_parts_from_db(candidate.title_parts),
+                party_size: candidate.party_size,
+            };
+            // the events of the ones cancelled before are already deleted or marked
+            if old.invalid && !reservations.contains_key(&old.id) {
+                continue;
+            }
+            let mut new_id = None;
+            for id in reservations
+                .values()
+                .filter(|reservation| !reservation.invalid && reservation.is_renamed_from(&old))
+                .map(|reservation| reservation.id.clone())
+                .collect::<Vec<_>>()
+            {
+                let new = sqlx::query!(
+                    r#"SELECT `last_seen_at`, EXISTS(
+                        SELECT 1 FROM `google_event`
+                        WHERE `google_event`.`user_id` = `reservation`.`user_id`
+                            AND `google_event`.`reservation_id` = `reservation`.`id`
+                    ) as `mapped: bool`
+                    FROM `reservation` WHERE `user_id` = ? AND `id` = ?"#,
+                    self.user_id,
+                    id
+                )
+                .fetch_one(db)
+                .await
+                .context("Failed to get renamed reservation")?;
+                if !new.mapped && (old.invalid || candidate.last_seen_at < new.last_seen_at) {
+                    new_id = Some(id);
+                    break;
+                }
+            }
+            let Some(new_id) = new_id else {
+                continue;
+            };
+
+            info!("Reservation {} is renamed to {new_id}", old.id);
+            let mut tx = db.begin().await?;
+            sqlx::query!(
+                "UPDATE `google_event` SET `reservation_id` = ? WHERE `user_id` = ? AND `event_id` = ?",
+                new_id,
+                self.user_id,
+                candidate.event_id
+            )
+            .execute(&mut tx)
+            .await
+            .context("Failed to move google event to renamed reservation")?;
+            sqlx::query!(
+                "UPDATE `reservation` SET `invalid` = TRUE WHERE `user_id` = ? AND `id` = ?",
+                self.user_id,
+                old.id
+            )
+            .execute(&mut tx)
+            .await
+            .context("Failed to invalidate renamed reservation")?;
+            tx.commit().await?;
+
+            // its event is taken by the new one
+            reservations.remove(&old.id);
+            if let Some(reservation) = reservations.remove(&new_id) {
+                renamed.push((candidate.event_id, candidate.calendar_id, reservation));
+            }
+        }
+
+        Ok(renamed)
+    }
+}
+
+/// Deletes `(calendar_id, event_id)` events no user has a mapping of, e.g.
+/// the duplicates left by merging users. The ones failed to delete are
+/// returned, events deleted already are not.
+pub async fn delete_events(events: &[(String, String)]) -> anyhow::Result<Vec<(String, String)>> {
+    if events.is_empty() {
+        return Ok(Vec::new());
+    }
+    let hub = service_account_hub(&Config::get()).await?;
+    Ok(delete_events_on(&hub, events).await)
+}
+
+async fn delete_events_on(hub: &Hub, events: &[(String, String)]) -> Vec<(String, String)> {
+    let mut left = Vec::new();
+    for (calendar_id, event_id) in events {
+        match hub.events().delete(calendar_id, event_id).doit().await {
+            Ok(_) => {}
+            Err(e) if is_gone(&e) => debug!("Event {event_id} is already deleted"),
+            Err(e) => {
+                error!("Failed to delete event {event_id} of {calendar_id} - {e:?}");
+                left.push((calendar_id.clone(), event_id.clone()));
+            }
+        }
+    }
+
+    left
+}
+
+/// Email of the google account the user logged in with. Users logged in before
+/// the email is kept don't have it until they login again.
+pub async fn get_email(db: &SqlitePool, user_id: UserId) -> anyhow::Result<Option<String>> {
+    sqlx::query!(
+        "SELECT `email` FROM `google_user` WHERE `user_id` = ?",
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .context("Failed to get email from DB")
+    .map(|row| row.and_then(|row| row.email))
+}
+
+pub async fn get_last_synced(
+    db: SqlitePool,
+    user_id: UserId,
+) -> anyhow::Result<chrono::DateTime<chrono::Utc>> {
+    sqlx::query!(
+        "SELECT `last_synced` FROM `google_user` WHERE `user_id` = ?",
+        user_id
+    )
+    .fetch_one(&db)
+    .await
+    .context("Failed to get last_synced for ({user_id:?}) from DB")
+    .map(|row| chrono::DateTime::from_naive_utc_and_offset(row.last_synced, chrono::Utc))
+}
+
+/// Reservations failed to sync, split by whether they are retried
+#[derive(Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct SyncFailures {
+    pub retrying: i64,
+    /// Failed [`MAX_SYNC_ATTEMPTS`] times, retried only when they are updated
+    pub given_up: i64,
+}
+
+/// Result of [`GoogleUser::undo_last_sync`]
+#[derive(Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Undone {
+    pub reverted: usize,
+    /// Changes without the event before them, or of events deleted since
+    pub skipped: usize,
+}
+
+pub async fn get_sync_failures(db: &SqlitePool, user_id: UserId) -> anyhow::Result<SyncFailures> {
+    sqlx::query_as!(
+        SyncFailures,
+        r#"SELECT
+            COUNT(*) FILTER (WHERE `attempts` < ?1) as `retrying!: i64`,
+            COUNT(*) FILTER (WHERE `attempts` >= ?1) as `given_up!: i64`
+        FROM `sync_failure`
+        WHERE `user_id` = ?2"#,
+        MAX_SYNC_ATTEMPTS,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+    .with_context(|| format!("Failed to count sync failures of {user_id:?}"))
+}
+
+/// Reservations updated after `last_synced` are synced on the next sync
+async fn update_last_synced(
+    db: &SqlitePool,
+    user_id: UserId,
+    last_synced: NaiveDateTime,
+) -> anyhow::Result<()> {
+    let result = sqlx::query!(
+        "UPDATE `google_user` SET `last_synced` = ? WHERE `user_id` = ?",
+        last_synced,
+        user_id
+    )
+    .execute(db)
+    .await
+    .with_context(|| format!("Failed to update last_synced of {user_id:?}"))?;
+    anyhow::ensure!(
+        result.rows_affected() == 1,
+        "{user_id:?} is not a google user"
+    );
+
+    Ok(())
+}
+
+/// Number of reservations updated after the last sync, which the next sync will apply
+pub async fn get_pending_sync_count(db: SqlitePool, user_id: UserId) -> anyhow::Result<i64> {
+    sqlx::query!(
+        r#"SELECT COUNT(*) as `count!: i64` FROM `reservation`
+        INNER JOIN `google_user` ON `google_user`.`user_id` = `reservation`.`user_id`
+        WHERE `reservation`.`user_id` = ? AND `reservation`.`updated_at` > `google_user`.`last_synced`"#,
+        user_id
+    )
+    .fetch_one(&db)
+    .await
+    .context("Failed to count reservations pending sync for ({user_id:?}) from DB")
+    .map(|row| row.count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedId(Uuid);
+
+    impl IdSource for FixedId {
+        fn new_id(&self) -> Uuid {
+            self.0
+        }
+    }
+
+    /// Hub sending the requests to `router`, which stands for google calendar
+    fn mock_hub(router: axum::Router) -> Hub {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+        let mut hub = hub_with("token".to_string());
+        hub.base_url(format!("http://{address}/"));
+        hub
+    }
+
+    fn test_user() -> GoogleUser {
+        GoogleUser {
+            user_id: UserId(1),
+            calendar_id: "main".to_string(),
+            last_synced: NaiveDateTime::MIN,
+            service_account_email: None,
+            locale: Locale::default(),
+            cancelled_event_mode: CancelledEventMode::default(),
+            only_future_events: false,
+            calendar_revoked: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn secret_is_read_from_env_when_file_is_absent() {
+        const ENV: &str = "CALENDAR_HUB_TEST_APP_SECRET_JSON";
+        let directory = std::env::temp_dir().join(format!("calendar-hub-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("google.json");
+        let secret = |client_id: &str| {
+            format!(
+                r#"{{"web": {{"client_id": "{client_id}", "client_secret": "secret",
+                "auth_uri": "https://accounts.google.com/o/oauth2/auth",
+                "token_uri": "https://oauth2.googleapis.com/token", "redirect_uris": []}}}}"#
+            )
+        };
+
+        let error = read_json_file_or_env(&path, ENV, oauth2::parse_application_secret)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("google.json"));
+        assert!(error.to_string().contains(ENV));
+
+        std::env::set_var(ENV, secret("from-env"));
+        let loaded = read_json_file_or_env(&path, ENV, oauth2::parse_application_secret)
+            .await
+            .unwrap();
+        assert_eq!(loaded.client_id, "from-env");
+
+        // the file is preferred
+        std::fs::write(&path, secret("from-file")).unwrap();
+        let loaded = read_json_file_or_env(&path, ENV, oauth2::parse_application_secret)
+            .await
+            .unwrap();
+        assert_eq!(loaded.client_id, "from-file");
+
+        std::env::remove_var(ENV);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn login_callback_creates_session_of_logged_in_user() {
+        use axum_sessions::{async_session::MemoryStore, SessionLayer};
+        use hyper::service::Service;
+
+        let id = Uuid::from_u128(0x1234);
+        let contexts = Arc::new(Mutex::new(LoginContextMap::new()));
+        let (state, code_receiver, user_id_sender) = register_login(&contexts, &FixedId(id)).await;
+        assert_eq!(state, id);
+
+        // stands for the authenticator exchanging the code
+        let authenticator = tokio::spawn(async move {
+            let code = code_receiver.await.unwrap();
+            user_id_sender.send(Some(UserId(7))).unwrap();
+            code.0
+        });
+
+        let scope = CALENDAR_SCOPE.join("%20");
+        let mut router: axum::Router = axum::Router::new()
+            .route("/callback", get(login_callback))
+            .layer(Extension(contexts.clone()))
+            .layer(SessionLayer::new(MemoryStore::new(), &[0; 64]));
+        let response = router
+            .call(
+                hyper::Request::get(format!("/callback?state={id}&code=auth-code&scope={scope}"))
+                    .body(hyper::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(authenticator.await.unwrap(), "auth-code");
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert!(response.headers().contains_key(hyper::header::SET_COOKIE));
+        assert!(contexts.lock().await.is_empty());
+
+        // the context is consumed, replaying the callback is rejected
+        let response = router
+            .call(
+                hyper::Request::get(format!("/callback?state={id}&code=auth-code&scope={scope}"))
+                    .body(hyper::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn login_callback_with_reduced_scope_asks_for_permissions() {
+        use axum_sessions::{async_session::MemoryStore, SessionLayer};
+        use hyper::service::Service;
+
+        let id = Uuid::from_u128(0x1234);
+        let contexts = Arc::new(Mutex::new(LoginContextMap::new()));
+        let (_, code_receiver, _user_id_sender) = register_login(&contexts, &FixedId(id)).await;
+
+        let mut router: axum::Router = axum::Router::new()
+            .route("/callback", get(login_callback))
+            .layer(Extension(contexts.clone()))
+            .layer(SessionLayer::new(MemoryStore::new(), &[0; 64]));
+        // calendar permissions are unchecked on the consent screen
+        let response = router
+            .call(
+                hyper::Request::get(format!(
+                    "/callback?state={id}&code=auth-code&scope=openid%20https://www.googleapis.com/auth/userinfo.email"
+                ))
+                .body(hyper::Body::empty())
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers()[hyper::header::LOCATION],
+            "/permissions-required"
+        );
+        assert!(contexts.lock().await.is_empty());
+        // the code is not passed to the authenticator
+        assert!(code_receiver.await.is_err());
+
+        assert_eq!(
+            missing_scopes(
+                "https://www.googleapis.com/auth/calendar https://www.googleapis.com/auth/calendar.events"
+            ),
+            ["https://www.googleapis.com/auth/calendar.readonly"]
+        );
+        assert!(missing_scopes(&CALENDAR_SCOPE[..3].join(" ")).is_empty());
+    }
+
+    async fn wait_for(emails: &AllowedEmails, email: &str) -> bool {
+        for _ in 0..50 {
+            if emails.as_ref().read().await.contains(email) {
+                return true;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        false
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn allowed_emails_reloads_on_rewrite_and_replace() {
+        let directory = std::env::temp_dir().join(format!("calendar-hub-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("allowed-emails");
+        std::fs::write(&path, "first@example.com\n").unwrap();
+
+        let (emails, _watcher) = AllowedEmails::new(path.clone()).await.unwrap();
+        assert!(emails.as_ref().read().await.contains("first@example.com"));
+
+        // truncate and rewrite in place
+        std::fs::write(&path, "second@example.com\n").unwrap();
+        assert!(wait_for(&emails, "second@example.com").await);
+
+        // atomically replace with a new file
+        let temp_path = directory.join("allowed-emails.tmp");
+        std::fs::write(&temp_path, "third@example.com\n").unwrap();
+        std::fs::rename(&temp_path, &path).unwrap();
+        assert!(wait_for(&emails, "third@example.com").await);
+        assert!(!emails.as_ref().read().await.contains("second@example.com"));
+
+        // the watch still works after the replacement
+        std::fs::write(&path, "fourth@example.com\n").unwrap();
+        assert!(wait_for(&emails, "fourth@example.com").await);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    fn google_key(kid: &str, n: &str, e: &str, alg: &str) -> GoogleKey {
+        GoogleKey {
+            n: n.to_string(),
+            e: e.to_string(),
+            kid: kid.to_string(),
+            alg: alg.to_string(),
+        }
+    }
+
+    #[test]
+    fn google_key_store_skips_malformed_keys() {
+        let n = base64_url::encode(&[0xc5u8; 256]);
+        let keys = vec![
+            google_key("valid", &n, "AQAB", "RS256"),
+            google_key("bad-base64", "!!!", "AQAB", "RS256"),
+            google_key("bad-exponent", &n, "AA", "RS256"),
+            google_key("bad-algorithm", &n, "AQAB", "ES256"),
+        ];
+
+        let store = parse_google_key_store(keys).unwrap();
+        assert_eq!(store.keys().collect::<Vec<_>>(), vec!["valid"]);
+
+        assert!(parse_google_key_store(vec![google_key("bad", "!!!", "AQAB", "RS256")]).is_err());
+        assert!(parse_google_key_store(Vec::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn refresh_replaces_rotated_keys() {
+        type KeyIds = Arc<Mutex<Vec<&'static str>>>;
+        let key_ids: KeyIds = Arc::new(Mutex::new(vec!["old"]));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let certs_url = format!("http://{}/oauth2/v3/certs", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener).unwrap().serve(
+                axum::Router::new()
+                    .route(
+                        "/oauth2/v3/certs",
+                        axum::routing::get(|Extension(key_ids): Extension<KeyIds>| async move {
+                            let n = base64_url::encode(&[0xc5u8; 256]);
+                            let keys = key_ids
+                                .lock()
+                                .await
+                                .iter()
+                                .map(|kid| {
+                                    serde_json::json!({"kid": kid, "n": n, "e": "AQAB", "alg": "RS256"})
+                                })
+                                .collect::<Vec<_>>();
+                            axum::Json(serde_json::json!({ "keys": keys }))
+                        }),
+                    )
+                    .layer(Extension(key_ids.clone()))
+                    .into_make_service(),
+            ),
+        );
+        let store = RwLock::new(fetch_google_key_store(&certs_url).await.unwrap());
+        assert_eq!(store.read().await.keys().collect::<Vec<_>>(), ["old"]);
+
+        *key_ids.lock().await = vec!["new", "next"];
+        refresh_key_store(&store, &certs_url).await.unwrap();
+        assert_eq!(
+            store.read().await.keys().collect::<Vec<_>>(),
+            ["new", "next"]
+        );
+
+        // a broken answer keeps the keys
+        key_ids.lock().await.clear();
+        assert!(refresh_key_store(&store, &certs_url).await.is_err());
+        assert_eq!(store.read().await.len(), 2);
+    }
+
+    #[test]
+    fn recurrence_is_passed_to_google_event() {
+        let event = CalendarEvent {
+            id: "naver/1003".to_string(),
+            title: "테스트 요가".to_string(),
+            detail: "화요일 오전반 4회".to_string(),
+            invalid: false,
+            date_begin: NaiveDate::from_ymd_opt(2024, 5, 7).unwrap(),
+            time_begin: NaiveTime::from_hms_opt(11, 0, 0),
+            date_end: None,
+            time_end: None,
+            location: None,
+            url: None,
+            recurrence: Some(vec!["RRULE:FREQ=WEEKLY;COUNT=4".to_string()]),
+            tentative: false,
+            title_parts: None,
+            party_size: None,
+        };
+
+        let google_event = to_google_event(
+            event.clone(),
+            &SourceSetting::default(),
+            Locale::default(),
+            CancelledEventMode::default(),
+            DEFAULT_EVENT_TIME_ZONE,
+        );
+        assert_eq!(
+            google_event.recurrence,
+            Some(vec!["RRULE:FREQ=WEEKLY;COUNT=4".to_string()])
+        );
+        // shown and repeated in KST, though the time is passed in UTC
+        assert_eq!(
+            google_event.start.unwrap().time_zone.as_deref(),
+            Some("Asia/Seoul")
+        );
+
+        let google_event = to_google_event(
+            CalendarEvent {
+                recurrence: None,
+                ..event
+            },
+            &SourceSetting::default(),
+            Locale::default(),
+            CancelledEventMode::default(),
+            DEFAULT_EVENT_TIME_ZONE,
+        );
+        assert_eq!(google_event.recurrence, None);
+    }
+
+    #[test]
+    fn color_follows_status_when_tentative_color_is_set() {
+        let event = |tentative| CalendarEvent {
+            id: "catch_table/CT-WAIT-0002".to_string(),
+            title: "[대기] 테스트 오마카세".to_string(),
+            detail: String::new(),
+            invalid: false,
+            date_begin: NaiveDate::from_ymd_opt(2024, 5, 7).unwrap(),
+            time_begin: NaiveTime::from_hms_opt(11, 0, 0),
+            date_end: None,
+            time_end: None,
+            location: None,
+            url: None,
+            recurrence: None,
+            tentative,
+            title_parts: None,
+            party_size: None,
+        };
+        let color = |setting: &SourceSetting, tentative| {
+            to_google_event(
+                event(tentative),
+                setting,
+                Locale::default(),
+                CancelledEventMode::default(),
+                DEFAULT_EVENT_TIME_ZONE,
+            )
+            .color_id
+        };
+
+        let source_colored = SourceSetting {
+            color_id: Some("7".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(color(&source_colored, false).as_deref(), Some("7"));
+        assert_eq!(color(&source_colored, true).as_deref(), Some("7"));
+
+        let status_colored = SourceSetting {
+            tentative_color_id: Some("8".to_string()),
+            ..source_colored
+        };
+        assert_eq!(color(&status_colored, false).as_deref(), Some("7"));
+        assert_eq!(color(&status_colored, true).as_deref(), Some("8"));
+    }
+
+    #[test]
+    fn transparency_follows_source_and_status() {
+        let event = |tentative| {
+            CalendarEvent::builder(
+                "catch_table/1",
+                "테스트 오마카세",
+                NaiveDate::from_ymd_opt(2024, 5, 7).unwrap(),
+            )
+            .tentative(tentative)
+            .build()
+            .unwrap()
+        };
+        let transparency = |setting: &SourceSetting, tentative| {
+            to_google_event(
+                event(tentative),
+                setting,
+                Locale::default(),
+                CancelledEventMode::default(),
+                DEFAULT_EVENT_TIME_ZONE,
+            )
+            .transparency
+        };
+
+        let busy = SourceSetting::default();
+        assert_eq!(transparency(&busy, false).as_deref(), Some("opaque"));
+        assert_eq!(transparency(&busy, true).as_deref(), Some("transparent"));
+
+        let free = SourceSetting {
+            transparency: Transparency::Transparent,
+            ..Default::default()
+        };
+        assert_eq!(transparency(&free, false).as_deref(), Some("transparent"));
+    }
+
+    #[test]
+    fn event_time_zone_must_be_known() {
+        assert_eq!(
+            event_time_zone_from_var(None).unwrap(),
+            DEFAULT_EVENT_TIME_ZONE
+        );
+        assert_eq!(
+            event_time_zone_from_var(Some("Europe/Berlin".to_string())).unwrap(),
+            "Europe/Berlin"
+        );
+        assert!(event_time_zone_from_var(Some("Asia/Seol".to_string())).is_err());
+        assert!(event_time_zone_from_var(Some(String::new())).is_err());
+    }
+
+    #[test]
+    fn party_size_is_in_description_and_shared_property() {
+        let event = CalendarEvent::builder(
+            "catch_table/1",
+            "테스트 오마카세",
+            NaiveDate::from_ymd_opt(2024, 5, 7).unwrap(),
+        )
+        .detail("압구정 - 스시")
+        .party_size(4)
+        .build()
+        .unwrap();
+        let google_event = |locale| {
+            to_google_event(
+                event.clone(),
+                &SourceSetting::default(),
+                locale,
+                CancelledEventMode::default(),
+                DEFAULT_EVENT_TIME_ZONE,
+            )
+        };
+        assert_eq!(
+            google_event(Locale::En).description.as_deref(),
+            Some("압구정 - 스시\nParty of 4")
+        );
+        // the detail is stored without it
+        assert_eq!(event.detail, "압구정 - 스시");
+        let google_event = google_event(Locale::Ko);
+
+        assert_eq!(
+            google_event.description.as_deref(),
+            Some("압구정 - 스시\n인원: 4명")
+        );
+        assert_eq!(
+            google_event
+                .extended_properties
+                .and_then(|properties| properties.shared)
+                .unwrap()
+                .get(PARTY_SIZE_PROPERTY)
+                .map(String::as_str),
+            Some("4")
+        );
+    }
+
+    #[test]
+    fn summary_is_in_locale_of_user() {
+        let event = CalendarEvent::builder_of(
+            "bustago/1",
+            crate::title::Title::Bus {
+                express: false,
+                departure: "동서울".to_string(),
+                arrival: "속초".to_string(),
+            },
+            NaiveDate::from_ymd_opt(2024, 5, 7).unwrap(),
+        )
+        .build()
+        .unwrap();
+        let summary = |locale| {
+            to_google_event(
+                event.clone(),
+                &SourceSetting::default(),
+                locale,
+                CancelledEventMode::default(),
+                DEFAULT_EVENT_TIME_ZONE,
+            )
+            .summary
+        };
+
+        assert_eq!(event.title, "동서울발 속초행 시외버스");
+        assert_eq!(
+            summary(Locale::Ko).as_deref(),
+            Some("동서울발 속초행 시외버스")
+        );
+        assert_eq!(
+            summary(Locale::En).as_deref(),
+            Some("Intercity bus 동서울 → 속초")
+        );
+
+        let templated = |template: &str| {
+            to_google_event(
+                event.clone(),
+                &SourceSetting {
+                    title_template: Some(template.to_string()),
+                    ..Default::default()
+                },
+                Locale::En,
+                CancelledEventMode::default(),
+                DEFAULT_EVENT_TIME_ZONE,
+            )
+            .summary
+        };
+        assert_eq!(
+            templated("🚌 {departure} → {arrival}").as_deref(),
+            Some("🚌 동서울 → 속초")
+        );
+        // not a part of the event
+        assert_eq!(
+            templated("{place}").as_deref(),
+            Some("Intercity bus 동서울 → 속초")
+        );
+    }
+
+    #[test]
+    fn cancelled_events_are_marked_in_mark_cancelled_mode() {
+        let event = CalendarEvent::builder(
+            "cgv/1",
+            "영화",
+            NaiveDate::from_ymd_opt(2024, 5, 7).unwrap(),
+        )
+        .invalid(true)
+        .build()
+        .unwrap();
+        let google_event = |mode| {
+            to_google_event(
+                event.clone(),
+                &SourceSetting::default(),
+                Locale::default(),
+                mode,
+                DEFAULT_EVENT_TIME_ZONE,
+            )
+        };
+
+        let marked = google_event(CancelledEventMode::MarkCancelled);
+        assert_eq!(marked.summary.as_deref(), Some("[취소] 영화"));
+        assert_eq!(marked.status.as_deref(), Some("cancelled"));
+        // never converted to be deleted, but not marked either
+        let kept = google_event(CancelledEventMode::Delete);
+        assert_eq!(kept.summary.as_deref(), Some("영화"));
+        assert_eq!(kept.status.as_deref(), Some("confirmed"));
+    }
+
+    #[test]
+    fn past_reservations_are_not_inserted_with_only_future_events() {
+        let today = NaiveDate::from_ymd_opt(2024, 5, 7).unwrap();
+        let on = |date| {
+            CalendarEvent::builder("cgv/1", "영화", date)
+                .build()
+                .unwrap()
+        };
+        let yesterday = on(today.pred_opt().unwrap());
+
+        assert!(is_to_insert(&yesterday, false, today));
+        assert!(!is_to_insert(&yesterday, true, today));
+        assert!(is_to_insert(&on(today), true, today));
+        // ends today
+        let mut trip = yesterday.clone();
+        trip.date_end = Some(today);
+        assert!(is_to_insert(&trip, true, today));
+        let mut recurring = yesterday.clone();
+        recurring.recurrence = Some(vec!["RRULE:FREQ=WEEKLY".to_string()]);
+        assert!(is_to_insert(&recurring, true, today));
+        let mut cancelled = on(today);
+        cancelled.invalid = true;
+        assert!(!is_to_insert(&cancelled, false, today));
+    }
+
+    #[test]
+    fn description_puts_link_after_detail() {
+        let event = |url: Option<&str>| {
+            let event = CalendarEvent::builder(
+                "kobus/1",
+                "서울경부 → 부산",
+                NaiveDate::from_ymd_opt(2024, 5, 7).unwrap(),
+            )
+            .detail("출발: 서울경부\n도착: 부산\n좌석: 3\n")
+            .url(url.map(String::from))
+            .build()
+            .unwrap();
+            to_google_event(
+                event,
+                &SourceSetting::default(),
+                Locale::default(),
+                CancelledEventMode::default(),
+                DEFAULT_EVENT_TIME_ZONE,
+            )
+        };
+
+        let with_url = event(Some("https://www.kobus.co.kr/mrs/mrscfm.do"));
+        assert_eq!(
+            with_url.description.as_deref(),
+            Some("출발: 서울경부\n도착: 부산\n좌석: 3\n\n예약 상세: https://www.kobus.co.kr/mrs/mrscfm.do")
+        );
+        let source = with_url.source.unwrap();
+        assert_eq!(
+            source.url.as_deref(),
+            Some("https://www.kobus.co.kr/mrs/mrscfm.do")
+        );
+        assert_eq!(source.title.as_deref(), Some("서울경부 → 부산"));
+
+        let without_url = event(None);
+        assert_eq!(
+            without_url.description.as_deref(),
+            Some("출발: 서울경부\n도착: 부산\n좌석: 3")
+        );
+        assert!(without_url.source.is_none());
+
+        assert_eq!(
+            describe("", None, Locale::Ko, Some("https://example.com")),
+            "예약 상세: https://example.com"
+        );
+        assert_eq!(
+            describe("좌석: 3", Some(2), Locale::En, Some("https://example.com")),
+            "좌석: 3\nParty of 2\n\nReservation details: https://example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn pending_sync_count_follows_last_synced() {
+        let db = crate::testing::db().await;
+        let user_id = crate::testing::USER_ID;
+        let last_synced = NaiveDate::from_ymd_opt(2024, 5, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO `google_user` (`user_id`, `subject`, `calendar_id`, `last_synced`)
+            VALUES (?, 'subject', 'calendar', ?)",
+        )
+        .bind(user_id)
+        .bind(last_synced)
+        .execute(&db)
+        .await
+        .unwrap();
+
+        for (id, user_id, updated_at) in [
+            ("naver/1", user_id, last_synced - chrono::Duration::hours(1)),
+            ("naver/2", user_id, last_synced + chrono::Duration::hours(1)),
+            ("cgv/1", user_id, last_synced + chrono::Duration::days(1)),
+            ("cgv/2", UserId(2), last_synced + chrono::Duration::days(1)),
+        ] {
+            sqlx::query(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `invalid`, `updated_at`)
+                VALUES (?, ?, 'title', 'detail', '2024-05-02', FALSE, ?)",
+            )
+            .bind(id)
+            .bind(user_id)
+            .bind(updated_at)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            get_pending_sync_count(db.clone(), user_id).await.unwrap(),
+            2
+        );
+        assert_eq!(
+            get_pending_sync_count(db.clone(), UserId(2)).await.unwrap(),
+            0
+        );
+
+        let synced = last_synced + chrono::Duration::hours(2);
+        update_last_synced(&db, user_id, synced).await.unwrap();
+        assert_eq!(
+            get_last_synced(db.clone(), user_id)
+                .await
+                .unwrap()
+                .naive_utc(),
+            synced
+        );
+        assert_eq!(
+            get_pending_sync_count(db.clone(), user_id).await.unwrap(),
+            1
+        );
+        // not logged in to google
+        assert!(update_last_synced(&db, UserId(2), synced).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn revoked_calendar_is_forgotten() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        sqlx::query(
+            "INSERT INTO `google_user` (`user_id`, `subject`, `calendar_id`, `acl_id`, `last_synced`)
+            VALUES (1, 'subject', 'main', 'acl', '2024-05-01 00:00:00')",
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        for (event_id, reservation_id, calendar_id) in
+            [("1", "cgv/1", None), ("2", "cgv/2", Some("movie"))]
+        {
+            sqlx::query(
+                "INSERT INTO `google_event` (`event_id`, `user_id`, `reservation_id`, `calendar_id`) VALUES (?, 1, ?, ?)",
+            )
+            .bind(event_id)
+            .bind(reservation_id)
+            .bind(calendar_id)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        // google answers like the calendar is deleted
+        let hub = mock_hub(axum::Router::new().fallback(|| async {
+            (
+                StatusCode::NOT_FOUND,
+                axum::Json(serde_json::json!({"error": {"code": 404, "message": "Not Found"}})),
+            )
+        }));
+
+        let user = GoogleUser::from_user_id(&db, UserId(1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!user.calendar_revoked());
+        let error = user.check_calendar(&hub, &db).await.unwrap_err();
+        assert!(error.is::<CalendarRevoked>());
+
+        let user = GoogleUser::from_user_id(&db, UserId(1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user.calendar_revoked());
+        let event_ids: Vec<String> = sqlx::query_scalar("SELECT `event_id` FROM `google_event`")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(event_ids, ["2"]);
+        let acl_id: Option<String> =
+            sqlx::query_scalar("SELECT `acl_id` FROM `google_user` WHERE `user_id` = 1")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(acl_id, None);
+        assert_eq!(
+            get_pending_sync_count(db.clone(), UserId(1)).await.unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_syncs_are_given_up_after_max_attempts() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        let user = test_user();
+        let failures = || get_sync_failures(&db, UserId(1));
+
+        user.account(&db, "cgv/1", Err(anyhow::anyhow!("Failed to patch event")))
+            .await;
+        assert_eq!(
+            failures().await.unwrap(),
+            SyncFailures {
+                retrying: 1,
+                given_up: 0
+            }
+        );
+
+        for _ in 1..MAX_SYNC_ATTEMPTS {
+            user.account(&db, "cgv/1", Err(anyhow::anyhow!("Failed to patch event")))
+                .await;
+        }
+        user.account(&db, "cgv/2", Err(anyhow::anyhow!("Failed to insert event")))
+            .await;
+        assert_eq!(
+            failures().await.unwrap(),
+            SyncFailures {
+                retrying: 1,
+                given_up: 1
+            }
+        );
+
+        user.account(&db, "cgv/1", Ok(())).await;
+        user.account(&db, "cgv/2", Ok(())).await;
+        assert_eq!(failures().await.unwrap(), SyncFailures::default());
+    }
+
+    #[tokio::test]
+    async fn only_failed_reservations_are_retried() {
+        let db = crate::testing::db().await;
+        let user = test_user();
+        for id in ["cgv/1", "cgv/2", "cgv/3", "cgv/4"] {
+            sqlx::query(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `invalid`, `updated_at`)
+                VALUES (?, 1, 'title', 'detail', '2024-05-02', FALSE, '2024-05-01 00:00:00')",
+            )
+            .bind(id)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        // cgv/1 failed once, cgv/2 is given up and cgv/3 failed then synced
+        user.account(&db, "cgv/1", Err(anyhow::anyhow!("Failed to patch event")))
+            .await;
+        for _ in 0..MAX_SYNC_ATTEMPTS {
+            user.account(&db, "cgv/2", Err(anyhow::anyhow!("Failed to insert event")))
+                .await;
+        }
+        user.account(&db, "cgv/3", Err(anyhow::anyhow!("Failed to insert event")))
+            .await;
+        user.account(&db, "cgv/3", Ok(())).await;
+
+        let reservations = user.failed_reservations(&db).await.unwrap();
+        assert_eq!(reservations.keys().collect::<Vec<_>>(), ["cgv/1"]);
+        assert_eq!(reservations["cgv/1"].title, "title");
+    }
+
+    #[tokio::test]
+    async fn event_imported_twice_is_created_once() {
+        // stands for google calendar, keeping events by iCalUID
+        let events = Arc::new(Mutex::new(HashMap::<String, String>::new()));
+        let hub = mock_hub(
+            axum::Router::new()
+            .route(
+                "/calendars/:calendar_id/events/import",
+                axum::routing::post(
+                    |Extension(events): Extension<Arc<Mutex<HashMap<String, String>>>>,
+                     axum::Json(mut event): axum::Json<serde_json::Value>| async move {
+                        let uid = event["iCalUID"].as_str().unwrap().to_string();
+                        let mut events = events.lock().await;
+                        let id = format!("event{}", events.len());
+                        event["id"] = events.entry(uid).or_insert(id).clone().into();
+                        axum::Json(event)
+                    },
+                ),
+            )
+            .layer(Extension(events.clone())),
+        );
+        let user = test_user();
+        let event = || Event {
+            summary: Some("movie".to_string()),
+            ..Default::default()
+        };
+
+        let (first, _) = user
+            .import_event(&hub, "main", "cgv/1", event())
+            .await
+            .unwrap();
+        // inserted again as the mapping of the first one was lost
+        let (second, inserted) = user
+            .import_event(&hub, "main", "cgv/1", event())
+            .await
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(inserted.i_cal_uid.as_deref(), Some("cgv/1.1@calendar-hub"));
+        assert_eq!(events.lock().await.len(), 1);
+
+        let (other, _) = user
+            .import_event(&hub, "main", "cgv/2", event())
+            .await
+            .unwrap();
+        assert_ne!(first, other);
+        assert_eq!(events.lock().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn calendar_is_renamed() {
+        type Renamed = Arc<Mutex<Option<(String, String)>>>;
+        let renamed: Renamed = Arc::new(Mutex::new(None));
+        let hub = mock_hub(
+            axum::Router::new()
+                .route(
+                    "/users/me/calendarList/:calendar_id",
+                    axum::routing::get(
+                        |axum::extract::Path(calendar_id): axum::extract::Path<String>| async move {
+                            let access_role = match calendar_id.as_str() {
+                                "main" => "owner",
+                                _ => "writer",
+                            };
+                            axum::Json(serde_json::json!({
+                                "id": calendar_id,
+                                "summary": "Calendar hub",
+                                "accessRole": access_role,
+                            }))
+                        },
+                    ),
+                )
+                .route(
+                    "/calendars/:calendar_id",
+                    axum::routing::patch(
+                        |Extension(renamed): Extension<Renamed>,
+                         axum::extract::Path(calendar_id): axum::extract::Path<String>,
+                         axum::Json(calendar): axum::Json<Calendar>| async move {
+                            *renamed.lock().await =
+                                Some((calendar_id, calendar.summary.clone().unwrap()));
+                            axum::Json(calendar)
+                        },
+                    ),
+                )
+                .layer(Extension(renamed.clone())),
+        );
+        let user = test_user();
+
+        assert_eq!(
+            user.calendar_name_on(&hub).await.unwrap().as_deref(),
+            Some("Calendar hub")
+        );
+        assert!(user.rename_calendar_on(&hub, "예약").await.unwrap());
+        assert_eq!(
+            *renamed.lock().await,
+            Some(("main".to_string(), "예약".to_string()))
+        );
+
+        // only shared to the service account, google answers 403 to a rename
+        *renamed.lock().await = None;
+        let user = GoogleUser {
+            calendar_id: "owned_by_user".to_string(),
+            ..test_user()
+        };
+        assert_eq!(user.calendar_name_on(&hub).await.unwrap(), None);
+        assert!(!user.rename_calendar_on(&hub, "예약").await.unwrap());
+        assert_eq!(*renamed.lock().await, None);
+    }
+
+    #[tokio::test]
+    async fn only_calendars_writable_by_user_are_synced_into() {
+        let hub = mock_hub(axum::Router::new().route(
+            "/calendars/:calendar_id/acl",
+            axum::routing::get(
+                |axum::extract::Path(calendar_id): axum::extract::Path<String>| async move {
+                    let rule = |role: &str, email: &str| {
+                        serde_json::json!({"role": role, "scope": {"type": "user", "value": email}})
+                    };
+                    let items = match calendar_id.as_str() {
+                        "own" => vec![rule("owner", "me@example.com")],
+                        "shared" => vec![
+                            rule("owner", "other@example.com"),
+                            rule("writer", "ME@example.com"),
+                        ],
+                        "read_only" => vec![
+                            rule("owner", "other@example.com"),
+                            rule("reader", "me@example.com"),
+                        ],
+                        // hub calendar of another user
+                        "other" => vec![
+                            rule("owner", "other@example.com"),
+                            rule("writer", "hub@example.iam.gserviceaccount.com"),
+                        ],
+                        _ => {
+                            return (
+                                StatusCode::FORBIDDEN,
+                                axum::Json(serde_json::json!({"error": {
+                                    "code": 403,
+                                    "message": "Forbidden",
+                                    "errors": [{"reason": "forbidden"}],
+                                }})),
+                            )
+                        }
+                    };
+                    (StatusCode::OK, axum::Json(serde_json::json!({ "items": items })))
+                },
+            ),
+        ));
+        let user = test_user();
+        let can_sync_into =
+            |calendar_id| user.can_sync_into_on(&hub, "me@example.com", calendar_id);
+
+        assert!(can_sync_into("main").await.unwrap());
+        assert!(can_sync_into("own").await.unwrap());
+        assert!(can_sync_into("shared").await.unwrap());
+        assert!(!can_sync_into("read_only").await.unwrap());
+        assert!(!can_sync_into("other").await.unwrap());
+        assert!(!can_sync_into("unknown").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn identical_bookings_keep_their_own_events() {
+        let db = crate::testing::db().await;
+        let date = |day| NaiveDate::from_ymd_opt(2024, 6, day).unwrap();
+        for (id, day, invalid, last_seen_at, event_id) in [
+            // not listed by the last crawl
+            ("cgv/old", 1, false, "2024-05-01 00:00:00", Some("old")),
+            ("cgv/new", 1, false, "2024-05-02 00:00:00", None),
+            // both listed
+            ("cgv/first", 2, false, "2024-05-02 00:00:00", Some("first")),
+            ("cgv/second", 2, false, "2024-05-02 00:00:00", None),
+            (
+                "cgv/cancelled",
+                3,
+                true,
+                "2024-05-02 00:00:00",
+                Some("cancelled"),
+            ),
+            ("cgv/reissued", 3, false, "2024-05-02 00:00:00", None),
+        ] {
+            sqlx::query(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `invalid`, `updated_at`, `last_seen_at`)
+                VALUES (?, 1, '영화', '', ?, ?, ?, ?)",
+            )
+            .bind(id)
+            .bind(date(day))
+            .bind(invalid)
+            .bind(last_seen_at)
+            .bind(last_seen_at)
+            .execute(&db)
+            .await
+            .unwrap();
+            if let Some(event_id) = event_id {
+                sqlx::query(
+                    "INSERT INTO `google_event` (`event_id`, `user_id`, `reservation_id`) VALUES (?, 1, ?)",
+                )
+                .bind(event_id)
+                .bind(id)
+                .execute(&db)
+                .await
+                .unwrap();
+            }
+        }
+        let mut reservations = [
+            ("cgv/new", 1, false),
+            ("cgv/second", 2, false),
+            ("cgv/cancelled", 3, true),
+            ("cgv/reissued", 3, false),
+        ]
+        .into_iter()
+        .map(|(id, day, invalid)| {
+            let reservation = CalendarEvent::builder(id, "영화", date(day))
+                .invalid(invalid)
+                .build()
+                .unwrap();
+            (id.to_string(), reservation)
+        })
+        .collect::<HashMap<_, _>>();
+
+        let renamed = test_user()
+            .take_renamed(&db, &mut reservations)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(event_id, _, reservation)| (event_id, reservation.id))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(
+            renamed,
+            BTreeMap::from([
+                ("cancelled".to_string(), "cgv/reissued".to_string()),
+                ("old".to_string(), "cgv/new".to_string()),
+            ])
+        );
+        assert_eq!(
+            reservations.keys().collect::<Vec<_>>(),
+            [&"cgv/second".to_string()]
+        );
+        let old_invalid: bool =
+            sqlx::query_scalar("SELECT `invalid` FROM `reservation` WHERE `id` = 'cgv/old'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert!(old_invalid);
+
+        // synced again, the ones taken already are not matched again
+        let mut reservations = HashMap::from([(
+            "cgv/second".to_string(),
+            CalendarEvent::builder("cgv/second", "영화", date(2))
+                .build()
+                .unwrap(),
+        )]);
+        assert!(test_user()
+            .take_renamed(&db, &mut reservations)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn event_edited_on_google_after_written_is_told() {
+        let db = crate::testing::db().await;
+        for (event_id, written_at) in [
+            ("edited", Some("2024-05-01 00:00:00")),
+            ("kept", Some("2024-05-02 00:00:00")),
+            ("untracked", None),
+        ] {
+            sqlx::query(
+                "INSERT INTO `google_event` (`event_id`, `user_id`, `reservation_id`, `written_at`) VALUES (?, 1, ?, ?)",
+            )
+            .bind(event_id)
+            .bind(format!("cgv/{event_id}"))
+            .bind(written_at)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        // every event is updated on google at the same time
+        let hub =
+            mock_hub(
+                axum::Router::new().route(
+                    "/calendars/:calendar_id/events/:event_id",
+                    axum::routing::get(
+                        |axum::extract::Path((_, event_id)): axum::extract::Path<(
+                            String,
+                            String,
+                        )>| async move {
+                            axum::Json(serde_json::json!({
+                                "id": event_id,
+                                "updated": "2024-05-02T00:00:00.000Z",
+                            }))
+                        },
+                    ),
+                ),
+            );
+        let user = test_user();
+        let edited = |event_id| user.edited_on_google(&hub, &db, "main", event_id);
+
+        assert!(edited("edited").await.unwrap());
+        assert!(!edited("kept").await.unwrap());
+        assert!(!edited("untracked").await.unwrap());
+
+        user.record_written(
+            &db,
+            "edited",
+            &Event {
+                updated: Some(
+                    chrono::DateTime::parse_from_rfc3339("2024-05-02T00:00:00Z")
+                        .unwrap()
+                        .into(),
+                ),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(!edited("edited").await.unwrap());
+    }
+
+    #[test]
+    fn rate_limited_calendar_is_not_revoked() {
+        let error = |reason: &str| {
+            google_calendar3::Error::BadRequest(serde_json::json!({"error": {
+                "code": 403,
+                "message": "Forbidden",
+                "errors": [{"domain": "usageLimits", "reason": reason}],
+            }}))
+        };
+
+        assert!(is_revoked(&error("forbidden")));
+        assert!(is_revoked(&error("notFound")));
+        assert!(!is_revoked(&error("rateLimitExceeded")));
+        assert!(!is_revoked(&error("userRateLimitExceeded")));
+        assert!(!is_revoked(&error("quotaExceeded")));
+        assert!(is_revoked(&google_calendar3::Error::BadRequest(
+            serde_json::json!({"error": {"code": 404, "message": "Not Found"}})
+        )));
+    }
+
+    #[test]
+    fn acl_is_shared_again_when_service_account_changed() {
+        let acl_id = || Some("user:old@example.iam.gserviceaccount.com".to_string());
+
+        assert_eq!(
+            acl_to_reuse(
+                acl_id(),
+                Some("old@example.iam.gserviceaccount.com"),
+                "old@example.iam.gserviceaccount.com"
+            ),
+            acl_id()
+        );
+        // saved before the email is recorded
+        assert_eq!(
+            acl_to_reuse(acl_id(), None, "new@example.iam.gserviceaccount.com"),
+            acl_id()
+        );
+        assert_eq!(
+            acl_to_reuse(
+                acl_id(),
+                Some("old@example.iam.gserviceaccount.com"),
+                "new@example.iam.gserviceaccount.com"
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn events_left_undeleted_are_returned() {
+        let hub =
+            mock_hub(
+                axum::Router::new().route(
+                    "/calendars/:calendar_id/events/:event_id",
+                    axum::routing::delete(
+                        |axum::extract::Path((_, event_id)): axum::extract::Path<(
+                            String,
+                            String,
+                        )>| async move {
+                            match event_id.as_str() {
+                                "deleted" => StatusCode::NO_CONTENT,
+                                "gone" => StatusCode::GONE,
+                                _ => StatusCode::INTERNAL_SERVER_ERROR,
+                            }
+                        },
+                    ),
+                ),
+            );
+        let events = ["deleted", "gone", "failing"]
+            .map(|event_id| ("calendar".to_string(), event_id.to_string()));
+
+        assert_eq!(
+            delete_events_on(&hub, &events).await,
+            [("calendar".to_string(), "failing".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn calendar_is_shared_to_new_service_account() {
+        type Shared = Arc<Mutex<Vec<String>>>;
+        let shared: Shared = Arc::new(Mutex::new(Vec::new()));
+        let hub = mock_hub(
+            axum::Router::new()
+                .route(
+                    "/calendars/:calendar_id/acl/:rule_id",
+                    axum::routing::get(
+                        |Extension(shared): Extension<Shared>,
+                         axum::extract::Path((_, rule_id)): axum::extract::Path<(
+                            String,
+                            String,
+                        )>| async move {
+                            let known = rule_id == "user:old@example.iam.gserviceaccount.com"
+                                || shared
+                                    .lock()
+                                    .await
+                                    .iter()
+                                    .any(|email| rule_id == format!("user:{email}"));
+                            if known {
+                                axum::Json(serde_json::json!({ "id": rule_id })).into_response()
+                            } else {
+                                StatusCode::NOT_FOUND.into_response()
+                            }
+                        },
+                    ),
+                )
+                .route(
+                    "/calendars/:calendar_id/acl",
+                    axum::routing::post(
+                        |Extension(shared): Extension<Shared>,
+                         axum::Json(rule): axum::Json<AclRule>| async move {
+                            let email = rule.scope.as_ref().unwrap().value.clone().unwrap();
+                            shared.lock().await.push(email.clone());
+                            axum::Json(AclRule {
+                                id: Some(format!("user:{email}")),
+                                ..rule
+                            })
+                        },
+                    ),
+                )
+                .layer(Extension(shared.clone())),
+        );
+        let saved = || Some("user:old@example.iam.gserviceaccount.com".to_string());
+        let old = "old@example.iam.gserviceaccount.com";
+        let new = "new@example.iam.gserviceaccount.com";
+
+        // same service account, the saved rule is kept
+        let acl_id = acl_to_reuse(saved(), Some(old), old);
+        assert_eq!(
+            share_calendar(&hub, "main", acl_id, old).await.unwrap(),
+            saved().unwrap()
+        );
+        assert!(shared.lock().await.is_empty());
+
+        let acl_id = acl_to_reuse(saved(), Some(old), new);
+        assert_eq!(
+            share_calendar(&hub, "main", acl_id, new).await.unwrap(),
+            format!("user:{new}")
+        );
+        assert_eq!(*shared.lock().await, vec![new.to_string()]);
+
+        // the saved rule is removed on the calendar
+        let acl_id = share_calendar(&hub, "main", Some("user:gone".to_string()), new)
+            .await
+            .unwrap();
+        assert_eq!(acl_id, format!("user:{new}"));
+        assert_eq!(shared.lock().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn last_sync_is_undone() {
+        type Calls = Arc<Mutex<Vec<String>>>;
+        let calls: Calls = Arc::new(Mutex::new(Vec::new()));
+        let hub = mock_hub(
+            axum::Router::new()
+                .route(
+                    "/calendars/:calendar_id/events/:event_id/move",
+                    axum::routing::post(
+                        |Extension(calls): Extension<Calls>,
+                         axum::extract::Path((calendar_id, event_id)): axum::extract::Path<(String, String)>,
+                         axum::extract::Query(query): axum::extract::Query<HashMap<String, String>>| async move {
+                            calls.lock().await.push(format!(
+                                "move {event_id} {calendar_id} -> {}",
+                                query["destination"]
+                            ));
+                            axum::Json(serde_json::json!({ "id": event_id }))
+                        },
+                    ),
+                )
+                .route(
+                    "/calendars/:calendar_id/events/:event_id",
+                    axum::routing::patch(
+                        |Extension(calls): Extension<Calls>,
+                         axum::extract::Path((calendar_id, event_id)): axum::extract::Path<(String, String)>,
+                         axum::Json(mut event): axum::Json<serde_json::Value>| async move {
+                            if event_id == "gone" {
+                                return (
+                                    StatusCode::GONE,
+                                    axum::Json(serde_json::json!({"error": {"code": 410, "message": "Gone"}})),
+                                );
+                            }
+                            calls.lock().await.push(format!(
+                                "patch {event_id} {calendar_id} {}",
+                                event["summary"].as_str().unwrap()
+                            ));
+                            event["id"] = event_id.into();
+                            (StatusCode::OK, axum::Json(event))
+                        },
+                    )
+                    .delete(
+                        |Extension(calls): Extension<Calls>,
+                         axum::extract::Path((calendar_id, event_id)): axum::extract::Path<(String, String)>| async move {
+                            calls.lock().await.push(format!("delete {event_id} {calendar_id}"));
+                            StatusCode::NO_CONTENT
+                        },
+                    ),
+                )
+                .layer(Extension(calls.clone())),
+        );
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        let event = |summary: &str| Event {
+            summary: Some(summary.to_string()),
+            ..Default::default()
+        };
+        let user = test_user();
+
+        let first = audit::Batch::new(user.user_id);
+        for event_id in ["moved", "gone"] {
+            first
+                .record(
+                    &db,
+                    Action::Insert,
+                    event_id,
+                    "main",
+                    "cgv/1",
+                    Some(&event("old")),
+                )
+                .await;
+        }
+        // the source is synced into another calendar since
+        let last = audit::Batch::new(user.user_id);
+        last.record(
+            &db,
+            Action::Patch,
+            "moved",
+            "other",
+            "cgv/1",
+            Some(&event("new")),
+        )
+        .await;
+        last.record(
+            &db,
+            Action::Patch,
+            "gone",
+            "main",
+            "cgv/2",
+            Some(&event("new")),
+        )
+        .await;
+        last.record(
+            &db,
+            Action::Insert,
+            "inserted",
+            "main",
+            "cgv/3",
+            Some(&event("new")),
+        )
+        .await;
+        for (event_id, reservation_id, calendar_id) in [
+            ("moved", "cgv/1", Some("other")),
+            ("gone", "cgv/2", None),
+            ("inserted", "cgv/3", None),
+        ] {
+            sqlx::query(
+                "INSERT INTO `google_event` (`event_id`, `user_id`, `reservation_id`, `calendar_id`) VALUES (?, 1, ?, ?)",
+            )
+            .bind(event_id)
+            .bind(reservation_id)
+            .bind(calendar_id)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        let entries = audit::last_batch(&db, user.user_id).await.unwrap();
+        assert_eq!(
+            user.undo_on(&hub, &db, entries).await.unwrap(),
+            Undone {
+                reverted: 2,
+                skipped: 1
+            }
+        );
+        assert_eq!(
+            *calls.lock().await,
+            [
+                "delete inserted main",
+                "move moved other -> main",
+                "patch moved main old",
+            ]
+        );
+        let mappings: Vec<(String, Option<String>)> = sqlx::query_as(
+            "SELECT `event_id`, `calendar_id` FROM `google_event` ORDER BY `event_id`",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(
+            mappings,
+            [("gone".to_string(), None), ("moved".to_string(), None)]
+        );
+        // the skipped change is undone too, not to block undoing the sync before
+        let summary = audit::get_last_batch(&db, user.user_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (summary.inserted, summary.patched, summary.deleted),
+            (2, 0, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn events_deleted_in_every_calendar_are_restored() {
+        let hub = mock_hub(axum::Router::new().route(
+            "/calendars/:calendar_id/events",
+            axum::routing::get(
+                |axum::extract::Path(calendar_id): axum::extract::Path<String>| async move {
+                    let event =
+                        |id: &str, status: &str| serde_json::json!({"id": id, "status": status});
+                    let items = match calendar_id.as_str() {
+                        "main" => vec![event("1", "cancelled")],
+                        "movie" => vec![event("2", "cancelled"), event("3", "confirmed")],
+                        _ => vec![],
+                    };
+                    axum::Json(serde_json::json!({ "items": items }))
+                },
+            ),
+        ));
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        for (event_id, reservation_id, calendar_id) in [
+            ("1", "cgv/1", None),
+            ("2", "cgv/2", Some("movie")),
+            ("3", "cgv/3", Some("movie")),
+        ] {
+            sqlx::query(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `invalid`, `updated_at`)
+                VALUES (?, 1, '영화', '', '2024-05-01', FALSE, '2024-04-01 00:00:00')",
+            )
+            .bind(reservation_id)
+            .execute(&db)
+            .await
+            .unwrap();
+            sqlx::query(
+                "INSERT INTO `google_event` (`event_id`, `user_id`, `reservation_id`, `calendar_id`) VALUES (?, 1, ?, ?)",
+            )
+            .bind(event_id)
+            .bind(reservation_id)
+            .bind(calendar_id)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        let user = GoogleUser {
+            last_synced: NaiveDate::from_ymd_opt(2024, 5, 1)
+                .unwrap()
+                .and_time(NaiveTime::MIN),
+            ..test_user()
+        };
+
+        let mut restored = user
+            .take_externally_deleted(&hub, &db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|reservation| reservation.id)
+            .collect::<Vec<_>>();
+        restored.sort();
+        assert_eq!(restored, ["cgv/1", "cgv/2"]);
+        let event_ids: Vec<String> = sqlx::query_scalar("SELECT `event_id` FROM `google_event`")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(event_ids, ["3"]);
+    }
 }
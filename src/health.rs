@@ -0,0 +1,151 @@
+use axum::{
+    response::{IntoResponse as _, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use hyper::StatusCode;
+use log::error;
+use sqlx::SqlitePool;
+
+use crate::google_calendar::Config;
+
+async fn check_database(db: &SqlitePool) -> bool {
+    match sqlx::query("SELECT 1").execute(db).await {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Health check of database failed - {e:?}");
+            false
+        }
+    }
+}
+
+/// `{"status": "ok", "components": {...}}` with 503 when any of them is not ok
+fn status_response(components: &[(&str, bool)]) -> Response {
+    status_response_with(components, serde_json::Map::new())
+}
+
+/// Same as [`status_response`], with `extra` fields in the body
+fn status_response_with(
+    components: &[(&str, bool)],
+    extra: serde_json::Map<String, serde_json::Value>,
+) -> Response {
+    let ok = components.iter().all(|(_, ok)| *ok);
+    let status = |ok: bool| if ok { "ok" } else { "error" };
+    let mut body = serde_json::json!({
+        "status": status(ok),
+        "components": components
+            .iter()
+            .map(|(name, ok)| (name.to_string(), serde_json::json!(status(*ok))))
+            .collect::<serde_json::Map<_, _>>(),
+    });
+    if let Some(body) = body.as_object_mut() {
+        body.extend(extra);
+    }
+
+    if ok {
+        Json(body).into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response()
+    }
+}
+
+/// Alive once the DB is connected, migrations run before serving. Crawls
+/// running now are given in `crawls`.
+async fn healthz(Extension(db): Extension<SqlitePool>) -> Response {
+    let mut extra = serde_json::Map::new();
+    extra.insert(
+        "crawls".to_string(),
+        serde_json::json!(crate::source::crawl_load()),
+    );
+    status_response_with(&[("database", check_database(&db).await)], extra)
+}
+
+/// Ready to accept logins when the google keys to verify id tokens are loaded
+async fn readyz(Extension(db): Extension<SqlitePool>) -> Response {
+    readiness(&db, Config::get().has_google_keys().await).await
+}
+
+async fn readiness(db: &SqlitePool, has_google_keys: bool) -> Response {
+    status_response(&[
+        ("database", check_database(db).await),
+        ("google_keys", has_google_keys),
+    ])
+}
+
+/// Probes for deployment, to be merged outside of the session layer not to
+/// create sessions
+pub fn web_router(db: SqlitePool) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .layer(Extension(db))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::service::Service as _;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn healthz_reports_database() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let mut router = web_router(db.clone());
+        let healthz = || {
+            hyper::Request::get("/healthz")
+                .body(hyper::Body::empty())
+                .unwrap()
+        };
+
+        let response = router.call(healthz()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(hyper::header::SET_COOKIE));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["components"], serde_json::json!({ "database": "ok" }));
+        // crawls of other tests may be running
+        assert!(
+            body["crawls"]["in_flight"].as_u64().unwrap()
+                <= body["crawls"]["max"].as_u64().unwrap()
+        );
+
+        db.close().await;
+        let response = router.call(healthz()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn readyz_waits_for_google_keys() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let body = |response: Response| async move {
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let response = readiness(&db, false).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body(response).await,
+            serde_json::json!({
+                "status": "error",
+                "components": { "database": "ok", "google_keys": "error" },
+            })
+        );
+
+        let response = readiness(&db, true).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            body(response).await,
+            serde_json::json!({
+                "status": "ok",
+                "components": { "database": "ok", "google_keys": "ok" },
+            })
+        );
+
+        db.close().await;
+        let response = readiness(&db, true).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body(response).await["components"]["database"], "error");
+    }
+}
@@ -0,0 +1,237 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Context;
+use log::{error, info};
+use once_cell::sync::Lazy;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.5 Safari/605.1.15";
+const HEADERS_PATH: &str = "headers.json";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_DETAIL_CONCURRENCY: usize = 4;
+
+static CONFIG: Lazy<HttpConfig> = Lazy::new(|| {
+    let user_agent = std::env::var("USER_AGENT").ok();
+    let headers = match std::fs::read_to_string(HEADERS_PATH) {
+        Ok(headers) => Some(headers),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            error!("Failed to read {HEADERS_PATH} - {e:?}");
+            None
+        }
+    };
+
+    HttpConfig::new(user_agent, headers.as_deref()).unwrap_or_else(|e| {
+        error!("Invalid {HEADERS_PATH}, ignored - {e:?}");
+        HttpConfig::new(std::env::var("USER_AGENT").ok(), None).unwrap()
+    })
+});
+
+static TIMEOUT: Lazy<Duration> =
+    Lazy::new(|| parse_timeout(std::env::var("HTTP_TIMEOUT").ok().as_deref()));
+
+/// Seconds to wait for a response, the default is used when not set or invalid
+fn parse_timeout(secs: Option<&str>) -> Duration {
+    match secs.map(str::parse::<u64>) {
+        None => DEFAULT_TIMEOUT,
+        Some(Ok(secs)) if secs > 0 => Duration::from_secs(secs),
+        Some(_) => {
+            error!("Invalid HTTP_TIMEOUT, {DEFAULT_TIMEOUT:?} is used");
+            DEFAULT_TIMEOUT
+        }
+    }
+}
+
+/// Number of details fetched at once
+static DETAIL_CONCURRENCY: Lazy<usize> =
+    Lazy::new(|| count_from_env("DETAIL_CONCURRENCY", DEFAULT_DETAIL_CONCURRENCY));
+
+/// Positive count in the environment variable `name`, e.g. how many things
+/// run at once. `default` is used when not set or invalid.
+pub(crate) fn count_from_env(name: &str, default: usize) -> usize {
+    parse_count(name, std::env::var(name).ok().as_deref(), default)
+}
+
+fn parse_count(name: &str, count: Option<&str>, default: usize) -> usize {
+    match count.map(str::parse::<usize>) {
+        None => default,
+        Some(Ok(count)) if count > 0 => count,
+        Some(_) => {
+            error!("Invalid {name}, {default} is used");
+            default
+        }
+    }
+}
+
+/// Headers sent to the sources, the user agent can be overridden with `USER_AGENT`
+/// and extra headers per source with `headers.json` on working directory.
+/// ex) `{"catch_table": {"Accept-Language": "ko-KR"}}`
+struct HttpConfig {
+    default: HeaderMap,
+    sources: HashMap<String, HeaderMap>,
+}
+
+impl HttpConfig {
+    fn new(user_agent: Option<String>, headers: Option<&str>) -> anyhow::Result<Self> {
+        let user_agent = user_agent.unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
+        let mut default = HeaderMap::new();
+        default.insert(
+            reqwest::header::USER_AGENT,
+            HeaderValue::from_str(&user_agent).context("Invalid user agent")?,
+        );
+
+        let sources = headers
+            .map(serde_json::from_str::<HashMap<String, HashMap<String, String>>>)
+            .transpose()
+            .context("Failed to parse headers")?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(source, headers)| {
+                let headers = headers
+                    .into_iter()
+                    .map(|(name, value)| {
+                        Ok((
+                            HeaderName::try_from(name.as_str())
+                                .with_context(|| format!("Invalid header name for {source}"))?,
+                            HeaderValue::try_from(value.as_str())
+                                .with_context(|| format!("Invalid {name} header for {source}"))?,
+                        ))
+                    })
+                    .collect::<anyhow::Result<HeaderMap>>()?;
+                info!("{} headers are overridden for {source}", headers.len());
+                Ok((source, headers))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { default, sources })
+    }
+
+    fn headers(&self, source: &str) -> HeaderMap {
+        let mut headers = self.default.clone();
+        if let Some(overrides) = self.sources.get(source) {
+            headers.extend(overrides.clone());
+        }
+        headers
+    }
+}
+
+/// Headers to send with requests to the source
+pub(crate) fn headers(source: &str) -> HeaderMap {
+    CONFIG.headers(source)
+}
+
+fn client_builder_with(timeout: Duration) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .connect_timeout(CONNECT_TIMEOUT.min(timeout))
+}
+
+/// Builder of clients to the sources, not to wait for a hung source forever.
+/// The timeout can be changed with `HTTP_TIMEOUT` in seconds.
+pub(crate) fn client_builder() -> reqwest::ClientBuilder {
+    client_builder_with(*TIMEOUT)
+}
+
+pub(crate) fn client() -> reqwest::Client {
+    client_builder()
+        .build()
+        .expect("Failed to build http client")
+}
+
+/// Requests of reservation details sent at once to a source, can be changed
+/// with `DETAIL_CONCURRENCY`
+pub(crate) fn detail_concurrency() -> usize {
+    *DETAIL_CONCURRENCY
+}
+
+/// Sends the request to the source. With the `vcr` feature, it is answered from
+/// the cassette in scope when there is one.
+pub(crate) async fn execute(
+    client: &reqwest::Client,
+    req: reqwest::Request,
+) -> anyhow::Result<reqwest::Response> {
+    #[cfg(any(test, feature = "vcr"))]
+    if let Some(cassette) = crate::vcr::current() {
+        return cassette.execute(client, req).await;
+    }
+
+    Ok(client.execute(req).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_headers_override_defaults() {
+        let config = HttpConfig::new(
+            Some("calendar-hub".to_string()),
+            Some(r#"{"cgv": {"User-Agent": "cgv-agent", "Accept-Language": "ko-KR"}}"#),
+        )
+        .unwrap();
+
+        let headers = config.headers("naver");
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[reqwest::header::USER_AGENT], "calendar-hub");
+
+        let headers = config.headers("cgv");
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[reqwest::header::USER_AGENT], "cgv-agent");
+        assert_eq!(headers[reqwest::header::ACCEPT_LANGUAGE], "ko-KR");
+    }
+
+    #[test]
+    fn default_user_agent_is_used_without_override() {
+        let config = HttpConfig::new(None, None).unwrap();
+        assert_eq!(
+            config.headers("kobus")[reqwest::header::USER_AGENT],
+            DEFAULT_USER_AGENT
+        );
+
+        assert!(HttpConfig::new(None, Some(r#"{"cgv": {"Bad Header": "value"}}"#)).is_err());
+    }
+
+    #[test]
+    fn invalid_timeout_falls_back_to_default() {
+        assert_eq!(parse_timeout(None), DEFAULT_TIMEOUT);
+        assert_eq!(parse_timeout(Some("5")), Duration::from_secs(5));
+        assert_eq!(parse_timeout(Some("0")), DEFAULT_TIMEOUT);
+        assert_eq!(parse_timeout(Some("soon")), DEFAULT_TIMEOUT);
+    }
+
+    #[test]
+    fn invalid_count_falls_back_to_default() {
+        let parse = |count| parse_count("DETAIL_CONCURRENCY", count, 4);
+        assert_eq!(parse(None), 4);
+        assert_eq!(parse(Some("8")), 8);
+        assert_eq!(parse(Some("0")), 4);
+        assert_eq!(parse(Some("-1")), 4);
+        assert_eq!(parse(Some("many")), 4);
+    }
+
+    #[tokio::test]
+    async fn hung_source_times_out() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // accepts but never responds
+        let server = tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let client = client_builder_with(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let started = std::time::Instant::now();
+        let error = client
+            .get(format!("http://{addr}/"))
+            .send()
+            .await
+            .unwrap_err();
+
+        assert!(error.is_timeout(), "{error:?}");
+        assert!(started.elapsed() < Duration::from_secs(2));
+        server.abort();
+    }
+}
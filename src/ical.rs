@@ -0,0 +1,575 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use axum::{async_trait, Router};
+use futures::StreamExt;
+use itertools::Itertools;
+use log::{debug, warn};
+use sqlx::SqlitePool;
+
+use crate::{kst, CalendarEvent, CrawlStats, UserId, UserImpl as _};
+
+/// Title of events without `SUMMARY`
+const NO_TITLE: &str = "(제목 없음)";
+
+/// Standard offsets of the `VTIMEZONE`s of the feed by `TZID`, for the ids
+/// which are not of the tz database
+type FeedZones = HashMap<String, chrono::FixedOffset>;
+
+/// Zone of the tz database by `TZID`, also with a prefix like
+/// `/mozilla.org/20050126_1/Asia/Seoul`
+fn tz_of(tzid: &str) -> Option<chrono_tz::Tz> {
+    tzid.parse().ok().or_else(|| {
+        let mut segments = tzid.rsplit('/');
+        let city = segments.next()?;
+        let area = segments.next()?;
+        format!("{area}/{city}").parse().ok()
+    })
+}
+
+/// UTC of the local time in `TZID`. Floating times are regarded as KST, as
+/// the other sources.
+fn local_to_utc(
+    local: chrono::NaiveDateTime,
+    tzid: Option<&str>,
+    zones: &FeedZones,
+) -> chrono::NaiveDateTime {
+    let Some(tzid) = tzid else {
+        return local - kst();
+    };
+    if let Some(tz) = tz_of(tzid) {
+        // skipped by DST, the same time after the transition
+        return local
+            .and_local_timezone(tz)
+            .latest()
+            .or_else(|| {
+                (local + chrono::Duration::hours(1))
+                    .and_local_timezone(tz)
+                    .earliest()
+            })
+            .map_or(local - kst(), |date_time| date_time.naive_utc());
+    }
+    match zones.get(tzid) {
+        Some(offset) => local - *offset,
+        None => {
+            warn!("Unknown time zone {tzid}, regarded as KST");
+            local - kst()
+        }
+    }
+}
+
+/// A content line of iCalendar, `NAME;PARAM=VALUE:value`
+#[derive(Debug)]
+struct Property {
+    name: String,
+    params: HashMap<String, String>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Self> {
+        // `:` can be in quoted parameter values
+        let mut quoted = false;
+        let colon = line.char_indices().find_map(|(index, c)| {
+            match c {
+                '"' => quoted = !quoted,
+                ':' if !quoted => return Some(index),
+                _ => {}
+            }
+            None
+        })?;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+        let mut head = head.split(';');
+        let name = head.next()?.to_ascii_uppercase();
+        let params = head
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| {
+                (
+                    key.to_ascii_uppercase(),
+                    value.trim_matches('"').to_string(),
+                )
+            })
+            .collect();
+
+        Some(Self {
+            name,
+            params,
+            value: value.to_string(),
+        })
+    }
+
+    fn text(&self) -> String {
+        let mut text = String::with_capacity(self.value.len());
+        let mut chars = self.value.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                text.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n' | 'N') => text.push('\n'),
+                Some(c) => text.push(c),
+                None => {}
+            }
+        }
+        text
+    }
+
+    /// Date and time in UTC
+    fn date_time(
+        &self,
+        zones: &FeedZones,
+    ) -> anyhow::Result<(chrono::NaiveDate, Option<chrono::NaiveTime>)> {
+        self.date_time_of(&self.value, zones)
+    }
+
+    /// `value` of the property, which can have several of them like `EXDATE`
+    fn date_time_of(
+        &self,
+        value: &str,
+        zones: &FeedZones,
+    ) -> anyhow::Result<(chrono::NaiveDate, Option<chrono::NaiveTime>)> {
+        if self.params.get("VALUE").map(String::as_str) == Some("DATE") || value.len() == 8 {
+            let date = chrono::NaiveDate::parse_from_str(value, "%Y%m%d")
+                .with_context(|| format!("Failed to parse date - {value}"))?;
+            return Ok((date, None));
+        }
+
+        if let Some(value) = value.strip_suffix('Z') {
+            let date_time = chrono::NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+                .with_context(|| format!("Failed to parse date time - {value}"))?;
+            return Ok((date_time.date(), Some(date_time.time())));
+        }
+
+        let date_time = chrono::NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+            .with_context(|| format!("Failed to parse date time - {value}"))?;
+        let date_time = local_to_utc(
+            date_time,
+            self.params.get("TZID").map(String::as_str),
+            zones,
+        );
+        Ok((date_time.date(), Some(date_time.time())))
+    }
+
+    /// `EXDATE` line of the recurrence with the times in UTC, as the time zone
+    /// of the feed is not kept
+    fn exdate(&self, zones: &FeedZones) -> anyhow::Result<String> {
+        let mut dates = Vec::new();
+        let mut all_day = false;
+        for value in self.value.split(',') {
+            match self.date_time_of(value, zones)? {
+                (date, Some(time)) => {
+                    dates.push(format!("{}Z", date.and_time(time).format("%Y%m%dT%H%M%S")))
+                }
+                (date, None) => {
+                    all_day = true;
+                    dates.push(date.format("%Y%m%d").to_string());
+                }
+            }
+        }
+        Ok(if all_day {
+            format!("EXDATE;VALUE=DATE:{}", dates.join(","))
+        } else {
+            format!("EXDATE:{}", dates.join(","))
+        })
+    }
+}
+
+/// Standard offsets of the `VTIMEZONE`s in the content lines. Daylight saving
+/// time of the zones not in the tz database is not followed.
+fn feed_zones(properties: &[Property]) -> FeedZones {
+    let mut zones = FeedZones::new();
+    let mut tzid = None;
+    let mut standard = false;
+    for property in properties {
+        match (property.name.as_str(), property.value.as_str()) {
+            ("BEGIN", "VTIMEZONE") => tzid = None,
+            ("TZID", value) => tzid = Some(value.to_string()),
+            ("BEGIN", "STANDARD") => standard = true,
+            ("END", "STANDARD") => standard = false,
+            ("TZOFFSETTO", value) if standard => {
+                let offset = chrono::DateTime::parse_from_str(
+                    &format!("19700101000000{value}"),
+                    "%Y%m%d%H%M%S%z",
+                )
+                .map(|date_time| *date_time.offset());
+                match (&tzid, offset) {
+                    (Some(tzid), Ok(offset)) => {
+                        zones.entry(tzid.clone()).or_insert(offset);
+                    }
+                    (_, Err(e)) => warn!("Invalid TZOFFSETTO {value} - {e}"),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    zones
+}
+
+/// Content lines of iCalendar, folded lines are joined
+fn unfold(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continued), Some(last)) => last.push_str(continued),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+fn to_event(properties: &[Property], zones: &FeedZones) -> anyhow::Result<Option<CalendarEvent>> {
+    let get = |name: &str| properties.iter().find(|property| property.name == name);
+
+    let uid = get("UID").context("UID is missing")?;
+    if get("RECURRENCE-ID").is_some() {
+        debug!(
+            "Modified instance of recurring event is skipped - {}",
+            uid.value
+        );
+        return Ok(None);
+    }
+    let (date_begin, time_begin) = get("DTSTART")
+        .with_context(|| format!("DTSTART is missing - {}", uid.value))?
+        .date_time(zones)?;
+    let (date_end, time_end) = get("DTEND")
+        .map(|end| end.date_time(zones))
+        .transpose()?
+        .map_or((None, None), |(date, time)| (Some(date), time));
+    let status = get("STATUS").map(|status| status.value.to_ascii_uppercase());
+    let mut recurrence = properties
+        .iter()
+        .filter(|property| property.name == "RRULE")
+        .map(|property| format!("RRULE:{}", property.value))
+        .collect_vec();
+    // cancelled occurrences
+    if !recurrence.is_empty() {
+        for exdate in properties
+            .iter()
+            .filter(|property| property.name == "EXDATE")
+        {
+            recurrence.push(exdate.exdate(zones)?);
+        }
+    }
+
+    // SUMMARY is optional
+    let title = get("SUMMARY")
+        .map(Property::text)
+        .filter(|title| !title.trim().is_empty())
+        .unwrap_or_else(|| NO_TITLE.to_string());
+
+    CalendarEvent::builder(
+        format!("{}{}", IcalUser::SOURCE_PREFIX, uid.value),
+        title,
+        date_begin,
+    )
+    .detail(get("DESCRIPTION").map(Property::text).unwrap_or_default())
+    .invalid(status.as_deref() == Some("CANCELLED"))
+    .time_begin(time_begin)
+    .end(date_end, time_end)
+    .location(get("LOCATION").map(Property::text))
+    .url(get("URL").map(|url| url.value.clone()))
+    .recurrence((!recurrence.is_empty()).then_some(recurrence))
+    .tentative(status.as_deref() == Some("TENTATIVE"))
+    .build()
+    .map(Some)
+}
+
+/// `VEVENT`s of iCalendar
+fn parse_events(ics: &str) -> anyhow::Result<Vec<CalendarEvent>> {
+    let lines = unfold(ics)
+        .iter()
+        .filter_map(|line| Property::parse(line))
+        .collect_vec();
+    let zones = feed_zones(&lines);
+    let mut events = Vec::new();
+    let mut properties = None;
+    // depth of components in VEVENT, like VALARM, whose properties are not of the event
+    let mut nested = 0;
+    for property in lines {
+        match (property.name.as_str(), property.value.as_str()) {
+            ("BEGIN", "VEVENT") => properties = Some(Vec::new()),
+            ("END", "VEVENT") => {
+                if let Some(event) = to_event(&properties.take().unwrap_or_default(), &zones)? {
+                    events.push(event);
+                }
+            }
+            ("BEGIN", _) if properties.is_some() => nested += 1,
+            ("END", _) if properties.is_some() => nested -= 1,
+            _ if nested == 0 => {
+                if let Some(properties) = properties.as_mut() {
+                    properties.push(property);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(events)
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+pub struct IcalUserDetail {
+    urls: Vec<String>,
+}
+
+impl crate::user::UserDetail for IcalUserDetail {
+    fn missing_sessions(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+}
+
+impl crate::user::UserDetail for IcalUser {
+    fn missing_sessions(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+}
+
+/// External iCalendar feeds of the user
+pub struct IcalUser {
+    user_id: UserId,
+    urls: Vec<String>,
+}
+
+impl From<IcalUser> for IcalUserDetail {
+    fn from(value: IcalUser) -> Self {
+        Self { urls: value.urls }
+    }
+}
+
+impl From<(UserId, IcalUserDetail)> for IcalUser {
+    fn from((user_id, detail): (UserId, IcalUserDetail)) -> Self {
+        Self {
+            user_id,
+            urls: detail
+                .urls
+                .into_iter()
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty())
+                .collect(),
+        }
+    }
+}
+
+impl IcalUser {
+    pub fn user_id(&self) -> UserId {
+        self.user_id
+    }
+
+    /// URLs are stored joined with new line
+    fn from_row(user_id: UserId, urls: String) -> Self {
+        Self {
+            user_id,
+            urls: urls.lines().map(ToString::to_string).collect(),
+        }
+    }
+
+    pub fn all(db: &SqlitePool) -> impl futures::Stream<Item = anyhow::Result<Self>> + '_ {
+        sqlx::query!("SELECT `user_id` as `user_id: UserId`, `urls` FROM `ical_user`")
+            .fetch(db)
+            .map(|result| {
+                result
+                    .map(|row| Self::from_row(row.user_id, row.urls))
+                    .context("Failed to get ical_user")
+            })
+    }
+
+    async fn fetch_feed(client: &reqwest::Client, url: &str) -> anyhow::Result<String> {
+        // webcal is http(s) actually
+        let url = match url.strip_prefix("webcal://") {
+            Some(url) => format!("https://{url}"),
+            None => url.to_string(),
+        };
+        let req = client
+            .get(&url)
+            .headers(crate::http::headers("ical"))
+            .build()?;
+        crate::http::execute(client, req)
+            .await
+            .and_then(|res| Ok(res.error_for_status()?))
+            .with_context(|| format!("Failed to fetch {url}"))?
+            .text()
+            .await
+            .with_context(|| format!("Failed to read {url}"))
+    }
+}
+
+#[async_trait]
+impl crate::UserImpl for IcalUser {
+    type Detail = IcalUserDetail;
+    const SOURCE: &'static str = "ical";
+    const SOURCE_PREFIX: &'static str = "ical/";
+    const PING_INTERVAL: Option<std::time::Duration> = None;
+
+    fn user_id(&self) -> UserId {
+        self.user_id
+    }
+
+    fn all_users(db: &SqlitePool) -> futures::stream::BoxStream<'_, anyhow::Result<Self>> {
+        Self::all(db).boxed()
+    }
+
+    async fn scrape(&self, _db: &SqlitePool) -> anyhow::Result<Vec<CalendarEvent>> {
+        let client = crate::http::client();
+        let mut events = Vec::new();
+        // all feeds should be fetched not to cancel events of failed feed
+        for url in &self.urls {
+            let ics = Self::fetch_feed(&client, url).await?;
+            events.extend(parse_events(&ics).with_context(|| format!("Failed to parse {url}"))?);
+        }
+        // same event can be in several feeds
+        Ok(events
+            .into_iter()
+            .unique_by(|event| event.id.clone())
+            .collect_vec())
+    }
+
+    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<CrawlStats> {
+        let events = self.scrape(&db).await?;
+
+        self.store_reservations(&db, &events).await
+    }
+
+    async fn from_user_id(db: SqlitePool, user_id: UserId) -> anyhow::Result<Option<Self>> {
+        sqlx::query!(
+            "SELECT `user_id` as `user_id: UserId`, `urls` FROM `ical_user` WHERE `user_id` = ?",
+            user_id
+        )
+        .fetch_optional(&db)
+        .await
+        .map(|row| row.map(|row| Self::from_row(row.user_id, row.urls)))
+        .with_context(|| format!("Failed to get ical_user of {user_id:?}"))
+    }
+
+    async fn delete(db: &mut sqlx::SqliteConnection, user_id: UserId) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM `ical_user` WHERE `user_id` = ?", user_id)
+            .execute(db)
+            .await
+            .context("Failed to delete ical user")
+            .map(|_| ())
+    }
+
+    async fn update_session(&self, db: SqlitePool) -> anyhow::Result<()> {
+        let urls = self.urls.join("\n");
+        sqlx::query!(
+            r#"INSERT INTO `ical_user` (`user_id`, `urls`) VALUES (?, ?)
+            ON CONFLICT (`user_id`)
+            DO UPDATE SET `urls`=`excluded`.`urls` WHERE `user_id` = `excluded`.`user_id`"#,
+            self.user_id,
+            urls
+        )
+        .execute(&db)
+        .await
+        .context("Failed to update ical user feeds")
+        .map(|_| ())
+    }
+
+    /// Whether all feeds are reachable
+    async fn validate_session(&self) -> anyhow::Result<bool> {
+        let client = crate::http::client();
+        for url in &self.urls {
+            if let Err(e) = Self::fetch_feed(&client, url).await {
+                debug!("Feed is not reachable - {e:?}");
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+pub fn web_router() -> Router {
+    crate::user_web_router::<IcalUser>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ics_fixture() {
+        let events = parse_events(include_str!("../tests/fixtures/ical.ics")).unwrap();
+        let date = |year, month, day| chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap();
+
+        assert_eq!(events.len(), 4);
+
+        let flight = &events[0];
+        assert_eq!(flight.id, "ical/flight-ke123@example.com");
+        assert_eq!(flight.title, "KE123 ICN-NRT");
+        assert_eq!(flight.detail, "Seat 32A\nGate 12, Terminal 2");
+        assert_eq!(flight.date_begin, date(2024, 9, 1));
+        assert_eq!(flight.time_begin, chrono::NaiveTime::from_hms_opt(0, 30, 0));
+        assert_eq!(flight.date_end, Some(date(2024, 9, 1)));
+        assert_eq!(flight.time_end, chrono::NaiveTime::from_hms_opt(2, 45, 0));
+        assert_eq!(
+            flight.location.as_deref(),
+            Some("Incheon International Airport")
+        );
+        assert!(!flight.invalid && !flight.tentative);
+
+        let hotel = &events[1];
+        assert_eq!(hotel.date_begin, date(2024, 9, 1));
+        assert_eq!(hotel.time_begin, None);
+        assert_eq!(hotel.date_end, Some(date(2024, 9, 3)));
+        assert_eq!(hotel.time_end, None);
+        assert_eq!(
+            hotel.url.as_deref(),
+            Some("https://hotel.example.com/booking/1")
+        );
+
+        let lecture = &events[2];
+        assert_eq!(lecture.date_begin, date(2024, 9, 2));
+        assert_eq!(lecture.time_begin, chrono::NaiveTime::from_hms_opt(0, 0, 0));
+        assert_eq!(
+            lecture.recurrence,
+            Some(vec!["RRULE:FREQ=WEEKLY;COUNT=15".to_string()])
+        );
+        assert!(lecture.tentative);
+
+        let cancelled = &events[3];
+        assert_eq!(cancelled.id, "ical/cancelled@example.com");
+        assert!(cancelled.invalid);
+    }
+
+    #[test]
+    fn times_are_converted_from_their_zones() {
+        let events = parse_events(include_str!("../tests/fixtures/ical_time_zones.ics")).unwrap();
+        let date = |year, month, day| chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        let time = |hour, min| chrono::NaiveTime::from_hms_opt(hour, min, 0);
+
+        assert_eq!(events.len(), 4);
+
+        // CEST
+        let concert = &events[0];
+        assert_eq!(concert.date_begin, date(2024, 7, 15));
+        assert_eq!(concert.time_begin, time(18, 0));
+        assert_eq!(concert.time_end, time(20, 30));
+
+        // VTIMEZONE of the feed
+        let meeting = &events[1];
+        assert_eq!(meeting.date_begin, date(2024, 1, 10));
+        assert_eq!(meeting.time_begin, time(14, 0));
+
+        // EDT, with the prefix of the zone
+        let class = &events[2];
+        assert_eq!(class.date_begin, date(2024, 7, 2));
+        assert_eq!(class.time_begin, time(22, 0));
+        assert_eq!(
+            class.recurrence,
+            Some(vec![
+                "RRULE:FREQ=WEEKLY;COUNT=6".to_string(),
+                "EXDATE:20240709T220000Z,20240716T220000Z".to_string(),
+            ])
+        );
+
+        let holiday = &events[3];
+        assert_eq!(
+            holiday.recurrence,
+            Some(vec![
+                "RRULE:FREQ=YEARLY".to_string(),
+                "EXDATE;VALUE=DATE:20250801".to_string(),
+            ])
+        );
+    }
+}
@@ -0,0 +1,54 @@
+import React from "react";
+import { ActionFunctionArgs, Form, Navigate, useActionData, useLoaderData } from "react-router-dom";
+import { AsyncReturnType, formValue, postJson, request as sendRequest } from './utils';
+import { RemoveSource } from './remove_source';
+import { preview, PreviewButton, PreviewEvents, PreviewResult } from './preview';
+
+export async function loader() {
+    const resp = await fetch("/ical/user", {
+        credentials: "same-origin",
+    });
+
+    if (resp.ok) {
+        const parsed = await resp.json();
+        return {
+            urls: parsed.urls as string[],
+        }
+    } else {
+        return null;
+    }
+}
+
+export async function action({ request }: ActionFunctionArgs) {
+    const formData = await request.formData();
+    if (formData.get("intent") === "remove") {
+        return await sendRequest("/ical/user", { method: "delete" });
+    }
+    const urls = formValue(formData, "urls").split("\n").map((url) => url.trim()).filter((url) => url !== "");
+    const body = JSON.stringify({ urls });
+    if (formData.get("intent") === "preview") {
+        return await preview("/ical/user/preview", body);
+    }
+    return await postJson("/ical/user", body);
+}
+
+export function Component() {
+    const data = useLoaderData() as AsyncReturnType<typeof loader>;
+    const result = useActionData() as PreviewResult | undefined;
+
+    if (data !== null) {
+        return <div>
+            <Form method="post" action="/ical">
+                <label htmlFor="urls">Feed URLs (one per line)</label>
+                <textarea name="urls" rows={5} placeholder="https://example.com/calendar.ics" defaultValue={data.urls.join("\n")} />
+                {result?.error && <p role="alert">{result.error}</p>}
+                <button type="submit">Update</button>
+                <PreviewButton />
+            </Form>
+            <PreviewEvents result={result} />
+            <RemoveSource action="/ical" name="iCal" />
+        </div>;
+    } else {
+        return <Navigate to="/" />;
+    }
+}
@@ -0,0 +1,29 @@
+import React, { useEffect, useState } from "react";
+import { Form, useNavigation } from "react-router-dom";
+
+// Submits `intent=history` to the action of the source page, shown until the
+// history of the source is imported
+export function ImportHistory({ action }: { action: string }) {
+    const [imported, setImported] = useState<boolean | undefined>(undefined);
+    const navigation = useNavigation();
+
+    useEffect(() => {
+        if (navigation.state !== "idle") {
+            return;
+        }
+        fetch(`${action}/user/history`, { credentials: "same-origin" })
+            .then((resp) => resp.ok ? resp.json() : null)
+            .then((parsed) => setImported(parsed !== null && parsed.imported_at !== null))
+            .catch(() => setImported(undefined));
+    }, [action, navigation.state]);
+
+    if (imported !== false) {
+        return null;
+    }
+    return <Form method="post" action={action}>
+        <input type="hidden" name="intent" value="history" />
+        <button type="submit" className="secondary" disabled={navigation.state !== "idle"}>
+            Import history of 180 days
+        </button>
+    </Form>;
+}
@@ -1,17 +1,22 @@
-import React, { useCallback } from "react";
+import React, { useCallback, useEffect, useState } from "react";
 import { createRoot } from "react-dom/client";
 import {
+  ActionFunctionArgs,
   createRoutesFromElements,
   Outlet,
   Route,
   RouterProvider,
+  useActionData,
   useRouteLoaderData,
 } from "react-router";
 import { createBrowserRouter, Form, NavLink } from "react-router-dom";
 import "@picocss/pico/css/pico.classless.min.css";
-import { AsyncReturnType } from "./utils";
+import { ActionResult, AsyncReturnType, request } from "./utils";
 
 function Layout() {
+  // result of sync
+  const result = useActionData() as ActionResult | undefined;
+  const user = useRouteLoaderData("user") as AsyncReturnType<typeof getUser>;
   return (
     <>
       <nav>
@@ -38,18 +43,90 @@ function Layout() {
           <li>
             <NavLink to="/megabox">MEGABOX</NavLink>
           </li>
+          <li>
+            <NavLink to="/lotte-cinema">LOTTE CINEMA</NavLink>
+          </li>
           <li>
             <NavLink to="/bustago">Bustago</NavLink>
           </li>
+          <li>
+            <NavLink to="/ical">iCal</NavLink>
+          </li>
+          <li>
+            <NavLink to="/reservations">Reservations</NavLink>
+          </li>
+          <li>
+            <NavLink to="/setting">Setting</NavLink>
+          </li>
+          {user?.admin && (
+            <li>
+              <NavLink to="/admin">Admin</NavLink>
+            </li>
+          )}
         </ul>
       </nav>
       <div>
+        {result?.error && <p role="alert">Sync failed - {result.error}</p>}
         <Outlet />
       </div>
     </>
   );
 }
 
+const SOURCES = [
+  { path: "naver", name: "Naver" },
+  { path: "kobus", name: "kobus" },
+  { path: "catch-table", name: "catch table" },
+  { path: "cgv", name: "cgv" },
+  { path: "megabox", name: "MEGABOX" },
+  { path: "lotte-cinema", name: "LOTTE CINEMA" },
+  { path: "bustago", name: "Bustago" },
+  { path: "ical", name: "iCal" },
+];
+
+function SessionStatus({ path, name }: { path: string; name: string }) {
+  // undefined: checking, null: not registered
+  const [valid, setValid] = useState<
+    boolean | "needs_setup" | null | undefined
+  >(undefined);
+  useEffect(() => {
+    fetch(`/${path}/user/session`, {
+      credentials: "same-origin",
+    })
+      .then(async (resp) => {
+        if (resp.ok) {
+          const parsed = await resp.json();
+          setValid(
+            parsed.needs_setup ? "needs_setup" : (parsed.valid as boolean)
+          );
+        } else if (resp.status === 404) {
+          setValid(null);
+        } else {
+          setValid(false);
+        }
+      })
+      .catch(() => setValid(false));
+  }, [path]);
+
+  const [color, status] =
+    valid === undefined
+      ? ["gray", "checking"]
+      : valid === null
+      ? ["gray", "not registered"]
+      : valid === "needs_setup"
+      ? ["orange", "needs setup"]
+      : valid
+      ? ["green", "valid"]
+      : ["red", "expired"];
+
+  return (
+    <li>
+      <span style={{ color }}>●</span>{" "}
+      <NavLink to={`/${path}`}>{name}</NavLink> - {status}
+    </li>
+  );
+}
+
 function Index() {
   const logged_in = useRouteLoaderData("user") as AsyncReturnType<
     typeof getUser
@@ -57,14 +134,68 @@ function Index() {
   if (logged_in !== null) {
     return (
       <>
+        {logged_in.reshare_required && (
+          <p role="alert">
+            Service account is changed. Login again to share the calendar.
+          </p>
+        )}
+        {logged_in.calendar_revoked && (
+          <p role="alert">
+            Calendar is deleted or not shared anymore.{" "}
+            <a href="/login">Reconnect your Google Calendar</a>.
+          </p>
+        )}
         <Form method="post" action="/">
           <button className="primary" type="submit">
-            sync (last: {logged_in.last_synced.toLocaleString()})
+            sync (last: {logged_in.last_synced.toLocaleString()},{" "}
+            {logged_in.pending} pending)
           </button>
         </Form>
+        {logged_in.sync_failures.retrying > 0 && (
+          <Form method="post" action="/">
+            <input type="hidden" name="intent" value="retry" />
+            <button type="submit">
+              retry {logged_in.sync_failures.retrying} failed events
+            </button>
+          </Form>
+        )}
+        {logged_in.sync_failures.given_up > 0 && (
+          <p role="alert">
+            {logged_in.sync_failures.given_up} events kept failing to sync and
+            are retried when they are updated.
+          </p>
+        )}
+        {logged_in.last_sync && (
+          <Form
+            method="post"
+            action="/"
+            onSubmit={(e) => {
+              if (!confirm("Revert the changes of the last sync?")) {
+                e.preventDefault();
+              }
+            }}
+          >
+            <input type="hidden" name="intent" value="undo" />
+            <p>
+              Last sync at {logged_in.last_sync.synced_at.toLocaleString()}{" "}
+              created {logged_in.last_sync.inserted}, updated{" "}
+              {logged_in.last_sync.patched} and deleted{" "}
+              {logged_in.last_sync.deleted} events.
+            </p>
+            <button type="submit">undo last sync</button>
+          </Form>
+        )}
         <a href="/logout">
           <button>logout</button>
         </a>
+        <a href="/api/reservations.csv" download>
+          <button>export CSV</button>
+        </a>
+        <ul>
+          {SOURCES.map((source) => (
+            <SessionStatus key={source.path} {...source} />
+          ))}
+        </ul>
       </>
     );
   } else {
@@ -76,6 +207,31 @@ function Index() {
   }
 }
 
+function PermissionsRequired() {
+  return (
+    <>
+      <p role="alert">
+        Calendar Hub can't sync without all the requested permissions of
+        Google Calendar. Check every permission on the consent screen.
+      </p>
+      <a href="/login">
+        <button>login again</button>
+      </a>
+    </>
+  );
+}
+
+async function syncAction({ request: req }: ActionFunctionArgs) {
+  const formData = await req.formData();
+  if (formData.get("intent") === "retry") {
+    return request("/sync/failed", { method: "POST" });
+  }
+  if (formData.get("intent") === "undo") {
+    return request("/sync/undo", { method: "POST" });
+  }
+  return request("/sync", { method: "POST" });
+}
+
 async function getUser() {
   const resp = await fetch("/user", {
     credentials: "same-origin",
@@ -89,6 +245,22 @@ async function getUser() {
       case "User":
         return {
           last_synced: new Date(parsed.last_synced),
+          pending: parsed.pending as number,
+          reshare_required: parsed.reshare_required as boolean,
+          calendar_revoked: parsed.calendar_revoked as boolean,
+          sync_failures: parsed.sync_failures as {
+            retrying: number;
+            given_up: number;
+          },
+          last_sync: parsed.last_sync
+            ? {
+                synced_at: new Date(parsed.last_sync.synced_at),
+                inserted: parsed.last_sync.inserted as number,
+                patched: parsed.last_sync.patched as number,
+                deleted: parsed.last_sync.deleted as number,
+              }
+            : null,
+          admin: parsed.admin as boolean,
         };
       default:
         return null;
@@ -105,23 +277,25 @@ const router = createBrowserRouter(
         path="/"
         id="user"
         loader={getUser}
-        action={async () => {
-          const resp = await fetch("/sync", {
-            method: "POST",
-            credentials: "same-origin",
-          });
-
-          return await resp.json();
-        }}
+        action={syncAction}
         element={<Layout />}
       >
         <Route path="" element={<Index />} />
+        <Route
+          path="permissions-required"
+          element={<PermissionsRequired />}
+        />
         <Route path="naver" lazy={() => import("./naver_reservation")} />
         <Route path="kobus" lazy={() => import("./kobus")} />
         <Route path="catch-table" lazy={() => import("./catch_table")} />
         <Route path="cgv" lazy={() => import("./cgv")} />
         <Route path="megabox" lazy={() => import("./megabox")} />
+        <Route path="lotte-cinema" lazy={() => import("./lotte_cinema")} />
         <Route path="bustago" lazy={() => import("./bustago")} />
+        <Route path="ical" lazy={() => import("./ical")} />
+        <Route path="reservations" lazy={() => import("./reservations")} />
+        <Route path="setting" lazy={() => import("./setting")} />
+        <Route path="admin" lazy={() => import("./admin")} />
       </Route>
     </>
   )
@@ -14,12 +14,20 @@ use chrono::LocalResult;
 use futures::StreamExt;
 use hyper::StatusCode;
 use itertools::Itertools;
-use log::info;
+use log::warn;
 use reqwest::cookie::CookieStore;
 use scraper::{ElementRef, Html};
 use sqlx::SqlitePool;
 
-use crate::{regex, selector, url, CalendarEvent, UserId};
+use crate::{
+    kst, regex,
+    reservation::{linked_data_events, LinkedDataEvent},
+    selector,
+    title::Title,
+    url,
+    user::SessionExpired,
+    CalendarEvent, CrawlStats, UserId, UserImpl as _,
+};
 
 fn parse_ticket(element: ElementRef<'_>, canceled: bool) -> anyhow::Result<CalendarEvent> {
     use chrono::TimeZone;
@@ -33,16 +41,19 @@ fn parse_ticket(element: ElementRef<'_>, canceled: bool) -> anyhow::Result<Calen
     let date_matched = regex!(r#"^(\d+)\.\s*(\d+)\.\s*(\d+)[^\d]+(\d+):(\d+)"#)
         .captures(&date)
         .ok_or_else(|| anyhow::anyhow!("Failed to parse date - {}", date))?;
-    let LocalResult::Single(date_time) =
-        unsafe { chrono::FixedOffset::east_opt(9 * 60 * 60).unwrap_unchecked() }.with_ymd_and_hms(
-            date_matched.get(1).unwrap().as_str().parse().unwrap(),
-            date_matched.get(2).unwrap().as_str().parse().unwrap(),
-            date_matched.get(3).unwrap().as_str().parse().unwrap(),
-            date_matched.get(4).unwrap().as_str().parse().unwrap(),
-            date_matched.get(5).unwrap().as_str().parse().unwrap(),
-            0,
-        )
-    else {
+    let number = |i: usize| -> anyhow::Result<u32> {
+        date_matched[i]
+            .parse()
+            .with_context(|| format!("Invalid number in date - {date}"))
+    };
+    let LocalResult::Single(date_time) = kst().with_ymd_and_hms(
+        number(1)? as i32,
+        number(2)?,
+        number(3)?,
+        number(4)?,
+        number(5)?,
+        0,
+    ) else {
         return Err(anyhow::anyhow!(
             "Ambiguous or invalid date - {:?}",
             date_matched
@@ -63,28 +74,15 @@ fn parse_ticket(element: ElementRef<'_>, canceled: bool) -> anyhow::Result<Calen
         .text()
         .join("");
     let arrive = arrive.trim();
-    let detail_info = element
+    // end is left to the default duration of the source setting when the
+    // duration is missing, rather than losing the ticket
+    let duration = element
         .select(selector!(".detail_info"))
         .next()
-        .ok_or_else(|| anyhow::anyhow!("Failed to find detail_info from ticket"))?
-        .text()
-        .join("");
-    let detail_info = detail_info.trim();
-    let detail_info = regex!(r#"^((\d+)시간)?\s*((\d+)분)?\s*소요$"#)
-        .captures(&detail_info)
-        .ok_or_else(|| anyhow::anyhow!("Failed to parse duration - {:?}", detail_info))?;
-    let duration = chrono::Duration::minutes(
-        (detail_info
-            .get(2)
-            .map(|i| i.as_str().parse().unwrap())
-            .unwrap_or(0)
-            * 60)
-            + detail_info
-                .get(4)
-                .map(|i| i.as_str().parse().unwrap())
-                .unwrap_or(0),
-    );
-    let end_date_time = begin_date_time + duration;
+        .ok_or_else(|| anyhow::anyhow!("Failed to find detail_info from ticket"))
+        .and_then(|detail_info| parse_duration(detail_info.text().join("").trim()))
+        .map_err(|e| warn!("End of the ticket is unknown - {e:?}"))
+        .ok();
     let reservation_number = element
         .select(selector!(".tbl_info tr:first-child td"))
         .next()
@@ -93,18 +91,98 @@ fn parse_ticket(element: ElementRef<'_>, canceled: bool) -> anyhow::Result<Calen
         .join("");
     let reservation_number = reservation_number.trim();
 
-    Ok(CalendarEvent {
-        id: format!("kobus/{reservation_number}"),
-        title: format!("{departure}발 {arrive}행 고속버스"),
-        detail: "".to_string(),
-        invalid: canceled,
-        date_begin: begin_date_time.date(),
-        time_begin: Some(begin_date_time.time()),
-        date_end: Some(end_date_time.date()),
-        time_end: Some(end_date_time.time()),
-        location: None,
-        url: None,
-    })
+    let end_date_time = duration.map(|duration| begin_date_time + duration);
+
+    CalendarEvent::builder_of(
+        format!("{}{reservation_number}", KobusUser::SOURCE_PREFIX),
+        Title::Bus {
+            express: true,
+            departure: departure.to_string(),
+            arrival: arrive.to_string(),
+        },
+        begin_date_time.date(),
+    )
+    .invalid(canceled)
+    .time_begin(begin_date_time.time())
+    .end(
+        end_date_time.map(|end| end.date()),
+        end_date_time.map(|end| end.time()),
+    )
+    .build()
+}
+
+/// Duration of the trip like `2시간 30분 소요`
+fn parse_duration(detail_info: &str) -> anyhow::Result<chrono::Duration> {
+    let matched = regex!(r#"^((\d+)시간)?\s*((\d+)분)?\s*소요$"#)
+        .captures(detail_info)
+        .ok_or_else(|| anyhow::anyhow!("Failed to parse duration - {detail_info:?}"))?;
+    let minutes = |i: usize| -> anyhow::Result<i64> {
+        matched
+            .get(i)
+            .map(|matched| matched.as_str().parse())
+            .transpose()
+            .with_context(|| format!("Invalid number in duration - {detail_info:?}"))
+            .map(Option::unwrap_or_default)
+    };
+
+    Ok(chrono::Duration::minutes(minutes(2)? * 60 + minutes(4)?))
+}
+
+/// Trip of a JSON-LD bus reservation, when the markup of tickets is changed
+fn parse_linked_data_ticket(event: LinkedDataEvent) -> anyhow::Result<CalendarEvent> {
+    let reservation_number = event
+        .reservation_number
+        .ok_or_else(|| anyhow::anyhow!("Failed to find reservation_number from JSON-LD"))?;
+    let begin_date_time = event
+        .start
+        .ok_or_else(|| anyhow::anyhow!("Failed to find departure time from JSON-LD"))?;
+    let id = format!("{}{reservation_number}", KobusUser::SOURCE_PREFIX);
+    let date_begin = begin_date_time.date();
+    let builder = match (event.departure, event.arrival) {
+        (Some(departure), Some(arrival)) => CalendarEvent::builder_of(
+            id,
+            Title::Bus {
+                express: true,
+                departure,
+                arrival,
+            },
+            date_begin,
+        ),
+        _ => CalendarEvent::builder(
+            id,
+            event
+                .name
+                .ok_or_else(|| anyhow::anyhow!("Failed to find trip from JSON-LD"))?,
+            date_begin,
+        ),
+    };
+
+    builder
+        .invalid(event.cancelled)
+        .time_begin(begin_date_time.time())
+        .end(
+            event.end.map(|end| end.date()),
+            event.end.map(|end| end.time()),
+        )
+        .build()
+}
+
+fn parse_tickets(html: &str) -> anyhow::Result<Vec<CalendarEvent>> {
+    let fragment = Html::parse_fragment(html);
+
+    let events = fragment
+        .select(selector!("section.newMobileTicket"))
+        .map(|ticket| parse_ticket(ticket, false))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !events.is_empty() {
+        return Ok(events);
+    }
+
+    linked_data_events(&fragment)
+        .into_iter()
+        .filter(|event| event.reservation_number.is_some())
+        .map(parse_linked_data_ticket)
+        .collect()
 }
 
 crate::define_user_data! {
@@ -123,26 +201,37 @@ impl KobusUser {
             "SELECT `user_id` as `user_id: UserId`, `jsessionid` FROM `kobus_user`"
         )
         .fetch(db)
-        .map(|result| result.context("Failed to get naver_user"))
+        .map(|result| result.context("Failed to get kobus_user"))
     }
 }
 
 #[async_trait]
 impl crate::UserImpl for KobusUser {
     type Detail = KobusUserDetail;
+    const SOURCE: &'static str = "kobus";
+    const SOURCE_PREFIX: &'static str = "kobus/";
     const PING_INTERVAL: Option<std::time::Duration> =
         Some(std::time::Duration::from_secs(29 * 60));
 
-    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<bool> {
+    fn user_id(&self) -> UserId {
+        self.user_id
+    }
+
+    fn all_users(db: &SqlitePool) -> futures::stream::BoxStream<'_, anyhow::Result<Self>> {
+        Self::all(db).boxed()
+    }
+
+    async fn scrape(&self, _db: &SqlitePool) -> anyhow::Result<Vec<CalendarEvent>> {
         let jar = self.to_cookie_jar();
         let planned_url = url!("https://kobus.co.kr/mrs/mrscfm.do");
-        let client = reqwest::Client::new();
+        let client = crate::http::client();
         let req = client
             .post(planned_url.as_ref())
+            .headers(crate::http::headers("kobus"))
             .header(reqwest::header::COOKIE, jar.cookies(planned_url).unwrap())
             .build()?;
 
-        let res = client.execute(req).await?;
+        let res = crate::http::execute(&client, req).await?;
 
         if res.status() != StatusCode::OK {
             return Err(anyhow::anyhow!(
@@ -152,44 +241,34 @@ impl crate::UserImpl for KobusUser {
         let res = res.bytes().await?;
 
         let html = std::str::from_utf8(&res)?;
-        let events = {
-            let fragment = Html::parse_fragment(html);
-
-            fragment
-                .select(selector!("section.newMobileTicket"))
-                .into_iter()
-                .map(|ticket| parse_ticket(ticket, false))
-                .collect::<Result<Vec<_>, _>>()?
-        };
-
-        let updated_item_count = if events.is_empty() {
-            0
-        } else {
-            CalendarEvent::upsert_events_to_db(self.user_id, &db, events.iter()).await?
-        };
-        let updated_item_count = updated_item_count
-            + CalendarEvent::cancel_not_expired_and_not_in(
-                self.user_id,
-                &db,
-                "kobus_",
-                events.iter().map(|event| event.id.as_str()),
-            )
-            .await?;
-        info!("updated item count: {updated_item_count}",);
-
-        Ok(true)
+        parse_tickets(html)
+    }
+
+    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<CrawlStats> {
+        let events = self.scrape(&db).await?;
+
+        self.store_reservations(&db, &events).await
     }
 
     async fn from_user_id(db: SqlitePool, user_id: UserId) -> anyhow::Result<Option<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT `user_id` as `user_id: UserId`, `jsessionid` FROM `kobus_user`"
+            "SELECT `user_id` as `user_id: UserId`, `jsessionid` FROM `kobus_user` WHERE `user_id` = ?",
+            user_id
         )
         .fetch_optional(&db)
         .await
         .with_context(|| format!("Failed to get kobus_user of {user_id:?}"))
     }
 
+    async fn delete(db: &mut sqlx::SqliteConnection, user_id: UserId) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM `kobus_user` WHERE `user_id` = ?", user_id)
+            .execute(db)
+            .await
+            .context("Failed to delete kobus user")
+            .map(|_| ())
+    }
+
     async fn update_session(&self, db: SqlitePool) -> anyhow::Result<()> {
         sqlx::query!(
             r#"INSERT INTO `kobus_user` (`user_id`, `jsessionid`) VALUES (?, ?)
@@ -207,18 +286,20 @@ impl crate::UserImpl for KobusUser {
     async fn ping(&self) -> anyhow::Result<()> {
         let jar = self.to_cookie_jar();
         let planned_url = url!("https://kobus.co.kr/mrs/mrscfm.do");
-        let client = reqwest::Client::new();
+        let client = crate::http::client();
         let req = client
             .post(planned_url.as_ref())
+            .headers(crate::http::headers("kobus"))
             .header(reqwest::header::COOKIE, jar.cookies(planned_url).unwrap())
             .build()?;
 
-        let res = client.execute(req).await?;
+        let res = crate::http::execute(&client, req).await?;
 
+        if res.status().is_client_error() {
+            return Err(SessionExpired(format!("Session is expired - {}", res.status())).into());
+        }
         if res.status() != StatusCode::OK {
-            return Err(anyhow::anyhow!(
-                "Failed to fetch data. Session could be expired"
-            ));
+            anyhow::bail!("Failed to ping - {}", res.status());
         }
 
         Ok(())
@@ -228,3 +309,58 @@ impl crate::UserImpl for KobusUser {
 pub fn web_router() -> Router {
     crate::user_web_router::<KobusUser>()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ticket_fixture() {
+        let events = parse_tickets(include_str!("../tests/fixtures/kobus.html")).unwrap();
+
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.id, "kobus/1234-5678-9012");
+        assert_eq!(event.title, "서울경부발 부산행 고속버스");
+        assert!(!event.invalid);
+        assert_eq!(
+            event.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 4, 30).unwrap()
+        );
+        assert_eq!(event.time_begin, chrono::NaiveTime::from_hms_opt(22, 30, 0));
+        assert_eq!(event.date_end, chrono::NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert_eq!(event.time_end, chrono::NaiveTime::from_hms_opt(2, 45, 0));
+    }
+
+    #[test]
+    fn ticket_without_duration_has_no_end() {
+        let html = include_str!("../tests/fixtures/kobus.html")
+            .replace(r#"<p class="detail_info">4시간 15분 소요</p>"#, "");
+        let events = parse_tickets(&html).unwrap();
+
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.id, "kobus/1234-5678-9012");
+        assert_eq!(event.time_begin, chrono::NaiveTime::from_hms_opt(22, 30, 0));
+        assert_eq!(event.date_end, None);
+        assert_eq!(event.time_end, None);
+    }
+
+    #[test]
+    fn tickets_fall_back_to_linked_data() {
+        let events = parse_tickets(include_str!("../tests/fixtures/kobus_ld.html")).unwrap();
+
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.id, "kobus/1234-5678-9012");
+        assert_eq!(event.title, "서울경부발 부산행 고속버스");
+        assert!(!event.invalid);
+        assert_eq!(
+            event.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 4, 30).unwrap()
+        );
+        assert_eq!(event.time_begin, chrono::NaiveTime::from_hms_opt(22, 30, 0));
+        assert_eq!(event.date_end, chrono::NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert_eq!(event.time_end, chrono::NaiveTime::from_hms_opt(2, 45, 0));
+    }
+}
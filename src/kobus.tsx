@@ -1,6 +1,9 @@
 import React from "react";
-import { ActionFunctionArgs, Form, Navigate, Params, useLoaderData } from "react-router-dom";
-import { AsyncReturnType, formDataToJsonString } from './utils';
+import { ActionFunctionArgs, Form, Navigate, Params, useActionData, useLoaderData } from "react-router-dom";
+import { AsyncReturnType, formDataToJsonString, postJson, request as sendRequest } from './utils';
+import { RemoveSource } from './remove_source';
+import { preview, PreviewButton, PreviewEvents, PreviewResult } from './preview';
+import { SessionInput } from './session_input';
 
 export async function loader() {
     const resp = await fetch("/kobus/user", {
@@ -10,7 +13,8 @@ export async function loader() {
     if (resp.ok) {
         const parsed = await resp.json();
         return {
-            jsessionid: parsed.jsessionid as string,
+            saved: parsed.saved as string[],
+            required: parsed.required as string[],
         }
     } else {
         return null;
@@ -19,25 +23,29 @@ export async function loader() {
 
 export async function action({ request }: ActionFunctionArgs) {
     const formData = await request.formData();
-    return await fetch("/kobus/user", {
-        headers: {
-            'Content-Type': 'application/json'
-        },
-        method: "POST",
-        body: formDataToJsonString(formData)
-    });
+    if (formData.get("intent") === "remove") {
+        return await sendRequest("/kobus/user", { method: "delete" });
+    }
+    if (formData.get("intent") === "preview") {
+        return await preview("/kobus/user/preview", formDataToJsonString(formData));
+    }
+    return await postJson("/kobus/user", formDataToJsonString(formData));
 }
 
 export function Component() {
     const data = useLoaderData() as AsyncReturnType<typeof loader>;
+    const result = useActionData() as PreviewResult | undefined;
 
     if (data !== null) {
         return <div>
             <Form method="post" action="/kobus">
-                <label htmlFor="ses">JSESSIONID</label>
-                <input type="text" name="jsessionid" defaultValue={data.jsessionid} />
+                <SessionInput name="jsessionid" label="JSESSIONID" saved={data.saved} required={data.required} />
+                {result?.error && <p role="alert">{result.error}</p>}
                 <button type="submit">Update</button>
+                <PreviewButton />
             </Form>
+            <PreviewEvents result={result} />
+            <RemoveSource action="/kobus" name="kobus" />
         </div>;
     } else {
         return <Navigate to="/" />;
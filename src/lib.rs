@@ -1,18 +1,41 @@
+pub mod admin;
+pub mod audit;
 pub mod bustago;
 pub mod catch_table;
 pub mod cgv;
+pub mod cleanup;
+pub mod correlation;
+pub mod db;
+mod dump;
+pub mod export;
 pub mod google_calendar;
+pub mod health;
+mod http;
+pub mod ical;
 pub mod kobus;
+pub mod listen;
+pub mod lotte_cinema;
 pub mod megabox;
+pub mod migration;
 pub mod naver_reservation;
 pub mod reservation;
+pub mod search;
+pub mod setting;
+pub mod shutdown;
+pub mod source;
+#[cfg(test)]
+mod testing;
+pub mod title;
+pub mod tls;
 pub mod user;
+#[cfg(any(test, feature = "vcr"))]
+pub mod vcr;
 
-pub use reservation::{date_time_to_utc, CalendarEvent, ReservationId};
+pub use reservation::{
+    date_time_from_utc, date_time_to_utc, kst, CalendarEvent, CrawlStats, ReservationId,
+};
 pub use user::{user_web_router, UserId, UserImpl};
 
-const USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.5 Safari/605.1.15";
-
 #[macro_export]
 macro_rules! selector {
     ($selector:literal) => {{
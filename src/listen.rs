@@ -0,0 +1,54 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use anyhow::Context as _;
+
+const DEFAULT_BIND_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+const DEFAULT_PORT: u16 = 3000;
+
+/// Address to listen on, from `BIND_ADDR` and `PORT`. All interfaces on 3000
+/// by default.
+pub fn address_from_env() -> anyhow::Result<SocketAddr> {
+    address_from_vars(|name| std::env::var(name).ok())
+}
+
+fn address_from_vars(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<SocketAddr> {
+    let ip = var("BIND_ADDR")
+        .map(|value| value.parse())
+        .transpose()
+        .context("Invalid BIND_ADDR")?
+        .unwrap_or(DEFAULT_BIND_ADDR);
+    let port = var("PORT")
+        .map(|value| value.parse())
+        .transpose()
+        .context("Invalid PORT")?
+        .unwrap_or(DEFAULT_PORT);
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_falls_back_to_defaults() {
+        assert_eq!(
+            address_from_vars(|_| None).unwrap(),
+            "0.0.0.0:3000".parse().unwrap()
+        );
+        assert_eq!(
+            address_from_vars(|name| match name {
+                "BIND_ADDR" => Some("::1".to_string()),
+                "PORT" => Some("8080".to_string()),
+                _ => None,
+            })
+            .unwrap(),
+            "[::1]:8080".parse().unwrap()
+        );
+        assert!(address_from_vars(|name| (name == "PORT").then(|| "http".to_string())).is_err());
+        assert!(
+            address_from_vars(|name| (name == "BIND_ADDR").then(|| "localhost".to_string()))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,488 @@
+use anyhow::Context;
+use axum::{async_trait, Router};
+use futures::StreamExt;
+use log::warn;
+use reqwest::cookie::CookieStore;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    date_time_to_utc, kst,
+    reservation::business_day_time,
+    title::{Chain, Title},
+    url,
+    user::SessionExpired,
+    CalendarEvent, CrawlStats, UserId, UserImpl as _,
+};
+
+#[derive(Debug, Deserialize)]
+struct ReservationResponse {
+    /// `"true"` or `"false"` in string
+    #[serde(rename = "IsOK")]
+    is_ok: String,
+    #[serde(rename = "ResultMessage", default)]
+    message: String,
+    /// Not given with error responses
+    #[serde(rename = "TicketingList", default)]
+    ticketing_list: TicketingList,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TicketingList {
+    #[serde(rename = "Items", default)]
+    items: Vec<Reservation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Reservation {
+    #[serde(rename = "ReserveNo")]
+    reservation_number: String,
+    #[serde(rename = "MovieNameKR")]
+    movie_name: String,
+    #[serde(rename = "CinemaNameKR")]
+    cinema_name: String,
+    #[serde(rename = "ScreenNameKR")]
+    screen_name: String,
+    #[serde(rename = "SeatNo")]
+    seat_number: String,
+    /// `%Y-%m-%d`
+    #[serde(rename = "PlayDt")]
+    play_date: String,
+    /// `%H:%M`, over 24 for shows after midnight
+    #[serde(rename = "StartTime")]
+    start_time: String,
+    #[serde(rename = "EndTime")]
+    end_time: String,
+    #[serde(rename = "CancelYN", default)]
+    cancelled: String,
+}
+
+impl ReservationResponse {
+    fn into_events(self) -> anyhow::Result<Vec<CalendarEvent>> {
+        if self.is_ok != "true" {
+            return Err(anyhow::anyhow!(
+                "Receive error response, session could be expired - {}",
+                self.message
+            ));
+        }
+
+        self.ticketing_list
+            .items
+            .into_iter()
+            .map(CalendarEvent::try_from)
+            .collect()
+    }
+}
+
+/// Reservations requested at once, the list ends with a shorter page
+const PAGE_SIZE: usize = 50;
+/// Not to loop forever when the list doesn't end
+const MAX_PAGES: u32 = 10;
+const SESSION_EXPIRED: &str = "LOTTE CINEMA session expired, re-paste ASP.NET_SessionId";
+
+/// Page of the ticketing history, starting from 1
+fn list_request(
+    client: &reqwest::Client,
+    jar: &reqwest::cookie::Jar,
+    page: u32,
+) -> anyhow::Result<reqwest::Request> {
+    let url = url!("https://www.lottecinema.co.kr/LCWS/Ticketing/TicketingData.aspx");
+    let param_list = serde_json::json!({
+        "MethodName": "GetTicketingHistory",
+        "channelType": "HO",
+        "osType": "W",
+        "osVersion": "",
+        "PageNo": page,
+        "PageSize": PAGE_SIZE,
+    });
+    client
+        .post(url.as_ref())
+        .headers(crate::http::headers("lotte_cinema"))
+        .header(
+            reqwest::header::REFERER,
+            "https://www.lottecinema.co.kr/NLCHS/Mypage/MyTicket",
+        )
+        .header(reqwest::header::COOKIE, jar.cookies(url).unwrap())
+        .form(&[("paramList", param_list.to_string())])
+        .build()
+        .context("Failed to build request of ticketing history")
+}
+
+/// Reservations of all pages, `fetch_page` gives the list of the page starting
+/// from 1. The list ends with a page shorter than [`PAGE_SIZE`] or without new
+/// reservations, which also stops the loop if the page is ignored.
+async fn collect_pages<F, Fut>(mut fetch_page: F) -> anyhow::Result<ReservationResponse>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<ReservationResponse>>,
+{
+    let mut items: Vec<Reservation> = Vec::new();
+    for page in 1..=MAX_PAGES {
+        let res = fetch_page(page).await?;
+        if res.is_ok != "true" {
+            return Ok(res);
+        }
+        let listed = res.ticketing_list.items.len();
+        let new_items = res
+            .ticketing_list
+            .items
+            .into_iter()
+            .filter(|item| {
+                items
+                    .iter()
+                    .all(|saved| saved.reservation_number != item.reservation_number)
+            })
+            .collect::<Vec<_>>();
+        let is_last = listed < PAGE_SIZE || new_items.is_empty();
+        items.extend(new_items);
+        if is_last {
+            break;
+        }
+        if page == MAX_PAGES {
+            warn!("Reservations after page {MAX_PAGES} are ignored");
+        }
+    }
+
+    Ok(ReservationResponse {
+        is_ok: "true".to_string(),
+        message: String::new(),
+        ticketing_list: TicketingList { items },
+    })
+}
+
+/// Without the login, MyTicket redirects to the login page instead of
+/// answering the page
+fn check_ping(response: &crate::dump::RawResponse) -> anyhow::Result<()> {
+    if matches!(
+        response.status,
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN
+    ) {
+        return Err(SessionExpired(format!("{SESSION_EXPIRED} - {}", response.status)).into());
+    }
+    if response.status.is_redirection() {
+        let location = response
+            .headers
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .unwrap_or_default();
+        if location.to_ascii_lowercase().contains("login") {
+            return Err(
+                SessionExpired(format!("{SESSION_EXPIRED} - login page is returned")).into(),
+            );
+        }
+        anyhow::bail!("LOTTE CINEMA redirected to {location}");
+    }
+    if !response.status.is_success() {
+        anyhow::bail!("LOTTE CINEMA responded {}", response.status);
+    }
+
+    Ok(())
+}
+
+/// Hour and minute of `%H:%M`, the hour can be over 24
+fn parse_time(time: &str) -> anyhow::Result<(u32, u32)> {
+    let (hour, minute) = time
+        .split_once(':')
+        .with_context(|| format!("Invalid time {time}"))?;
+    Ok((
+        hour.parse().context("Failed to parse hour")?,
+        minute.parse().context("Failed to parse minute")?,
+    ))
+}
+
+impl TryFrom<Reservation> for CalendarEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Reservation) -> Result<Self, Self::Error> {
+        let id = format!(
+            "{}{}",
+            LotteCinemaUser::SOURCE_PREFIX,
+            value.reservation_number
+        );
+        let title = Title::Movie {
+            movie: value.movie_name,
+            chain: Chain::LotteCinema,
+            theater: value.cinema_name,
+        };
+        let detail = format!("상영관: {}\n좌석: {}", value.screen_name, value.seat_number);
+        let date = chrono::NaiveDate::parse_from_str(&value.play_date, "%Y-%m-%d")
+            .context("Failed to parse date")?;
+        let (hour, minute) = parse_time(&value.start_time)?;
+        let (date_begin, time_begin) = business_day_time(date, hour, minute)?;
+        let (hour, minute) = parse_time(&value.end_time)?;
+        let (date_end, time_end) = business_day_time(date, hour, minute)?;
+        let (date_begin, time_begin) = date_time_to_utc(date_begin, time_begin, kst());
+        let (date_end, time_end) = date_time_to_utc(date_end, time_end, kst());
+
+        CalendarEvent::builder_of(id, title, date_begin)
+            .detail(detail)
+            .invalid(value.cancelled == "Y")
+            .time_begin(time_begin)
+            .end(date_end, time_end)
+            .build()
+    }
+}
+
+crate::define_user_data! {
+    #[table_name = "lotte_cinema"]
+    #[base_url = "https://www.lottecinema.co.kr/"]
+    struct LotteCinemaUser {
+        #[session_name = "ASP.NET_SessionId"]
+        session_id: String,
+    }
+}
+
+impl LotteCinemaUser {
+    pub fn all(db: &SqlitePool) -> impl futures::Stream<Item = anyhow::Result<Self>> + '_ {
+        sqlx::query_as!(
+            Self,
+            "SELECT `user_id` as `user_id: UserId`, `session_id` FROM `lotte_cinema_user`"
+        )
+        .fetch(db)
+        .map(|result| result.context("Failed to get lotte_cinema_user"))
+    }
+}
+
+#[async_trait]
+impl crate::UserImpl for LotteCinemaUser {
+    type Detail = LotteCinemaUserDetail;
+    const SOURCE: &'static str = "lotte_cinema";
+    const SOURCE_PREFIX: &'static str = "lotte_cinema/";
+
+    const PING_INTERVAL: Option<std::time::Duration> =
+        Some(std::time::Duration::from_secs(10 * 60));
+
+    fn user_id(&self) -> UserId {
+        self.user_id
+    }
+
+    fn all_users(db: &SqlitePool) -> futures::stream::BoxStream<'_, anyhow::Result<Self>> {
+        Self::all(db).boxed()
+    }
+
+    async fn scrape(&self, _db: &SqlitePool) -> anyhow::Result<Vec<CalendarEvent>> {
+        let jar = self.to_cookie_jar();
+        let client = crate::http::client();
+        let res = collect_pages(|page| {
+            let req = list_request(&client, &jar, page);
+            let client = &client;
+            async move {
+                crate::dump::parse_json("lotte_cinema", crate::http::execute(client, req?).await?)
+                    .await
+            }
+        })
+        .await?;
+        res.into_events()
+    }
+
+    /// An empty list is stored too, to cancel the reservations not listed
+    /// anymore
+    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<CrawlStats> {
+        let reservations = self.scrape(&db).await?;
+
+        self.store_reservations(&db, &reservations).await
+    }
+
+    async fn ping(&self) -> anyhow::Result<()> {
+        let jar = self.to_cookie_jar();
+        let url = url!("https://www.lottecinema.co.kr/NLCHS/Mypage/MyTicket");
+        // the redirect to the login page is checked instead of followed
+        let client = crate::http::client_builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let req = client
+            .get(url.as_ref())
+            .headers(crate::http::headers("lotte_cinema"))
+            .header(reqwest::header::COOKIE, jar.cookies(url).unwrap())
+            .build()?;
+        let res = crate::dump::RawResponse::read(
+            crate::http::execute(&client, req)
+                .await
+                .context("Error occurred while sending ping")?,
+        )
+        .await?;
+        check_ping(&res).context("Ping is rejected")
+    }
+
+    async fn from_user_id(db: SqlitePool, user_id: UserId) -> anyhow::Result<Option<Self>> {
+        sqlx::query_as!(
+            Self,
+            "SELECT `user_id` as `user_id: UserId`, `session_id` FROM `lotte_cinema_user` WHERE `user_id` = ?",
+            user_id
+        )
+        .fetch_optional(&db)
+        .await
+        .with_context(|| format!("Failed to get lotte_cinema_user of {user_id:?}"))
+    }
+
+    async fn delete(db: &mut sqlx::SqliteConnection, user_id: UserId) -> anyhow::Result<()> {
+        sqlx::query!(
+            "DELETE FROM `lotte_cinema_user` WHERE `user_id` = ?",
+            user_id
+        )
+        .execute(db)
+        .await
+        .context("Failed to delete lotte cinema user")
+        .map(|_| ())
+    }
+
+    async fn update_session(&self, db: SqlitePool) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO `lotte_cinema_user` (`session_id`, `user_id`) VALUES (?, ?)
+                ON CONFLICT (`user_id`) DO UPDATE
+                SET `session_id` = `excluded`.`session_id` WHERE `user_id` = `excluded`.`user_id`",
+            self.session_id,
+            self.user_id
+        )
+        .execute(&db)
+        .await
+        .context("Failed to update lotte cinema user session data")
+        .map(|_| ())
+    }
+}
+
+pub fn web_router() -> Router {
+    crate::user_web_router::<LotteCinemaUser>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reservations_fixture() {
+        let res: ReservationResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/lotte_cinema.json")).unwrap();
+
+        let events = res.into_events().unwrap();
+
+        assert_eq!(events.len(), 2);
+        let event = &events[0];
+        assert_eq!(event.id, "lotte_cinema/1234567890123");
+        assert_eq!(event.title, "테스트 무비 - 롯데시네마 월드타워");
+        assert_eq!(event.detail, "상영관: 수퍼플렉스\n좌석: H12,H13");
+        assert!(!event.invalid);
+        // 23:40 ~ 25:50 in KST
+        assert_eq!(
+            event.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+        );
+        assert_eq!(event.time_begin, chrono::NaiveTime::from_hms_opt(14, 40, 0));
+        assert_eq!(event.date_end, chrono::NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert_eq!(event.time_end, chrono::NaiveTime::from_hms_opt(16, 50, 0));
+
+        let cancelled = &events[1];
+        assert_eq!(cancelled.id, "lotte_cinema/1234567890124");
+        assert!(cancelled.invalid);
+    }
+
+    #[test]
+    fn error_response_is_reported() {
+        let res: ReservationResponse =
+            serde_json::from_str(r#"{"IsOK": "false", "ResultMessage": "로그인이 필요합니다."}"#)
+                .unwrap();
+
+        let error = res.into_events().unwrap_err().to_string();
+        assert!(error.contains("로그인이 필요합니다."));
+    }
+
+    /// Response of `count` reservations numbered from `first`
+    fn page_of(first: usize, count: usize) -> ReservationResponse {
+        let items = (first..first + count)
+            .map(|n| {
+                serde_json::json!({
+                    "ReserveNo": n.to_string(),
+                    "MovieNameKR": "테스트 무비",
+                    "CinemaNameKR": "월드타워",
+                    "ScreenNameKR": "1관",
+                    "SeatNo": "A1",
+                    "PlayDt": "2024-05-01",
+                    "StartTime": "19:00",
+                    "EndTime": "21:00",
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::from_value(serde_json::json!({
+            "IsOK": "true",
+            "TicketingList": { "Items": items },
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reservations_of_all_pages_are_collected() {
+        let mut requested = Vec::new();
+        let res = collect_pages(|n| {
+            requested.push(n);
+            let count = if n < 3 { PAGE_SIZE } else { 3 };
+            std::future::ready(Ok(page_of((n as usize - 1) * PAGE_SIZE, count)))
+        })
+        .await
+        .unwrap();
+        assert_eq!(requested, [1, 2, 3]);
+        assert_eq!(res.into_events().unwrap().len(), 2 * PAGE_SIZE + 3);
+
+        // the same page answered again
+        let mut requested = Vec::new();
+        let res = collect_pages(|n| {
+            requested.push(n);
+            std::future::ready(Ok(page_of(0, PAGE_SIZE)))
+        })
+        .await
+        .unwrap();
+        assert_eq!(requested, [1, 2]);
+        assert_eq!(res.ticketing_list.items.len(), PAGE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn error_response_of_later_page_is_reported() {
+        let res = collect_pages(|n| {
+            std::future::ready(Ok(if n == 1 {
+                page_of(0, PAGE_SIZE)
+            } else {
+                serde_json::from_str(
+                    r#"{"IsOK": "false", "ResultMessage": "로그인이 필요합니다."}"#,
+                )
+                .unwrap()
+            }))
+        })
+        .await
+        .unwrap();
+
+        let error = res.into_events().unwrap_err().to_string();
+        assert!(error.contains("로그인이 필요합니다."));
+    }
+
+    #[test]
+    fn ping_tells_login_page() {
+        let response = |status, location: Option<&str>| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            if let Some(location) = location {
+                headers.insert(reqwest::header::LOCATION, location.parse().unwrap());
+            }
+            crate::dump::RawResponse {
+                status,
+                headers,
+                body: "<html></html>".into(),
+            }
+        };
+
+        assert!(check_ping(&response(
+            reqwest::StatusCode::FOUND,
+            Some("/NLCHS/Member/Login?returnUrl=%2FNLCHS%2FMypage%2FMyTicket")
+        ))
+        .unwrap_err()
+        .is::<SessionExpired>());
+        assert!(
+            check_ping(&response(reqwest::StatusCode::UNAUTHORIZED, None))
+                .unwrap_err()
+                .is::<SessionExpired>()
+        );
+        assert!(
+            !check_ping(&response(reqwest::StatusCode::INTERNAL_SERVER_ERROR, None))
+                .unwrap_err()
+                .is::<SessionExpired>()
+        );
+        check_ping(&response(reqwest::StatusCode::OK, None)).unwrap();
+    }
+}
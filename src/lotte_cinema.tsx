@@ -0,0 +1,53 @@
+import React from "react";
+import { ActionFunctionArgs, Form, Navigate, useActionData, useLoaderData } from "react-router-dom";
+import { AsyncReturnType, formDataToJsonString, postJson, request as sendRequest } from './utils';
+import { RemoveSource } from './remove_source';
+import { preview, PreviewButton, PreviewEvents, PreviewResult } from './preview';
+import { SessionInput } from './session_input';
+
+export async function loader() {
+    const resp = await fetch("/lotte-cinema/user", {
+        credentials: "same-origin",
+    });
+
+    if (resp.ok) {
+        const parsed = await resp.json();
+        return {
+            saved: parsed.saved as string[],
+            required: parsed.required as string[],
+        }
+    } else {
+        return null;
+    }
+}
+
+export async function action({ request }: ActionFunctionArgs) {
+    const formData = await request.formData();
+    if (formData.get("intent") === "remove") {
+        return await sendRequest("/lotte-cinema/user", { method: "delete" });
+    }
+    if (formData.get("intent") === "preview") {
+        return await preview("/lotte-cinema/user/preview", formDataToJsonString(formData));
+    }
+    return await postJson("/lotte-cinema/user", formDataToJsonString(formData));
+}
+
+export function Component() {
+    const data = useLoaderData() as AsyncReturnType<typeof loader>;
+    const result = useActionData() as PreviewResult | undefined;
+
+    if (data !== null) {
+        return <div>
+            <Form method="post" action="/lotte-cinema">
+                <SessionInput name="session_id" label="ASP.NET_SessionId" saved={data.saved} required={data.required} />
+                {result?.error && <p role="alert">{result.error}</p>}
+                <button type="submit">Update</button>
+                <PreviewButton />
+            </Form>
+            <PreviewEvents result={result} />
+            <RemoveSource action="/lotte-cinema" name="LOTTE CINEMA" />
+        </div>;
+    } else {
+        return <Navigate to="/" />;
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::BTreeSet, ffi::OsStr, path::Path, sync::Arc};
+use std::{collections::BTreeMap, ffi::OsStr, path::Path, sync::Arc};
 
 use axum::{
     body::{Bytes, StreamBody},
@@ -13,22 +13,18 @@ use axum_sessions::{
     PersistencePolicy, SessionLayer,
 };
 use calendar_hub::{
-    bustago::BustagoUser,
-    catch_table::CatchTableUser,
-    cgv::CgvUser,
+    audit, cleanup, correlation, db,
     google_calendar::{self, GoogleUser},
-    kobus::KobusUser,
-    megabox::MegaboxUser,
-    naver_reservation::NaverUser,
-    UserId, UserImpl,
+    listen, migration,
+    shutdown::Shutdown,
+    source, tls, CrawlStats, UserId,
 };
 use futures::{Future, TryStream};
-use hyper::{header, Uri};
-use log::{debug, error, info};
+use hyper::{header, StatusCode, Uri};
+use log::{debug, error, info, warn};
 use sqlx::SqlitePool;
 use tokio::sync::mpsc;
 use tokio_cron_scheduler::{Job, JobScheduler};
-use tokio_stream::StreamExt;
 use uuid::Uuid;
 
 async fn serve_static_res<S, F, FUT>(uri: Uri, f: F) -> Response
@@ -124,29 +120,51 @@ mod static_res {
     }
 }
 
-async fn ping_user<U: UserImpl>(user: anyhow::Result<U>) -> anyhow::Result<()> {
-    user?.ping().await
-}
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::init();
+    correlation::init_logger();
 
     let url_prefix =
         std::env::var("URL_PREFIX").unwrap_or_else(|_| "http://127.0.0.1:3000".to_string());
+    let address = listen::address_from_env()?;
+
+    // loaded first to fail fast on invalid files
+    let tls = match tls::TlsConfig::from_env()? {
+        Some(tls) => {
+            if !url_prefix.starts_with("https") {
+                warn!("TLS is enabled, but URL_PREFIX({url_prefix}) is not https");
+            }
+            Some(tls.load().await?)
+        }
+        None => None,
+    };
+
+    calendar_hub::reservation::DateBounds::from_env()?.init()?;
+    let cleanup = cleanup::CleanupConfig::from_env()?.map(Arc::new);
+    let audit_retention_days = audit::retention_days_from_env()?;
 
-    let db_pool = sqlx::SqlitePool::connect("./db.db").await?;
-    sqlx::migrate!().run(&db_pool).await?;
+    let db_pool = db::DbConfig::from_env()?.connect().await?;
+    migration::run(&db_pool).await?;
     info!("DB migration completed");
 
-    let scheduler = JobScheduler::new().await?;
+    // to migrate ahead of replacing the running server
+    if std::env::args().nth(1).as_deref() == Some("migrate") {
+        return Ok(());
+    }
+
+    // background jobs finish the running iteration on shutdown
+    let shutdown = Shutdown::default();
+    let mut scheduler = JobScheduler::new().await?;
     scheduler
         .add(Job::new_async("0 0,30 * * * *", {
             let db = db_pool.clone();
+            let shutdown = shutdown.clone();
             move |_, _| {
                 let db = db.clone();
+                let shutdown = shutdown.clone();
                 Box::pin(async move {
-                    if let Err(e) = poll(db).await {
+                    let poll = correlation::scope(Uuid::new_v4(), poll(db));
+                    if let Some(Err(e)) = shutdown.run(poll).await {
                         error!("Failed to poll reservation - {}", e);
                     }
                 })
@@ -155,108 +173,45 @@ async fn main() -> anyhow::Result<()> {
         .await
         .unwrap();
 
-    if let Some(duration) = NaverUser::PING_INTERVAL {
-        scheduler
-            .add(Job::new_repeated_async(duration, {
-                let db = db_pool.clone();
-                move |_, _| {
-                    let db = db.clone();
-                    Box::pin(async move {
-                        let mut users = NaverUser::all(&db);
-                        while let Some(user) = users.next().await {
-                            if let Err(e) = ping_user(user).await {
-                                error!("Failed to ping - {}", e);
-                            } else {
-                                info!("Success ping for kobus");
-                            }
-                        }
-                    })
-                }
-            })?)
-            .await
-            .unwrap();
-    }
-
-    if let Some(duration) = CatchTableUser::PING_INTERVAL {
-        scheduler
-            .add(Job::new_repeated_async(duration, {
-                let db = db_pool.clone();
-                move |_, _| {
-                    let db = db.clone();
-                    Box::pin(async move {
-                        let mut users = CatchTableUser::all(&db);
-                        while let Some(user) = users.next().await {
-                            if let Err(e) = ping_user(user).await {
-                                error!("Failed to ping - {}", e);
-                            } else {
-                                info!("Success ping for kobus");
-                            }
-                        }
-                    })
-                }
-            })?)
-            .await
-            .unwrap();
-    }
-
-    if let Some(duration) = KobusUser::PING_INTERVAL {
-        scheduler
-            .add(Job::new_repeated_async(duration, {
-                let db = db_pool.clone();
-                move |_, _| {
-                    let db = db.clone();
-                    Box::pin(async move {
-                        let mut users = KobusUser::all(&db);
-                        while let Some(user) = users.next().await {
-                            if let Err(e) = ping_user(user).await {
-                                error!("Failed to ping - {}", e);
-                            } else {
-                                info!("Success ping for kobus");
-                            }
-                        }
-                    })
-                }
-            })?)
-            .await
-            .unwrap();
+    // sessions are kept warm apart from the poll above
+    for job in source::ALL
+        .iter()
+        .map(|source| source.ping_job(db_pool.clone(), shutdown.clone()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+    {
+        scheduler.add(job).await.unwrap();
     }
 
-    if let Some(duration) = CgvUser::PING_INTERVAL {
-        scheduler
-            .add(Job::new_repeated_async(duration, {
-                let db = db_pool.clone();
-                move |_, _| {
-                    let db = db.clone();
-                    Box::pin(async move {
-                        let mut users = CgvUser::all(&db);
-                        while let Some(user) = users.next().await {
-                            if let Err(e) = ping_user(user).await {
-                                error!("Failed to ping - {}", e);
-                            } else {
-                                info!("Success ping for cgv");
-                            }
-                        }
-                    })
+    scheduler
+        .add(Job::new_async("0 15 * * * *", |_, _| {
+            Box::pin(async move {
+                if let Err(e) = google_calendar::Config::get()
+                    .refresh_google_key_store()
+                    .await
+                {
+                    error!("Failed to refresh google key store - {e:?}");
                 }
-            })?)
-            .await
-            .unwrap();
-    }
+            })
+        })?)
+        .await
+        .unwrap();
 
-    if let Some(duration) = MegaboxUser::PING_INTERVAL {
+    if let Some(cleanup) = cleanup {
         scheduler
-            .add(Job::new_repeated_async(duration, {
+            .add(Job::new_async("0 45 4 * * *", {
                 let db = db_pool.clone();
+                let shutdown = shutdown.clone();
                 move |_, _| {
                     let db = db.clone();
+                    let shutdown = shutdown.clone();
+                    let cleanup = cleanup.clone();
                     Box::pin(async move {
-                        let mut users = MegaboxUser::all(&db);
-                        while let Some(user) = users.next().await {
-                            if let Err(e) = ping_user(user).await {
-                                error!("Failed to ping - {}", e);
-                            } else {
-                                info!("Success ping for megabox");
-                            }
+                        match shutdown.run(async { cleanup.run(&db).await }).await {
+                            Some(Ok(removed)) => info!("{removed} stale reservations are removed"),
+                            Some(Err(e)) => error!("Failed to remove stale reservations - {e:?}"),
+                            None => {}
                         }
                     })
                 }
@@ -265,8 +220,21 @@ async fn main() -> anyhow::Result<()> {
             .unwrap();
     }
 
-    tokio::spawn(async move { scheduler.start().await });
-    info!("Scheduler started");
+    scheduler
+        .add(Job::new_async("0 50 4 * * *", {
+            let db = db_pool.clone();
+            move |_, _| {
+                let db = db.clone();
+                Box::pin(async move {
+                    match audit::remove_older_than(&db, audit_retention_days).await {
+                        Ok(removed) => info!("{removed} old event audit entries are removed"),
+                        Err(e) => error!("{e:?}"),
+                    }
+                })
+            }
+        })?)
+        .await
+        .unwrap();
 
     static_res::init().await;
 
@@ -274,9 +242,17 @@ async fn main() -> anyhow::Result<()> {
         .await
         .unwrap();
 
+    tokio::spawn({
+        let scheduler = scheduler.clone();
+        async move { scheduler.start().await }
+    });
+    info!("Scheduler started");
+
     let router = Router::new()
         .fallback(static_res::serve)
         .route("/sync", post(poll_user))
+        .route("/sync/failed", post(retry_failed_sync))
+        .route("/sync/undo", post(undo_last_sync))
         .route("/user", get(get_user))
         .route(
             "/login",
@@ -296,34 +272,36 @@ async fn main() -> anyhow::Result<()> {
     let router = router.nest("/catch-table", calendar_hub::catch_table::web_router());
     let router = router.nest("/cgv", calendar_hub::cgv::web_router());
     let router = router.nest("/megabox", calendar_hub::megabox::web_router());
+    let router = router.nest("/lotte-cinema", calendar_hub::lotte_cinema::web_router());
     let router = router.nest("/bustago", calendar_hub::bustago::web_router());
+    let router = router.nest("/ical", calendar_hub::ical::web_router());
+    let router = router.nest("/setting", calendar_hub::setting::web_router());
+    let router = router.nest("/admin", calendar_hub::admin::web_router());
+    let router = router.nest(
+        "/api",
+        calendar_hub::export::web_router().merge(calendar_hub::search::web_router()),
+    );
 
     #[cfg(debug_assertions)]
     let router = router.route("/poll_force", get(poll_dev));
 
     let session_secret = {
-        let mut buffer = std::mem::MaybeUninit::<[u8; 64]>::uninit();
-        const UUID_SIZE: usize = std::mem::size_of::<Uuid>();
-        {
-            let mut ptr = buffer.as_mut_ptr() as *mut u8;
-            for _ in 0..(64 / UUID_SIZE) {
-                unsafe {
-                    std::ptr::copy_nonoverlapping(
-                        Uuid::new_v4().as_bytes().as_ptr(),
-                        ptr,
-                        UUID_SIZE,
-                    );
-                    ptr = ptr.add(UUID_SIZE);
-                }
-            }
+        let mut secret = [0u8; 64];
+        for chunk in secret.chunks_mut(std::mem::size_of::<Uuid>()) {
+            chunk.copy_from_slice(Uuid::new_v4().as_bytes());
         }
-        unsafe { buffer.assume_init() }
+        secret
     };
-    let app = router.layer(Extension(db_pool)).layer(
-        SessionLayer::new(MemoryStore::new(), &session_secret)
-            .with_secure(url_prefix.starts_with("https"))
-            .with_persistence_policy(PersistencePolicy::ChangedOnly),
-    );
+    let health = calendar_hub::health::web_router(db_pool.clone());
+    let app = router
+        .layer(Extension(db_pool))
+        .layer(
+            SessionLayer::new(MemoryStore::new(), &session_secret)
+                // behind a proxy serving https, or serving it directly
+                .with_secure(tls.is_some() || url_prefix.starts_with("https"))
+                .with_persistence_policy(PersistencePolicy::ChangedOnly),
+        )
+        .merge(health);
 
     let (stop_sender, stop_receiver) = tokio::sync::oneshot::channel();
 
@@ -349,20 +327,43 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
-    axum::Server::bind(&"0.0.0.0:3000".parse()?)
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(async move {
-            let _ = stop_receiver.await;
-        })
-        .await
-        .unwrap();
+    if let Some(tls) = tls {
+        info!("Serve HTTPS on {address}");
+        let handle = axum_server::Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                let _ = stop_receiver.await;
+                handle.graceful_shutdown(None);
+            }
+        });
+        axum_server::bind_rustls(address, tls)
+            .handle(handle)
+            .serve(app.into_make_service())
+            .await?;
+    } else {
+        info!("Serve HTTP on {address}");
+        axum::Server::bind(&address)
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(async move {
+                let _ = stop_receiver.await;
+            })
+            .await
+            .unwrap();
+    }
+
+    info!("Waiting for running jobs");
+    shutdown.shutdown().await;
+    if let Err(e) = scheduler.shutdown().await {
+        error!("Failed to shutdown scheduler - {e:?}");
+    }
 
     Ok(())
 }
 
 #[cfg(debug_assertions)]
 async fn poll_dev(Extension(db): Extension<SqlitePool>) {
-    if let Err(e) = poll(db).await {
+    if let Err(e) = correlation::scope(Uuid::new_v4(), poll(db)).await {
         error!("Failed to poll - {:?}", e);
     }
 }
@@ -372,6 +373,12 @@ async fn poll_dev(Extension(db): Extension<SqlitePool>) {
 enum ClientUserData {
     User {
         last_synced: chrono::DateTime<chrono::Utc>,
+        pending: i64,
+        reshare_required: bool,
+        calendar_revoked: bool,
+        sync_failures: google_calendar::SyncFailures,
+        last_sync: Option<audit::BatchSummary>,
+        admin: bool,
     },
     None,
 }
@@ -385,10 +392,60 @@ async fn get_user(
             #[cfg(feature = "crawl_test")]
             let last_synced = chrono::Utc::now();
             #[cfg(not(feature = "crawl_test"))]
-            let last_synced = google_calendar::get_last_synced(_db, user_id)
+            let last_synced = google_calendar::get_last_synced(_db.clone(), user_id)
                 .await
                 .unwrap();
-            ClientUserData::User { last_synced }
+            let pending = google_calendar::get_pending_sync_count(_db.clone(), user_id)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to count pending reservations - {e:?}");
+                    0
+                });
+            #[cfg(feature = "crawl_test")]
+            let (reshare_required, calendar_revoked) = (false, false);
+            #[cfg(not(feature = "crawl_test"))]
+            let (reshare_required, calendar_revoked) = GoogleUser::from_user_id(&_db, user_id)
+                .await
+                .ok()
+                .flatten()
+                .map_or((false, false), |user| {
+                    (user.reshare_required(), user.calendar_revoked())
+                });
+            let sync_failures = google_calendar::get_sync_failures(&_db, user_id)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to count sync failures - {e:?}");
+                    Default::default()
+                });
+            let last_sync = audit::get_last_batch(&_db, user_id)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to summarize last sync - {e:?}");
+                    None
+                });
+            let admin = match google_calendar::get_email(&_db, user_id).await {
+                Ok(Some(email)) => google_calendar::Config::get()
+                    .is_admin(&_db, &email)
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("Failed to check admin - {e:?}");
+                        false
+                    }),
+                Ok(None) => false,
+                Err(e) => {
+                    error!("Failed to get email - {e:?}");
+                    false
+                }
+            };
+            ClientUserData::User {
+                last_synced,
+                pending,
+                reshare_required,
+                calendar_revoked,
+                sync_failures,
+                last_sync,
+                admin,
+            }
         }
         None => ClientUserData::None,
     };
@@ -402,55 +459,109 @@ async fn logout(mut session: WritableSession) -> Response {
     Redirect::to("/").into_response()
 }
 
-async fn poll_user(session: ReadableSession, Extension(db): Extension<SqlitePool>) -> Json<bool> {
-    if let Some(user_id) = session.get::<UserId>("user_id") {
-        #[cfg(not(feature = "crawl_test"))]
-        {
-            let Ok(last_synced) = google_calendar::get_last_synced(db.clone(), user_id).await
-            else {
-                return Json(false);
-            };
+async fn poll_user(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Json<serde_json::Value> {
+    let correlation_id = Uuid::new_v4();
+    let synced = correlation::scope(correlation_id, async move {
+        info!("Sync is requested");
+        sync_user(session, db).await
+    })
+    .await;
+
+    Json(serde_json::json!({ "synced": synced, "correlation_id": correlation_id }))
+}
 
-            let duration = chrono::Utc::now() - last_synced;
-            if duration < chrono::Duration::minutes(1) {
-                info!("Recently updated");
-                return Json(false);
-            }
-        }
+/// Syncs only the reservations failed on the previous syncs, without crawling
+async fn retry_failed_sync(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
 
-        if let Ok(Some(user)) = NaverUser::from_user_id(db.clone(), user_id).await {
-            if let Err(e) = user.fetch(db.clone()).await {
-                error!("fetch naver - {e:?}");
+    #[cfg(not(feature = "crawl_test"))]
+    {
+        let user = match GoogleUser::from_user_id(&db, user_id).await {
+            Ok(Some(user)) => user,
+            Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+            Err(e) => {
+                error!("Error occurred while get google user - {e:?}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
-        }
-
-        if let Ok(Some(user)) = KobusUser::from_user_id(db.clone(), user_id).await {
-            if let Err(e) = user.fetch(db.clone()).await {
-                error!("fetch kobus - {e:?}");
+        };
+        match correlation::scope(Uuid::new_v4(), user.retry_failed(&db)).await {
+            Ok(retried) => Json(serde_json::json!({ "retried": retried })).into_response(),
+            Err(e) => {
+                error!("Error occurred while retry failed sync - {e:?}");
+                (StatusCode::BAD_GATEWAY, format!("{e:#}")).into_response()
             }
         }
+    }
+    #[cfg(feature = "crawl_test")]
+    {
+        let _ = (db, user_id);
+        Json(serde_json::json!({ "retried": 0 })).into_response()
+    }
+}
 
-        if let Ok(Some(user)) = CatchTableUser::from_user_id(db.clone(), user_id).await {
-            if let Err(e) = user.fetch(db.clone()).await {
-                error!("fetch catch table - {e:?}");
-            }
-        }
+/// Reverts the changes which the last sync made on google calendar
+async fn undo_last_sync(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
 
-        if let Ok(Some(user)) = CgvUser::from_user_id(db.clone(), user_id).await {
-            if let Err(e) = user.fetch(db.clone()).await {
-                error!("fetch cgv - {e:?}");
+    #[cfg(not(feature = "crawl_test"))]
+    {
+        let user = match GoogleUser::from_user_id(&db, user_id).await {
+            Ok(Some(user)) => user,
+            Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+            Err(e) => {
+                error!("Error occurred while get google user - {e:?}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        match correlation::scope(Uuid::new_v4(), user.undo_last_sync(&db)).await {
+            Ok(undone) => Json(undone).into_response(),
+            Err(e) => {
+                error!("Error occurred while undo last sync - {e:?}");
+                (StatusCode::BAD_GATEWAY, format!("{e:#}")).into_response()
             }
         }
+    }
+    #[cfg(feature = "crawl_test")]
+    {
+        let _ = (db, user_id);
+        Json(google_calendar::Undone::default()).into_response()
+    }
+}
 
-        if let Ok(Some(user)) = MegaboxUser::from_user_id(db.clone(), user_id).await {
-            if let Err(e) = user.fetch(db.clone()).await {
-                error!("fetch megabox - {e:?}");
+async fn sync_user(session: ReadableSession, db: SqlitePool) -> bool {
+    if let Some(user_id) = session.get::<UserId>("user_id") {
+        #[cfg(not(feature = "crawl_test"))]
+        {
+            let Ok(last_synced) = google_calendar::get_last_synced(db.clone(), user_id).await
+            else {
+                return false;
+            };
+
+            let duration = chrono::Utc::now() - last_synced;
+            if duration < chrono::Duration::minutes(1) {
+                info!("Recently updated");
+                return false;
             }
         }
 
-        if let Ok(Some(user)) = BustagoUser::from_user_id(db.clone(), user_id).await {
-            if let Err(e) = user.fetch(db.clone()).await {
-                error!("fetch bustago - {e:?}");
+        for source in source::ALL {
+            if let Err(e) = source.crawl(db.clone(), user_id).await {
+                error!("fetch {} - {e:?}", source.name);
             }
         }
 
@@ -462,174 +573,41 @@ async fn poll_user(session: ReadableSession, Extension(db): Extension<SqlitePool
         }
     }
 
-    return Json(true);
+    true
 }
 
 async fn poll(db: SqlitePool) -> anyhow::Result<()> {
-    let (user_id_sender, mut user_id_receiver) = mpsc::unbounded_channel();
-
-    let user_id_collector = tokio::spawn(async move {
-        let mut user_ids = BTreeSet::new();
-
-        while let Some(user_id) = user_id_receiver.recv().await {
-            user_ids.insert(user_id);
-        }
-
-        user_ids
-    });
-
-    let naver = tokio::spawn({
-        let db = db.clone();
-        let user_id_sender = user_id_sender.clone();
-        async move {
-            let mut users = NaverUser::all(&db);
-            while let Some(user) = users.next().await {
-                match user {
-                    Ok(user) => {
-                        let user_id = user.user_id();
-
-                        user_id_sender.send(user_id).unwrap();
-
-                        if let Err(e) = user.fetch(db.clone()).await {
-                            error!(
-                                "Failed to fetch naver reservation data for {user_id:?} - {e:?}"
-                            );
-                        }
-                    }
-                    Err(e) => error!("Failed to get naver user info from DB - {e:?}"),
-                }
-            }
-        }
-    });
-
-    let kobus = tokio::spawn({
-        let db = db.clone();
-        let user_id_sender = user_id_sender.clone();
-        async move {
-            let mut users = KobusUser::all(&db);
-            while let Some(user) = users.next().await {
-                match user {
-                    Ok(user) => {
-                        let user_id = user.user_id();
-
-                        user_id_sender.send(user_id).unwrap();
-
-                        if let Err(e) = user.fetch(db.clone()).await {
-                            error!("Failed to fetch kobus data for {user_id:?} - {e:?}");
-                        }
-                    }
-                    Err(e) => error!("Failed to get kobus user info from DB - {e:?}"),
-                }
-            }
-        }
-    });
-
-    let catch_table = tokio::spawn({
-        let db = db.clone();
-        let user_id_sender = user_id_sender.clone();
-        async move {
-            let mut users = CatchTableUser::all(&db);
-            while let Some(user) = users.next().await {
-                match user {
-                    Ok(user) => {
-                        let user_id = user.user_id();
-
-                        user_id_sender.send(user_id).unwrap();
-
-                        if let Err(e) = user.fetch(db.clone()).await {
-                            error!("Failed to fetch catch table data for {user_id:?} - {e:?}");
-                        }
-                    }
-                    Err(e) => error!("Failed to get catch table user info from DB - {e:?}"),
-                }
-            }
-        }
-    });
-
-    let cgv = tokio::spawn({
-        let db = db.clone();
-        let user_id_sender = user_id_sender.clone();
-        async move {
-            let mut users = CgvUser::all(&db);
-            while let Some(user) = users.next().await {
-                match user {
-                    Ok(user) => {
-                        let user_id = user.user_id();
+    let (stats_sender, mut stats_receiver) = mpsc::unbounded_channel();
 
-                        user_id_sender.send(user_id).unwrap();
+    let stats_collector = correlation::spawn(async move {
+        let mut stats = BTreeMap::<UserId, CrawlStats>::new();
 
-                        if let Err(e) = user.fetch(db.clone()).await {
-                            error!("Failed to fetch cgv data for {user_id:?} - {e:?}");
-                        }
-                    }
-                    Err(e) => error!("Failed to get cgv user info from DB - {e:?}"),
-                }
-            }
+        while let Some((user_id, user_stats)) = stats_receiver.recv().await {
+            *stats.entry(user_id).or_default() += user_stats;
         }
-    });
 
-    let megabox = tokio::spawn({
-        let db = db.clone();
-        let user_id_sender = user_id_sender.clone();
-        async move {
-            let mut users = MegaboxUser::all(&db);
-            while let Some(user) = users.next().await {
-                match user {
-                    Ok(user) => {
-                        let user_id = user.user_id();
-
-                        user_id_sender.send(user_id).unwrap();
-
-                        if let Err(e) = user.fetch(db.clone()).await {
-                            error!("Failed to fetch megabox data for {user_id:?} - {e:?}");
-                        }
-                    }
-                    Err(e) => error!("Failed to get megabox user info from DB - {e:?}"),
-                }
-            }
-        }
+        stats
     });
 
-    let bustago = tokio::spawn({
-        let db = db.clone();
-        let user_id_sender = user_id_sender.clone();
-        async move {
-            let mut users = BustagoUser::all(&db);
-            while let Some(user) = users.next().await {
-                match user {
-                    Ok(user) => {
-                        let user_id = user.user_id();
-
-                        user_id_sender.send(user_id).unwrap();
+    let sources = source::ALL
+        .iter()
+        .map(|source| correlation::spawn(source.crawl_all(db.clone(), stats_sender.clone())))
+        .collect::<Vec<_>>();
 
-                        if let Err(e) = user.fetch(db.clone()).await {
-                            error!("Failed to fetch bustago data for {user_id:?} - {e:?}");
-                        }
-                    }
-                    Err(e) => error!("Failed to get bustago user info from DB - {e:?}"),
-                }
-            }
-        }
-    });
+    drop(stats_sender);
 
-    drop(user_id_sender);
-
-    let user_ids = Arc::new(
-        tokio::join!(
-            user_id_collector,
-            naver,
-            kobus,
-            catch_table,
-            cgv,
-            megabox,
-            bustago
-        )
-        .0?,
-    );
+    for source in sources {
+        source.await?;
+    }
+    let stats = stats_collector.await?;
+    for (user_id, stats) in &stats {
+        info!("Polled {user_id:?} - {stats}");
+    }
+    let user_ids = Arc::new(stats.into_keys().collect::<Vec<_>>());
 
     #[cfg(not(feature = "crawl_test"))]
     {
-        let google = tokio::spawn({
+        let google = correlation::spawn({
             let db = db.clone();
             let user_ids = user_ids.clone();
 
@@ -2,12 +2,20 @@
 use anyhow::Context;
 use axum::{async_trait, Router};
 use futures::StreamExt;
-use log::info;
+use log::warn;
 use reqwest::cookie::CookieStore;
 use serde::Deserialize;
 use sqlx::SqlitePool;
 
-use crate::{date_time_to_utc, url, CalendarEvent, UserId};
+use crate::{
+    date_time_to_utc, kst,
+    reservation::business_day_time,
+    setting::SourceSetting,
+    title::{Chain, Title},
+    url,
+    user::SessionExpired,
+    CalendarEvent, CrawlStats, UserId, UserImpl as _,
+};
 
 #[derive(Debug, Deserialize)]
 struct ReservationResponse {
@@ -15,8 +23,8 @@ struct ReservationResponse {
     status_code: i32,
     #[serde(rename = "msg")]
     message: String,
-    // #[serde(rename = "imgSvrUrl")]
-    // image_server_url: String,
+    #[serde(rename = "imgSvrUrl", default)]
+    image_server_url: String,
     #[serde(rename = "list")]
     items: Vec<Reservation>,
 }
@@ -24,13 +32,15 @@ struct ReservationResponse {
 #[derive(Debug, Deserialize)]
 struct Reservation {
     // sellTranNo: String,
-    // sellStatCd: String,
+    #[serde(rename = "sellStatCd", default)]
+    sell_status_code: String,
     #[serde(rename = "bokdNo")]
     booking_id: String,
     // sellItemNo: String,
     // payDe: String,
     // payDt: String,
-    // imgPath: String,
+    #[serde(rename = "imgPath", default)]
+    image_path: Option<String>,
     // playDt: String,
     #[serde(rename = "movieNm")]
     movie_name: String,
@@ -42,6 +52,9 @@ struct Reservation {
     theater_floor_name: String,
     #[serde(rename = "seatNm")]
     seat_name: String,
+    /// Number of admitted people, not given for some bookings
+    #[serde(rename = "admisPcnt", default)]
+    admission_count: Option<u32>,
     #[serde(rename = "playDe")]
     play_date: String,
     #[serde(rename = "playStartTime")]
@@ -50,12 +63,173 @@ struct Reservation {
     play_end_time: String,
 }
 
+/// `divCd` of the lists to fetch. Bookings of today are sometimes listed only
+/// in the past ones (`P`) instead of the upcoming ones (`B`).
+const DIV_CODES: &[&str] = &["B", "P"];
+/// `sellStatCd` of refunded reservations
+const CANCELLED_SELL_STATUS: &str = "02";
+/// Not to loop forever when the list doesn't end
+const MAX_PAGES: u32 = 10;
+const SESSION_EXPIRED: &str = "MEGABOX session expired, re-paste SESSION";
+
+/// Without the login, the booking list answers the login page instead of the
+/// list
+fn check_response(response: &crate::dump::RawResponse) -> anyhow::Result<()> {
+    if matches!(
+        response.status,
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN
+    ) {
+        return Err(SessionExpired(format!("{SESSION_EXPIRED} - {}", response.status)).into());
+    }
+    if response.body.trim_ascii_start().starts_with(b"<") {
+        return Err(SessionExpired(format!("{SESSION_EXPIRED} - login page is returned")).into());
+    }
+    if !response.status.is_success() {
+        anyhow::bail!("MEGABOX responded {}", response.status);
+    }
+
+    Ok(())
+}
+
+/// Page of the booking list of `div_code`, starting from 1
+fn list_request(
+    client: &reqwest::Client,
+    jar: &reqwest::cookie::Jar,
+    div_code: &str,
+    page: u32,
+) -> anyhow::Result<reqwest::Request> {
+    let url = url!("https://www.megabox.co.kr/on/oh/ohh/MyBokdPurc/selectBokdList.do");
+    client
+        .get(url.as_ref())
+        .headers(crate::http::headers("megabox"))
+        .header(
+            reqwest::header::REFERER,
+            "https://www.megabox.co.kr/mypage/bookinglist",
+        )
+        .header(reqwest::header::COOKIE, jar.cookies(url).unwrap())
+        .json(&serde_json::json!({
+            "divCd": div_code,
+            "localeCode": "kr",
+            "currentPage": page
+        }))
+        .build()
+        .context("Failed to build request of booking list")
+}
+
+/// Fetches the page of the booking list, checking the login first
+async fn fetch_list(
+    client: &reqwest::Client,
+    jar: &reqwest::cookie::Jar,
+    div_code: &str,
+    page: u32,
+) -> anyhow::Result<ReservationResponse> {
+    let req = list_request(client, jar, div_code, page)?;
+    let res = crate::dump::RawResponse::read(crate::http::execute(client, req).await?).await?;
+    check_response(&res)?;
+    res.parse_json("megabox")
+}
+
+/// Reservations of all pages, `fetch_page` gives the list of the page starting
+/// from 1. The list ends with a page without new bookings, which also stops
+/// the loop if the page is ignored.
+async fn collect_pages<F, Fut>(mut fetch_page: F) -> anyhow::Result<ReservationResponse>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<ReservationResponse>>,
+{
+    let mut image_server_url = None;
+    let mut items: Vec<Reservation> = Vec::new();
+    for page in 1..=MAX_PAGES {
+        let res = fetch_page(page).await?;
+        if res.status_code != 0 {
+            return Err(anyhow::anyhow!("Receive error response - {}", res.message));
+        }
+        let new_items = res
+            .items
+            .into_iter()
+            .filter(|item| {
+                items
+                    .iter()
+                    .all(|saved| saved.booking_id != item.booking_id)
+            })
+            .collect::<Vec<_>>();
+        if new_items.is_empty() {
+            break;
+        }
+        image_server_url.get_or_insert(res.image_server_url);
+        items.extend(new_items);
+        if page == MAX_PAGES {
+            warn!("Reservations after page {MAX_PAGES} are ignored");
+        }
+    }
+
+    Ok(ReservationResponse {
+        status_code: 0,
+        message: String::new(),
+        image_server_url: image_server_url.unwrap_or_default(),
+        items,
+    })
+}
+
+/// Reservations of all lists, the first one of the same booking is kept
+fn merge_lists(lists: Vec<ReservationResponse>) -> ReservationResponse {
+    let mut image_server_url = String::new();
+    let mut items: Vec<Reservation> = Vec::new();
+    for list in lists {
+        if image_server_url.is_empty() {
+            image_server_url = list.image_server_url;
+        }
+        for item in list.items {
+            if items
+                .iter()
+                .all(|saved| saved.booking_id != item.booking_id)
+            {
+                items.push(item);
+            }
+        }
+    }
+
+    ReservationResponse {
+        status_code: 0,
+        message: String::new(),
+        image_server_url,
+        items,
+    }
+}
+
+impl ReservationResponse {
+    fn into_events(self, include_image: bool) -> anyhow::Result<Vec<CalendarEvent>> {
+        let image_server_url = self.image_server_url;
+        self.items
+            .into_iter()
+            .filter_map(|item| {
+                let image_url = item
+                    .image_path
+                    .as_deref()
+                    .filter(|path| include_image && !path.is_empty())
+                    .map(|path| format!("{image_server_url}{path}"));
+                let event = <Option<CalendarEvent>>::try_from(item).transpose()?;
+                Some(event.map(|mut event| {
+                    if let Some(image_url) = image_url {
+                        event.detail = format!("{}\n티켓: {image_url}", event.detail);
+                    }
+                    event
+                }))
+            })
+            .collect()
+    }
+}
+
 impl TryFrom<Reservation> for Option<CalendarEvent> {
     type Error = anyhow::Error;
 
     fn try_from(value: Reservation) -> Result<Self, Self::Error> {
-        let id = format!("megabox/{}", value.booking_id);
-        let title = format!("{} - MEGABOX {}", value.movie_name, value.branch_name);
+        let id = format!("{}{}", MegaboxUser::SOURCE_PREFIX, value.booking_id);
+        let title = Title::Movie {
+            movie: value.movie_name,
+            chain: Chain::Megabox,
+            theater: value.branch_name,
+        };
         let detail = format!(
             "상영관: {}({})\n좌석: {}",
             value.theater_name, value.theater_floor_name, value.seat_name
@@ -70,55 +244,20 @@ impl TryFrom<Reservation> for Option<CalendarEvent> {
             .play_end_time
             .parse()
             .context("Failed to parse time")?;
-        let hour_begin = time_begin / 100;
-        let minute_begin = time_begin % 100;
-        let hour_end = time_end / 100;
-        let minute_end = time_end % 100;
-        let (date_end, time_end) = if hour_end < 24 {
-            (
-                date_begin,
-                chrono::NaiveTime::from_hms_opt(hour_end, minute_end, 0).unwrap(),
-            )
-        } else {
-            (
-                date_begin.succ_opt().unwrap(),
-                chrono::NaiveTime::from_hms_opt(hour_end - 24, minute_end, 0).unwrap(),
-            )
-        };
-        let (date_begin, time_begin) = if hour_begin < 24 {
-            (
-                date_begin,
-                chrono::NaiveTime::from_hms_opt(hour_begin, minute_begin, 0).unwrap(),
-            )
-        } else {
-            (
-                date_begin.succ_opt().unwrap(),
-                chrono::NaiveTime::from_hms_opt(hour_begin - 24, minute_begin, 0).unwrap(),
-            )
-        };
-        let (date_begin, time_begin) = date_time_to_utc(
-            date_begin,
-            time_begin,
-            chrono::FixedOffset::east_opt(9 * 60 * 60).unwrap(),
-        );
-        let (date_end, time_end) = date_time_to_utc(
-            date_end,
-            time_end,
-            chrono::FixedOffset::east_opt(9 * 60 * 60).unwrap(),
-        );
+        let (date_end, time_end) = business_day_time(date_begin, time_end / 100, time_end % 100)?;
+        let (date_begin, time_begin) =
+            business_day_time(date_begin, time_begin / 100, time_begin % 100)?;
+        let (date_begin, time_begin) = date_time_to_utc(date_begin, time_begin, kst());
+        let (date_end, time_end) = date_time_to_utc(date_end, time_end, kst());
 
-        Ok(Some(CalendarEvent {
-            id,
-            title,
-            detail,
-            invalid: false,
-            date_begin,
-            time_begin: Some(time_begin),
-            date_end: Some(date_end),
-            time_end: Some(time_end),
-            location: None,
-            url: None,
-        }))
+        CalendarEvent::builder_of(id, title, date_begin)
+            .detail(detail)
+            .invalid(value.sell_status_code == CANCELLED_SELL_STATUS)
+            .time_begin(time_begin)
+            .end(date_end, time_end)
+            .party_size(value.admission_count)
+            .build()
+            .map(Some)
     }
 }
 
@@ -126,7 +265,8 @@ crate::define_user_data! {
     #[table_name = "megabox"]
     #[base_url = "https://www.megabox.co.kr/"]
     struct MegaboxUser {
-        #[session_name = "JSESSIONID"]
+        // login state is kept in `SESSION`, `JSESSIONID` is reissued on demand
+        #[session_name = "JSESSIONID", optional]
         jsessionid: String,
         #[session_name = "SESSION"]
         session: String,
@@ -147,59 +287,59 @@ impl MegaboxUser {
 #[async_trait]
 impl crate::UserImpl for MegaboxUser {
     type Detail = MegaboxUserDetail;
+    const SOURCE: &'static str = "megabox";
+    const SOURCE_PREFIX: &'static str = "megabox/";
 
     const PING_INTERVAL: Option<std::time::Duration> =
         Some(std::time::Duration::from_secs(10 * 60));
 
-    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<bool> {
+    fn user_id(&self) -> UserId {
+        self.user_id
+    }
+
+    fn all_users(db: &SqlitePool) -> futures::stream::BoxStream<'_, anyhow::Result<Self>> {
+        Self::all(db).boxed()
+    }
+
+    async fn scrape(&self, db: &SqlitePool) -> anyhow::Result<Vec<CalendarEvent>> {
         let jar = self.to_cookie_jar();
-        let planned_url = url!("https://www.megabox.co.kr/on/oh/ohh/MyBokdPurc/selectBokdList.do");
-        let client = reqwest::Client::new();
-        let req = client
-            .get(planned_url.as_ref())
-            .header(
-                reqwest::header::REFERER,
-                "https://www.megabox.co.kr/mypage/bookinglist",
-            )
-            .header(reqwest::header::COOKIE, jar.cookies(planned_url).unwrap())
-            .json(&serde_json::json!({
-                "divCd": "B",
-                "localeCode": "kr"
-            }))
-            .build()?;
-        let res: ReservationResponse = client.execute(req).await?.json().await?;
-        if res.status_code != 0 {
-            return Err(anyhow::anyhow!("Receive error response - {}", res.message));
+        let client = crate::http::client();
+        let mut lists = Vec::new();
+        for div_code in DIV_CODES {
+            lists.push(
+                collect_pages(|page| fetch_list(&client, &jar, div_code, page))
+                    .await
+                    .with_context(|| format!("Failed to fetch list of {div_code}"))?,
+            );
         }
+        let res = merge_lists(lists);
         if res.items.is_empty() {
-            return Ok(false);
+            return Ok(Vec::new());
         }
 
-        let reservations = res
-            .items
-            .into_iter()
-            .filter_map(|item| <Option<CalendarEvent>>::try_from(item).transpose())
-            .collect::<Result<Vec<_>, _>>()?;
+        let setting = SourceSetting::of(db, self.user_id, "megabox").await?;
+        res.into_events(setting.include_image)
+    }
 
-        let updated_item_count =
-            CalendarEvent::upsert_events_to_db(self.user_id, &db, reservations.iter()).await?;
-        info!("updated item count: {updated_item_count}",);
+    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<CrawlStats> {
+        let reservations = self.scrape(&db).await?;
+        if reservations.is_empty() {
+            return Ok(CrawlStats::default());
+        }
 
-        Ok(updated_item_count > 0)
+        self.store_reservations(&db, &reservations).await
     }
 
+    /// Requests the first page of upcoming bookings, which needs the login
     async fn ping(&self) -> anyhow::Result<()> {
         let jar = self.to_cookie_jar();
-        let url = url!("https://www.megabox.co.kr/sessionChk.do");
-        let client = reqwest::Client::new();
-        let req = client
-            .post(url.as_ref())
-            .header(reqwest::header::COOKIE, jar.cookies(url).unwrap())
-            .build()?;
-        client
-            .execute(req)
+        let client = crate::http::client();
+        let res = fetch_list(&client, &jar, DIV_CODES[0], 1)
             .await
             .context("Error occurred while sending ping")?;
+        if res.status_code != 0 {
+            anyhow::bail!("Receive error response - {}", res.message);
+        }
 
         Ok(())
     }
@@ -207,13 +347,22 @@ impl crate::UserImpl for MegaboxUser {
     async fn from_user_id(db: SqlitePool, user_id: UserId) -> anyhow::Result<Option<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT `user_id` as `user_id: UserId`, `jsessionid`, `session` FROM `megabox_user`"
+            "SELECT `user_id` as `user_id: UserId`, `jsessionid`, `session` FROM `megabox_user` WHERE `user_id` = ?",
+            user_id
         )
         .fetch_optional(&db)
         .await
         .with_context(|| format!("Failed to get megabox_user of {user_id:?}"))
     }
 
+    async fn delete(db: &mut sqlx::SqliteConnection, user_id: UserId) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM `megabox_user` WHERE `user_id` = ?", user_id)
+            .execute(db)
+            .await
+            .context("Failed to delete megabox user")
+            .map(|_| ())
+    }
+
     async fn update_session(&self, db: SqlitePool) -> anyhow::Result<()> {
         sqlx::query!(
             "INSERT INTO `megabox_user` (`jsessionid`, `session`, `user_id`) VALUES (?, ?, ?)
@@ -233,3 +382,179 @@ impl crate::UserImpl for MegaboxUser {
 pub fn web_router() -> Router {
     crate::user_web_router::<MegaboxUser>()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::user::UserDetail;
+
+    use super::*;
+
+    #[test]
+    fn parse_reservations_fixture() {
+        let res: ReservationResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/megabox.json")).unwrap();
+        assert_eq!(res.status_code, 0);
+
+        let events = res.into_events(false).unwrap();
+
+        assert_eq!(events.len(), 2);
+        let event = &events[0];
+        assert_eq!(event.id, "megabox/12345678");
+        assert_eq!(event.title, "테스트 무비 - MEGABOX 코엑스");
+        assert_eq!(event.detail, "상영관: 1관(5층)\n좌석: F10");
+        assert!(!event.invalid);
+        assert_eq!(
+            event.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+        );
+        assert_eq!(event.time_begin, chrono::NaiveTime::from_hms_opt(14, 30, 0));
+        assert_eq!(event.date_end, chrono::NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert_eq!(event.time_end, chrono::NaiveTime::from_hms_opt(16, 55, 0));
+
+        let cancelled = &events[1];
+        assert_eq!(cancelled.id, "megabox/12345679");
+        assert!(cancelled.invalid);
+    }
+
+    #[tokio::test]
+    async fn reservations_of_all_pages_are_collected() {
+        let page = |page| -> anyhow::Result<ReservationResponse> {
+            Ok(serde_json::from_str(match page {
+                1 => include_str!("../tests/fixtures/megabox.json"),
+                2 => include_str!("../tests/fixtures/megabox_page2.json"),
+                _ => r#"{"statCd": 0, "msg": "성공", "list": []}"#,
+            })?)
+        };
+
+        let mut requested = Vec::new();
+        let res = collect_pages(|n| {
+            requested.push(n);
+            std::future::ready(page(n))
+        })
+        .await
+        .unwrap();
+        assert_eq!(requested, [1, 2, 3]);
+        assert_eq!(
+            res.items
+                .iter()
+                .map(|item| item.booking_id.as_str())
+                .collect::<Vec<_>>(),
+            ["12345678", "12345679", "12345680"]
+        );
+        assert_eq!(res.image_server_url, "https://img.megabox.co.kr");
+
+        // page is ignored, the same list is given again
+        let mut requested = Vec::new();
+        let res = collect_pages(|n| {
+            requested.push(n);
+            std::future::ready(page(1))
+        })
+        .await
+        .unwrap();
+        assert_eq!(requested, [1, 2]);
+        assert_eq!(res.items.len(), 2);
+    }
+
+    #[test]
+    fn upcoming_and_past_lists_are_merged() {
+        let list = |json| -> ReservationResponse { serde_json::from_str(json).unwrap() };
+
+        let res = merge_lists(vec![
+            list(include_str!("../tests/fixtures/megabox.json")),
+            list(include_str!("../tests/fixtures/megabox_past.json")),
+        ]);
+
+        assert_eq!(
+            res.items
+                .iter()
+                .map(|item| item.booking_id.as_str())
+                .collect::<Vec<_>>(),
+            ["12345678", "12345679", "12345681"]
+        );
+        assert_eq!(res.image_server_url, "https://img.megabox.co.kr");
+        let events = res.into_events(false).unwrap();
+        assert_eq!(events[2].id, "megabox/12345681");
+        assert_eq!(events[2].title, "오늘 무비 - MEGABOX 코엑스");
+    }
+
+    #[test]
+    fn ticket_image_is_included_when_enabled() {
+        let res = || -> ReservationResponse {
+            serde_json::from_str(include_str!("../tests/fixtures/megabox.json")).unwrap()
+        };
+
+        let events = res().into_events(true).unwrap();
+        assert_eq!(
+            events[0].detail,
+            "상영관: 1관(5층)\n좌석: F10\n티켓: https://img.megabox.co.kr/SharedImg/ticket/12345678.png"
+        );
+
+        let events = res().into_events(false).unwrap();
+        assert!(!events[0].detail.contains("https://img.megabox.co.kr"));
+    }
+
+    #[test]
+    fn missing_required_session_is_reported() {
+        let detail = MegaboxUserDetail {
+            jsessionid: "jsessionid".to_string(),
+            session: "".to_string(),
+        };
+
+        assert_eq!(detail.missing_sessions(), ["SESSION"]);
+    }
+
+    #[test]
+    fn missing_optional_session_is_allowed() {
+        let detail = MegaboxUserDetail {
+            jsessionid: "".to_string(),
+            session: "session".to_string(),
+        };
+        assert!(detail.missing_sessions().is_empty());
+
+        let user = MegaboxUser::from((crate::UserId(1), detail));
+        let cookies = user
+            .to_cookie_jar()
+            .cookies(url!("https://www.megabox.co.kr/"))
+            .unwrap();
+        assert_eq!(cookies.to_str().unwrap(), "SESSION=session");
+    }
+
+    #[tokio::test]
+    async fn ping_tells_login_page_from_bookings() {
+        let user = MegaboxUser {
+            user_id: crate::testing::USER_ID,
+            jsessionid: String::new(),
+            session: "session".to_string(),
+        };
+
+        let (expired, alive) =
+            crate::vcr::Cassette::load("tests/fixtures/cassettes/megabox_ping.json")
+                .unwrap()
+                .scope(async { (user.ping().await, user.ping().await) })
+                .await
+                .unwrap();
+        assert!(expired
+            .unwrap_err()
+            .chain()
+            .any(|cause| cause.is::<SessionExpired>()));
+        alive.unwrap();
+    }
+
+    #[test]
+    fn failures_are_not_expired_sessions() {
+        let response = |status| crate::dump::RawResponse {
+            status,
+            headers: Default::default(),
+            body: "{}".into(),
+        };
+        assert!(check_response(&response(reqwest::StatusCode::UNAUTHORIZED))
+            .unwrap_err()
+            .is::<SessionExpired>());
+        assert!(
+            !check_response(&response(reqwest::StatusCode::INTERNAL_SERVER_ERROR))
+                .unwrap_err()
+                .is::<SessionExpired>()
+        );
+        check_response(&response(reqwest::StatusCode::OK)).unwrap();
+    }
+}
@@ -1,6 +1,9 @@
 import React from "react";
-import { ActionFunctionArgs, Form, Navigate, Params, useLoaderData } from "react-router-dom";
-import { AsyncReturnType, formDataToJsonString } from './utils';
+import { ActionFunctionArgs, Form, Navigate, Params, useActionData, useLoaderData } from "react-router-dom";
+import { AsyncReturnType, formDataToJsonString, postJson, request as sendRequest } from './utils';
+import { RemoveSource } from './remove_source';
+import { preview, PreviewButton, PreviewEvents, PreviewResult } from './preview';
+import { ClearSession, SessionInput } from './session_input';
 
 export async function loader() {
     const resp = await fetch("/megabox/user", {
@@ -10,8 +13,8 @@ export async function loader() {
     if (resp.ok) {
         const parsed = await resp.json();
         return {
-            jsessionid: parsed.jsessionid as string,
-            session: parsed.session as string,
+            saved: parsed.saved as string[],
+            required: parsed.required as string[],
         }
     } else {
         return null;
@@ -20,27 +23,31 @@ export async function loader() {
 
 export async function action({ request }: ActionFunctionArgs) {
     const formData = await request.formData();
-    return await fetch("/megabox/user", {
-        headers: {
-            'Content-Type': 'application/json'
-        },
-        method: "post",
-        body: formDataToJsonString(formData)
-    });
+    if (formData.get("intent") === "remove") {
+        return await sendRequest("/megabox/user", { method: "delete" });
+    }
+    if (formData.get("intent") === "preview") {
+        return await preview("/megabox/user/preview", formDataToJsonString(formData));
+    }
+    return await postJson("/megabox/user", formDataToJsonString(formData));
 }
 
 export function Component() {
     const data = useLoaderData() as AsyncReturnType<typeof loader>;
+    const result = useActionData() as PreviewResult | undefined;
 
     if (data !== null) {
         return <div>
             <Form method="post" action="/megabox">
-                <label htmlFor="jsessionid">JSESSIONID</label>
-                <input type="text" name="jsessionid" defaultValue={data.jsessionid} />
-                <label htmlFor="session">SESSION</label>
-                <input type="text" name="session" defaultValue={data.session} />
+                <SessionInput name="jsessionid" label="JSESSIONID" saved={data.saved} required={data.required} />
+                <SessionInput name="session" label="SESSION" saved={data.saved} required={data.required} />
+                {result?.error && <p role="alert">{result.error}</p>}
                 <button type="submit">Update</button>
+                <PreviewButton />
             </Form>
+            <PreviewEvents result={result} />
+            <ClearSession action="/megabox" name="jsessionid" label="JSESSIONID" saved={data.saved} required={data.required} />
+            <RemoveSource action="/megabox" name="MEGABOX" />
         </div>;
     } else {
         return <Navigate to="/" />;
@@ -0,0 +1,56 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use log::info;
+use sqlx::SqlitePool;
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
+
+/// Runs pending migrations, logging each applied one. Returns the applied
+/// versions.
+pub async fn run(db: &SqlitePool) -> anyhow::Result<Vec<i64>> {
+    // not created yet before the first migration
+    let applied = sqlx::query_scalar::<_, i64>(
+        "SELECT `version` FROM `_sqlx_migrations` WHERE `success` = TRUE",
+    )
+    .fetch_all(db)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .collect::<HashSet<_>>();
+
+    MIGRATOR.run(db).await.context("Failed to migrate DB")?;
+
+    let newly_applied = MIGRATOR
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .inspect(|migration| {
+            info!(
+                "Migration {} is applied - {}",
+                migration.version, migration.description
+            )
+        })
+        .map(|migration| migration.version)
+        .collect::<Vec<_>>();
+    if newly_applied.is_empty() {
+        info!("DB is up to date");
+    }
+
+    Ok(newly_applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn applied_versions_are_reported_once() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+        let applied = run(&db).await.unwrap();
+        assert_eq!(applied.len(), MIGRATOR.iter().count());
+        assert!(applied.windows(2).all(|versions| versions[0] < versions[1]));
+
+        assert!(run(&db).await.unwrap().is_empty());
+    }
+}
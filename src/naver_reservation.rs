@@ -3,10 +3,10 @@ use axum::{async_trait, Router};
 #[allow(unused_imports)]
 use chrono::Timelike; // false warning
 use futures::StreamExt;
-use log::info;
+use reqwest::cookie::CookieStore as _;
 use sqlx::SqlitePool;
 
-use crate::{CalendarEvent, UserId};
+use crate::{url, CalendarEvent, CrawlStats, UserId};
 
 mod graphql;
 mod main_page;
@@ -36,37 +36,72 @@ impl NaverUser {
 #[async_trait]
 impl crate::UserImpl for NaverUser {
     type Detail = NaverUserDetail;
+    const SOURCE: &'static str = "naver";
+    const SOURCE_PREFIX: &'static str = "naver/";
 
     const PING_INTERVAL: Option<std::time::Duration> = None;
 
-    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<bool> {
+    fn user_id(&self) -> UserId {
+        self.user_id
+    }
+
+    fn all_users(db: &SqlitePool) -> futures::stream::BoxStream<'_, anyhow::Result<Self>> {
+        Self::all(db).boxed()
+    }
+
+    async fn scrape(&self, _db: &SqlitePool) -> anyhow::Result<Vec<CalendarEvent>> {
         let jar = self.to_cookie_jar();
 
         let mut scrapped_reservations = main_page::fetch(&jar).await?;
         scrapped_reservations.extend(graphql::fetch(&jar).await?);
 
-        if scrapped_reservations.is_empty() {
-            Ok(false)
-        } else {
-            let updated_item_count =
-                CalendarEvent::upsert_events_to_db(self.user_id, &db, scrapped_reservations.iter())
-                    .await?;
-            info!("updated item count: {updated_item_count}",);
+        Ok(scrapped_reservations)
+    }
+
+    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<CrawlStats> {
+        let scrapped_reservations = self.scrape(&db).await?;
 
-            Ok(updated_item_count > 0)
-        }
+        CalendarEvent::upsert_events_to_db(self.user_id, &db, scrapped_reservations.iter()).await
+    }
+
+    async fn validate_session(&self) -> anyhow::Result<bool> {
+        let jar = self.to_cookie_jar();
+        let url = url!("https://m.booking.naver.com/my/bookings");
+        let client = crate::http::client_builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let req = client
+            .get(url.as_ref())
+            .headers(crate::http::headers("naver"))
+            .header(reqwest::header::COOKIE, jar.cookies(url).unwrap())
+            .build()?;
+        let res = crate::http::execute(&client, req)
+            .await
+            .context("Error occurred while checking session")?;
+
+        // redirected to the login page when the session is expired
+        Ok(res.status().is_success())
     }
 
     async fn from_user_id(db: SqlitePool, user_id: UserId) -> anyhow::Result<Option<Self>> {
         sqlx::query_as!(
             Self,
-            "SELECT `user_id` as `user_id: UserId`, `aut`, `ses` FROM `naver_user`"
+            "SELECT `user_id` as `user_id: UserId`, `aut`, `ses` FROM `naver_user` WHERE `user_id` = ?",
+            user_id
         )
         .fetch_optional(&db)
         .await
         .with_context(|| format!("Failed to get naver_user of {user_id:?}"))
     }
 
+    async fn delete(db: &mut sqlx::SqliteConnection, user_id: UserId) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM `naver_user` WHERE `user_id` = ?", user_id)
+            .execute(db)
+            .await
+            .context("Failed to delete naver user")
+            .map(|_| ())
+    }
+
     async fn update_session(&self, db: SqlitePool) -> anyhow::Result<()> {
         sqlx::query!(
             "INSERT INTO `naver_user` (`ses`, `aut`, `user_id`) VALUES (?, ?, ?)
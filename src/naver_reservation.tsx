@@ -1,6 +1,9 @@
 import React from "react";
-import { ActionFunctionArgs, Form, Navigate, Params, useLoaderData } from "react-router-dom";
-import { AsyncReturnType, formDataToJsonString } from './utils';
+import { ActionFunctionArgs, Form, Navigate, Params, useActionData, useLoaderData } from "react-router-dom";
+import { AsyncReturnType, formDataToJsonString, postJson, request as sendRequest } from './utils';
+import { RemoveSource } from './remove_source';
+import { preview, PreviewButton, PreviewEvents, PreviewResult } from './preview';
+import { SessionInput } from './session_input';
 
 export async function loader() {
     const resp = await fetch("/naver/user", {
@@ -10,8 +13,8 @@ export async function loader() {
     if (resp.ok) {
         const parsed = await resp.json();
         return {
-            ses: parsed.ses as string,
-            aut: parsed.aut as string,
+            saved: parsed.saved as string[],
+            required: parsed.required as string[],
         }
     } else {
         return null;
@@ -20,27 +23,30 @@ export async function loader() {
 
 export async function action({ request }: ActionFunctionArgs) {
     const formData = await request.formData();
-    return await fetch("/naver/user", {
-        headers: {
-            'Content-Type': 'application/json'
-        },
-        method: "POST",
-        body: formDataToJsonString(formData)
-    });
+    if (formData.get("intent") === "remove") {
+        return await sendRequest("/naver/user", { method: "delete" });
+    }
+    if (formData.get("intent") === "preview") {
+        return await preview("/naver/user/preview", formDataToJsonString(formData));
+    }
+    return await postJson("/naver/user", formDataToJsonString(formData));
 }
 
 export function Component() {
     const data = useLoaderData() as AsyncReturnType<typeof loader>;
+    const result = useActionData() as PreviewResult | undefined;
 
     if (data !== null) {
         return <div>
             <Form method="post" action="/naver">
-                <label htmlFor="ses">SES</label>
-                <input type="text" name="ses" defaultValue={data.ses} />
-                <label htmlFor="aut">AUT</label>
-                <input type="text" name="aut" defaultValue={data.aut} />
+                <SessionInput name="ses" label="SES" saved={data.saved} required={data.required} />
+                <SessionInput name="aut" label="AUT" saved={data.saved} required={data.required} />
+                {result?.error && <p role="alert">{result.error}</p>}
                 <button type="submit">Update</button>
+                <PreviewButton />
             </Form>
+            <PreviewEvents result={result} />
+            <RemoveSource action="/naver" name="Naver" />
         </div>;
     } else {
         return <Navigate to="/" />;
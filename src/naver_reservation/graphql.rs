@@ -1,10 +1,12 @@
 use std::{fmt::Display, ops::Add};
 
-use anyhow::{anyhow, Context};
+use anyhow::anyhow;
+use log::warn;
 use reqwest::cookie::{CookieStore, Jar};
 use serde_with::serde_as;
 
-use crate::{url, CalendarEvent, USER_AGENT};
+use super::NaverUser;
+use crate::{kst, url, CalendarEvent, UserImpl as _};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 enum ReservationStatusCode {
@@ -24,7 +26,7 @@ enum ReservationStatusCode {
 
 impl Display for ReservationStatusCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let stringified = unsafe { serde_json::to_string(self).unwrap_unchecked() };
+        let stringified = serde_json::to_string(self).map_err(|_| std::fmt::Error)?;
         write!(f, "{}", stringified.trim_matches('"'))
     }
 }
@@ -93,6 +95,9 @@ struct Booking {
     #[serde_as(deserialize_as = "serde_with::DefaultOnError")]
     business_item_address_json: Option<Address>,
     booking_time_unit_code: BookingTimeUnitCode,
+    /// Start of every slot when a booking takes multiple slots at once, e.g. weekly classes
+    #[serde(default)]
+    repeat_start_date_times: Vec<chrono::DateTime<chrono::Utc>>,
 }
 
 impl Booking {
@@ -108,9 +113,7 @@ impl Booking {
             BookingTimeUnitCode::Daily => {
                 // make fit to google calendar...
                 let timezone = match self.global_timezone.as_str() {
-                    "Asia/Seoul" => unsafe {
-                        chrono::FixedOffset::east_opt(9 * 3600).unwrap_unchecked()
-                    },
+                    "Asia/Seoul" => kst(),
                     timezone => return Err(anyhow!("Not mapped timezone found - {timezone}")),
                 };
                 let start_date_time = self.start_date_time.with_timezone(&timezone).date_naive();
@@ -124,21 +127,67 @@ impl Booking {
             // other cases has valid date & time info
             _ => {
                 let start_date_time = self.start_date_time.naive_utc();
+                // in UTC, the end date follows when the booking crosses midnight
+                let end_date_time = (self.end_date_time > self.start_date_time)
+                    .then(|| self.end_date_time.naive_utc());
                 (
                     start_date_time.date(),
                     Some(start_date_time.time()),
-                    None,
-                    None,
+                    end_date_time.map(|end| end.date()),
+                    end_date_time.map(|end| end.time()),
                 )
             }
         })
     }
 
-    fn location(&self) -> String {
-        let address = self
-            .business_item_address_json
+    /// `RRULE` for a booking of evenly spaced slots
+    fn recurrence(&self) -> Option<String> {
+        if matches!(self.booking_time_unit_code, BookingTimeUnitCode::Daily) {
+            return None;
+        }
+
+        let mut slots = self.repeat_start_date_times.clone();
+        slots.push(self.start_date_time);
+        slots.sort();
+        slots.dedup();
+        if slots.len() < 2 || slots[0] != self.start_date_time {
+            return None;
+        }
+
+        let interval = slots[1] - slots[0];
+        if slots.windows(2).any(|slot| slot[1] - slot[0] != interval) {
+            warn!(
+                "Slots of naver/{} are not evenly spaced, only the first one is synced",
+                self.booking_id
+            );
+            return None;
+        }
+
+        let days = interval.num_days();
+        if interval != chrono::Duration::days(days) {
+            return None;
+        }
+        let (freq, interval) = if days % 7 == 0 {
+            ("WEEKLY", days / 7)
+        } else {
+            ("DAILY", days)
+        };
+
+        Some(if interval == 1 {
+            format!("FREQ={freq};COUNT={}", slots.len())
+        } else {
+            format!("FREQ={freq};INTERVAL={interval};COUNT={}", slots.len())
+        })
+    }
+
+    fn address(&self) -> &Address {
+        self.business_item_address_json
             .as_ref()
-            .unwrap_or(&self.business_address_json);
+            .unwrap_or(&self.business_address_json)
+    }
+
+    fn location(&self) -> String {
+        let address = self.address();
         if let Some(place_name) = &address.place_name {
             if let Some(detail) = &address.detail {
                 format!(
@@ -159,32 +208,61 @@ impl Booking {
                 .clone()
         }
     }
+
+    /// Google Maps link to the place, searched by the address when the
+    /// coordinate is not given
+    fn map_url(&self) -> String {
+        let address = self.address();
+        match (address.latitude, address.longitude) {
+            (Some(latitude), Some(longitude)) => {
+                format!("https://www.google.com/maps/search/?api=1&query={latitude},{longitude}")
+            }
+            _ => {
+                let mut url = reqwest::Url::parse("https://www.google.com/maps/search/?api=1")
+                    .expect("valid url");
+                url.query_pairs_mut().append_pair("query", &self.location());
+                url.to_string()
+            }
+        }
+    }
 }
 
 impl TryFrom<BookingWrap> for CalendarEvent {
     type Error = anyhow::Error;
 
     fn try_from(booking: BookingWrap) -> Result<Self, Self::Error> {
-        let id = format!("naver/{}", booking.snapshot_json.booking_id);
+        let id = format!(
+            "{}{}",
+            NaverUser::SOURCE_PREFIX,
+            booking.snapshot_json.booking_id
+        );
         let (date_begin, time_begin, date_end, time_end) = booking.snapshot_json.get_date_time()?;
         let url = Some(format!(
             "https://m.booking.naver.com/my/bookings/{}",
             booking.snapshot_json.booking_id
         ));
         let location = Some(booking.snapshot_json.location());
+        let detail = format!(
+            "{}\n지도: {}",
+            booking.snapshot_json.business_item_name,
+            booking.snapshot_json.map_url()
+        );
+        let recurrence = booking
+            .snapshot_json
+            .recurrence()
+            .map(|rrule| vec![format!("RRULE:{rrule}")]);
 
-        Ok(CalendarEvent {
-            id,
-            title: booking.snapshot_json.service_name,
-            detail: booking.snapshot_json.business_item_name,
-            invalid: booking.booking_status_code == ReservationStatusCode::Cancelled,
-            date_begin,
-            time_begin,
-            date_end,
-            time_end,
-            url,
-            location,
-        })
+        CalendarEvent::builder(id, booking.snapshot_json.service_name, date_begin)
+            .detail(detail)
+            .invalid(booking.booking_status_code == ReservationStatusCode::Cancelled)
+            // not confirmed by the business yet
+            .tentative(booking.booking_status_code == ReservationStatusCode::Requested)
+            .time_begin(time_begin)
+            .end(date_end, time_end)
+            .url(url)
+            .location(location)
+            .recurrence(recurrence)
+            .build()
     }
 }
 
@@ -194,6 +272,7 @@ struct ReservationOption {
     // name: String,
 }
 
+#[serde_as]
 #[derive(Debug, Clone, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Address {
@@ -201,10 +280,17 @@ struct Address {
     address: String,
     place_name: Option<String>,
     detail: Option<String>,
+    /// Given in number or string
+    #[serde(rename = "posLat", default)]
+    #[serde_as(as = "Option<serde_with::PickFirst<(_, serde_with::DisplayFromStr)>>")]
+    latitude: Option<f64>,
+    #[serde(rename = "posLong", default)]
+    #[serde_as(as = "Option<serde_with::PickFirst<(_, serde_with::DisplayFromStr)>>")]
+    longitude: Option<f64>,
 }
 
 pub(super) async fn fetch(jar: &Jar) -> anyhow::Result<Vec<CalendarEvent>> {
-    let client = reqwest::Client::new();
+    let client = crate::http::client();
     let graphql_url = url!("https://m.booking.naver.com/graphql");
     let payload = serde_json::json!({
         "operationName": "bookings",
@@ -254,17 +340,12 @@ pub(super) async fn fetch(jar: &Jar) -> anyhow::Result<Vec<CalendarEvent>> {
     });
     let req = client
         .post(graphql_url.as_ref())
+        .headers(crate::http::headers("naver"))
         .header(reqwest::header::COOKIE, jar.cookies(graphql_url).unwrap())
-        .header(reqwest::header::USER_AGENT, USER_AGENT)
         .json(&payload)
         .build()?;
-    let res = client.execute(req).await?;
-    let res = res.bytes().await?;
-    let res: NaverCalendarResponse = serde_json::from_slice(&res).with_context(|| {
-        format!("Failed to parse\n{}", unsafe {
-            std::str::from_utf8_unchecked(&res)
-        })
-    })?;
+    let res: NaverCalendarResponse =
+        crate::dump::parse_json("naver", crate::http::execute(&client, req).await?).await?;
 
     res.data
         .booking
@@ -273,3 +354,156 @@ pub(super) async fn fetch(jar: &Jar) -> anyhow::Result<Vec<CalendarEvent>> {
         .map(TryFrom::try_from)
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone as _;
+
+    use super::*;
+
+    #[test]
+    fn parse_bookings_fixture() {
+        let res: NaverCalendarResponse =
+            serde_json::from_str(include_str!("../../tests/fixtures/naver_graphql.json")).unwrap();
+        let events = res
+            .data
+            .booking
+            .bookings
+            .into_iter()
+            .map(CalendarEvent::try_from)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(events.len(), 4);
+
+        let timed = &events[0];
+        assert_eq!(timed.id, "naver/1001");
+        assert_eq!(timed.title, "테스트 스튜디오");
+        assert_eq!(
+            timed.detail,
+            "A홀 2시간\n지도: https://www.google.com/maps/search/?api=1&query=37.5006,127.0364"
+        );
+        assert!(!timed.invalid);
+        assert!(!timed.tentative);
+        assert_eq!(
+            timed.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+        );
+        assert_eq!(timed.time_begin, chrono::NaiveTime::from_hms_opt(10, 0, 0));
+        assert_eq!(timed.date_end, chrono::NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert_eq!(timed.time_end, chrono::NaiveTime::from_hms_opt(12, 0, 0));
+        assert_eq!(
+            timed.location.as_deref(),
+            Some("서울 강남구 테헤란로 1 테스트빌딩 3층")
+        );
+        assert_eq!(
+            timed.url.as_deref(),
+            Some("https://m.booking.naver.com/my/bookings/1001")
+        );
+        assert_eq!(timed.recurrence, None);
+
+        let daily = &events[1];
+        assert_eq!(daily.id, "naver/1002");
+        assert!(daily.invalid);
+        assert_eq!(
+            daily.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 6, 1).unwrap()
+        );
+        assert_eq!(daily.time_begin, None);
+        assert_eq!(daily.date_end, chrono::NaiveDate::from_ymd_opt(2024, 6, 3));
+        assert_eq!(daily.location.as_deref(), Some("강원 강릉시 주문진읍 1"));
+        // without coordinate, searched by the address
+        assert_eq!(
+            daily.detail,
+            "디럭스룸\n지도: https://www.google.com/maps/search/?api=1&query=%EA%B0%95%EC%9B%90+%EA%B0%95%EB%A6%89%EC%8B%9C+%EC%A3%BC%EB%AC%B8%EC%A7%84%EC%9D%8D+1"
+        );
+        assert_eq!(daily.recurrence, None);
+
+        let weekly = &events[2];
+        assert_eq!(weekly.id, "naver/1003");
+        assert_eq!(
+            weekly.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 7).unwrap()
+        );
+        assert_eq!(weekly.time_begin, chrono::NaiveTime::from_hms_opt(11, 0, 0));
+        assert_eq!(
+            weekly.recurrence,
+            Some(vec!["RRULE:FREQ=WEEKLY;COUNT=4".to_string()])
+        );
+
+        let requested = &events[3];
+        assert_eq!(requested.id, "naver/1004");
+        assert!(requested.tentative);
+        assert!(!requested.invalid);
+    }
+
+    #[test]
+    fn booking_crossing_midnight_ends_next_day() {
+        let res: NaverCalendarResponse =
+            serde_json::from_str(include_str!("../../tests/fixtures/naver_graphql.json")).unwrap();
+        let mut booking = res.data.booking.bookings[0].snapshot_json.clone();
+        let kst_at = |day, hour| {
+            kst()
+                .with_ymd_and_hms(2024, 5, day, hour, 0, 0)
+                .unwrap()
+                .with_timezone(&chrono::Utc)
+        };
+        // 3 hours studio rental from 22:00 KST
+        booking.start_date_time = kst_at(1, 22);
+        booking.end_date_time = kst_at(2, 1);
+
+        let (date_begin, time_begin, date_end, time_end) = booking.get_date_time().unwrap();
+        let begin = date_begin.and_time(time_begin.unwrap());
+        let end = date_end.unwrap().and_time(time_end.unwrap());
+        assert_eq!(begin, kst_at(1, 22).naive_utc());
+        assert_eq!(end, kst_at(2, 1).naive_utc());
+        // in KST, the end is on the next day
+        assert_eq!(
+            kst().from_utc_datetime(&end).date_naive(),
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 2).unwrap()
+        );
+        assert_eq!(end - begin, chrono::Duration::hours(3));
+
+        // 15:00 UTC is midnight of KST, crossing the UTC date
+        booking.start_date_time = kst_at(1, 23);
+        booking.end_date_time = kst_at(2, 10);
+        let (date_begin, _, date_end, _) = booking.get_date_time().unwrap();
+        assert_eq!(
+            date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+        );
+        assert_eq!(date_end, chrono::NaiveDate::from_ymd_opt(2024, 5, 2));
+    }
+
+    #[test]
+    fn recurrence_requires_evenly_spaced_slots() {
+        let res: NaverCalendarResponse =
+            serde_json::from_str(include_str!("../../tests/fixtures/naver_graphql.json")).unwrap();
+        let mut booking = res.data.booking.bookings[2].snapshot_json.clone();
+        let at = |day| {
+            chrono::NaiveDate::from_ymd_opt(2024, 5, day)
+                .unwrap()
+                .and_hms_opt(11, 0, 0)
+                .unwrap()
+                .and_utc()
+        };
+
+        booking.repeat_start_date_times = vec![at(11), at(9)];
+        assert_eq!(
+            booking.recurrence().as_deref(),
+            Some("FREQ=DAILY;INTERVAL=2;COUNT=3")
+        );
+
+        booking.repeat_start_date_times = vec![at(21)];
+        assert_eq!(
+            booking.recurrence().as_deref(),
+            Some("FREQ=WEEKLY;INTERVAL=2;COUNT=2")
+        );
+
+        booking.repeat_start_date_times = vec![at(8), at(21)];
+        assert_eq!(booking.recurrence(), None);
+
+        booking.repeat_start_date_times = Vec::new();
+        assert_eq!(booking.recurrence(), None);
+    }
+}
@@ -8,7 +8,7 @@ use reqwest::cookie::{CookieStore, Jar};
 use scraper::Html;
 use serde::de::Visitor;
 
-use crate::{selector, url, CalendarEvent, USER_AGENT};
+use crate::{selector, url, user::SessionExpired, CalendarEvent};
 
 use super::graphql::BookingWrap;
 
@@ -86,18 +86,45 @@ impl<'de> serde::Deserialize<'de> for MainPageApolloState {
 }
 
 pub(super) async fn fetch(jar: &Jar) -> anyhow::Result<Vec<CalendarEvent>> {
-    let client = reqwest::Client::new();
+    let client = crate::http::client();
     let main_url = url!("https://m.booking.naver.com/my/bookings");
     let req = client
         .post(main_url.as_ref())
+        .headers(crate::http::headers("naver"))
         .header(reqwest::header::COOKIE, jar.cookies(main_url).unwrap())
-        .header(reqwest::header::USER_AGENT, USER_AGENT)
         .build()?;
 
-    let res = client.execute(req).await?;
+    let res = crate::http::execute(&client, req).await?;
+    let status = res.status();
+    let url = res.url().clone();
     let res = res.bytes().await?;
 
     let html = std::str::from_utf8(&res)?;
+    check_login_page(status, &url, html)?;
+
+    parse(html)
+}
+
+/// With an expired session, the page is redirected to the login page of
+/// `nid.naver.com`, which has no apollo state either
+fn check_login_page(
+    status: reqwest::StatusCode,
+    url: &reqwest::Url,
+    html: &str,
+) -> anyhow::Result<()> {
+    if status == reqwest::StatusCode::UNAUTHORIZED {
+        return Err(SessionExpired(format!("Naver session expired - {status}")).into());
+    }
+    if url.host_str() == Some("nid.naver.com") || html.contains("nidlogin.login") {
+        return Err(
+            SessionExpired("Naver session expired - login page is returned".to_string()).into(),
+        );
+    }
+
+    Ok(())
+}
+
+fn parse(html: &str) -> anyhow::Result<Vec<CalendarEvent>> {
     let fragment = Html::parse_fragment(html);
 
     for script in fragment.select(selector!("script:not([src]):not([id])")) {
@@ -121,3 +148,84 @@ pub(super) async fn fetch(jar: &Jar) -> anyhow::Result<Vec<CalendarEvent>> {
 
     Err(anyhow!("Cannot find apollo state from main page"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../../tests/fixtures/naver_main_page.html");
+
+    #[test]
+    fn apollo_state_extracts_booking_details() {
+        let html = Html::parse_fragment(FIXTURE);
+        let text = html
+            .select(selector!("script:not([src]):not([id])"))
+            .map(|script| script.text().join(""))
+            .find_map(|text| {
+                text.trim()
+                    .strip_prefix("window.__APOLLO_STATE__=")
+                    .map(str::to_string)
+            })
+            .unwrap();
+        let state: MainPageApolloState = json5::from_str(&text).unwrap();
+
+        assert_eq!(
+            state.upcoming_bookings,
+            HashSet::from(["BookingDetails:2001".to_string()])
+        );
+        assert_eq!(state.bookings.len(), 2);
+        assert!(state.bookings.contains_key("BookingDetails:2001"));
+        assert!(state.bookings.contains_key("BookingDetails:1999"));
+    }
+
+    #[test]
+    fn parse_keeps_only_upcoming_bookings() {
+        let events = parse(FIXTURE).unwrap();
+
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.id, "naver/2001");
+        assert_eq!(event.title, "테스트 미용실");
+        assert_eq!(
+            event.detail,
+            "커트\n지도: https://www.google.com/maps/search/?api=1&query=%EC%84%9C%EC%9A%B8+%EB%A7%88%ED%8F%AC%EA%B5%AC+%EC%9B%94%EB%93%9C%EC%BB%B5%EB%A1%9C+1"
+        );
+        assert!(!event.invalid);
+        assert_eq!(
+            event.date_begin,
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 2).unwrap()
+        );
+        assert_eq!(event.time_begin, chrono::NaiveTime::from_hms_opt(2, 30, 0));
+        assert_eq!(event.location.as_deref(), Some("서울 마포구 월드컵로 1"));
+    }
+
+    #[test]
+    fn login_page_is_session_expired() {
+        let bookings = reqwest::Url::parse("https://m.booking.naver.com/my/bookings").unwrap();
+        let login_page = check_login_page(
+            reqwest::StatusCode::OK,
+            &bookings,
+            include_str!("../../tests/fixtures/naver_login_page.html"),
+        )
+        .unwrap_err();
+        assert!(login_page.is::<SessionExpired>());
+
+        let redirected =
+            reqwest::Url::parse("https://nid.naver.com/nidlogin.login?mode=form").unwrap();
+        assert!(check_login_page(reqwest::StatusCode::OK, &redirected, "")
+            .unwrap_err()
+            .is::<SessionExpired>());
+        assert!(
+            check_login_page(reqwest::StatusCode::UNAUTHORIZED, &bookings, "")
+                .unwrap_err()
+                .is::<SessionExpired>()
+        );
+
+        check_login_page(reqwest::StatusCode::OK, &bookings, FIXTURE).unwrap();
+    }
+
+    #[test]
+    fn parse_fails_without_apollo_state() {
+        assert!(parse("<html><body></body></html>").is_err());
+    }
+}
@@ -0,0 +1,60 @@
+import React from "react";
+import { ActionResult, FoundReservation } from './utils';
+
+export interface PreviewResult extends ActionResult {
+    events?: FoundReservation[];
+}
+
+// Scrapes the source with the cookies of the form without storing anything,
+// blank ones are filled from the saved cookies like updating
+export async function preview(url: string, body: string): Promise<PreviewResult> {
+    try {
+        const resp = await fetch(url, {
+            credentials: "same-origin",
+            headers: {
+                'Content-Type': 'application/json'
+            },
+            method: "post",
+            body,
+        });
+        if (resp.ok) {
+            return { error: null, events: await resp.json() as FoundReservation[] };
+        }
+        const message = await resp.text();
+        return { error: message !== "" ? message : `${resp.status} ${resp.statusText}` };
+    } catch (e) {
+        return { error: `${e}` };
+    }
+}
+
+// Submits `intent=preview` with the fields of the form it is in
+export function PreviewButton() {
+    return <button type="submit" name="intent" value="preview" className="secondary">Preview</button>;
+}
+
+export function PreviewEvents({ result }: { result: PreviewResult | undefined }) {
+    if (result?.events === undefined) {
+        return null;
+    }
+    if (result.events.length === 0) {
+        return <p>No reservations are found</p>;
+    }
+    return <table>
+        <thead>
+            <tr>
+                <th>Title</th>
+                <th>Begin</th>
+                <th>End</th>
+                <th>Location</th>
+            </tr>
+        </thead>
+        <tbody>
+            {result.events.map((event) => <tr key={event.id}>
+                <td>{event.invalid ? <del>{event.title}</del> : event.title}</td>
+                <td>{event.date_begin} {event.time_begin}</td>
+                <td>{event.date_end} {event.time_end}</td>
+                <td>{event.location ?? ""}</td>
+            </tr>)}
+        </tbody>
+    </table>;
+}
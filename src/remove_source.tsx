@@ -0,0 +1,14 @@
+import React from "react";
+import { Form } from "react-router-dom";
+
+// Submits `intent=remove` to the action of the source page
+export function RemoveSource({ action, name }: { action: string; name: string }) {
+    return <Form method="post" action={action} onSubmit={(event) => {
+        if (!confirm(`Remove ${name} with all of its events from google calendar?`)) {
+            event.preventDefault();
+        }
+    }}>
+        <input type="hidden" name="intent" value="remove" />
+        <button type="submit" className="secondary">Remove</button>
+    </Form>;
+}
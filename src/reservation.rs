@@ -1,9 +1,13 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, fmt::Display, ops::AddAssign};
 
-use log::info;
+use anyhow::Context as _;
+use log::{info, warn};
 use sqlx::{Row as _, SqlitePool};
 
-use crate::UserId;
+use crate::{
+    title::{Locale, Title},
+    UserId,
+};
 
 #[repr(transparent)]
 #[derive(Debug, Clone, PartialEq, Eq, sqlx::Type)]
@@ -51,6 +55,95 @@ impl AsRef<str> for EventId {
     }
 }
 
+const DEFAULT_MAX_DAYS: i64 = 2 * 365;
+
+static DATE_BOUNDS: once_cell::sync::OnceCell<DateBounds> = once_cell::sync::OnceCell::new();
+
+/// How far from today the begin date of scraped reservations can be, read from
+/// `RESERVATION_MAX_PAST_DAYS` and `RESERVATION_MAX_FUTURE_DAYS`. Reservations
+/// out of it are taken as a broken parse, e.g. a wrong year, and not stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DateBounds {
+    pub max_past_days: i64,
+    pub max_future_days: i64,
+}
+
+impl Default for DateBounds {
+    fn default() -> Self {
+        Self {
+            max_past_days: DEFAULT_MAX_DAYS,
+            max_future_days: DEFAULT_MAX_DAYS,
+        }
+    }
+}
+
+impl DateBounds {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let days = |name| -> anyhow::Result<i64> {
+            let days = var(name)
+                .map(|value| value.parse())
+                .transpose()
+                .with_context(|| format!("Invalid {name}"))?
+                .unwrap_or(DEFAULT_MAX_DAYS);
+            anyhow::ensure!(days > 0, "{name} must be positive");
+            Ok(days)
+        };
+
+        Ok(Self {
+            max_past_days: days("RESERVATION_MAX_PAST_DAYS")?,
+            max_future_days: days("RESERVATION_MAX_FUTURE_DAYS")?,
+        })
+    }
+
+    /// Bounds of the crawls of this process, the default is used until set
+    pub fn init(self) -> anyhow::Result<()> {
+        DATE_BOUNDS
+            .set(self)
+            .map_err(|_| anyhow::anyhow!("Date bounds are already set"))
+    }
+
+    fn get() -> Self {
+        *DATE_BOUNDS.get_or_init(Self::default)
+    }
+
+    /// Recurring events are only bounded in the future, as a series can
+    /// begin long ago
+    fn contains(&self, event: &CalendarEvent, today: chrono::NaiveDate) -> bool {
+        let days = (event.date_begin - today).num_days();
+        days <= self.max_future_days && (event.recurrence.is_some() || days >= -self.max_past_days)
+    }
+}
+
+/// Max count of ids bound in an `IN (...)` at once. SQLite limits the count of
+/// variables in a statement, 999 before 3.32.0. Bulk inserts bind a row of
+/// several variables for each item, so fewer rows fit in a statement.
+pub(crate) const ID_CHUNK_SIZE: usize = 900;
+
+/// Variables bound for each row of the reservation upsert
+const UPSERT_COLUMNS: usize = 17;
+
+/// Duration of an event without the end, by the kind of the source in minutes.
+/// Bus trips have their ends computed by the sources, others not in the table
+/// such as iCal feeds are kept as they are.
+const DEFAULT_DURATIONS: &[(&str, i64)] = &[
+    // movie
+    ("cgv", 120),
+    ("megabox", 120),
+    ("lotte_cinema", 120),
+    // dining
+    ("catch_table", 90),
+    ("naver", 90),
+];
+
+/// Korea standard time, in which the sources show their schedules
+pub fn kst() -> chrono::FixedOffset {
+    chrono::FixedOffset::east_opt(9 * 60 * 60).unwrap()
+}
+
 pub fn date_time_to_utc(
     date: chrono::NaiveDate,
     time: chrono::NaiveTime,
@@ -65,6 +158,76 @@ pub fn date_time_to_utc(
     (date_time.date(), date_time.time())
 }
 
+/// Inverse of [`date_time_to_utc`], local date and time in `tz`
+pub fn date_time_from_utc(
+    date: chrono::NaiveDate,
+    time: chrono::NaiveTime,
+    tz: &impl chrono::TimeZone,
+) -> (chrono::NaiveDate, chrono::NaiveTime) {
+    let date_time = tz.from_utc_datetime(&date.and_time(time)).naive_local();
+    (date_time.date(), date_time.time())
+}
+
+/// Date and time of a show listed in the hours of its business day like
+/// cinemas do, e.g. `25:10` of a day for `01:10` of the next day
+pub(crate) fn business_day_time(
+    date: chrono::NaiveDate,
+    hour: u32,
+    minute: u32,
+) -> anyhow::Result<(chrono::NaiveDate, chrono::NaiveTime)> {
+    let time = chrono::NaiveTime::from_hms_opt(hour % 24, minute, 0)
+        .with_context(|| format!("Invalid time {hour}:{minute}"))?;
+    let date = date
+        .checked_add_days(chrono::Days::new((hour / 24).into()))
+        .context("Date out of range")?;
+    Ok((date, time))
+}
+
+/// What a crawl did, logged for each crawl and summed up per user by the poll
+/// job
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct CrawlStats {
+    /// Reservations scraped from the source
+    pub fetched: u64,
+    pub new: u64,
+    /// Existing reservations changed by the crawl
+    pub updated: u64,
+    /// Reservations no longer listed by the source
+    pub cancelled: u64,
+    /// Crawls failed
+    pub errors: u64,
+    /// Reservations dropped for the begin date out of [`DateBounds`]
+    pub rejected: u64,
+}
+
+impl CrawlStats {
+    /// Reservations to be synced to google calendar
+    pub fn changed(&self) -> u64 {
+        self.new + self.updated + self.cancelled
+    }
+}
+
+impl AddAssign for CrawlStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.fetched += rhs.fetched;
+        self.new += rhs.new;
+        self.updated += rhs.updated;
+        self.cancelled += rhs.cancelled;
+        self.errors += rhs.errors;
+        self.rejected += rhs.rejected;
+    }
+}
+
+impl Display for CrawlStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "fetched={} new={} updated={} cancelled={} errors={} rejected={}",
+            self.fetched, self.new, self.updated, self.cancelled, self.errors, self.rejected
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CalendarEvent {
     pub id: String,
@@ -77,66 +240,341 @@ pub struct CalendarEvent {
     pub time_end: Option<chrono::NaiveTime>,
     pub location: Option<String>,
     pub url: Option<String>,
+    /// `RRULE`, `EXDATE`... lines of recurring event
+    pub recurrence: Option<Vec<String>>,
+    /// Not confirmed yet, e.g. on a waiting list
+    pub tentative: bool,
+    /// Parts of `title` to compose it in the locale of the user, `title` is
+    /// in the default locale
+    pub title_parts: Option<Title>,
+    /// Number of people of the booking, when the source tells it
+    pub party_size: Option<u32>,
+}
+
+/// Builds a [`CalendarEvent`] of a scraped reservation, checked to be placed
+/// on a calendar as is
+pub struct CalendarEventBuilder(CalendarEvent);
+
+impl CalendarEventBuilder {
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.0.detail = detail.into();
+        self
+    }
+
+    pub fn invalid(mut self, invalid: bool) -> Self {
+        self.0.invalid = invalid;
+        self
+    }
+
+    pub fn time_begin(mut self, time_begin: impl Into<Option<chrono::NaiveTime>>) -> Self {
+        self.0.time_begin = time_begin.into();
+        self
+    }
+
+    pub fn end(
+        mut self,
+        date_end: impl Into<Option<chrono::NaiveDate>>,
+        time_end: impl Into<Option<chrono::NaiveTime>>,
+    ) -> Self {
+        self.0.date_end = date_end.into();
+        self.0.time_end = time_end.into();
+        self
+    }
+
+    pub fn location(mut self, location: impl Into<Option<String>>) -> Self {
+        self.0.location = location.into();
+        self
+    }
+
+    pub fn url(mut self, url: impl Into<Option<String>>) -> Self {
+        self.0.url = url.into();
+        self
+    }
+
+    pub fn recurrence(mut self, recurrence: impl Into<Option<Vec<String>>>) -> Self {
+        self.0.recurrence = recurrence.into();
+        self
+    }
+
+    pub fn tentative(mut self, tentative: bool) -> Self {
+        self.0.tentative = tentative;
+        self
+    }
+
+    /// Shown at the end of the description in the locale of the user on sync
+    pub fn party_size(mut self, party_size: impl Into<Option<u32>>) -> Self {
+        self.0.party_size = party_size.into();
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<CalendarEvent> {
+        let event = self.0;
+        anyhow::ensure!(
+            !event.title.trim().is_empty(),
+            "Title of {} is empty",
+            event.id
+        );
+        anyhow::ensure!(
+            event.time_end.is_none() || event.date_end.is_some(),
+            "End time of {} is set without the end date",
+            event.id
+        );
+        if let Some(date_end) = event.date_end {
+            let ends_before = match (event.time_begin, event.time_end) {
+                (Some(time_begin), Some(time_end)) => {
+                    date_end.and_time(time_end) < event.date_begin.and_time(time_begin)
+                }
+                _ => date_end < event.date_begin,
+            };
+            anyhow::ensure!(
+                !ends_before,
+                "{} ends before it begins - {:?} {:?} ~ {:?} {:?}",
+                event.id,
+                event.date_begin,
+                event.time_begin,
+                event.date_end,
+                event.time_end
+            );
+        }
+
+        Ok(event)
+    }
 }
 
 impl CalendarEvent {
+    pub fn builder(
+        id: impl Into<String>,
+        title: impl Into<String>,
+        date_begin: chrono::NaiveDate,
+    ) -> CalendarEventBuilder {
+        CalendarEventBuilder(CalendarEvent {
+            id: id.into(),
+            title: title.into(),
+            detail: String::new(),
+            invalid: false,
+            date_begin,
+            time_begin: None,
+            date_end: None,
+            time_end: None,
+            location: None,
+            url: None,
+            recurrence: None,
+            tentative: false,
+            title_parts: None,
+            party_size: None,
+        })
+    }
+
+    /// Same as [`Self::builder`], with the title composed in the locale of the
+    /// user on sync
+    pub fn builder_of(
+        id: impl Into<String>,
+        title: Title,
+        date_begin: chrono::NaiveDate,
+    ) -> CalendarEventBuilder {
+        let mut builder = Self::builder(id, title.format(Locale::default()), date_begin);
+        builder.0.title_parts = Some(title);
+        builder
+    }
+
+    /// Title in `locale`, the stored one without the parts
+    pub fn title_in(&self, locale: Locale) -> String {
+        self.title_parts
+            .as_ref()
+            .map(|parts| parts.format(locale))
+            .unwrap_or_else(|| self.title.clone())
+    }
+
+    /// Title filled in the template of the source, the title in `locale` when
+    /// the event doesn't have a part the template uses
+    pub fn title_with(&self, locale: Locale, template: Option<&str>) -> String {
+        let title = self.title_in(locale);
+        let Some(template) = template else {
+            return title;
+        };
+        let mut parts = self
+            .title_parts
+            .as_ref()
+            .map(|parts| parts.parts(locale))
+            .unwrap_or_default();
+        parts.insert("title", title.clone());
+        crate::title::render_template(template, &parts).unwrap_or(title)
+    }
+
+    pub(crate) fn title_parts_to_db(title_parts: &Option<Title>) -> Option<String> {
+        title_parts
+            .as_ref()
+            .map(|parts| serde_json::to_string(parts).unwrap())
+    }
+
+    /// Unknown parts, e.g. of a newer version, fall back to the stored title
+    pub(crate) fn title_parts_from_db(title_parts: Option<String>) -> Option<Title> {
+        title_parts.and_then(|parts| serde_json::from_str(&parts).ok())
+    }
+
+    /// Recurrence lines are stored joined with new line
+    pub(crate) fn recurrence_to_db(recurrence: &Option<Vec<String>>) -> Option<String> {
+        recurrence.as_ref().map(|lines| lines.join("\n"))
+    }
+
+    pub(crate) fn recurrence_from_db(recurrence: Option<String>) -> Option<Vec<String>> {
+        recurrence.map(|lines| lines.lines().map(ToString::to_string).collect())
+    }
+
+    pub(crate) fn source_prefix(&self) -> &str {
+        self.id
+            .split_once('/')
+            .map(|(prefix, _)| prefix)
+            .unwrap_or("")
+    }
+
+    /// Ends the event after `duration` minutes, or the default duration of the
+    /// source, when it has a start time but no end
+    pub(crate) fn with_default_end(mut self, duration: Option<u32>) -> Self {
+        let (Some(time_begin), None) = (self.time_begin, self.date_end) else {
+            return self;
+        };
+        let source = self.source_prefix();
+        let Some(minutes) = duration.map(i64::from).or_else(|| {
+            DEFAULT_DURATIONS
+                .iter()
+                .find(|(s, _)| *s == source)
+                .map(|&(_, minutes)| minutes)
+        }) else {
+            return self;
+        };
+        let end = self.date_begin.and_time(time_begin) + chrono::Duration::minutes(minutes);
+        self.date_end = Some(end.date());
+        self.time_end = Some(end.time());
+        self
+    }
+
+    /// Whether `self` looks like the same booking as `other` re-issued under
+    /// another id, e.g. after the upstream changed its id scheme.
+    pub(crate) fn is_renamed_from(&self, other: &CalendarEvent) -> bool {
+        self.id != other.id
+            && self.source_prefix() == other.source_prefix()
+            && self.title == other.title
+            && self.date_begin == other.date_begin
+            && self.time_begin == other.time_begin
+            && self.location == other.location
+    }
+
+    /// Saves the scraped events, counting new ones and changed ones among them
     pub(crate) async fn upsert_events_to_db(
         user_id: UserId,
         db: &SqlitePool,
         items: impl Iterator<Item = &Self>,
-    ) -> anyhow::Result<u64> {
+    ) -> anyhow::Result<CrawlStats> {
+        let bounds = DateBounds::get();
+        let today = chrono::Utc::now().date_naive();
+        let (items, rejected): (Vec<_>, Vec<_>) =
+            items.partition(|event| bounds.contains(event, today));
+        for event in &rejected {
+            warn!(
+                "{} is dropped for {user_id:?}, it begins on {} out of {bounds:?}",
+                event.id, event.date_begin
+            );
+        }
+        let rejected = rejected.len() as u64;
+        if items.is_empty() {
+            return Ok(CrawlStats {
+                fetched: rejected,
+                rejected,
+                ..Default::default()
+            });
+        }
+        // a reservation listed twice is saved once, the last one wins like the
+        // upsert does, not to count it twice
+        let mut seen = HashSet::new();
+        let mut items = items
+            .into_iter()
+            .rev()
+            .filter(|event| seen.insert(event.id.as_str()))
+            .collect::<Vec<_>>();
+        items.reverse();
         info!("Update events for {user_id:?}");
-        let mut builder = sqlx::query_builder::QueryBuilder::new(
-            r#"INSERT INTO `reservation` (
-            `id`, `user_id`,
-            `title`, `detail`,
-            `date_begin`, `time_begin`,
-            `date_end`, `time_end`,
-            `invalid`, `url`, `location`,
-            `updated_at`
-        ) "#,
-        );
-
+        let ids = items
+            .iter()
+            .map(|event| event.id.as_str())
+            .collect::<Vec<_>>();
+        let new = Self::filter_ids(user_id, db, &ids).await?.len() as u64;
         let now = chrono::Utc::now().naive_utc();
+        let mut updated = 0;
+        // `updated_at` of the conflict is bound once more
+        for items in items.chunks((ID_CHUNK_SIZE - 1) / UPSERT_COLUMNS) {
+            let mut builder = sqlx::query_builder::QueryBuilder::new(
+                r#"INSERT INTO `reservation` (
+                `id`, `user_id`,
+                `title`, `detail`,
+                `date_begin`, `time_begin`,
+                `date_end`, `time_end`,
+                `invalid`, `url`, `location`,
+                `recurrence`, `tentative`, `title_parts`, `party_size`, `updated_at`, `last_seen_at`
+            ) "#,
+            );
 
-        let result = builder
-            .push_values(items, |mut builder, event| {
-                builder
-                    .push_bind(&event.id)
-                    .push_bind(user_id)
-                    .push_bind(&event.title)
-                    .push_bind(&event.detail)
-                    .push_bind(event.date_begin)
-                    .push_bind(event.time_begin)
-                    .push_bind(event.date_end)
-                    .push_bind(event.time_end)
-                    .push_bind(event.invalid)
-                    .push_bind(&event.url)
-                    .push_bind(&event.location)
-                    .push_bind(now);
-            })
-            .push(
-                r#"ON CONFLICT(`id`, `user_id`) DO UPDATE SET
-                `title`=`excluded`.`title`, `detail`=`excluded`.`detail`,
-                `date_begin`=`excluded`.`date_begin`, `time_begin`=`excluded`.`time_begin`,
-                `date_end`=`excluded`.`date_end`, `time_end`=`excluded`.`time_end`,
-                `invalid`=`excluded`.`invalid`, `url`=`excluded`.`url`, `location`=`excluded`.`location`,
-                `updated_at`="#,
-            )
-            .push_bind(now)
-            .push(
-                r#"WHERE 
-                `reservation`.`title` IS NOT `excluded`.`title` OR `reservation`.`detail` IS NOT `excluded`.`detail` OR
-                `reservation`.`date_begin` IS NOT `excluded`.`date_begin` OR `reservation`.`time_begin` IS NOT `excluded`.`time_begin` OR
-                `reservation`.`date_end` IS NOT `excluded`.`date_end` OR `reservation`.`time_end` IS NOT `excluded`.`time_end` OR
-                `reservation`.`invalid` IS NOT `excluded`.`invalid` OR `reservation`.`url` IS NOT `excluded`.`url` OR
-                `reservation`.`location` IS NOT `excluded`.`location`"#,
-            )
-            .build()
-            .execute(db)
-            .await?;
+            let result = builder
+                .push_values(items, |mut builder, event| {
+                    builder
+                        .push_bind(&event.id)
+                        .push_bind(user_id)
+                        .push_bind(&event.title)
+                        .push_bind(&event.detail)
+                        .push_bind(event.date_begin)
+                        .push_bind(event.time_begin)
+                        .push_bind(event.date_end)
+                        .push_bind(event.time_end)
+                        .push_bind(event.invalid)
+                        .push_bind(&event.url)
+                        .push_bind(&event.location)
+                        .push_bind(Self::recurrence_to_db(&event.recurrence))
+                        .push_bind(event.tentative)
+                        .push_bind(Self::title_parts_to_db(&event.title_parts))
+                        .push_bind(event.party_size)
+                        .push_bind(now)
+                        .push_bind(now);
+                })
+                .push(
+                    r#"ON CONFLICT(`id`, `user_id`) DO UPDATE SET
+                    `title`=`excluded`.`title`, `detail`=`excluded`.`detail`,
+                    `date_begin`=`excluded`.`date_begin`, `time_begin`=`excluded`.`time_begin`,
+                    `date_end`=`excluded`.`date_end`, `time_end`=`excluded`.`time_end`,
+                    `invalid`=`excluded`.`invalid` OR `reservation`.`hidden`,
+                    `url`=`excluded`.`url`, `location`=`excluded`.`location`,
+                    `recurrence`=`excluded`.`recurrence`, `tentative`=`excluded`.`tentative`,
+                    `title_parts`=`excluded`.`title_parts`, `party_size`=`excluded`.`party_size`,
+                    `updated_at`="#,
+                )
+                .push_bind(now)
+                .push(
+                    r#"WHERE 
+                    `reservation`.`title` IS NOT `excluded`.`title` OR `reservation`.`detail` IS NOT `excluded`.`detail` OR
+                    `reservation`.`date_begin` IS NOT `excluded`.`date_begin` OR `reservation`.`time_begin` IS NOT `excluded`.`time_begin` OR
+                    `reservation`.`date_end` IS NOT `excluded`.`date_end` OR `reservation`.`time_end` IS NOT `excluded`.`time_end` OR
+                    `reservation`.`invalid` IS NOT (`excluded`.`invalid` OR `reservation`.`hidden`) OR
+                    `reservation`.`url` IS NOT `excluded`.`url` OR
+                    `reservation`.`location` IS NOT `excluded`.`location` OR `reservation`.`recurrence` IS NOT `excluded`.`recurrence` OR
+                    `reservation`.`tentative` IS NOT `excluded`.`tentative` OR
+                    `reservation`.`title_parts` IS NOT `excluded`.`title_parts` OR
+                    `reservation`.`party_size` IS NOT `excluded`.`party_size`"#,
+                )
+                .build()
+                .execute(db)
+                .await?;
+            updated += result.rows_affected();
+        }
+        // unchanged ones are not updated above
+        Self::mark_seen(user_id, db, &ids).await?;
 
-        Ok(result.rows_affected())
+        Ok(CrawlStats {
+            fetched: ids.len() as u64 + rejected,
+            new,
+            // inserted rows are always affected
+            updated: updated - new,
+            rejected,
+            ..Default::default()
+        })
     }
 
     pub(crate) async fn filter_ids<'a>(
@@ -144,27 +582,123 @@ impl CalendarEvent {
         db: &SqlitePool,
         ids: &'a [impl AsRef<str> + 'a],
     ) -> anyhow::Result<Vec<&'a str>> {
-        let mut builder = sqlx::query_builder::QueryBuilder::new(
-            "SELECT `id` FROM `reservation` WHERE `user_id` = ",
-        );
-        builder.push_bind(user_id).push(" AND `id` IN ");
-        let result = builder
-            .push_tuples(ids, |mut f, id| {
-                f.push_bind(id.as_ref());
-            })
-            .build()
-            .fetch_all(db)
-            .await?;
-        let mut existing_ids = result
-            .into_iter()
-            .map(|item| item.get_unchecked::<String, _>(0))
-            .collect::<HashSet<String>>();
+        let mut existing_ids = HashSet::new();
+        for ids in ids.chunks(ID_CHUNK_SIZE) {
+            let mut builder = sqlx::query_builder::QueryBuilder::new(
+                "SELECT `id` FROM `reservation` WHERE `user_id` = ",
+            );
+            builder.push_bind(user_id).push(" AND `id` IN ");
+            let result = builder
+                .push_tuples(ids, |mut f, id| {
+                    f.push_bind(id.as_ref());
+                })
+                .build()
+                .fetch_all(db)
+                .await?;
+            existing_ids.extend(
+                result
+                    .into_iter()
+                    .map(|item| item.get_unchecked::<String, _>(0)),
+            );
+        }
         Ok(ids
             .iter()
             .filter_map(|i| (!existing_ids.remove(i.as_ref())).then(|| i.as_ref()))
             .collect())
     }
 
+    /// Keeps that the reservations are listed by the source now, for the ones
+    /// whose details are not fetched again
+    pub(crate) async fn mark_seen(
+        user_id: UserId,
+        db: &SqlitePool,
+        ids: &[impl AsRef<str>],
+    ) -> anyhow::Result<()> {
+        let now = chrono::Utc::now().naive_utc();
+        for ids in ids.chunks(ID_CHUNK_SIZE) {
+            sqlx::query_builder::QueryBuilder::new("UPDATE `reservation` SET `last_seen_at` = ")
+                .push_bind(now)
+                .push(" WHERE `user_id` = ")
+                .push_bind(user_id)
+                .push(" AND `id` IN ")
+                .push_tuples(ids, |mut f, id| {
+                    f.push_bind(id.as_ref());
+                })
+                .build()
+                .execute(db)
+                .await
+                .context("Failed to mark reservations seen")?;
+        }
+
+        Ok(())
+    }
+
+    /// Reservations over `days` ago which the sources haven't listed for `days`
+    /// either, by user. Recurring ones are kept as they go on.
+    pub(crate) async fn stale(
+        db: &SqlitePool,
+        days: i64,
+        now: chrono::NaiveDateTime,
+    ) -> anyhow::Result<Vec<(UserId, String)>> {
+        let seen_before = now - chrono::Duration::days(days);
+        let ended_before = seen_before.date();
+        let stale = sqlx::query!(
+            r#"SELECT `user_id` as `user_id: UserId`, `id` FROM `reservation`
+            WHERE `last_seen_at` < ? AND COALESCE(`date_end`, `date_begin`) < ?
+                AND `recurrence` IS NULL
+            ORDER BY `user_id`, `id`"#,
+            seen_before,
+            ended_before
+        )
+        .fetch_all(db)
+        .await
+        .context("Failed to get stale reservations")?;
+
+        Ok(stale.into_iter().map(|row| (row.user_id, row.id)).collect())
+    }
+
+    /// Deletes the reservations and their failed syncs, their google events have
+    /// to be deleted first
+    pub(crate) async fn delete_ids(
+        user_id: UserId,
+        db: &SqlitePool,
+        ids: &[impl AsRef<str>],
+    ) -> anyhow::Result<u64> {
+        let mut tx = db.begin().await?;
+        let mut deleted = 0;
+        for ids in ids.chunks(ID_CHUNK_SIZE) {
+            sqlx::query_builder::QueryBuilder::new("DELETE FROM `sync_failure` WHERE `user_id` = ")
+                .push_bind(user_id)
+                .push(" AND `reservation_id` IN ")
+                .push_tuples(ids, |mut f, id| {
+                    f.push_bind(id.as_ref());
+                })
+                .build()
+                .execute(&mut tx)
+                .await
+                .context("Failed to delete failed syncs")?;
+            deleted += sqlx::query_builder::QueryBuilder::new(
+                "DELETE FROM `reservation` WHERE `user_id` = ",
+            )
+            .push_bind(user_id)
+            .push(" AND `id` IN ")
+            .push_tuples(ids, |mut f, id| {
+                f.push_bind(id.as_ref());
+            })
+            .build()
+            .execute(&mut tx)
+            .await
+            .context("Failed to delete reservations")?
+            .rows_affected();
+        }
+        tx.commit().await?;
+
+        Ok(deleted)
+    }
+
+    /// Cancels upcoming reservations with `prefix` which are not fetched
+    /// anymore, all of them when `event_ids` is empty. `event_ids` must be of
+    /// the same source.
     pub(crate) async fn cancel_not_expired_and_not_in(
         user_id: UserId,
         db: &SqlitePool,
@@ -187,13 +721,108 @@ impl CalendarEvent {
             .push_bind(&date)
             .push(" AND `time_begin` >")
             .push_bind(&time)
-            .push(")) AND `id` NOT IN (");
-        let mut b = builder.separated(",");
-        for i in event_ids {
-            b.push_bind(i);
+            .push("))");
+        let event_ids = event_ids.collect::<Vec<_>>();
+        // nothing is fetched, then all upcoming ones are cancelled
+        if !event_ids.is_empty() {
+            builder.push(" AND `id` NOT IN (");
+            let mut b = builder.separated(",");
+            for i in event_ids {
+                // ids of another source would cancel every reservation of this one
+                anyhow::ensure!(
+                    i.starts_with(prefix),
+                    "{i} is not a reservation of {prefix}"
+                );
+                b.push_bind(i);
+            }
+            builder.push(")");
         }
-        let query = builder.push(")").build();
-        let res = query.execute(db).await?;
+        let res = builder.build().execute(db).await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Marks all reservations of the source invalid, then they are deleted
+    /// from google calendar on the next sync. They are kept hidden, not to be
+    /// made valid again by the next crawl.
+    pub(crate) async fn invalidate_all_of(
+        user_id: UserId,
+        db: &SqlitePool,
+        prefix: &str,
+    ) -> anyhow::Result<u64> {
+        let now = chrono::Utc::now().naive_utc();
+        // `_` of prefixes like `catch_table/` is a wildcard otherwise
+        let pattern = format!("{}%", crate::search::like_pattern(prefix));
+        let res = sqlx::query!(
+            r#"UPDATE `reservation` SET `invalid` = TRUE, `hidden` = TRUE, `updated_at` = ?
+            WHERE `user_id` = ? AND `id` LIKE ? ESCAPE '\' AND `invalid` = FALSE"#,
+            now,
+            user_id,
+            pattern
+        )
+        .execute(db)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Lets the reservations hidden by [`Self::invalidate_all_of`] be synced
+    /// again. They stay invalid until the next crawl lists them again.
+    pub(crate) async fn restore_all_of(
+        user_id: UserId,
+        db: &SqlitePool,
+        prefix: &str,
+    ) -> anyhow::Result<u64> {
+        let pattern = format!("{}%", crate::search::like_pattern(prefix));
+        let res = sqlx::query!(
+            r#"UPDATE `reservation` SET `hidden` = FALSE
+            WHERE `user_id` = ? AND `id` LIKE ? ESCAPE '\' AND `hidden` = TRUE"#,
+            user_id,
+            pattern
+        )
+        .execute(db)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Marks valid reservations with `prefix` as updated, to patch their
+    /// google events on the next sync
+    pub(crate) async fn touch_all_of(
+        user_id: UserId,
+        db: &SqlitePool,
+        prefix: &str,
+    ) -> anyhow::Result<u64> {
+        let now = chrono::Utc::now().naive_utc();
+        let pattern = format!("{prefix}%");
+        let res = sqlx::query!(
+            "UPDATE `reservation` SET `updated_at` = ?
+            WHERE `user_id` = ? AND `id` LIKE ? AND `invalid` = FALSE",
+            now,
+            user_id,
+            pattern
+        )
+        .execute(db)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Deletes reservations with `prefix`, their google events have to be
+    /// deleted first
+    pub(crate) async fn delete_all_of(
+        user_id: UserId,
+        db: &mut sqlx::SqliteConnection,
+        prefix: &str,
+    ) -> anyhow::Result<u64> {
+        let pattern = format!("{prefix}%");
+        let res = sqlx::query!(
+            "DELETE FROM `reservation` WHERE `user_id` = ? AND `id` LIKE ?",
+            user_id,
+            pattern
+        )
+        .execute(db)
+        .await?;
 
         Ok(res.rows_affected())
     }
@@ -231,3 +860,791 @@ impl CalendarEvent {
         Ok(())
     }
 }
+
+/// schema.org `Event` or `Reservation` embedded in a page as JSON-LD. Sources
+/// fall back to it when their markup is changed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkedDataEvent {
+    pub reservation_number: Option<String>,
+    pub name: Option<String>,
+    /// In UTC
+    pub start: Option<chrono::NaiveDateTime>,
+    /// In UTC
+    pub end: Option<chrono::NaiveDateTime>,
+    pub location: Option<String>,
+    /// Departure and arrival stop names of a trip
+    pub departure: Option<String>,
+    pub arrival: Option<String>,
+    pub url: Option<String>,
+    pub cancelled: bool,
+}
+
+fn is_linked_data_type(value: &serde_json::Value, suffix: &str) -> bool {
+    let matches = |value: &serde_json::Value| value.as_str().is_some_and(|t| t.ends_with(suffix));
+    match value.get("@type") {
+        Some(serde_json::Value::Array(types)) => types.iter().any(matches),
+        Some(value) => matches(value),
+        None => false,
+    }
+}
+
+fn linked_data_text(value: &serde_json::Value, key: &str) -> Option<String> {
+    match value.get(key)? {
+        serde_json::Value::String(text) => Some(text.clone()),
+        serde_json::Value::Number(number) => Some(number.to_string()),
+        // `Place`, `BusStation`...
+        object => object.get("name")?.as_str().map(ToString::to_string),
+    }
+}
+
+/// Date time without offset is in KST, as the sources show
+fn linked_data_date_time(
+    value: &serde_json::Value,
+    keys: &[&str],
+) -> Option<chrono::NaiveDateTime> {
+    let text = keys.iter().find_map(|key| value.get(key)?.as_str())?;
+    if let Ok(date_time) = chrono::DateTime::parse_from_rfc3339(text) {
+        return Some(date_time.naive_utc());
+    }
+    let date_time = chrono::NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| chrono::NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M"))
+        .ok()?;
+    let (date, time) = date_time_to_utc(date_time.date(), date_time.time(), kst());
+    Some(date.and_time(time))
+}
+
+fn collect_linked_data(value: &serde_json::Value, events: &mut Vec<LinkedDataEvent>) {
+    match value {
+        serde_json::Value::Array(values) => {
+            for value in values {
+                collect_linked_data(value, events);
+            }
+        }
+        serde_json::Value::Object(object) => {
+            if let Some(graph) = object.get("@graph") {
+                collect_linked_data(graph, events);
+            }
+            if is_linked_data_type(value, "Reservation") {
+                let mut event = value
+                    .get("reservationFor")
+                    .map(linked_data_event)
+                    .unwrap_or_default();
+                event.reservation_number = linked_data_text(value, "reservationNumber");
+                event.cancelled |= value
+                    .get("reservationStatus")
+                    .and_then(serde_json::Value::as_str)
+                    .is_some_and(|status| status.ends_with("ReservationCancelled"));
+                event.url = linked_data_text(value, "url").or(event.url);
+                events.push(event);
+            } else if is_linked_data_type(value, "Event") {
+                events.push(linked_data_event(value));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn linked_data_event(value: &serde_json::Value) -> LinkedDataEvent {
+    LinkedDataEvent {
+        reservation_number: None,
+        name: linked_data_text(value, "name"),
+        start: linked_data_date_time(value, &["startDate", "departureTime"]),
+        end: linked_data_date_time(value, &["endDate", "arrivalTime"]),
+        location: linked_data_text(value, "location"),
+        departure: ["departureBusStop", "departureStation", "departureAirport"]
+            .iter()
+            .find_map(|key| linked_data_text(value, key)),
+        arrival: ["arrivalBusStop", "arrivalStation", "arrivalAirport"]
+            .iter()
+            .find_map(|key| linked_data_text(value, key)),
+        url: linked_data_text(value, "url"),
+        cancelled: value
+            .get("eventStatus")
+            .and_then(serde_json::Value::as_str)
+            .is_some_and(|status| status.ends_with("EventCancelled")),
+    }
+}
+
+/// `Event`s and `Reservation`s in `<script type="application/ld+json">` of the
+/// page. Scripts failed to parse are skipped.
+pub fn linked_data_events(html: &scraper::Html) -> Vec<LinkedDataEvent> {
+    let mut events = Vec::new();
+    for script in html.select(crate::selector!(r#"script[type="application/ld+json"]"#)) {
+        let text = script.text().collect::<String>();
+        match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(value) => collect_linked_data(&value, &mut events),
+            Err(e) => info!("Skip malformed JSON-LD - {e:?}"),
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, USER_ID};
+
+    fn event(id: &str) -> CalendarEvent {
+        CalendarEvent {
+            id: id.to_string(),
+            title: "테스트 오마카세".to_string(),
+            detail: "압구정 - 스시".to_string(),
+            invalid: false,
+            date_begin: chrono::Utc::now().date_naive(),
+            time_begin: chrono::NaiveTime::from_hms_opt(10, 30, 0),
+            date_end: None,
+            time_end: None,
+            location: Some("서울 강남구 도산대로 1".to_string()),
+            url: None,
+            recurrence: None,
+            tentative: false,
+            title_parts: None,
+            party_size: None,
+        }
+    }
+
+    fn utc_of(
+        (year, month, day): (i32, u32, u32),
+        (hour, minute): (u32, u32),
+    ) -> (chrono::NaiveDate, chrono::NaiveTime) {
+        date_time_to_utc(
+            chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap(),
+            chrono::NaiveTime::from_hms_opt(hour, minute, 0).unwrap(),
+            kst(),
+        )
+    }
+
+    #[test]
+    fn kst_is_nine_hours_ahead() {
+        assert_eq!(kst().local_minus_utc(), 9 * 60 * 60);
+        assert_eq!(kst().to_string(), "+09:00");
+    }
+
+    #[test]
+    fn kst_round_trips_through_utc() {
+        for (date, time) in [
+            ((2024, 5, 1), (0, 0)),
+            ((2024, 5, 1), (8, 59)),
+            ((2024, 5, 1), (23, 30)),
+            ((2024, 12, 31), (23, 59)),
+            ((2024, 2, 29), (3, 15)),
+        ] {
+            let (utc_date, utc_time) = utc_of(date, time);
+            assert_eq!(
+                date_time_from_utc(utc_date, utc_time, &kst()),
+                (
+                    chrono::NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap(),
+                    chrono::NaiveTime::from_hms_opt(time.0, time.1, 0).unwrap()
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn kst_to_utc_crosses_date_before_nine() {
+        let date = |year, month, day| chrono::NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        let time = |hour, minute| chrono::NaiveTime::from_hms_opt(hour, minute, 0).unwrap();
+
+        // after midnight showtime belongs to the previous day in UTC
+        assert_eq!(
+            utc_of((2024, 5, 1), (0, 30)),
+            (date(2024, 4, 30), time(15, 30))
+        );
+        assert_eq!(
+            utc_of((2024, 5, 1), (8, 59)),
+            (date(2024, 4, 30), time(23, 59))
+        );
+        // exactly UTC midnight
+        assert_eq!(utc_of((2024, 5, 1), (9, 0)), (date(2024, 5, 1), time(0, 0)));
+        assert_eq!(
+            utc_of((2024, 5, 1), (23, 59)),
+            (date(2024, 5, 1), time(14, 59))
+        );
+        // across year
+        assert_eq!(
+            utc_of((2025, 1, 1), (0, 0)),
+            (date(2024, 12, 31), time(15, 0))
+        );
+    }
+
+    #[tokio::test]
+    async fn events_out_of_date_bounds_are_rejected() {
+        let db = testing::db().await;
+        // e.g. a timestamp parsed as 0
+        let broken = CalendarEvent {
+            date_begin: chrono::NaiveDate::from_ymd_opt(1970, 1, 1).unwrap(),
+            ..event("cgv/broken")
+        };
+        let far = CalendarEvent {
+            date_begin: chrono::NaiveDate::from_ymd_opt(2100, 1, 1).unwrap(),
+            ..event("cgv/far")
+        };
+        // a weekly series can begin long ago
+        let recurring = CalendarEvent {
+            recurrence: Some(vec!["RRULE:FREQ=WEEKLY".to_string()]),
+            ..CalendarEvent {
+                id: "ical/recurring".to_string(),
+                ..broken.clone()
+            }
+        };
+        let events = [broken, far, event("cgv/1"), recurring];
+
+        let stats = CalendarEvent::upsert_events_to_db(USER_ID, &db, events.iter())
+            .await
+            .unwrap();
+        assert_eq!((stats.fetched, stats.new, stats.rejected), (4, 2, 2));
+        let ids: Vec<String> = sqlx::query_scalar("SELECT `id` FROM `reservation` ORDER BY `id`")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(ids, ["cgv/1", "ical/recurring"]);
+
+        let bounds = DateBounds::from_vars(|name| {
+            (name == "RESERVATION_MAX_FUTURE_DAYS").then(|| "30".to_string())
+        })
+        .unwrap();
+        assert_eq!(bounds.max_past_days, DEFAULT_MAX_DAYS);
+        assert_eq!(bounds.max_future_days, 30);
+        assert!(DateBounds::from_vars(|_| Some("0".to_string())).is_err());
+    }
+
+    #[tokio::test]
+    async fn upsert_counts_new_and_updated_events() {
+        let db = testing::db().await;
+        let user_id = USER_ID;
+        let upsert = |events: Vec<CalendarEvent>| {
+            let db = db.clone();
+            async move {
+                CalendarEvent::upsert_events_to_db(user_id, &db, events.iter())
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let stats = upsert(vec![event("catch_table/1"), event("catch_table/2")]).await;
+        assert_eq!((stats.fetched, stats.new, stats.updated), (2, 2, 0));
+
+        let moved = CalendarEvent {
+            time_begin: chrono::NaiveTime::from_hms_opt(12, 0, 0),
+            ..event("catch_table/2")
+        };
+        let mut stats = upsert(vec![event("catch_table/1"), moved, event("catch_table/3")]).await;
+        assert_eq!((stats.fetched, stats.new, stats.updated), (3, 1, 1));
+
+        stats += upsert(Vec::new()).await;
+        assert_eq!(stats.changed(), 2);
+
+        // nothing is changed, `updated_at` is kept not to sync again
+        let updated_at = || {
+            sqlx::query_scalar::<_, chrono::NaiveDateTime>(
+                "SELECT MAX(`updated_at`) FROM `reservation`",
+            )
+            .fetch_one(&db)
+        };
+        let before = updated_at().await.unwrap();
+        let unchanged = upsert(vec![event("catch_table/1"), event("catch_table/3")]).await;
+        assert_eq!(
+            (unchanged.fetched, unchanged.new, unchanged.updated),
+            (2, 0, 0)
+        );
+        assert_eq!(updated_at().await.unwrap(), before);
+
+        let duplicated = upsert(vec![
+            event("catch_table/4"),
+            event("catch_table/1"),
+            event("catch_table/4"),
+        ])
+        .await;
+        assert_eq!(
+            (duplicated.fetched, duplicated.new, duplicated.updated),
+            (2, 1, 0)
+        );
+        assert_eq!(
+            stats.to_string(),
+            "fetched=3 new=1 updated=1 cancelled=0 errors=0 rejected=0"
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_requires_ids_of_same_source() {
+        let db = testing::db().await;
+        let user_id = USER_ID;
+        let upcoming = |id| CalendarEvent {
+            date_begin: chrono::Utc::now().date_naive() + chrono::Duration::days(7),
+            ..event(id)
+        };
+        let saved = [upcoming("kobus/1"), upcoming("kobus/2"), upcoming("cgv/1")];
+        CalendarEvent::upsert_events_to_db(user_id, &db, saved.iter())
+            .await
+            .unwrap();
+
+        assert!(CalendarEvent::cancel_not_expired_and_not_in(
+            user_id,
+            &db,
+            "kobus/",
+            ["cgv/1"].into_iter()
+        )
+        .await
+        .is_err());
+        assert_eq!(
+            CalendarEvent::cancel_not_expired_and_not_in(
+                user_id,
+                &db,
+                "kobus/",
+                ["kobus/1"].into_iter()
+            )
+            .await
+            .unwrap(),
+            1
+        );
+        let cancelled: Vec<String> =
+            sqlx::query_scalar("SELECT `id` FROM `reservation` WHERE `invalid` = TRUE")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(cancelled, vec!["kobus/2"]);
+    }
+
+    #[tokio::test]
+    async fn invalidated_source_stays_invalid_after_crawl() {
+        let db = testing::db().await;
+        let saved = [event("cgv/1"), event("megabox/1")];
+        CalendarEvent::upsert_events_to_db(USER_ID, &db, saved.iter())
+            .await
+            .unwrap();
+        assert_eq!(
+            CalendarEvent::invalidate_all_of(USER_ID, &db, "cgv/")
+                .await
+                .unwrap(),
+            1
+        );
+
+        // listed again as they are
+        let crawled = [event("cgv/1"), event("cgv/2"), event("megabox/1")];
+        let stats = CalendarEvent::upsert_events_to_db(USER_ID, &db, crawled.iter())
+            .await
+            .unwrap();
+        assert_eq!((stats.new, stats.updated), (1, 0));
+
+        let invalid: Vec<(String, bool)> =
+            sqlx::query_as("SELECT `id`, `invalid` FROM `reservation` ORDER BY `id`")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            invalid,
+            [
+                ("cgv/1".to_string(), true),
+                ("cgv/2".to_string(), false),
+                ("megabox/1".to_string(), false)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn restored_source_is_valid_after_crawl() {
+        let db = testing::db().await;
+        let saved = [event("cgv/1"), event("cgv/2")];
+        CalendarEvent::upsert_events_to_db(USER_ID, &db, saved.iter())
+            .await
+            .unwrap();
+        CalendarEvent::invalidate_all_of(USER_ID, &db, "cgv/")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            CalendarEvent::restore_all_of(USER_ID, &db, "cgv/")
+                .await
+                .unwrap(),
+            2
+        );
+        // cgv/2 is not listed anymore
+        let crawled = [event("cgv/1")];
+        let stats = CalendarEvent::upsert_events_to_db(USER_ID, &db, crawled.iter())
+            .await
+            .unwrap();
+        assert_eq!((stats.new, stats.updated), (0, 1));
+
+        let invalid: Vec<(String, bool)> =
+            sqlx::query_as("SELECT `id`, `invalid` FROM `reservation` ORDER BY `id`")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            invalid,
+            [("cgv/1".to_string(), false), ("cgv/2".to_string(), true)]
+        );
+        assert_eq!(
+            CalendarEvent::restore_all_of(USER_ID, &db, "cgv/")
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn only_the_exact_prefix_is_invalidated() {
+        let db = testing::db().await;
+        let saved = [event("catch_table/1"), event("catchxtable/1")];
+        CalendarEvent::upsert_events_to_db(USER_ID, &db, saved.iter())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            CalendarEvent::invalidate_all_of(USER_ID, &db, "catch_table/")
+                .await
+                .unwrap(),
+            1
+        );
+        let invalid: Vec<String> =
+            sqlx::query_scalar("SELECT `id` FROM `reservation` WHERE `invalid` = TRUE")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(invalid, ["catch_table/1"]);
+    }
+
+    #[tokio::test]
+    async fn same_ids_of_users_do_not_conflict() {
+        let db = testing::db().await;
+        let other = UserId(2);
+        let saved = [event("cgv/1")];
+        CalendarEvent::upsert_events_to_db(USER_ID, &db, saved.iter())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            CalendarEvent::filter_ids(other, &db, &["cgv/1"])
+                .await
+                .unwrap(),
+            ["cgv/1"]
+        );
+        let renamed = CalendarEvent {
+            title: "다른 영화".to_string(),
+            ..event("cgv/1")
+        };
+        let stats = CalendarEvent::upsert_events_to_db(other, &db, [renamed].iter())
+            .await
+            .unwrap();
+        assert_eq!((stats.new, stats.updated), (1, 0));
+
+        let titles: Vec<(i64, String)> =
+            sqlx::query_as("SELECT `user_id`, `title` FROM `reservation` ORDER BY `user_id`")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            titles,
+            [
+                (1, "테스트 오마카세".to_string()),
+                (2, "다른 영화".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_reservations_are_over_and_not_seen() {
+        let db = testing::db().await;
+        let today = chrono::Utc::now().date_naive();
+        let on = |id, date_begin| CalendarEvent {
+            date_begin,
+            ..event(id)
+        };
+        let saved = [
+            on("kobus/old", today - chrono::Duration::days(100)),
+            on("kobus/seen", today - chrono::Duration::days(100)),
+            on("kobus/recent", today - chrono::Duration::days(10)),
+            CalendarEvent {
+                date_end: Some(today - chrono::Duration::days(10)),
+                ..on("kobus/long", today - chrono::Duration::days(100))
+            },
+            CalendarEvent {
+                recurrence: Some(vec!["RRULE:FREQ=WEEKLY".to_string()]),
+                ..on("ical/weekly", today - chrono::Duration::days(100))
+            },
+        ];
+        CalendarEvent::upsert_events_to_db(USER_ID, &db, saved.iter())
+            .await
+            .unwrap();
+        // not listed for 100 days, except one
+        let now = chrono::Utc::now().naive_utc();
+        sqlx::query("UPDATE `reservation` SET `last_seen_at` = ? WHERE `id` != 'kobus/seen'")
+            .bind(now - chrono::Duration::days(100))
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let stale = CalendarEvent::stale(&db, 5, now).await.unwrap();
+        assert_eq!(
+            stale,
+            [
+                (USER_ID, "kobus/long".to_string()),
+                (USER_ID, "kobus/old".to_string()),
+                (USER_ID, "kobus/recent".to_string()),
+            ]
+        );
+        // ended within the days
+        let stale = CalendarEvent::stale(&db, 30, now).await.unwrap();
+        assert_eq!(stale, [(USER_ID, "kobus/old".to_string())]);
+
+        let ids = stale.into_iter().map(|(_, id)| id).collect::<Vec<_>>();
+        assert_eq!(
+            CalendarEvent::delete_ids(USER_ID, &db, &ids).await.unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_skips_past_cancelled_and_other_users() {
+        let db = testing::db().await;
+        let today = chrono::Utc::now().date_naive();
+        let on = |id, date_begin| CalendarEvent {
+            date_begin,
+            ..event(id)
+        };
+        let saved = [
+            on("kobus/past", today - chrono::Duration::days(1)),
+            CalendarEvent {
+                invalid: true,
+                ..on("kobus/cancelled", today + chrono::Duration::days(1))
+            },
+            on("kobus/upcoming", today + chrono::Duration::days(1)),
+        ];
+        CalendarEvent::upsert_events_to_db(USER_ID, &db, saved.iter())
+            .await
+            .unwrap();
+        let others = [on("kobus/upcoming", today + chrono::Duration::days(1))];
+        CalendarEvent::upsert_events_to_db(UserId(2), &db, others.iter())
+            .await
+            .unwrap();
+
+        // nothing is fetched
+        assert_eq!(
+            CalendarEvent::cancel_not_expired_and_not_in(
+                USER_ID,
+                &db,
+                "kobus/",
+                std::iter::empty()
+            )
+            .await
+            .unwrap(),
+            1
+        );
+        // already cancelled
+        assert_eq!(
+            CalendarEvent::cancel_not_expired_and_not_in(
+                USER_ID,
+                &db,
+                "kobus/",
+                std::iter::empty()
+            )
+            .await
+            .unwrap(),
+            0
+        );
+        let valid: Vec<(i64, String)> = sqlx::query_as(
+            "SELECT `user_id`, `id` FROM `reservation` WHERE `invalid` = FALSE ORDER BY `user_id`",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(
+            valid,
+            [
+                (1, "kobus/past".to_string()),
+                (2, "kobus/upcoming".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_ids_over_variable_limit() {
+        let db = testing::db().await;
+        let user_id = USER_ID;
+        let saved = [event("catch_table/7"), event("catch_table/1234")];
+        CalendarEvent::upsert_events_to_db(user_id, &db, saved.iter())
+            .await
+            .unwrap();
+
+        let ids = (0..1500)
+            .map(|i| format!("catch_table/{i}"))
+            .collect::<Vec<_>>();
+        let new_ids = CalendarEvent::filter_ids(user_id, &db, &ids).await.unwrap();
+
+        assert_eq!(new_ids.len(), 1498);
+        assert!(!new_ids.contains(&"catch_table/7"));
+        assert!(!new_ids.contains(&"catch_table/1234"));
+    }
+
+    #[tokio::test]
+    async fn upsert_over_variable_limit() {
+        let db = testing::db().await;
+        let events = (0..2000)
+            .map(|i| event(&format!("catch_table/{i}")))
+            .collect::<Vec<_>>();
+
+        let stats = CalendarEvent::upsert_events_to_db(USER_ID, &db, events.iter())
+            .await
+            .unwrap();
+        assert_eq!(stats.new, 2000);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM `reservation`")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(count, 2000);
+
+        let mut changed = events;
+        changed[1999].title = "변경된 오마카세".to_string();
+        let stats = CalendarEvent::upsert_events_to_db(USER_ID, &db, changed.iter())
+            .await
+            .unwrap();
+        assert_eq!((stats.new, stats.updated), (0, 1));
+    }
+
+    #[test]
+    fn renamed_reservation_matches_same_booking() {
+        let old = event("catch_table/1234");
+        let new = event("catch_table/CT-RESV-1234");
+
+        assert!(new.is_renamed_from(&old));
+        assert!(!old.is_renamed_from(&old));
+    }
+
+    #[test]
+    fn renamed_reservation_requires_same_source_and_time() {
+        let old = event("catch_table/1234");
+
+        let other_source = event("naver/1234");
+        assert!(!other_source.is_renamed_from(&old));
+
+        let mut other_time = event("catch_table/CT-RESV-1234");
+        other_time.time_begin = chrono::NaiveTime::from_hms_opt(11, 0, 0);
+        assert!(!other_time.is_renamed_from(&old));
+
+        let mut other_location = event("catch_table/CT-RESV-1234");
+        other_location.location = None;
+        assert!(!other_location.is_renamed_from(&old));
+    }
+
+    #[test]
+    fn linked_data_events_in_graph_and_list() {
+        let html = scraper::Html::parse_document(
+            r#"<html><head>
+            <script type="application/ld+json">{"@context": "https://schema.org", "@graph": [
+                {"@type": "WebPage", "name": "page"},
+                {"@type": "EventReservation", "reservationNumber": 1234,
+                 "reservationStatus": "https://schema.org/ReservationCancelled",
+                 "reservationFor": {"@type": "ScreeningEvent", "name": "영화",
+                    "startDate": "2024-05-01T23:40:00+09:00",
+                    "location": {"@type": "MovieTheater", "name": "CGV용산"}}}
+            ]}</script>
+            <script type="application/ld+json">[{"@type": ["Event"], "name": "공연", "startDate": "2024-05-02T19:00"}]</script>
+            <script type="application/ld+json">{ broken</script>
+            </head></html>"#,
+        );
+        let date_time = |day, hour, minute| {
+            chrono::NaiveDate::from_ymd_opt(2024, 5, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+        };
+
+        let events = linked_data_events(&html);
+
+        assert_eq!(
+            events,
+            vec![
+                LinkedDataEvent {
+                    reservation_number: Some("1234".to_string()),
+                    name: Some("영화".to_string()),
+                    start: date_time(1, 14, 40),
+                    location: Some("CGV용산".to_string()),
+                    cancelled: true,
+                    ..Default::default()
+                },
+                LinkedDataEvent {
+                    name: Some("공연".to_string()),
+                    start: date_time(2, 10, 0),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn default_end_by_source() {
+        let end_of = |id: &str| {
+            let event = event(id).with_default_end(None);
+            event
+                .date_end
+                .map(|date| date.and_time(event.time_end.unwrap()))
+        };
+        let at = |hour, minute| event("cgv/1").date_begin.and_hms_opt(hour, minute, 0);
+
+        assert_eq!(end_of("cgv/1"), at(12, 30));
+        assert_eq!(end_of("megabox/1"), at(12, 30));
+        assert_eq!(end_of("catch_table/1"), at(12, 0));
+        assert_eq!(end_of("naver/1"), at(12, 0));
+        // not in the table
+        assert_eq!(end_of("ical/1"), None);
+
+        let mut explicit = event("naver/1");
+        explicit.date_end = explicit.date_begin.succ_opt();
+        explicit.time_end = chrono::NaiveTime::from_hms_opt(1, 0, 0);
+        let normalized = explicit.clone().with_default_end(Some(30));
+        assert_eq!(normalized.date_end, explicit.date_end);
+        assert_eq!(normalized.time_end, explicit.time_end);
+
+        // all day events stay all day
+        let mut all_day = event("naver/1");
+        all_day.time_begin = None;
+        assert_eq!(all_day.with_default_end(Some(30)).date_end, None);
+
+        // set by the user, even for the sources not in the table
+        for id in ["catch_table/1", "ical/1"] {
+            let event = event(id).with_default_end(Some(45));
+            assert_eq!(
+                event
+                    .date_end
+                    .map(|date| date.and_time(event.time_end.unwrap())),
+                at(11, 15)
+            );
+        }
+    }
+
+    #[test]
+    fn builder_rejects_broken_events() {
+        let date = |day| chrono::NaiveDate::from_ymd_opt(2024, 5, day).unwrap();
+        let time = |hour| chrono::NaiveTime::from_hms_opt(hour, 0, 0);
+
+        let event = CalendarEvent::builder("cgv/1", "영화", date(1))
+            .time_begin(time(23))
+            .end(date(2), time(1))
+            .build()
+            .unwrap();
+        assert_eq!(event.date_end, Some(date(2)));
+        assert_eq!(event.time_end, time(1));
+        // all day event ending on the day
+        assert!(CalendarEvent::builder("ical/1", "휴가", date(1))
+            .end(date(1), None)
+            .build()
+            .is_ok());
+
+        assert!(CalendarEvent::builder("cgv/1", " ", date(1))
+            .build()
+            .is_err());
+        assert!(CalendarEvent::builder("cgv/1", "영화", date(1))
+            .time_begin(time(10))
+            .end(None, time(12))
+            .build()
+            .is_err());
+        assert!(CalendarEvent::builder("cgv/1", "영화", date(1))
+            .time_begin(time(10))
+            .end(date(1), time(9))
+            .build()
+            .is_err());
+        assert!(CalendarEvent::builder("naver/1", "숙소", date(3))
+            .end(date(2), None)
+            .build()
+            .is_err());
+    }
+}
@@ -0,0 +1,106 @@
+import React from "react";
+import { Form, Link, LoaderFunctionArgs, Navigate, useLoaderData } from "react-router-dom";
+import { AsyncReturnType, FoundReservation, SOURCES } from './utils';
+
+export async function loader({ request }: LoaderFunctionArgs) {
+    const params = new URL(request.url).searchParams;
+    // blank fields of the form are not filters
+    for (const [key, value] of [...params.entries()]) {
+        if (value.trim() === "") {
+            params.delete(key);
+        }
+    }
+    const resp = await fetch(`/api/reservations?${params}`, {
+        credentials: "same-origin",
+    });
+
+    if (resp.ok) {
+        return {
+            params,
+            result: await resp.json() as {
+                reservations: FoundReservation[],
+                total: number,
+                page: number,
+                per_page: number,
+            },
+        };
+    } else {
+        return null;
+    }
+}
+
+// same search with some of the filters replaced, back to the first page
+function withParams(params: URLSearchParams, changes: Record<string, string | null>) {
+    const changed = new URLSearchParams(params);
+    changed.delete("page");
+    for (const [key, value] of Object.entries(changes)) {
+        if (value === null) {
+            changed.delete(key);
+        } else {
+            changed.set(key, value);
+        }
+    }
+    return `/reservations?${changed}`;
+}
+
+export function Component() {
+    const data = useLoaderData() as AsyncReturnType<typeof loader>;
+
+    if (data === null) {
+        return <Navigate to="/" />;
+    }
+    const { params, result } = data;
+    const source = params.get("source");
+    const pages = Math.max(1, Math.ceil(result.total / result.per_page));
+
+    return <div>
+        <Form method="get" action="/reservations">
+            {source !== null && <input type="hidden" name="source" value={source} />}
+            <input type="search" name="query" placeholder="title, detail or location" defaultValue={params.get("query") ?? ""} />
+            <label htmlFor="from">From</label>
+            <input type="date" name="from" defaultValue={params.get("from") ?? ""} />
+            <label htmlFor="to">To</label>
+            <input type="date" name="to" defaultValue={params.get("to") ?? ""} />
+            <label>
+                <input type="checkbox" name="include_invalid" value="true" defaultChecked={params.get("include_invalid") === "true"} />
+                Include cancelled
+            </label>
+            <button type="submit">Search</button>
+        </Form>
+        <p>
+            <Link to={withParams(params, { source: null })} className={source === null ? "" : "secondary"} role="button">All</Link>{" "}
+            {Object.entries(SOURCES).map(([key, name]) => <React.Fragment key={key}>
+                <Link to={withParams(params, { source: key })} className={source === key ? "" : "secondary"} role="button">{name}</Link>{" "}
+            </React.Fragment>)}
+        </p>
+        <p>{result.total} reservations</p>
+        <table>
+            <thead>
+                <tr>
+                    <th>Source</th>
+                    <th>Title</th>
+                    <th>Begin</th>
+                    <th>End</th>
+                    <th>Location</th>
+                </tr>
+            </thead>
+            <tbody>
+                {result.reservations.map((reservation) => <tr key={reservation.id}>
+                    <td>{SOURCES[reservation.source as keyof typeof SOURCES] ?? reservation.source}</td>
+                    <td>
+                        {reservation.invalid ? <del>{reservation.title}</del> : reservation.title}
+                        {reservation.url !== null && <> <a href={reservation.url} target="_blank" rel="noreferrer">↗</a></>}
+                    </td>
+                    <td>{reservation.date_begin} {reservation.time_begin}</td>
+                    <td>{reservation.date_end} {reservation.time_end}</td>
+                    <td>{reservation.location ?? ""}</td>
+                </tr>)}
+            </tbody>
+        </table>
+        <p>
+            {result.page > 1 && <Link to={`${withParams(params, {})}&page=${result.page - 1}`}>Previous</Link>}
+            {" "}{result.page} / {pages}{" "}
+            {result.page < pages && <Link to={`${withParams(params, {})}&page=${result.page + 1}`}>Next</Link>}
+        </p>
+    </div>;
+}
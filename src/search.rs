@@ -0,0 +1,409 @@
+use anyhow::Context as _;
+use axum::{
+    extract::Query,
+    response::{IntoResponse as _, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use axum_sessions::extractors::ReadableSession;
+use hyper::StatusCode;
+use log::{debug, error};
+use sqlx::SqlitePool;
+
+use crate::{
+    export::{local_date_time, utc_end_of, utc_start_of},
+    setting::SOURCES,
+    CalendarEvent, UserId,
+};
+
+const PER_PAGE: u32 = 50;
+/// Last page which the offset of fits in `u32`, later ones are clamped to it
+const MAX_PAGE: u32 = u32::MAX / PER_PAGE;
+
+/// Filters of the reservations of the user. `query` is matched against the
+/// title, the detail and the location, and the begin date in KST is in
+/// `from` ~ `to` both inclusive.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct SearchQuery {
+    pub query: Option<String>,
+    pub source: Option<String>,
+    pub from: Option<chrono::NaiveDate>,
+    pub to: Option<chrono::NaiveDate>,
+    #[serde(default)]
+    pub include_invalid: bool,
+    /// Starts from 1
+    pub page: Option<u32>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct FoundReservation {
+    pub id: String,
+    pub source: String,
+    pub title: String,
+    pub detail: String,
+    /// Date and time in KST, like the sources show them
+    pub date_begin: String,
+    pub time_begin: String,
+    pub date_end: String,
+    pub time_end: String,
+    pub location: Option<String>,
+    pub url: Option<String>,
+    pub invalid: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SearchResult {
+    pub reservations: Vec<FoundReservation>,
+    /// Number of all matched reservations, not only of the page
+    pub total: i64,
+    pub page: u32,
+    pub per_page: u32,
+}
+
+/// Source of the reservation, from the prefix of the id
+fn source_of(id: &str) -> String {
+    id.split_once('/')
+        .map(|(source, _)| source.to_string())
+        .unwrap_or_default()
+}
+
+/// Scraped but not stored one, e.g. for a preview of the source
+impl From<CalendarEvent> for FoundReservation {
+    fn from(event: CalendarEvent) -> Self {
+        let (date_begin, time_begin) = local_date_time(Some(event.date_begin), event.time_begin);
+        let (date_end, time_end) = local_date_time(event.date_end, event.time_end);
+        Self {
+            source: source_of(&event.id),
+            id: event.id,
+            title: event.title,
+            detail: event.detail,
+            date_begin,
+            time_begin,
+            date_end,
+            time_end,
+            location: event.location,
+            url: event.url,
+            invalid: event.invalid,
+        }
+    }
+}
+
+/// Pattern of `LIKE` matching `text` as it is, with `\` as the escape
+pub(crate) fn like_pattern(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Latest reservations first
+pub async fn search_reservations(
+    db: &SqlitePool,
+    user_id: UserId,
+    query: &SearchQuery,
+) -> anyhow::Result<SearchResult> {
+    let text = query
+        .query
+        .as_deref()
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(|text| format!("%{}%", like_pattern(text)));
+    let source = query
+        .source
+        .as_deref()
+        .map(|source| format!("{}%", like_pattern(&format!("{source}/"))));
+    let page = query.page.unwrap_or(1).clamp(1, MAX_PAGE);
+    let offset = (page - 1) * PER_PAGE;
+    let from_utc = query.from.map(utc_start_of);
+    let to_utc = query.to.and_then(utc_end_of);
+
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as `total!: i64` FROM `reservation`
+        WHERE `user_id` = ?1
+            AND (?2 IS NULL OR `title` LIKE ?2 ESCAPE '\' OR `detail` LIKE ?2 ESCAPE '\'
+                OR `location` LIKE ?2 ESCAPE '\')
+            AND (?3 IS NULL OR `id` LIKE ?3 ESCAPE '\')
+            AND (?4 IS NULL OR CASE WHEN `time_begin` IS NULL THEN `date_begin` >= ?4
+                ELSE `date_begin` || ' ' || `time_begin` >= ?5 END)
+            AND (?6 IS NULL OR CASE WHEN `time_begin` IS NULL THEN `date_begin` <= ?6
+                ELSE ?7 IS NULL OR `date_begin` || ' ' || `time_begin` < ?7 END)
+            AND (?8 OR NOT `invalid`)"#,
+        user_id,
+        text,
+        source,
+        query.from,
+        from_utc,
+        query.to,
+        to_utc,
+        query.include_invalid
+    )
+    .fetch_one(db)
+    .await
+    .context("Failed to count reservations")?;
+
+    let reservations = sqlx::query!(
+        r#"SELECT
+            `id` as `id!`, `title` as `title!`, `detail` as `detail!`,
+            `date_begin` as `date_begin!: chrono::NaiveDate`,
+            `time_begin` as `time_begin: chrono::NaiveTime`,
+            `date_end` as `date_end: chrono::NaiveDate`,
+            `time_end` as `time_end: chrono::NaiveTime`,
+            `location`, `url`, `invalid` as `invalid!: bool`
+        FROM `reservation`
+        WHERE `user_id` = ?1
+            AND (?2 IS NULL OR `title` LIKE ?2 ESCAPE '\' OR `detail` LIKE ?2 ESCAPE '\'
+                OR `location` LIKE ?2 ESCAPE '\')
+            AND (?3 IS NULL OR `id` LIKE ?3 ESCAPE '\')
+            AND (?4 IS NULL OR CASE WHEN `time_begin` IS NULL THEN `date_begin` >= ?4
+                ELSE `date_begin` || ' ' || `time_begin` >= ?5 END)
+            AND (?6 IS NULL OR CASE WHEN `time_begin` IS NULL THEN `date_begin` <= ?6
+                ELSE ?7 IS NULL OR `date_begin` || ' ' || `time_begin` < ?7 END)
+            AND (?8 OR NOT `invalid`)
+        ORDER BY `date_begin` DESC, `time_begin` DESC, `id`
+        LIMIT ?9 OFFSET ?10"#,
+        user_id,
+        text,
+        source,
+        query.from,
+        from_utc,
+        query.to,
+        to_utc,
+        query.include_invalid,
+        PER_PAGE,
+        offset
+    )
+    .fetch_all(db)
+    .await
+    .context("Failed to search reservations")?
+    .into_iter()
+    .map(|row| {
+        let (date_begin, time_begin) = local_date_time(Some(row.date_begin), row.time_begin);
+        let (date_end, time_end) = local_date_time(row.date_end, row.time_end);
+        FoundReservation {
+            source: source_of(&row.id),
+            id: row.id,
+            title: row.title,
+            detail: row.detail,
+            date_begin,
+            time_begin,
+            date_end,
+            time_end,
+            location: row.location,
+            url: row.url,
+            invalid: row.invalid,
+        }
+    })
+    .collect();
+
+    Ok(SearchResult {
+        reservations,
+        total,
+        page,
+        per_page: PER_PAGE,
+    })
+}
+
+async fn get_reservations(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Query(query): Query<SearchQuery>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    if let Some(source) = &query.source {
+        if !SOURCES.contains(&source.as_str()) {
+            debug!("Unknown source - {source}");
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Unknown source - {source}"),
+            )
+                .into_response();
+        }
+    }
+
+    match search_reservations(&db, user_id, &query).await {
+        Ok(result) => Json(result).into_response(),
+        Err(e) => {
+            error!("Error occurred while search reservations - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn web_router() -> Router {
+    Router::new().route("/reservations", get(get_reservations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, USER_ID};
+
+    #[test]
+    fn scraped_event_is_shown_in_local_time() {
+        let event = CalendarEvent::builder(
+            "cgv/1",
+            "영화",
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap(),
+        )
+        .time_begin(chrono::NaiveTime::from_hms_opt(15, 30, 0))
+        .build()
+        .unwrap();
+
+        let found = FoundReservation::from(event);
+        assert_eq!(found.source, "cgv");
+        assert_eq!(found.date_begin, "2024-05-02");
+        assert_eq!(found.time_begin, "00:30");
+        assert_eq!(found.date_end, "");
+    }
+
+    #[tokio::test]
+    async fn reservations_are_filtered_and_paged() {
+        let db = testing::db().await;
+        for (id, title, date_begin, invalid) in [
+            ("cgv/1", "100% 영화", "2024-05-01", false),
+            ("cgv/2", "영화", "2024-06-01", true),
+            ("catch_table/1", "스시", "2024-06-02", false),
+            // `_` of the source is not a wildcard
+            ("catchXtable/1", "스시", "2024-06-03", false),
+        ] {
+            sqlx::query(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `invalid`, `updated_at`, `location`)
+                VALUES (?, ?, ?, '', ?, ?, '2024-04-01 00:00:00', '압구정')",
+            )
+            .bind(id)
+            .bind(USER_ID)
+            .bind(title)
+            .bind(date_begin)
+            .bind(invalid)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        let search = |query: SearchQuery| {
+            let db = db.clone();
+            async move {
+                search_reservations(&db, USER_ID, &query)
+                    .await
+                    .unwrap()
+                    .reservations
+                    .into_iter()
+                    .map(|reservation| reservation.id)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            search(SearchQuery::default()).await,
+            ["catchXtable/1", "catch_table/1", "cgv/1"]
+        );
+        assert_eq!(
+            search(SearchQuery {
+                include_invalid: true,
+                source: Some("cgv".to_string()),
+                ..Default::default()
+            })
+            .await,
+            ["cgv/2", "cgv/1"]
+        );
+        assert_eq!(
+            search(SearchQuery {
+                source: Some("catch_table".to_string()),
+                ..Default::default()
+            })
+            .await,
+            ["catch_table/1"]
+        );
+        // `%` is matched as it is
+        assert_eq!(
+            search(SearchQuery {
+                query: Some("0%".to_string()),
+                ..Default::default()
+            })
+            .await,
+            ["cgv/1"]
+        );
+        assert_eq!(
+            search(SearchQuery {
+                query: Some("압구정".to_string()),
+                from: chrono::NaiveDate::from_ymd_opt(2024, 6, 1),
+                to: chrono::NaiveDate::from_ymd_opt(2024, 6, 2),
+                ..Default::default()
+            })
+            .await,
+            ["catch_table/1"]
+        );
+
+        let result = search_reservations(
+            &db,
+            USER_ID,
+            &SearchQuery {
+                page: Some(2),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.total, 3);
+        assert!(result.reservations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn dates_are_searched_in_kst() {
+        let db = testing::db().await;
+        for (id, date_begin, time_begin) in [
+            // 2024-06-01 00:30 KST
+            ("cgv/1", "2024-05-31", Some("15:30:00")),
+            // 2024-05-31 23:30 KST
+            ("cgv/2", "2024-05-31", Some("14:30:00")),
+            // 2024-06-03 00:00 KST
+            ("cgv/3", "2024-06-02", Some("15:00:00")),
+            ("cgv/4", "2024-06-02", None),
+        ] {
+            sqlx::query(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `time_begin`, `invalid`, `updated_at`)
+                VALUES (?, ?, '영화', '', ?, ?, FALSE, '2024-04-01 00:00:00')",
+            )
+            .bind(id)
+            .bind(USER_ID)
+            .bind(date_begin)
+            .bind(time_begin)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        let result = search_reservations(
+            &db,
+            USER_ID,
+            &SearchQuery {
+                from: chrono::NaiveDate::from_ymd_opt(2024, 6, 1),
+                to: chrono::NaiveDate::from_ymd_opt(2024, 6, 2),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let ids = result
+            .reservations
+            .into_iter()
+            .map(|reservation| reservation.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["cgv/4", "cgv/1"]);
+
+        let result = search_reservations(
+            &db,
+            USER_ID,
+            &SearchQuery {
+                page: Some(u32::MAX),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.page, MAX_PAGE);
+        assert_eq!(result.total, 4);
+    }
+}
@@ -0,0 +1,27 @@
+import React from "react";
+import { Form } from "react-router-dom";
+
+// Stored session cookies are not sent back, left blank to keep them. Required
+// ones can't be left blank until saved.
+export function SessionInput({ name, label, saved, required }: { name: string; label: string; saved: string[]; required: string[] }) {
+    return <>
+        <label htmlFor={name}>{label}</label>
+        <input
+            type="text"
+            name={name}
+            placeholder={saved.includes(name) ? "•••• (saved, leave blank to keep)" : ""}
+            required={required.includes(name) && !saved.includes(name)}
+        />
+    </>;
+}
+
+// Submits `clear=<name>` to drop a stored optional session cookie
+export function ClearSession({ action, name, label, saved }: { action: string; name: string; label: string; saved: string[] }) {
+    if (!saved.includes(name)) {
+        return null;
+    }
+    return <Form method="post" action={action}>
+        <input type="hidden" name="clear" value={name} />
+        <button type="submit" className="secondary">Clear {label}</button>
+    </Form>;
+}
@@ -0,0 +1,996 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use axum::{
+    extract::Path,
+    response::{IntoResponse as _, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use axum_sessions::extractors::ReadableSession;
+use hyper::StatusCode;
+use log::{debug, error, info};
+use sqlx::SqlitePool;
+
+use crate::{
+    google_calendar::GoogleUser,
+    title::{self, Locale},
+    CalendarEvent, UserId,
+};
+
+/// Id prefixes of the sources, used as the key of per source settings
+pub const SOURCES: &[&str] = &[
+    "naver",
+    "kobus",
+    "catch_table",
+    "cgv",
+    "megabox",
+    "lotte_cinema",
+    "bustago",
+    "ical",
+];
+
+/// Colors of the sources without their own setting, to tell cinema, transport
+/// and dining apart
+const DEFAULT_COLOR_IDS: &[(&str, &str)] = &[
+    // Grape
+    ("cgv", "3"),
+    ("megabox", "3"),
+    ("lotte_cinema", "3"),
+    // Peacock
+    ("kobus", "7"),
+    ("bustago", "7"),
+    // Tangerine
+    ("catch_table", "6"),
+    // Sage
+    ("naver", "2"),
+];
+
+/// Whether the events block the time on google calendar
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Transparency {
+    /// Busy
+    #[default]
+    Opaque,
+    /// Free
+    Transparent,
+}
+
+/// Google rejects events with more override reminders or earlier ones
+const MAX_REMINDERS: usize = 5;
+const MAX_REMINDER_MINUTES: u32 = 4 * 7 * 24 * 60;
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SourceSetting {
+    /// Minutes before the event to show popup reminders.
+    /// `None` follows the default reminders of the calendar.
+    pub reminders: Option<Vec<u32>>,
+    /// Calendar to sync the events of the source into.
+    /// `None` uses the calendar created for the user.
+    pub calendar_id: Option<String>,
+    /// One of the event color ids of google calendar, `"1"` to `"11"`.
+    /// `None` follows the color of the calendar.
+    pub color_id: Option<String>,
+    /// Color of tentative events, e.g. on a waiting list, to tell them from
+    /// confirmed ones. `None` uses `color_id` for all events.
+    #[serde(default)]
+    pub tentative_color_id: Option<String>,
+    /// Add the link of the ticket image to the description, for the sources
+    /// providing it.
+    #[serde(default)]
+    pub include_image: bool,
+    /// Minutes of the events without the end. `None` follows the default of
+    /// the kind of the source.
+    #[serde(default)]
+    pub default_duration: Option<u32>,
+    /// Show the events as free, e.g. reservations only to be tracked.
+    /// Tentative events are always free.
+    #[serde(default)]
+    pub transparency: Transparency,
+    /// Title of the events with `{name}` placeholders of
+    /// [`title::placeholders_of`] the source. `None` uses the title in the
+    /// locale of the user.
+    #[serde(default)]
+    pub title_template: Option<String>,
+}
+
+impl SourceSetting {
+    /// Setting of the source until the user saves one
+    pub fn default_of(source: &str) -> Self {
+        Self {
+            color_id: DEFAULT_COLOR_IDS
+                .iter()
+                .find(|(default_source, _)| *default_source == source)
+                .map(|(_, color_id)| color_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.color_id
+            .iter()
+            .chain(self.tentative_color_id.iter())
+            .all(|color_id| matches!(color_id.parse(), Ok(1..=11)))
+            && self
+                .calendar_id
+                .iter()
+                .all(|calendar_id| !calendar_id.is_empty())
+            && self.default_duration != Some(0)
+            && self.reminders.iter().all(|reminders| {
+                reminders.len() <= MAX_REMINDERS
+                    && reminders
+                        .iter()
+                        .all(|minutes| *minutes <= MAX_REMINDER_MINUTES)
+            })
+    }
+
+    /// Color of the event, by the status if `tentative_color_id` is set
+    pub fn color_of(&self, event: &CalendarEvent) -> Option<String> {
+        if event.tentative && self.tentative_color_id.is_some() {
+            self.tentative_color_id.clone()
+        } else {
+            self.color_id.clone()
+        }
+    }
+
+    pub async fn all_of(
+        db: &SqlitePool,
+        user_id: UserId,
+    ) -> anyhow::Result<HashMap<String, SourceSetting>> {
+        sqlx::query!(
+            "SELECT `source`, `reminders`, `calendar_id`, `color_id`, `tentative_color_id`, `include_image`,
+                `default_duration` as `default_duration: u32`,
+                `transparency` as `transparency: Transparency`,
+                `title_template`
+            FROM `source_setting` WHERE `user_id` = ?",
+            user_id
+        )
+        .fetch_all(db)
+        .await
+        .with_context(|| format!("Failed to get source settings of {user_id:?}"))?
+        .into_iter()
+        .map(|row| {
+            let reminders = row
+                .reminders
+                .map(|reminders| {
+                    reminders
+                        .split(',')
+                        .filter(|minutes| !minutes.is_empty())
+                        .map(str::parse)
+                        .collect::<Result<Vec<u32>, _>>()
+                })
+                .transpose()
+                .with_context(|| format!("Invalid reminders for {}", row.source))?;
+            Ok((
+                row.source,
+                SourceSetting {
+                    reminders,
+                    calendar_id: row.calendar_id,
+                    color_id: row.color_id,
+                    tentative_color_id: row.tentative_color_id,
+                    include_image: row.include_image,
+                    default_duration: row.default_duration,
+                    transparency: row.transparency,
+                    title_template: row.title_template,
+                },
+            ))
+        })
+        .collect()
+    }
+
+    /// Settings of all sources, with the defaults of the ones not saved yet
+    pub async fn all_or_default_of(
+        db: &SqlitePool,
+        user_id: UserId,
+    ) -> anyhow::Result<HashMap<String, SourceSetting>> {
+        let mut settings = Self::all_of(db, user_id).await?;
+        for source in SOURCES {
+            settings
+                .entry(source.to_string())
+                .or_insert_with(|| Self::default_of(source));
+        }
+        Ok(settings)
+    }
+
+    pub async fn of(db: &SqlitePool, user_id: UserId, source: &str) -> anyhow::Result<Self> {
+        Ok(Self::all_of(db, user_id)
+            .await?
+            .remove(source)
+            .unwrap_or_else(|| Self::default_of(source)))
+    }
+
+    /// Saves the setting and marks the events of the source to be patched on
+    /// the next sync when it is changed. Returns the number of the marked events.
+    pub async fn update(
+        &self,
+        db: &SqlitePool,
+        user_id: UserId,
+        source: &str,
+    ) -> anyhow::Result<u64> {
+        let previous = Self::of(db, user_id, source).await?;
+        self.save(db, user_id, source).await?;
+        if previous == *self {
+            return Ok(0);
+        }
+        CalendarEvent::touch_all_of(user_id, db, &format!("{source}/")).await
+    }
+
+    pub async fn save(&self, db: &SqlitePool, user_id: UserId, source: &str) -> anyhow::Result<()> {
+        let reminders = self
+            .reminders
+            .as_ref()
+            .map(|reminders| itertools::join(reminders, ","));
+        sqlx::query!(
+            "INSERT INTO `source_setting` (
+                `user_id`, `source`, `reminders`, `calendar_id`, `color_id`, `tentative_color_id`,
+                `include_image`, `default_duration`, `transparency`, `title_template`
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT (`user_id`, `source`) DO UPDATE
+                SET `reminders` = `excluded`.`reminders`,
+                    `calendar_id` = `excluded`.`calendar_id`,
+                    `color_id` = `excluded`.`color_id`,
+                    `tentative_color_id` = `excluded`.`tentative_color_id`,
+                    `include_image` = `excluded`.`include_image`,
+                    `default_duration` = `excluded`.`default_duration`,
+                    `transparency` = `excluded`.`transparency`,
+                    `title_template` = `excluded`.`title_template`",
+            user_id,
+            source,
+            reminders,
+            self.calendar_id,
+            self.color_id,
+            self.tentative_color_id,
+            self.include_image,
+            self.default_duration,
+            self.transparency,
+            self.title_template
+        )
+        .execute(db)
+        .await
+        .with_context(|| format!("Failed to save {source} setting of {user_id:?}"))
+        .map(|_| ())
+    }
+}
+
+/// Language of the event titles, kept with the google calendar of the user.
+/// `None` before the user logs in to google.
+pub async fn locale_of(db: &SqlitePool, user_id: UserId) -> anyhow::Result<Option<Locale>> {
+    sqlx::query_scalar!(
+        r#"SELECT `locale` as `locale: Locale` FROM `google_user` WHERE `user_id` = ?"#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .with_context(|| format!("Failed to get locale of {user_id:?}"))
+}
+
+/// Saves the locale and marks all events of the user to be patched on the next
+/// sync when it is changed. Returns the number of the marked events, `None`
+/// before the user logs in to google.
+pub async fn update_locale(
+    db: &SqlitePool,
+    user_id: UserId,
+    locale: Locale,
+) -> anyhow::Result<Option<u64>> {
+    let Some(previous) = locale_of(db, user_id).await? else {
+        return Ok(None);
+    };
+    if previous == locale {
+        return Ok(Some(0));
+    }
+    sqlx::query!(
+        "UPDATE `google_user` SET `locale` = ? WHERE `user_id` = ?",
+        locale,
+        user_id
+    )
+    .execute(db)
+    .await
+    .with_context(|| format!("Failed to save locale of {user_id:?}"))?;
+    CalendarEvent::touch_all_of(user_id, db, "").await.map(Some)
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct LocaleSetting {
+    locale: Locale,
+}
+
+async fn get_locale(session: ReadableSession, Extension(db): Extension<SqlitePool>) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    match locale_of(&db, user_id).await {
+        Ok(locale) => Json(LocaleSetting {
+            locale: locale.unwrap_or_default(),
+        })
+        .into_response(),
+        Err(e) => {
+            error!("Error occurred while get locale - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn update_locale_setting(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Json(LocaleSetting { locale }): Json<LocaleSetting>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    match update_locale(&db, user_id, locale).await {
+        Ok(Some(count)) => {
+            if count > 0 {
+                info!("{count} events are to be patched for {user_id:?} in {locale:?}");
+            }
+            StatusCode::ACCEPTED.into_response()
+        }
+        Ok(None) => {
+            debug!("Google calendar is not linked for {user_id:?}");
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(e) => {
+            error!("Error occurred while update locale - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// What is done to the google event of a reservation cancelled after it is
+/// synced
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum CancelledEventMode {
+    #[default]
+    Delete,
+    /// Kept with the cancelled status and `[취소]` in front of the title
+    MarkCancelled,
+}
+
+/// `None` before the user logs in to google
+pub async fn cancelled_event_mode_of(
+    db: &SqlitePool,
+    user_id: UserId,
+) -> anyhow::Result<Option<CancelledEventMode>> {
+    sqlx::query_scalar!(
+        r#"SELECT `cancelled_event_mode` as `cancelled_event_mode: CancelledEventMode`
+        FROM `google_user` WHERE `user_id` = ?"#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .with_context(|| format!("Failed to get cancelled event mode of {user_id:?}"))
+}
+
+/// Applies to reservations cancelled from now on, events already deleted or
+/// marked are left as they are. `false` before the user logs in to google.
+pub async fn update_cancelled_event_mode(
+    db: &SqlitePool,
+    user_id: UserId,
+    mode: CancelledEventMode,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE `google_user` SET `cancelled_event_mode` = ? WHERE `user_id` = ?",
+        mode,
+        user_id
+    )
+    .execute(db)
+    .await
+    .with_context(|| format!("Failed to save cancelled event mode of {user_id:?}"))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CancelledEventModeSetting {
+    mode: CancelledEventMode,
+}
+
+async fn get_cancelled_event_mode(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    match cancelled_event_mode_of(&db, user_id).await {
+        Ok(mode) => Json(CancelledEventModeSetting {
+            mode: mode.unwrap_or_default(),
+        })
+        .into_response(),
+        Err(e) => {
+            error!("Error occurred while get cancelled event mode - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn update_cancelled_event_mode_setting(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Json(CancelledEventModeSetting { mode }): Json<CancelledEventModeSetting>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    match update_cancelled_event_mode(&db, user_id, mode).await {
+        Ok(true) => StatusCode::ACCEPTED.into_response(),
+        Ok(false) => {
+            debug!("Google calendar is not linked for {user_id:?}");
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(e) => {
+            error!("Error occurred while update cancelled event mode - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Whether reservations already over are left out of sync, `None` before the
+/// user logs in to google
+pub async fn only_future_events_of(
+    db: &SqlitePool,
+    user_id: UserId,
+) -> anyhow::Result<Option<bool>> {
+    sqlx::query_scalar!(
+        "SELECT `only_future_events` FROM `google_user` WHERE `user_id` = ?",
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .with_context(|| format!("Failed to get only future events of {user_id:?}"))
+}
+
+/// Applies to reservations synced from now on, events already on the
+/// calendar are left as they are. `false` before the user logs in to google.
+pub async fn update_only_future_events(
+    db: &SqlitePool,
+    user_id: UserId,
+    enabled: bool,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE `google_user` SET `only_future_events` = ? WHERE `user_id` = ?",
+        enabled,
+        user_id
+    )
+    .execute(db)
+    .await
+    .with_context(|| format!("Failed to save only future events of {user_id:?}"))?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct OnlyFutureEventsSetting {
+    enabled: bool,
+}
+
+async fn get_only_future_events(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    match only_future_events_of(&db, user_id).await {
+        Ok(enabled) => Json(OnlyFutureEventsSetting {
+            enabled: enabled.unwrap_or_default(),
+        })
+        .into_response(),
+        Err(e) => {
+            error!("Error occurred while get only future events - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn update_only_future_events_setting(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Json(OnlyFutureEventsSetting { enabled }): Json<OnlyFutureEventsSetting>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    match update_only_future_events(&db, user_id, enabled).await {
+        Ok(true) => StatusCode::ACCEPTED.into_response(),
+        Ok(false) => {
+            debug!("Google calendar is not linked for {user_id:?}");
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(e) => {
+            error!("Error occurred while update only future events - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CalendarName {
+    name: String,
+}
+
+/// Google user of the session whose calendar is still shared
+async fn linked_google_user(
+    session: &ReadableSession,
+    db: &SqlitePool,
+) -> Result<GoogleUser, Response> {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return Err(StatusCode::FORBIDDEN.into_response());
+    };
+
+    match GoogleUser::from_user_id(db, user_id).await {
+        Ok(Some(user)) if !user.calendar_revoked() => Ok(user),
+        Ok(_) => {
+            debug!("Google calendar is not linked for {user_id:?}");
+            Err(StatusCode::NOT_FOUND.into_response())
+        }
+        Err(e) => {
+            error!("Error occurred while get google user - {e:?}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+async fn get_calendar_name(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Response {
+    let user = match linked_google_user(&session, &db).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    match user.calendar_name().await {
+        Ok(Some(name)) => Json(CalendarName { name }).into_response(),
+        Ok(None) => {
+            debug!("Calendar is not owned by the service account");
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(e) => {
+            error!("Error occurred while get calendar name - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn rename_calendar(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Json(CalendarName { name }): Json<CalendarName>,
+) -> Response {
+    let user = match linked_google_user(&session, &db).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Calendar name is empty").into_response();
+    }
+
+    match user.rename_calendar(name).await {
+        Ok(true) => StatusCode::ACCEPTED.into_response(),
+        Ok(false) => (
+            StatusCode::FORBIDDEN,
+            "Only the calendar created by the hub can be renamed",
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Error occurred while rename calendar - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn get_source_settings(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    match SourceSetting::all_or_default_of(&db, user_id).await {
+        Ok(settings) => Json(settings).into_response(),
+        Err(e) => {
+            error!("Error occurred while get source settings - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn update_source_setting(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Path(source): Path<String>,
+    Json(setting): Json<SourceSetting>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    if !SOURCES.contains(&source.as_str()) {
+        debug!("Unknown source - {source}");
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    if !setting.is_valid() {
+        debug!("Invalid setting for {source} - {setting:?}");
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    if let Some(template) = &setting.title_template {
+        if let Err(e) = title::check_template(template, title::placeholders_of(&source)) {
+            debug!("Invalid title template for {source} - {e:#}");
+            return (StatusCode::BAD_REQUEST, format!("{e:#}")).into_response();
+        }
+    }
+
+    if let Some(calendar_id) = &setting.calendar_id {
+        let allowed = match GoogleUser::from_user_id(&db, user_id).await {
+            Ok(Some(user)) => user.can_sync_into(&db, calendar_id).await,
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        };
+        match allowed {
+            Ok(true) => {}
+            Ok(false) => {
+                debug!("{user_id:?} can't write to calendar {calendar_id}");
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "Calendar is not yours or can't be written by you",
+                )
+                    .into_response();
+            }
+            Err(e) => {
+                error!("Error occurred while check calendar {calendar_id} - {e:?}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
+    match setting.update(&db, user_id, &source).await {
+        Ok(count) => {
+            if count > 0 {
+                info!("{count} events of {source} are to be patched for {user_id:?}");
+            }
+            StatusCode::ACCEPTED.into_response()
+        }
+        Err(e) => {
+            error!("Error occurred while update source setting - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn invalidate_source_events(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Path(source): Path<String>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    if !SOURCES.contains(&source.as_str()) {
+        debug!("Unknown source - {source}");
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match CalendarEvent::invalidate_all_of(user_id, &db, &format!("{source}/")).await {
+        Ok(count) => {
+            info!("{count} events of {source} are invalidated for {user_id:?}");
+            Json(serde_json::json!({ "invalidated": count })).into_response()
+        }
+        Err(e) => {
+            error!("Error occurred while invalidate {source} events - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn restore_source_events(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Path(source): Path<String>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    if !SOURCES.contains(&source.as_str()) {
+        debug!("Unknown source - {source}");
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match CalendarEvent::restore_all_of(user_id, &db, &format!("{source}/")).await {
+        Ok(count) => {
+            info!("{count} events of {source} are restored for {user_id:?}");
+            Json(serde_json::json!({ "restored": count })).into_response()
+        }
+        Err(e) => {
+            error!("Error occurred while restore {source} events - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn web_router() -> Router {
+    Router::new()
+        .route("/locale", get(get_locale).post(update_locale_setting))
+        .route(
+            "/cancelled_event_mode",
+            get(get_cancelled_event_mode).post(update_cancelled_event_mode_setting),
+        )
+        .route(
+            "/only_future_events",
+            get(get_only_future_events).post(update_only_future_events_setting),
+        )
+        .route(
+            "/calendar_name",
+            get(get_calendar_name).post(rename_calendar),
+        )
+        .route("/source", get(get_source_settings))
+        .route("/source/:source", post(update_source_setting))
+        .route("/source/:source/invalidate", post(invalidate_source_events))
+        .route("/source/:source/restore", post(restore_source_events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn source_settings_are_persisted() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        let user_id = UserId(1);
+
+        let setting = SourceSetting {
+            reminders: Some(vec![30, 60]),
+            calendar_id: Some("movies@group.calendar.google.com".to_string()),
+            color_id: Some("5".to_string()),
+            tentative_color_id: Some("8".to_string()),
+            include_image: true,
+            default_duration: Some(150),
+            transparency: Transparency::Transparent,
+            title_template: Some("{movie} @ {theater}".to_string()),
+        };
+        setting.save(&db, user_id, "cgv").await.unwrap();
+        SourceSetting::default()
+            .save(&db, user_id, "naver")
+            .await
+            .unwrap();
+
+        let settings = SourceSetting::all_of(&db, user_id).await.unwrap();
+        assert_eq!(settings.len(), 2);
+        assert_eq!(settings["cgv"].reminders, Some(vec![30, 60]));
+        assert_eq!(
+            settings["cgv"].calendar_id.as_deref(),
+            Some("movies@group.calendar.google.com")
+        );
+        assert_eq!(settings["cgv"].color_id.as_deref(), Some("5"));
+        assert_eq!(settings["cgv"].tentative_color_id.as_deref(), Some("8"));
+        assert!(settings["cgv"].include_image);
+        assert_eq!(settings["cgv"].default_duration, Some(150));
+        assert_eq!(settings["cgv"].transparency, Transparency::Transparent);
+        assert_eq!(
+            settings["cgv"].title_template.as_deref(),
+            Some("{movie} @ {theater}")
+        );
+        assert_eq!(settings["naver"].transparency, Transparency::Opaque);
+        assert_eq!(settings["naver"].calendar_id, None);
+
+        // saving again overwrites the previous one
+        SourceSetting::default()
+            .save(&db, user_id, "cgv")
+            .await
+            .unwrap();
+        let settings = SourceSetting::all_of(&db, user_id).await.unwrap();
+        assert_eq!(settings["cgv"].color_id, None);
+        assert!(SourceSetting::all_of(&db, UserId(2))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn changed_color_patches_events_of_source() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        let user_id = UserId(1);
+        sqlx::query!(
+            "INSERT INTO `google_user` (`user_id`, `subject`, `calendar_id`, `last_synced`)
+            VALUES (1, 'subject', 'calendar', '2024-05-01 00:00:00')"
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        for id in ["cgv/1", "megabox/1"] {
+            sqlx::query!(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `invalid`, `updated_at`)
+                VALUES (?, 1, 'title', '', '2024-06-01', FALSE, '2024-04-01 00:00:00')",
+                id
+            )
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        let settings = SourceSetting::all_or_default_of(&db, user_id)
+            .await
+            .unwrap();
+        assert_eq!(settings.len(), SOURCES.len());
+        assert_eq!(settings["cgv"].color_id.as_deref(), Some("3"));
+        assert_eq!(settings["kobus"].color_id.as_deref(), Some("7"));
+        assert_eq!(settings["ical"].color_id, None);
+
+        // same as the default, nothing to patch
+        assert_eq!(
+            SourceSetting::default_of("cgv")
+                .update(&db, user_id, "cgv")
+                .await
+                .unwrap(),
+            0
+        );
+        let setting = SourceSetting {
+            color_id: Some("11".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(setting.update(&db, user_id, "cgv").await.unwrap(), 1);
+        assert_eq!(
+            crate::google_calendar::get_pending_sync_count(db.clone(), user_id)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(setting.update(&db, user_id, "cgv").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn invalidated_source_events_are_pending_deletion() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        let user_id = UserId(1);
+        sqlx::query!(
+            "INSERT INTO `google_user` (`user_id`, `subject`, `calendar_id`, `last_synced`)
+            VALUES (1, 'subject', 'calendar', '2024-05-01 00:00:00')"
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        for id in ["cgv/1", "cgv/2", "megabox/1"] {
+            sqlx::query!(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `invalid`, `updated_at`)
+                VALUES (?, 1, 'title', '', '2024-06-01', FALSE, '2024-04-01 00:00:00')",
+                id
+            )
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            CalendarEvent::invalidate_all_of(user_id, &db, "cgv/")
+                .await
+                .unwrap(),
+            2
+        );
+
+        let invalid = sqlx::query!(
+            "SELECT `id`, `invalid` FROM `reservation` WHERE `user_id` = 1 ORDER BY `id`"
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| (row.id, row.invalid))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            invalid,
+            vec![
+                ("cgv/1".to_string(), true),
+                ("cgv/2".to_string(), true),
+                ("megabox/1".to_string(), false)
+            ]
+        );
+        // updated after the last sync, so deleted from google calendar on the next one
+        assert_eq!(
+            crate::google_calendar::get_pending_sync_count(db.clone(), user_id)
+                .await
+                .unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    fn color_id_must_be_google_event_color() {
+        let with_color = |color_id: &str| SourceSetting {
+            color_id: Some(color_id.to_string()),
+            ..Default::default()
+        };
+        assert!(SourceSetting::default().is_valid());
+        assert!(with_color("1").is_valid());
+        assert!(with_color("11").is_valid());
+        assert!(!with_color("0").is_valid());
+        assert!(!with_color("12").is_valid());
+        assert!(!with_color("red").is_valid());
+        assert!(!SourceSetting {
+            tentative_color_id: Some("12".to_string()),
+            ..Default::default()
+        }
+        .is_valid());
+        assert!(!SourceSetting {
+            default_duration: Some(0),
+            ..Default::default()
+        }
+        .is_valid());
+        let with_reminders = |reminders: Vec<u32>| SourceSetting {
+            reminders: Some(reminders),
+            ..Default::default()
+        };
+        assert!(with_reminders(vec![10, 30, 60, 120, 40320]).is_valid());
+        assert!(!with_reminders(vec![10, 30, 60, 120, 240, 480]).is_valid());
+        assert!(!with_reminders(vec![40321]).is_valid());
+    }
+
+    #[tokio::test]
+    async fn changed_locale_patches_all_events() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        let user_id = UserId(1);
+        assert_eq!(update_locale(&db, user_id, Locale::En).await.unwrap(), None);
+
+        sqlx::query!(
+            "INSERT INTO `google_user` (`user_id`, `subject`, `calendar_id`, `last_synced`)
+            VALUES (1, 'subject', 'calendar', '2024-05-01 00:00:00')"
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        for id in ["kobus/1", "megabox/1"] {
+            sqlx::query!(
+                "INSERT INTO `reservation` (`id`, `user_id`, `title`, `detail`, `date_begin`, `invalid`, `updated_at`)
+                VALUES (?, 1, 'title', '', '2024-06-01', FALSE, '2024-04-01 00:00:00')",
+                id
+            )
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        assert_eq!(locale_of(&db, user_id).await.unwrap(), Some(Locale::Ko));
+
+        assert_eq!(
+            update_locale(&db, user_id, Locale::Ko).await.unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            update_locale(&db, user_id, Locale::En).await.unwrap(),
+            Some(2)
+        );
+        assert_eq!(locale_of(&db, user_id).await.unwrap(), Some(Locale::En));
+    }
+}
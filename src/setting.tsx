@@ -0,0 +1,230 @@
+import React from "react";
+import { ActionFunctionArgs, Form, Navigate, useActionData, useLoaderData } from "react-router-dom";
+import { ActionResult, AsyncReturnType, formValue, postJson, request as sendRequest, SOURCES } from './utils';
+
+// sources providing ticket image
+const IMAGE_SOURCES: string[] = ["megabox"];
+
+// placeholders of title templates besides {title}, same as `title::placeholders_of`
+const TITLE_PLACEHOLDERS: Record<string, string[] | undefined> = {
+    kobus: ["departure", "arrival", "bus"],
+    bustago: ["departure", "arrival", "bus"],
+    cgv: ["movie", "chain", "theater"],
+    megabox: ["movie", "chain", "theater"],
+    lotte_cinema: ["movie", "chain", "theater"],
+    catch_table: ["place"],
+};
+
+// event colors of google calendar
+const COLORS = {
+    "1": "Lavender",
+    "2": "Sage",
+    "3": "Grape",
+    "4": "Flamingo",
+    "5": "Banana",
+    "6": "Tangerine",
+    "7": "Peacock",
+    "8": "Graphite",
+    "9": "Blueberry",
+    "10": "Basil",
+    "11": "Tomato",
+} as const;
+
+// languages of the event titles
+const LOCALES = {
+    ko: "한국어",
+    en: "English",
+} as const;
+
+// what is done to the events of reservations cancelled after sync
+const CANCELLED_EVENT_MODES = {
+    delete: "Delete the event",
+    mark_cancelled: "Keep it as cancelled with [취소] in the title",
+} as const;
+
+interface SourceSetting {
+    reminders: number[] | null,
+    calendar_id: string | null,
+    color_id: string | null,
+    tentative_color_id: string | null,
+    include_image: boolean,
+    default_duration: number | null,
+    transparency: "opaque" | "transparent",
+    title_template: string | null,
+}
+
+export async function loader() {
+    const [resp, localeResp, cancelledResp, futureResp, calendarResp] = await Promise.all([
+        fetch("/setting/source", {
+            credentials: "same-origin",
+        }),
+        fetch("/setting/locale", {
+            credentials: "same-origin",
+        }),
+        fetch("/setting/cancelled_event_mode", {
+            credentials: "same-origin",
+        }),
+        fetch("/setting/only_future_events", {
+            credentials: "same-origin",
+        }),
+        fetch("/setting/calendar_name", {
+            credentials: "same-origin",
+        }),
+    ]);
+
+    if (resp.ok && localeResp.ok && cancelledResp.ok && futureResp.ok) {
+        return {
+            sources: await resp.json() as Record<string, SourceSetting | undefined>,
+            locale: (await localeResp.json()).locale as keyof typeof LOCALES,
+            cancelled_event_mode: (await cancelledResp.json()).mode as keyof typeof CANCELLED_EVENT_MODES,
+            only_future_events: (await futureResp.json()).enabled as boolean,
+            // not shown when the calendar is not linked, can't be renamed or google can't be reached
+            calendar_name: calendarResp.ok ? (await calendarResp.json()).name as string : null,
+        };
+    } else {
+        return null;
+    }
+}
+
+export async function action({ request }: ActionFunctionArgs) {
+    const formData = await request.formData();
+    if (formData.get("intent") === "locale") {
+        const locale = formValue(formData, "locale");
+        return { source: null, ...await postJson("/setting/locale", JSON.stringify({ locale })) };
+    }
+    if (formData.get("intent") === "cancelled_event_mode") {
+        const mode = formValue(formData, "mode");
+        return { source: "", ...await postJson("/setting/cancelled_event_mode", JSON.stringify({ mode })) };
+    }
+    if (formData.get("intent") === "only_future_events") {
+        const enabled = formData.get("enabled") !== null;
+        return { source: "", ...await postJson("/setting/only_future_events", JSON.stringify({ enabled })) };
+    }
+    if (formData.get("intent") === "calendar_name") {
+        const name = formValue(formData, "name").trim();
+        return { source: "calendar_name", ...await postJson("/setting/calendar_name", JSON.stringify({ name })) };
+    }
+    const source = formValue(formData, "source");
+    if (formData.get("intent") === "invalidate") {
+        return { source, ...await sendRequest(`/setting/source/${source}/invalidate`, { method: "post" }) };
+    }
+    if (formData.get("intent") === "restore") {
+        return { source, ...await sendRequest(`/setting/source/${source}/restore`, { method: "post" }) };
+    }
+    const reminders = formValue(formData, "reminders").trim();
+    const calendar_id = formValue(formData, "calendar_id").trim();
+    const color_id = formValue(formData, "color_id");
+    const tentative_color_id = formValue(formData, "tentative_color_id");
+    const default_duration = formValue(formData, "default_duration").trim();
+    const title_template = formValue(formData, "title_template").trim();
+    const setting: SourceSetting = {
+        reminders: reminders === "" ? null : reminders.split(",").map((minutes) => parseInt(minutes.trim())),
+        calendar_id: calendar_id === "" ? null : calendar_id,
+        color_id: color_id === "" ? null : color_id,
+        tentative_color_id: tentative_color_id === "" ? null : tentative_color_id,
+        include_image: formData.get("include_image") !== null,
+        default_duration: default_duration === "" ? null : parseInt(default_duration),
+        transparency: formData.get("free") !== null ? "transparent" : "opaque",
+        title_template: title_template === "" ? null : title_template,
+    };
+    return { source, ...await postJson(`/setting/source/${source}`, JSON.stringify(setting)) };
+}
+
+export function Component() {
+    const data = useLoaderData() as AsyncReturnType<typeof loader>;
+    const result = useActionData() as AsyncReturnType<typeof action> | undefined;
+
+    if (data !== null) {
+        return <div>
+            <Form method="post" action="/setting">
+                <h3>Language of event titles</h3>
+                <input type="hidden" name="intent" value="locale" />
+                <select name="locale" defaultValue={data.locale}>
+                    {Object.entries(LOCALES).map(([locale, name]) => <option value={locale} key={locale}>{name}</option>)}
+                </select>
+                {result?.source === null && result.error && <p role="alert">{result.error}</p>}
+                <button type="submit">Update</button>
+            </Form>
+            {data.calendar_name !== null && <Form method="post" action="/setting">
+                <h3>Calendar name</h3>
+                <input type="hidden" name="intent" value="calendar_name" />
+                <input type="text" name="name" defaultValue={data.calendar_name} required />
+                {result?.source === "calendar_name" && result.error && <p role="alert">{result.error}</p>}
+                <button type="submit">Rename</button>
+            </Form>}
+            <Form method="post" action="/setting">
+                <h3>Cancelled reservations</h3>
+                <input type="hidden" name="intent" value="cancelled_event_mode" />
+                <select name="mode" defaultValue={data.cancelled_event_mode}>
+                    {Object.entries(CANCELLED_EVENT_MODES).map(([mode, name]) => <option value={mode} key={mode}>{name}</option>)}
+                </select>
+                {result?.source === "" && result.error && <p role="alert">{result.error}</p>}
+                <button type="submit">Update</button>
+            </Form>
+            <Form method="post" action="/setting">
+                <h3>Past reservations</h3>
+                <input type="hidden" name="intent" value="only_future_events" />
+                <label>
+                    <input type="checkbox" name="enabled" defaultChecked={data.only_future_events} />
+                    Only sync reservations not over yet
+                </label>
+                <button type="submit">Update</button>
+            </Form>
+            {Object.entries(SOURCES).map(([source, name]) => {
+                const setting = data.sources[source];
+                return <div key={source}>
+                    <Form method="post" action="/setting">
+                        <h3>{name}</h3>
+                        <input type="hidden" name="source" value={source} />
+                        <label htmlFor={`${source}-calendar_id`}>Calendar ID</label>
+                        <input type="text" id={`${source}-calendar_id`} name="calendar_id" placeholder="calendar of calendar hub" defaultValue={setting?.calendar_id ?? ""} />
+                        <label htmlFor={`${source}-color_id`}>Color</label>
+                        <select id={`${source}-color_id`} name="color_id" defaultValue={setting?.color_id ?? ""}>
+                            <option value="">Calendar color</option>
+                            {Object.entries(COLORS).map(([id, color]) => <option value={id} key={id}>{color}</option>)}
+                        </select>
+                        <label htmlFor={`${source}-tentative_color_id`}>Color of tentative events</label>
+                        <select id={`${source}-tentative_color_id`} name="tentative_color_id" defaultValue={setting?.tentative_color_id ?? ""}>
+                            <option value="">Same as above</option>
+                            {Object.entries(COLORS).map(([id, color]) => <option value={id} key={id}>{color}</option>)}
+                        </select>
+                        <label htmlFor={`${source}-title_template`}>
+                            Title template ({["title", ...TITLE_PLACEHOLDERS[source] ?? []].map((name) => `{${name}}`).join(", ")})
+                        </label>
+                        <input type="text" id={`${source}-title_template`} name="title_template" placeholder="{title}" defaultValue={setting?.title_template ?? ""} />
+                        <label htmlFor={`${source}-reminders`}>Reminders (minutes, comma separated)</label>
+                        <input type="text" id={`${source}-reminders`} name="reminders" placeholder="calendar default" defaultValue={setting?.reminders?.join(",") ?? ""} />
+                        <label htmlFor={`${source}-default_duration`}>Duration of events without the end (minutes)</label>
+                        <input type="number" id={`${source}-default_duration`} name="default_duration" min="1" placeholder="source default" defaultValue={setting?.default_duration ?? ""} />
+                        <label>
+                            <input type="checkbox" name="free" defaultChecked={setting?.transparency === "transparent"} />
+                            Show as free
+                        </label>
+                        {IMAGE_SOURCES.includes(source) && <label>
+                            <input type="checkbox" name="include_image" defaultChecked={setting?.include_image ?? false} />
+                            Include ticket image link
+                        </label>}
+                        {result?.source === source && result.error && <p role="alert">{result.error}</p>}
+                        <button type="submit">Update</button>
+                    </Form>
+                    <Form method="post" action="/setting" onSubmit={(event) => {
+                        if (!confirm(`Remove all events of ${name} from google calendar? They are not synced again until "Sync removed events again" is pressed, only reservations made later are.`)) {
+                            event.preventDefault();
+                        }
+                    }}>
+                        <input type="hidden" name="source" value={source} />
+                        <input type="hidden" name="intent" value="invalidate" />
+                        <button type="submit">Remove all events</button>
+                    </Form>
+                    <Form method="post" action="/setting">
+                        <input type="hidden" name="source" value={source} />
+                        <input type="hidden" name="intent" value="restore" />
+                        <button type="submit" className="secondary">Sync removed events again</button>
+                    </Form>
+                </div>;
+            })}
+        </div>;
+    } else {
+        return <Navigate to="/" />;
+    }
+}
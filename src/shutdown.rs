@@ -0,0 +1,84 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use log::info;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+
+/// Lets background jobs finish the iteration running at shutdown and skips
+/// the following ones, not to leave half synced events behind
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    running: Arc<RwLock<()>>,
+}
+
+impl Shutdown {
+    /// Runs `f` unless shutting down, [`Shutdown::shutdown`] waits for it
+    pub async fn run<F: Future>(&self, f: F) -> Option<F::Output> {
+        let _running = self.running.read().await;
+        if self.token.is_cancelled() {
+            info!("Job is skipped, shutting down");
+            return None;
+        }
+        Some(f.await)
+    }
+
+    /// Sleeps for `duration`, `false` when woken early by shutting down
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => true,
+            _ = self.token.cancelled() => false,
+        }
+    }
+
+    pub async fn shutdown(&self) {
+        self.token.cancel();
+        // all running jobs release the read lock when done
+        let _ = self.running.write().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn running_job_finishes_and_next_is_skipped() {
+        let shutdown = Shutdown::default();
+        let (started_sender, started_receiver) = tokio::sync::oneshot::channel();
+        let job = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                shutdown
+                    .run(async {
+                        started_sender.send(()).unwrap();
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        "done"
+                    })
+                    .await
+            }
+        });
+        started_receiver.await.unwrap();
+
+        shutdown.shutdown().await;
+        assert!(job.is_finished());
+        assert_eq!(job.await.unwrap(), Some("done"));
+        assert_eq!(shutdown.run(async { "next" }).await, None);
+    }
+
+    #[tokio::test]
+    async fn sleep_is_woken_by_shutdown() {
+        let shutdown = Shutdown::default();
+        let sleep = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.sleep(Duration::from_secs(3600)).await }
+        });
+
+        shutdown.shutdown().await;
+        assert!(!tokio::time::timeout(Duration::from_secs(1), sleep)
+            .await
+            .expect("sleep is not woken")
+            .unwrap());
+        assert!(Shutdown::default().sleep(Duration::from_millis(1)).await);
+    }
+}
@@ -0,0 +1,225 @@
+use futures::{future::BoxFuture, FutureExt as _, StreamExt as _};
+use log::error;
+use once_cell::sync::Lazy;
+use sqlx::SqlitePool;
+use tokio::sync::{mpsc, Semaphore};
+use tokio_cron_scheduler::Job;
+
+use crate::{
+    bustago::BustagoUser,
+    catch_table::CatchTableUser,
+    cgv::CgvUser,
+    correlation,
+    http::count_from_env,
+    ical::IcalUser,
+    kobus::KobusUser,
+    lotte_cinema::LotteCinemaUser,
+    megabox::MegaboxUser,
+    naver_reservation::NaverUser,
+    shutdown::Shutdown,
+    user::{ping_job, UserImpl},
+    CrawlStats, UserId,
+};
+
+/// As many as the sources, which the scheduled poll crawls at once
+const DEFAULT_MAX_CONCURRENT_CRAWLS: usize = 8;
+
+/// Number of crawls run at once
+static MAX_CONCURRENT_CRAWLS: Lazy<usize> =
+    Lazy::new(|| count_from_env("MAX_CONCURRENT_CRAWLS", DEFAULT_MAX_CONCURRENT_CRAWLS));
+static CRAWL_PERMITS: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(*MAX_CONCURRENT_CRAWLS));
+
+/// Crawls of a user running now, out of `MAX_CONCURRENT_CRAWLS`
+#[derive(Debug, serde::Serialize)]
+pub struct CrawlLoad {
+    pub in_flight: usize,
+    pub max: usize,
+}
+
+pub fn crawl_load() -> CrawlLoad {
+    let max = *MAX_CONCURRENT_CRAWLS;
+    CrawlLoad {
+        in_flight: max - CRAWL_PERMITS.available_permits(),
+        max,
+    }
+}
+
+/// Waits until less than `MAX_CONCURRENT_CRAWLS` crawls are running, not to
+/// run out of memory on small hosts when many users are crawled at once
+pub(crate) async fn with_crawl_permit<F: std::future::Future>(crawl: F) -> F::Output {
+    with_permit_of(&CRAWL_PERMITS, crawl).await
+}
+
+async fn with_permit_of<F: std::future::Future>(permits: &Semaphore, crawl: F) -> F::Output {
+    let _permit = permits
+        .acquire()
+        .await
+        .expect("crawl permits are never closed");
+    crawl.await
+}
+
+/// Crawl entry points of a [`UserImpl`] without its type, for the sync request
+/// and the scheduled jobs to go through all sources in one path
+pub struct Source {
+    pub name: &'static str,
+    /// What the ids of reservations of the source start with
+    pub prefix: &'static str,
+    crawl: fn(SqlitePool, UserId) -> BoxFuture<'static, anyhow::Result<CrawlStats>>,
+    crawl_all:
+        fn(SqlitePool, mpsc::UnboundedSender<(UserId, CrawlStats)>) -> BoxFuture<'static, ()>,
+    ping_job: fn(SqlitePool, Shutdown) -> anyhow::Result<Option<Job>>,
+}
+
+/// All sources, in the order of [`crate::setting::SOURCES`]
+pub const ALL: &[Source] = &[
+    Source::of::<NaverUser>(),
+    Source::of::<KobusUser>(),
+    Source::of::<CatchTableUser>(),
+    Source::of::<CgvUser>(),
+    Source::of::<MegaboxUser>(),
+    Source::of::<LotteCinemaUser>(),
+    Source::of::<BustagoUser>(),
+    Source::of::<IcalUser>(),
+];
+
+async fn crawl<U: UserImpl>(db: SqlitePool, user_id: UserId) -> anyhow::Result<CrawlStats> {
+    match U::from_user_id(db.clone(), user_id).await? {
+        Some(user) => {
+            let crawl = correlation::crawl_scope(U::SOURCE, user_id, user.fetch_if_set_up(db));
+            with_crawl_permit(crawl).await
+        }
+        None => Ok(CrawlStats::default()),
+    }
+}
+
+async fn crawl_all<U: UserImpl>(
+    db: SqlitePool,
+    stats_sender: mpsc::UnboundedSender<(UserId, CrawlStats)>,
+) {
+    let mut users = U::all_users(&db);
+    while let Some(user) = users.next().await {
+        match user {
+            Ok(user) => {
+                let user_id = user.user_id();
+
+                let stats =
+                    with_crawl_permit(correlation::crawl_scope(U::SOURCE, user_id, async {
+                        user.fetch_if_set_up(db.clone()).await.unwrap_or_else(|e| {
+                            error!("Failed to fetch {} data for {user_id:?} - {e:?}", U::SOURCE);
+                            CrawlStats {
+                                errors: 1,
+                                ..Default::default()
+                            }
+                        })
+                    }))
+                    .await;
+                stats_sender.send((user_id, stats)).unwrap();
+            }
+            Err(e) => error!("Failed to get {} user info from DB - {e:?}", U::SOURCE),
+        }
+    }
+}
+
+impl Source {
+    pub const fn of<U: UserImpl>() -> Self {
+        Self {
+            name: U::SOURCE,
+            prefix: U::SOURCE_PREFIX,
+            crawl: |db, user_id| crawl::<U>(db, user_id).boxed(),
+            crawl_all: |db, stats_sender| crawl_all::<U>(db, stats_sender).boxed(),
+            ping_job: ping_job::<U>,
+        }
+    }
+
+    pub fn get(name: &str) -> Option<&'static Source> {
+        ALL.iter().find(|source| source.name == name)
+    }
+
+    /// Fetches reservations of the user, nothing is counted when the user has
+    /// not registered the source
+    pub async fn crawl(&self, db: SqlitePool, user_id: UserId) -> anyhow::Result<CrawlStats> {
+        (self.crawl)(db, user_id).await
+    }
+
+    /// Fetches reservations of all users of the source, sending the stats of
+    /// each user to sync their google calendar after. Failed crawls are
+    /// counted in `errors`.
+    pub async fn crawl_all(
+        &self,
+        db: SqlitePool,
+        stats_sender: mpsc::UnboundedSender<(UserId, CrawlStats)>,
+    ) {
+        (self.crawl_all)(db, stats_sender).await
+    }
+
+    pub fn ping_job(&self, db: SqlitePool, shutdown: Shutdown) -> anyhow::Result<Option<Job>> {
+        (self.ping_job)(db, shutdown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn all_sources_are_registered() {
+        assert_eq!(
+            ALL.iter().map(|source| source.name).collect::<Vec<_>>(),
+            crate::setting::SOURCES
+        );
+        assert_eq!(Source::get("cgv").unwrap().name, "cgv");
+        assert!(Source::get("unknown").is_none());
+    }
+
+    #[test]
+    fn prefixes_are_unique_and_end_with_slash() {
+        for source in ALL {
+            assert_eq!(source.prefix, format!("{}/", source.name));
+        }
+        let prefixes: std::collections::HashSet<_> =
+            ALL.iter().map(|source| source.prefix).collect();
+        assert_eq!(prefixes.len(), ALL.len());
+    }
+
+    #[tokio::test]
+    async fn crawls_wait_for_permits() {
+        const PERMITS: usize = 2;
+        tokio::time::pause();
+        let permits = Semaphore::new(PERMITS);
+        let in_flight = AtomicUsize::new(0);
+        let most_in_flight = AtomicUsize::new(0);
+        let crawl = || async {
+            let running = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            most_in_flight.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+        };
+
+        let started = tokio::time::Instant::now();
+        futures::future::join_all((0..=PERMITS).map(|_| with_permit_of(&permits, crawl()))).await;
+
+        assert_eq!(most_in_flight.load(Ordering::SeqCst), PERMITS);
+        // the last one ran after one of the others
+        let elapsed = started.elapsed();
+        assert!(
+            (2..3).contains(&elapsed.as_secs()),
+            "took {elapsed:?} on the paused clock"
+        );
+        assert_eq!(permits.available_permits(), PERMITS);
+    }
+
+    #[tokio::test]
+    async fn crawl_skips_unregistered_user() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+
+        for source in ALL {
+            assert_eq!(
+                source.crawl(db.clone(), UserId(1)).await.unwrap(),
+                CrawlStats::default()
+            );
+        }
+    }
+}
@@ -0,0 +1,22 @@
+//! Helpers for the tests touching the DB
+
+use sqlx::SqlitePool;
+
+use crate::UserId;
+
+/// User inserted by [`db`]
+pub const USER_ID: UserId = UserId(1);
+
+/// In-memory DB with the migrations applied and [`USER_ID`] inserted, not
+/// shared with other tests
+pub async fn db() -> SqlitePool {
+    let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    sqlx::migrate!().run(&db).await.unwrap();
+    sqlx::query("INSERT INTO `user` (`user_id`, `dummy`) VALUES (?, TRUE)")
+        .bind(USER_ID)
+        .execute(&db)
+        .await
+        .unwrap();
+
+    db
+}
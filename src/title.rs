@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context as _;
+
+/// Language of the titles of synced events, Korean by default
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    Ko,
+    En,
+}
+
+impl Locale {
+    /// Line of the event description telling the number of people
+    pub fn party_size(self, party_size: u32) -> String {
+        match self {
+            Self::Ko => format!("인원: {party_size}명"),
+            Self::En => format!("Party of {party_size}"),
+        }
+    }
+
+    /// Line of the event description linking to the reservation
+    pub fn reservation_link(self, url: &str) -> String {
+        match self {
+            Self::Ko => format!("예약 상세: {url}"),
+            Self::En => format!("Reservation details: {url}"),
+        }
+    }
+}
+
+/// Cinema chain shown next to the theater, when the theater name doesn't
+/// include it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Chain {
+    Cgv,
+    Megabox,
+    LotteCinema,
+}
+
+impl Chain {
+    fn name(self, locale: Locale) -> &'static str {
+        match (self, locale) {
+            (Self::Cgv, _) => "CGV",
+            (Self::Megabox, _) => "MEGABOX",
+            (Self::LotteCinema, Locale::Ko) => "롯데시네마",
+            (Self::LotteCinema, Locale::En) => "LOTTE CINEMA",
+        }
+    }
+}
+
+/// Parts of an event title, composed in the locale of the user on sync.
+/// Names from the sources are kept as they are.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Title {
+    /// Trip of an express bus, or an intercity bus if not `express`
+    Bus {
+        express: bool,
+        departure: String,
+        arrival: String,
+    },
+    Movie {
+        movie: String,
+        chain: Chain,
+        theater: String,
+    },
+    /// Booking of a place, titled with the place itself
+    Dining { place: String },
+    /// Place on a waiting list
+    Waiting { place: String },
+}
+
+impl Title {
+    /// Values of the placeholders of title templates in `locale`
+    pub fn parts(&self, locale: Locale) -> BTreeMap<&'static str, String> {
+        match self {
+            Self::Bus {
+                express,
+                departure,
+                arrival,
+            } => {
+                let bus = match (express, locale) {
+                    (true, Locale::Ko) => "고속버스",
+                    (false, Locale::Ko) => "시외버스",
+                    (true, Locale::En) => "Express bus",
+                    (false, Locale::En) => "Intercity bus",
+                };
+                BTreeMap::from([
+                    ("departure", departure.clone()),
+                    ("arrival", arrival.clone()),
+                    ("bus", bus.to_string()),
+                ])
+            }
+            Self::Movie {
+                movie,
+                chain,
+                theater,
+            } => BTreeMap::from([
+                ("movie", movie.clone()),
+                ("chain", chain.name(locale).to_string()),
+                ("theater", theater.clone()),
+            ]),
+            Self::Dining { place } | Self::Waiting { place } => {
+                BTreeMap::from([("place", place.clone())])
+            }
+        }
+    }
+
+    pub fn format(&self, locale: Locale) -> String {
+        match (self, locale) {
+            (
+                Self::Bus {
+                    express,
+                    departure,
+                    arrival,
+                },
+                Locale::Ko,
+            ) => {
+                let bus = if *express {
+                    "고속버스"
+                } else {
+                    "시외버스"
+                };
+                format!("{departure}발 {arrival}행 {bus}")
+            }
+            (
+                Self::Bus {
+                    express,
+                    departure,
+                    arrival,
+                },
+                Locale::En,
+            ) => {
+                let bus = if *express {
+                    "Express bus"
+                } else {
+                    "Intercity bus"
+                };
+                format!("{bus} {departure} → {arrival}")
+            }
+            (
+                Self::Movie {
+                    movie,
+                    chain,
+                    theater,
+                },
+                locale,
+            ) => {
+                let chain = chain.name(locale);
+                if theater.starts_with(chain) {
+                    format!("{movie} - {theater}")
+                } else {
+                    format!("{movie} - {chain} {theater}")
+                }
+            }
+            (Self::Dining { place }, _) => place.clone(),
+            (Self::Waiting { place }, Locale::Ko) => format!("[대기] {place}"),
+            (Self::Waiting { place }, Locale::En) => format!("[Waitlist] {place}"),
+        }
+    }
+}
+
+/// Placeholders which title templates of the source can use. `title` is the
+/// title as it is without a template, of any event.
+pub fn placeholders_of(source: &str) -> &'static [&'static str] {
+    match source {
+        "kobus" | "bustago" => &["title", "departure", "arrival", "bus"],
+        "cgv" | "megabox" | "lotte_cinema" => &["title", "movie", "chain", "theater"],
+        "catch_table" => &["title", "place"],
+        _ => &["title"],
+    }
+}
+
+/// Piece of a title template
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits the template into text and `{name}` placeholders. Braces can't be
+/// used as text.
+fn parse_template(template: &str) -> anyhow::Result<Vec<Segment<'_>>> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find(['{', '}']) {
+        anyhow::ensure!(
+            rest[open..].starts_with('{'),
+            "Unmatched `}}` in {template}"
+        );
+        if open > 0 {
+            segments.push(Segment::Text(&rest[..open]));
+        }
+        let close = rest[open..]
+            .find('}')
+            .with_context(|| format!("Unclosed placeholder in {template}"))?;
+        let name = &rest[open + 1..open + close];
+        anyhow::ensure!(
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_'),
+            "Invalid placeholder {{{name}}} in {template}"
+        );
+        segments.push(Segment::Placeholder(name));
+        rest = &rest[open + close + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+
+    Ok(segments)
+}
+
+/// Checks the template uses only `placeholders`, to be rejected on save
+pub fn check_template(template: &str, placeholders: &[&str]) -> anyhow::Result<()> {
+    anyhow::ensure!(!template.trim().is_empty(), "Template is empty");
+    for segment in parse_template(template)? {
+        if let Segment::Placeholder(name) = segment {
+            anyhow::ensure!(
+                placeholders.contains(&name),
+                "Unknown placeholder {{{name}}}, one of {placeholders:?} is expected"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills the placeholders of the template with `parts`, fails when one of
+/// them is not in `parts`
+pub fn render_template(template: &str, parts: &BTreeMap<&str, String>) -> anyhow::Result<String> {
+    parse_template(template)?
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => Ok(text),
+            Segment::Placeholder(name) => parts
+                .get(name)
+                .map(String::as_str)
+                .with_context(|| format!("No {{{name}}} for the title")),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_are_composed_in_locale() {
+        let bus = Title::Bus {
+            express: true,
+            departure: "서울경부".to_string(),
+            arrival: "부산".to_string(),
+        };
+        assert_eq!(bus.format(Locale::Ko), "서울경부발 부산행 고속버스");
+        assert_eq!(bus.format(Locale::En), "Express bus 서울경부 → 부산");
+
+        let movie = Title::Movie {
+            movie: "테스트 무비".to_string(),
+            chain: Chain::LotteCinema,
+            theater: "월드타워".to_string(),
+        };
+        assert_eq!(
+            movie.format(Locale::Ko),
+            "테스트 무비 - 롯데시네마 월드타워"
+        );
+        assert_eq!(
+            movie.format(Locale::En),
+            "테스트 무비 - LOTTE CINEMA 월드타워"
+        );
+
+        // CGV theaters are named with the chain
+        let movie = Title::Movie {
+            movie: "테스트 무비".to_string(),
+            chain: Chain::Cgv,
+            theater: "CGV용산아이파크몰".to_string(),
+        };
+        assert_eq!(movie.format(Locale::En), "테스트 무비 - CGV용산아이파크몰");
+
+        let dining = Title::Dining {
+            place: "스시".to_string(),
+        };
+        assert_eq!(dining.format(Locale::En), "스시");
+
+        let waiting = Title::Waiting {
+            place: "스시".to_string(),
+        };
+        assert_eq!(waiting.format(Locale::default()), "[대기] 스시");
+        assert_eq!(waiting.format(Locale::En), "[Waitlist] 스시");
+    }
+
+    #[test]
+    fn templates_are_filled_with_parts() {
+        let bus = Title::Bus {
+            express: false,
+            departure: "동서울".to_string(),
+            arrival: "속초".to_string(),
+        };
+        let template = "{departure} → {arrival} ({bus})";
+        check_template(template, placeholders_of("bustago")).unwrap();
+        assert_eq!(
+            render_template(template, &bus.parts(Locale::En)).unwrap(),
+            "동서울 → 속초 (Intercity bus)"
+        );
+
+        assert!(check_template("{movie}", placeholders_of("bustago")).is_err());
+        assert!(check_template("{title", placeholders_of("naver")).is_err());
+        assert!(check_template("title}", placeholders_of("naver")).is_err());
+        assert!(check_template("{}", placeholders_of("naver")).is_err());
+        assert!(check_template(" ", placeholders_of("naver")).is_err());
+        // known to the source, but not to the event
+        assert!(render_template("{place}", &BTreeMap::new()).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use axum_server::tls_rustls::RustlsConfig;
+
+/// PEM files to serve HTTPS directly without a reverse proxy, read from
+/// `TLS_CERT` and `TLS_KEY`
+#[derive(Debug, PartialEq)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsConfig {
+    /// `None` to serve plain HTTP when neither is set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Option<Self>> {
+        match (var("TLS_CERT"), var("TLS_KEY")) {
+            (Some(cert), Some(key)) => Ok(Some(Self {
+                cert: cert.into(),
+                key: key.into(),
+            })),
+            (None, None) => Ok(None),
+            _ => Err(anyhow::anyhow!(
+                "TLS_CERT and TLS_KEY have to be set together"
+            )),
+        }
+    }
+
+    pub async fn load(&self) -> anyhow::Result<RustlsConfig> {
+        RustlsConfig::from_pem_file(&self.cert, &self.key)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to load TLS certificate {} and key {}",
+                    self.cert.display(),
+                    self.key.display()
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cert_and_key_are_required_together() {
+        assert_eq!(TlsConfig::from_vars(|_| None).unwrap(), None);
+        assert_eq!(
+            TlsConfig::from_vars(|name| match name {
+                "TLS_CERT" => Some("cert.pem".to_string()),
+                "TLS_KEY" => Some("key.pem".to_string()),
+                _ => None,
+            })
+            .unwrap(),
+            Some(TlsConfig {
+                cert: "cert.pem".into(),
+                key: "key.pem".into(),
+            })
+        );
+        assert!(
+            TlsConfig::from_vars(|name| (name == "TLS_CERT").then(|| "cert.pem".to_string()))
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_files_are_reported() {
+        let config = TlsConfig {
+            cert: "/nonexistent/cert.pem".into(),
+            key: "/nonexistent/key.pem".into(),
+        };
+
+        let error = config.load().await.unwrap_err();
+        assert!(error.to_string().contains("/nonexistent/cert.pem"));
+    }
+}
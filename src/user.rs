@@ -1,12 +1,19 @@
+use std::time::Duration;
+
+use anyhow::Context as _;
 use axum::{
     async_trait,
     response::{IntoResponse as _, Response},
     Extension, Json, Router,
 };
 use axum_sessions::extractors::ReadableSession;
+use futures::{stream::BoxStream, StreamExt as _};
 use hyper::StatusCode;
-use log::{debug, error};
+use log::{debug, error, info};
 use sqlx::SqlitePool;
+use tokio_cron_scheduler::Job;
+
+use crate::{search::FoundReservation, shutdown::Shutdown, CalendarEvent, CrawlStats};
 
 #[repr(transparent)]
 #[derive(
@@ -38,7 +45,7 @@ macro_rules! define_user_data {
         #[base_url = $url:literal]
         struct $name:ident {
             $(
-                #[session_name = $session_name:literal]
+                #[session_name = $session_name:literal $(, $session_optional:ident)?]
                 $session_field_name:ident: $session_field_type:ty,
             )+
             $(
@@ -48,6 +55,7 @@ macro_rules! define_user_data {
     ) => {
         paste::paste!{
             #[derive(serde::Serialize, serde::Deserialize, Default)]
+            #[serde(default)]
             pub struct [<$name Detail>] {
                 $($session_field_name: $session_field_type,)+
                 $($field_name: $field_type,)*
@@ -65,6 +73,71 @@ macro_rules! define_user_data {
                 }
             }
 
+            impl $crate::user::UserDetail for [<$name Detail>] {
+                fn missing_sessions(&self) -> Vec<&'static str> {
+                    let mut missing = Vec::new();
+                    $(
+                        if $crate::session_required!($($session_optional)?)
+                            && self.$session_field_name.trim().is_empty()
+                        {
+                            missing.push($session_name);
+                        }
+                    )+
+                    missing
+                }
+
+                fn required_sessions() -> Vec<&'static str> {
+                    let mut required = Vec::new();
+                    $(
+                        if $crate::session_required!($($session_optional)?) {
+                            required.push(stringify!($session_field_name));
+                        }
+                    )+
+                    required
+                }
+
+                fn saved_sessions(&self) -> Vec<&'static str> {
+                    let mut saved = Vec::new();
+                    $(
+                        if !self.$session_field_name.trim().is_empty() {
+                            saved.push(stringify!($session_field_name));
+                        }
+                    )+
+                    saved
+                }
+
+                fn without_sessions(self) -> Self {
+                    Self {
+                        $($session_field_name: Default::default(),)+
+                        $($field_name: self.$field_name,)*
+                    }
+                }
+
+                fn keep_sessions_of(&mut self, saved: Self, clear: Option<&str>) {
+                    $(
+                        if self.$session_field_name.trim().is_empty()
+                            && clear != Some(stringify!($session_field_name))
+                        {
+                            self.$session_field_name = saved.$session_field_name;
+                        }
+                    )+
+                }
+            }
+
+            impl $crate::user::UserDetail for $name {
+                fn missing_sessions(&self) -> Vec<&'static str> {
+                    let mut missing = Vec::new();
+                    $(
+                        if $crate::session_required!($($session_optional)?)
+                            && self.$session_field_name.trim().is_empty()
+                        {
+                            missing.push($session_name);
+                        }
+                    )+
+                    missing
+                }
+            }
+
             impl From<$name> for [<$name Detail>] {
                 fn from(value: $name) -> Self {
                     Self {
@@ -89,7 +162,9 @@ macro_rules! define_user_data {
                     let endpoint_base = crate::url!($url);
                     let jar = reqwest::cookie::Jar::default();
                     $(
-                        jar.add_cookie_str(&format!("{}={}", $session_name, self.$session_field_name), endpoint_base);
+                        if !self.$session_field_name.trim().is_empty() {
+                            jar.add_cookie_str(&format!("{}={}", $session_name, self.$session_field_name), endpoint_base);
+                        }
                     )+
                     jar
                 }
@@ -111,24 +186,174 @@ macro_rules! define_user_data {
     };
 }
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! session_required {
+    () => {
+        true
+    };
+    (optional) => {
+        false
+    };
+}
+
+pub trait UserDetail {
+    /// Names of required session cookies which are not filled
+    fn missing_sessions(&self) -> Vec<&'static str>;
+
+    /// Fields of session cookies which can't be left blank
+    fn required_sessions() -> Vec<&'static str>
+    where
+        Self: Sized,
+    {
+        Vec::new()
+    }
+
+    /// Fields of session cookies which are filled
+    fn saved_sessions(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Blanks session cookies, not to send them back to the page
+    fn without_sessions(self) -> Self
+    where
+        Self: Sized,
+    {
+        self
+    }
+
+    /// Fills blank session cookies from `saved` except the `clear` field,
+    /// for the page to send only the changed ones
+    fn keep_sessions_of(&mut self, _saved: Self, _clear: Option<&str>)
+    where
+        Self: Sized,
+    {
+    }
+}
+
 #[async_trait]
-pub trait UserImpl: Sized + From<(UserId, Self::Detail)> + Send + Sync + 'static {
+pub trait UserImpl:
+    Sized + UserDetail + From<(UserId, Self::Detail)> + Send + Sync + 'static
+{
     type Detail: serde::Serialize
         + serde::de::DeserializeOwned
         + Default
+        + UserDetail
         + From<Self>
         + Sized
         + Send
         + Sync
         + 'static;
+    /// Key of the source in [`crate::setting::SOURCES`]
+    const SOURCE: &'static str;
+    /// [`Self::SOURCE`] with a trailing slash, which the ids of reservations of
+    /// the source start with
+    const SOURCE_PREFIX: &'static str;
+
+    /// Interval to ping to keep the session warm, apart from fetching.
+    /// `None` if the session doesn't expire.
     const PING_INTERVAL: Option<std::time::Duration>;
+    /// Whether [`Self::fetch_history`] reaches back further than `fetch`, the
+    /// history can be imported only then
+    const QUERIES_BY_DATE: bool = false;
+
+    fn user_id(&self) -> UserId;
 
-    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<bool>;
+    fn all_users(db: &SqlitePool) -> BoxStream<'_, anyhow::Result<Self>>;
+    /// Reservations on the source, parsed but neither stored nor synced. The
+    /// DB is only read, e.g. for the settings of the source.
+    async fn scrape(&self, db: &SqlitePool) -> anyhow::Result<Vec<CalendarEvent>>;
+    async fn fetch(&self, db: SqlitePool) -> anyhow::Result<CrawlStats>;
+    /// Same as `fetch`, but reaching back `days`. Only called when
+    /// [`Self::QUERIES_BY_DATE`] is set.
+    async fn fetch_history(&self, db: SqlitePool, _days: i64) -> anyhow::Result<CrawlStats> {
+        self.fetch(db).await
+    }
+    /// Same as `fetch`, but skipped when required sessions are not set up yet,
+    /// e.g. the row is stored without them
+    async fn fetch_if_set_up(&self, db: SqlitePool) -> anyhow::Result<CrawlStats> {
+        let missing = self.missing_sessions();
+        if !missing.is_empty() {
+            info!("Fetch is skipped, sessions are not set up - {missing:?}");
+            return Ok(CrawlStats::default());
+        }
+        let result = self.fetch(db.clone()).await;
+        // a fetch tells whether the session is alive as well as a ping, other
+        // failures say nothing about it
+        let session_error = match &result {
+            Ok(_) => None,
+            Err(e) if is_session_expired(e) => Some(e),
+            Err(_) => return result,
+        };
+        if let Err(e) =
+            record_session_status(&db, self.user_id(), Self::SOURCE, session_error).await
+        {
+            error!("{e:?}");
+        }
+        let stats = result?;
+        info!(
+            "Crawled {} for {:?} - {stats}",
+            Self::SOURCE,
+            self.user_id()
+        );
+        Ok(stats)
+    }
+    /// Stores fetched reservations, cancelling upcoming ones of the source
+    /// which are not fetched anymore
+    async fn store_reservations(
+        &self,
+        db: &SqlitePool,
+        events: &[CalendarEvent],
+    ) -> anyhow::Result<CrawlStats> {
+        let user_id = self.user_id();
+        let mut stats = CalendarEvent::upsert_events_to_db(user_id, db, events.iter()).await?;
+        stats.cancelled = CalendarEvent::cancel_not_expired_and_not_in(
+            user_id,
+            db,
+            Self::SOURCE_PREFIX,
+            events.iter().map(|event| event.id.as_str()),
+        )
+        .await?;
+        Ok(stats)
+    }
     async fn from_user_id(db: SqlitePool, user_id: UserId) -> anyhow::Result<Option<Self>>;
     async fn update_session(&self, db: SqlitePool) -> anyhow::Result<()>;
+
+    /// Deletes the stored session of the user
+    async fn delete(db: &mut sqlx::SqliteConnection, user_id: UserId) -> anyhow::Result<()>;
+    /// Requests something cheap with the session not to be expired
     async fn ping(&self) -> anyhow::Result<()> {
         Ok(())
     }
+    /// Whether the stored session is still authenticated on the source
+    async fn validate_session(&self) -> anyhow::Result<bool> {
+        match self.ping().await {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                debug!("Ping failed - {e:?}");
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Stored detail with session cookies blanked, `saved` lists the filled ones
+/// and `required` the ones the page has to ask for unless saved
+#[derive(serde::Serialize)]
+struct SavedDetail<D> {
+    #[serde(flatten)]
+    detail: D,
+    saved: Vec<&'static str>,
+    required: Vec<&'static str>,
+}
+
+/// Detail from the page, blank session cookies are kept unless named in `clear`
+#[derive(serde::Deserialize)]
+struct UpdatedDetail<D> {
+    #[serde(flatten)]
+    detail: D,
+    #[serde(default)]
+    clear: Option<String>,
 }
 
 async fn get_info<U: UserImpl>(
@@ -140,31 +365,1002 @@ async fn get_info<U: UserImpl>(
         return StatusCode::FORBIDDEN.into_response();
     };
 
-    let naver_user = U::from_user_id(db, user_id).await.unwrap();
+    let user = match U::from_user_id(db, user_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            error!("Error occurred while get user data - {e:?}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let detail = user.map(U::Detail::from).unwrap_or_default();
 
-    Json(naver_user.map(U::Detail::from).unwrap_or_default()).into_response()
+    Json(SavedDetail {
+        saved: detail.saved_sessions(),
+        required: U::Detail::required_sessions(),
+        detail: detail.without_sessions(),
+    })
+    .into_response()
+}
+
+/// User of the detail from the page with the saved sessions filled, or the
+/// response telling what is missing
+async fn updated_user<U: UserImpl>(
+    db: &SqlitePool,
+    user_id: UserId,
+    UpdatedDetail { mut detail, clear }: UpdatedDetail<U::Detail>,
+) -> Result<U, Response> {
+    match U::from_user_id(db.clone(), user_id).await {
+        Ok(Some(user)) => detail.keep_sessions_of(user.into(), clear.as_deref()),
+        Ok(None) => {}
+        Err(e) => {
+            error!("Error occurred while get user data - {e:?}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    }
+
+    let missing = detail.missing_sessions();
+    if !missing.is_empty() {
+        debug!("Required sessions are missing - {missing:?}");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Required cookies are missing - {}", missing.join(", ")),
+        )
+            .into_response());
+    }
+
+    Ok(U::from((user_id, detail)))
 }
 
 async fn update_info<U: UserImpl>(
     session: ReadableSession,
     Extension(db): Extension<SqlitePool>,
-    Json(data): Json<U::Detail>,
+    Json(updated): Json<UpdatedDetail<U::Detail>>,
 ) -> Response {
     let Some(user_id) = session.get::<UserId>("user_id") else {
         debug!("Not logged in");
         return StatusCode::FORBIDDEN.into_response();
     };
 
-    if let Err(e) = U::from((user_id, data)).update_session(db).await {
-        error!("Error occurred while update naver session data - {e:?}");
+    let user = match updated_user::<U>(&db, user_id, updated).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Err(e) = user.update_session(db).await {
+        error!(
+            "Error occurred while update {} session data - {e:?}",
+            U::SOURCE
+        );
         StatusCode::INTERNAL_SERVER_ERROR.into_response()
     } else {
         StatusCode::ACCEPTED.into_response()
     }
 }
 
+/// Reservations scraped with the sessions from the page, nothing is stored or
+/// synced. The error of the source is shown as it is, to tell what is wrong
+/// with the cookies.
+async fn preview<U: UserImpl>(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+    Json(updated): Json<UpdatedDetail<U::Detail>>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    let user = match updated_user::<U>(&db, user_id, updated).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    match crate::source::with_crawl_permit(user.scrape(&db)).await {
+        Ok(events) => Json(
+            events
+                .into_iter()
+                .map(FoundReservation::from)
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(e) => {
+            debug!("Preview of {} failed - {e:?}", U::SOURCE);
+            (StatusCode::BAD_GATEWAY, format!("{e:#}")).into_response()
+        }
+    }
+}
+
+async fn check_session<U: UserImpl>(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    let user = match U::from_user_id(db, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Error occurred while get user data - {e:?}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    match session_status(&user).await {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => {
+            error!("Error occurred while validate session - {e:?}");
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+/// Removes the source from the user with all of its reservations. Google
+/// events are deleted before the rows, which are kept to retry on failure.
+pub async fn remove_source<U: UserImpl>(db: &SqlitePool, user_id: UserId) -> anyhow::Result<u64> {
+    let prefix = U::SOURCE_PREFIX;
+    // deleted on the next sync even when the rest fails
+    CalendarEvent::invalidate_all_of(user_id, db, prefix).await?;
+
+    #[cfg(not(feature = "crawl_test"))]
+    if let Some(google_user) = crate::google_calendar::GoogleUser::from_user_id(db, user_id).await?
+    {
+        let deleted = google_user.delete_events_of(db, prefix).await?;
+        info!("{deleted} google events of {} are deleted", U::SOURCE);
+    }
+
+    let mut tx = db.begin().await?;
+    U::delete(&mut tx, user_id).await?;
+    let removed = CalendarEvent::delete_all_of(user_id, &mut tx, prefix).await?;
+    sqlx::query!(
+        "DELETE FROM `source_session` WHERE `user_id` = ? AND `source` = ?",
+        user_id,
+        U::SOURCE
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM `source_history` WHERE `user_id` = ? AND `source` = ?",
+        user_id,
+        U::SOURCE
+    )
+    .execute(&mut tx)
+    .await?;
+    let pattern = format!("{prefix}%");
+    sqlx::query!(
+        "DELETE FROM `sync_failure` WHERE `user_id` = ? AND `reservation_id` LIKE ?",
+        user_id,
+        pattern
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(removed)
+}
+
+async fn remove_user<U: UserImpl>(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    match remove_source::<U>(&db, user_id).await {
+        Ok(removed) => {
+            info!(
+                "{} is removed from {user_id:?} with {removed} reservations",
+                U::SOURCE
+            );
+            Json(serde_json::json!({ "removed": removed })).into_response()
+        }
+        Err(e) => {
+            error!("Error occurred while remove {} - {e:?}", U::SOURCE);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Days reached back when the history of a source is imported
+const HISTORY_DAYS: i64 = 180;
+
+async fn history_imported_at(
+    db: &SqlitePool,
+    user_id: UserId,
+    source: &str,
+) -> anyhow::Result<Option<chrono::NaiveDateTime>> {
+    sqlx::query_scalar!(
+        "SELECT `imported_at` FROM `source_history` WHERE `user_id` = ? AND `source` = ?",
+        user_id,
+        source
+    )
+    .fetch_optional(db)
+    .await
+    .context("Failed to get imported history")
+}
+
+/// Fetches the last [`HISTORY_DAYS`] of the source once, `None` when it is
+/// already imported. Crawls after it fetch the usual range again.
+async fn import_history<U: UserImpl>(
+    db: &SqlitePool,
+    user: &U,
+) -> anyhow::Result<Option<CrawlStats>> {
+    let user_id = user.user_id();
+    let now = chrono::Utc::now().naive_utc();
+    // marked first not to import twice at once
+    let marked = sqlx::query!(
+        "INSERT INTO `source_history` (`user_id`, `source`, `imported_at`) VALUES (?, ?, ?)
+        ON CONFLICT DO NOTHING",
+        user_id,
+        U::SOURCE,
+        now
+    )
+    .execute(db)
+    .await
+    .context("Failed to mark history as imported")?
+    .rows_affected();
+    if marked == 0 {
+        return Ok(None);
+    }
+
+    let fetched = crate::correlation::crawl_scope(
+        U::SOURCE,
+        user_id,
+        user.fetch_history(db.clone(), HISTORY_DAYS),
+    )
+    .await;
+    match fetched {
+        Ok(stats) => {
+            info!(
+                "Imported history of {} for {user_id:?} - {stats}",
+                U::SOURCE
+            );
+            Ok(Some(stats))
+        }
+        Err(e) => {
+            // to retry
+            sqlx::query!(
+                "DELETE FROM `source_history` WHERE `user_id` = ? AND `source` = ?",
+                user_id,
+                U::SOURCE
+            )
+            .execute(db)
+            .await
+            .context("Failed to unmark history")?;
+            Err(e)
+        }
+    }
+}
+
+async fn get_history<U: UserImpl>(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    match history_imported_at(&db, user_id, U::SOURCE).await {
+        Ok(imported_at) => Json(serde_json::json!({ "imported_at": imported_at })).into_response(),
+        Err(e) => {
+            error!("Error occurred while get imported history - {e:?}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn post_history<U: UserImpl>(
+    session: ReadableSession,
+    Extension(db): Extension<SqlitePool>,
+) -> Response {
+    let Some(user_id) = session.get::<UserId>("user_id") else {
+        debug!("Not logged in");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    let user = match U::from_user_id(db.clone(), user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Error occurred while get user data - {e:?}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if !user.missing_sessions().is_empty() {
+        return (StatusCode::BAD_REQUEST, "Sessions are not set up").into_response();
+    }
+
+    match import_history(&db, &user).await {
+        Ok(Some(stats)) => Json(stats).into_response(),
+        Ok(None) => (StatusCode::CONFLICT, "History is already imported").into_response(),
+        Err(e) => {
+            error!("Error occurred while import history - {e:?}");
+            (StatusCode::BAD_GATEWAY, format!("{e:#}")).into_response()
+        }
+    }
+}
+
+/// `needs_setup` is set when required sessions are not stored, instead of
+/// asking the source with them
+async fn session_status<U: UserImpl>(user: &U) -> anyhow::Result<serde_json::Value> {
+    if !user.missing_sessions().is_empty() {
+        return Ok(serde_json::json!({ "valid": false, "needs_setup": true }));
+    }
+
+    let valid = user.validate_session().await?;
+    Ok(serde_json::json!({ "valid": valid, "needs_setup": false }))
+}
+
+/// Retries of a failed ping before the session is regarded as expired
+const PING_RETRIES: u32 = 2;
+const PING_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Random delay in `[0, max)`, not to request to a source for all users at once
+fn jitter(max: Duration) -> Duration {
+    max.mul_f64(rand::random::<f64>())
+}
+
+/// Delay before the `attempt`th retry, doubled on each retry with jitter
+fn retry_delay(attempt: u32) -> Duration {
+    let delay = PING_RETRY_DELAY * 2u32.pow(attempt);
+    delay + jitter(delay)
+}
+
+/// The source answered that the stored session is not authenticated anymore,
+/// e.g. 401 or the login page, rather than failing or having no reservations.
+/// It is not retried, the user has to paste the session again.
+#[derive(Debug)]
+pub struct SessionExpired(pub String);
+
+impl std::fmt::Display for SessionExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for SessionExpired {}
+
+fn is_session_expired(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<SessionExpired>())
+}
+
+/// `None` when shutting down while waiting for a retry
+async fn ping_with_retry<U: UserImpl>(user: &U, shutdown: &Shutdown) -> Option<anyhow::Result<()>> {
+    let mut attempt = 0;
+    loop {
+        match user.ping().await {
+            Ok(()) => return Some(Ok(())),
+            Err(e) if attempt < PING_RETRIES && !is_session_expired(&e) => {
+                debug!("Ping failed, retry - {e:?}");
+                if !shutdown.sleep(retry_delay(attempt)).await {
+                    return None;
+                }
+                attempt += 1;
+            }
+            Err(e) => return Some(Err(e)),
+        }
+    }
+}
+
+/// Keeps the result of the last ping or fetch, the session is expired when
+/// `error` is given
+async fn record_session_status(
+    db: &SqlitePool,
+    user_id: UserId,
+    source: &str,
+    error: Option<&anyhow::Error>,
+) -> anyhow::Result<()> {
+    let expired = error.is_some();
+    let last_error = error.map(|e| format!("{e:#}"));
+    let now = chrono::Utc::now().naive_utc();
+    sqlx::query!(
+        r#"INSERT INTO `source_session` (`user_id`, `source`, `expired`, `last_error`, `checked_at`)
+        VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT DO UPDATE SET
+        `expired`=`excluded`.`expired`, `last_error`=`excluded`.`last_error`,
+        `checked_at`=`excluded`.`checked_at`"#,
+        user_id,
+        source,
+        expired,
+        last_error,
+        now
+    )
+    .execute(db)
+    .await
+    .context("Failed to record session status")
+    .map(|_| ())
+}
+
+async fn ping_all<U: UserImpl>(db: &SqlitePool, interval: Duration, shutdown: &Shutdown) {
+    let source = U::SOURCE;
+    let users = U::all_users(db).collect::<Vec<_>>().await;
+    futures::future::join_all(users.into_iter().map(|user| async move {
+        let user = match user {
+            Ok(user) => user,
+            Err(e) => {
+                error!("Failed to get user to ping for {source} - {e:?}");
+                return;
+            }
+        };
+
+        // spread over the first quarter of the interval
+        if !shutdown.sleep(jitter(interval / 4)).await {
+            return;
+        }
+        let Some(result) = ping_with_retry(&user, shutdown).await else {
+            return;
+        };
+        // only an answer about the session tells it is expired, like fetches
+        let session_error = match &result {
+            Ok(()) => {
+                info!("Success ping for {source}");
+                None
+            }
+            Err(e) if is_session_expired(e) => {
+                error!("Session of {source} is expired - {e:?}");
+                Some(e)
+            }
+            Err(e) => {
+                error!("Failed to ping for {source} - {e:?}");
+                return;
+            }
+        };
+        if let Err(e) = record_session_status(db, user.user_id(), source, session_error).await {
+            error!("{e:?}");
+        }
+    }))
+    .await;
+}
+
+/// Job pinging all users of `U` every `PING_INTERVAL`,
+/// `None` if the source doesn't need it
+pub fn ping_job<U: UserImpl>(db: SqlitePool, shutdown: Shutdown) -> anyhow::Result<Option<Job>> {
+    let Some(interval) = U::PING_INTERVAL else {
+        return Ok(None);
+    };
+
+    Ok(Some(Job::new_repeated_async(interval, move |_, _| {
+        let db = db.clone();
+        let shutdown = shutdown.clone();
+        Box::pin(async move {
+            shutdown.run(ping_all::<U>(&db, interval, &shutdown)).await;
+        })
+    })?))
+}
+
 pub fn user_web_router<U: UserImpl>() -> Router {
-    Router::new()
+    let router = Router::new()
         .route("/user", axum::routing::get(get_info::<U>))
         .route("/user", axum::routing::post(update_info::<U>))
+        .route("/user", axum::routing::delete(remove_user::<U>))
+        .route("/user/session", axum::routing::get(check_session::<U>))
+        .route("/user/preview", axum::routing::post(preview::<U>));
+    // a history import of the other sources would be a plain fetch
+    if !U::QUERIES_BY_DATE {
+        return router;
+    }
+
+    router
+        .route("/user/history", axum::routing::get(get_history::<U>))
+        .route("/user/history", axum::routing::post(post_history::<U>))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::kobus::{KobusUser, KobusUserDetail};
+
+    static PINGS: AtomicUsize = AtomicUsize::new(0);
+    static FETCHES: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Default, serde::Serialize, serde::Deserialize)]
+    struct CountingUserDetail;
+
+    struct CountingUser;
+
+    impl UserDetail for CountingUserDetail {
+        fn missing_sessions(&self) -> Vec<&'static str> {
+            Vec::new()
+        }
+    }
+
+    impl UserDetail for CountingUser {
+        fn missing_sessions(&self) -> Vec<&'static str> {
+            Vec::new()
+        }
+    }
+
+    impl From<CountingUser> for CountingUserDetail {
+        fn from(_: CountingUser) -> Self {
+            Self
+        }
+    }
+
+    impl From<(UserId, CountingUserDetail)> for CountingUser {
+        fn from(_: (UserId, CountingUserDetail)) -> Self {
+            Self
+        }
+    }
+
+    #[async_trait]
+    impl UserImpl for CountingUser {
+        type Detail = CountingUserDetail;
+        const SOURCE: &'static str = "counting";
+        const SOURCE_PREFIX: &'static str = "counting/";
+        const PING_INTERVAL: Option<std::time::Duration> = Some(std::time::Duration::from_secs(1));
+
+        fn user_id(&self) -> UserId {
+            UserId(1)
+        }
+
+        fn all_users(_: &SqlitePool) -> BoxStream<'_, anyhow::Result<Self>> {
+            futures::stream::iter([Ok(Self)]).boxed()
+        }
+
+        async fn scrape(&self, _: &SqlitePool) -> anyhow::Result<Vec<CalendarEvent>> {
+            Ok(Vec::new())
+        }
+
+        async fn fetch(&self, _: SqlitePool) -> anyhow::Result<CrawlStats> {
+            FETCHES.fetch_add(1, Ordering::SeqCst);
+            Ok(CrawlStats::default())
+        }
+
+        // not counted as a fetch, other tests import the history concurrently
+        async fn fetch_history(&self, _: SqlitePool, _: i64) -> anyhow::Result<CrawlStats> {
+            Ok(CrawlStats::default())
+        }
+
+        async fn from_user_id(_: SqlitePool, _: UserId) -> anyhow::Result<Option<Self>> {
+            Ok(Some(Self))
+        }
+
+        async fn update_session(&self, _: SqlitePool) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn delete(_: &mut sqlx::SqliteConnection, _: UserId) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn ping(&self) -> anyhow::Result<()> {
+            PINGS.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn ping_and_fetch_run_on_their_own_intervals() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        assert!(
+            ping_job::<crate::naver_reservation::NaverUser>(db.clone(), Shutdown::default())
+                .unwrap()
+                .is_none()
+        );
+        assert!(ping_job::<CountingUser>(db.clone(), Shutdown::default())
+            .unwrap()
+            .is_some());
+
+        // the scheduler ticks on the wall clock, its repeated jobs are driven
+        // on the paused clock here instead, first run after one interval.
+        // Session statuses fail on the closed pool at once, not to wait for
+        // the database while the clock is paused.
+        db.close().await;
+        tokio::time::pause();
+        let repeat = |interval: Duration| {
+            tokio::time::interval_at(tokio::time::Instant::now() + interval, interval)
+        };
+        let ping_interval = CountingUser::PING_INTERVAL.unwrap();
+        let mut ping_ticks = repeat(ping_interval);
+        let pings = tokio::spawn({
+            let db = db.clone();
+            async move {
+                let shutdown = Shutdown::default();
+                loop {
+                    ping_ticks.tick().await;
+                    ping_all::<CountingUser>(&db, ping_interval, &shutdown).await;
+                }
+            }
+        });
+        let mut fetch_ticks = repeat(Duration::from_secs(2));
+        let fetches = tokio::spawn(async move {
+            loop {
+                fetch_ticks.tick().await;
+                CountingUser.fetch_if_set_up(db.clone()).await.unwrap();
+            }
+        });
+
+        // pings are spread over the first quarter of the interval
+        for _ in 0..250 {
+            tokio::time::advance(Duration::from_millis(10)).await;
+        }
+        assert_eq!(PINGS.load(Ordering::SeqCst), 2);
+        assert_eq!(FETCHES.load(Ordering::SeqCst), 1);
+
+        pings.abort();
+        fetches.abort();
+    }
+
+    static PREVIEW_WRITES: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Default, serde::Serialize, serde::Deserialize)]
+    struct PreviewUserDetail {
+        token: String,
+    }
+
+    struct PreviewUser(PreviewUserDetail);
+
+    impl UserDetail for PreviewUserDetail {
+        fn missing_sessions(&self) -> Vec<&'static str> {
+            if self.token.is_empty() {
+                vec!["token"]
+            } else {
+                Vec::new()
+            }
+        }
+    }
+
+    impl UserDetail for PreviewUser {
+        fn missing_sessions(&self) -> Vec<&'static str> {
+            self.0.missing_sessions()
+        }
+    }
+
+    impl From<PreviewUser> for PreviewUserDetail {
+        fn from(user: PreviewUser) -> Self {
+            user.0
+        }
+    }
+
+    impl From<(UserId, PreviewUserDetail)> for PreviewUser {
+        fn from((_, detail): (UserId, PreviewUserDetail)) -> Self {
+            Self(detail)
+        }
+    }
+
+    /// Scrapes a reservation named after the token, counts anything written
+    #[async_trait]
+    impl UserImpl for PreviewUser {
+        type Detail = PreviewUserDetail;
+        const SOURCE: &'static str = "preview";
+        const SOURCE_PREFIX: &'static str = "preview/";
+        const PING_INTERVAL: Option<std::time::Duration> = None;
+
+        fn user_id(&self) -> UserId {
+            UserId(1)
+        }
+
+        fn all_users(_: &SqlitePool) -> BoxStream<'_, anyhow::Result<Self>> {
+            futures::stream::empty().boxed()
+        }
+
+        async fn scrape(&self, _: &SqlitePool) -> anyhow::Result<Vec<CalendarEvent>> {
+            let event = CalendarEvent::builder(
+                "preview/1",
+                format!("예약 {}", self.0.token),
+                chrono::NaiveDate::from_ymd_opt(2024, 5, 1).unwrap(),
+            )
+            .build()?;
+            Ok(vec![event])
+        }
+
+        async fn fetch(&self, db: SqlitePool) -> anyhow::Result<CrawlStats> {
+            PREVIEW_WRITES.fetch_add(1, Ordering::SeqCst);
+            let events = self.scrape(&db).await?;
+            self.store_reservations(&db, &events).await
+        }
+
+        async fn from_user_id(_: SqlitePool, _: UserId) -> anyhow::Result<Option<Self>> {
+            Ok(None)
+        }
+
+        async fn update_session(&self, _: SqlitePool) -> anyhow::Result<()> {
+            PREVIEW_WRITES.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn delete(_: &mut sqlx::SqliteConnection, _: UserId) -> anyhow::Result<()> {
+            PREVIEW_WRITES.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn history_is_served_only_for_sources_querying_by_date() {
+        use axum_sessions::{async_session::MemoryStore, SessionLayer};
+        use hyper::service::Service as _;
+
+        let db = crate::testing::db().await;
+        let layered = |router: Router| {
+            router
+                .layer(Extension(db.clone()))
+                .layer(SessionLayer::new(MemoryStore::new(), &[0; 64]))
+        };
+        let mut router = layered(user_web_router::<PreviewUser>());
+        for request in [
+            hyper::Request::get("/user/history"),
+            hyper::Request::post("/user/history"),
+        ] {
+            let response = router
+                .call(request.body(hyper::Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+
+        // not logged in
+        let mut router = layered(user_web_router::<crate::cgv::CgvUser>());
+        let response = router
+            .call(
+                hyper::Request::get("/user/history")
+                    .body(hyper::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn preview_stores_nothing() {
+        use axum_sessions::{async_session::MemoryStore, SessionLayer};
+        use hyper::service::Service as _;
+
+        let db = crate::testing::db().await;
+        let mut router: Router = user_web_router::<PreviewUser>()
+            .route(
+                "/login",
+                axum::routing::get(
+                    |mut session: axum_sessions::extractors::WritableSession| async move {
+                        session.insert("user_id", UserId(1)).unwrap();
+                    },
+                ),
+            )
+            .layer(Extension(db.clone()))
+            .layer(SessionLayer::new(MemoryStore::new(), &[0; 64]));
+        let preview = |cookie: Option<&str>, body: serde_json::Value| {
+            let mut request = hyper::Request::post("/user/preview")
+                .header(hyper::header::CONTENT_TYPE, "application/json");
+            if let Some(cookie) = cookie {
+                request = request.header(hyper::header::COOKIE, cookie);
+            }
+            request.body(hyper::Body::from(body.to_string())).unwrap()
+        };
+
+        let response = router
+            .call(preview(None, serde_json::json!({ "token": "abc" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let login = router
+            .call(
+                hyper::Request::get("/login")
+                    .body(hyper::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let cookie = login.headers()[hyper::header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+
+        let response = router
+            .call(preview(Some(&cookie), serde_json::json!({ "token": "" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = router
+            .call(preview(
+                Some(&cookie),
+                serde_json::json!({ "token": "abc" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let found = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert_eq!(found.as_array().unwrap().len(), 1);
+        assert_eq!(found[0]["id"], "preview/1");
+        assert_eq!(found[0]["title"], "예약 abc");
+
+        // neither the session nor the reservations are stored or synced
+        assert_eq!(PREVIEW_WRITES.load(Ordering::SeqCst), 0);
+        for table in [
+            "reservation",
+            "google_event",
+            "source_session",
+            "sync_failure",
+        ] {
+            let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM `{table}`"))
+                .fetch_one(&db)
+                .await
+                .unwrap();
+            assert_eq!(count, 0, "{table}");
+        }
+    }
+
+    #[tokio::test]
+    async fn source_without_credentials_needs_setup() {
+        let detail: KobusUserDetail =
+            serde_json::from_value(serde_json::json!({ "jsessionid": "" })).unwrap();
+        let user = KobusUser::from((UserId(1), detail));
+
+        assert_eq!(user.missing_sessions(), vec!["JSESSIONID"]);
+        assert_eq!(
+            session_status(&user).await.unwrap(),
+            serde_json::json!({ "valid": false, "needs_setup": true })
+        );
+    }
+
+    #[test]
+    fn blank_sessions_keep_saved_ones() {
+        use crate::megabox::MegaboxUserDetail;
+
+        let saved: MegaboxUserDetail =
+            serde_json::from_value(serde_json::json!({ "jsessionid": "java", "session": "login" }))
+                .unwrap();
+        assert_eq!(saved.saved_sessions(), vec!["jsessionid", "session"]);
+        assert_eq!(MegaboxUserDetail::required_sessions(), vec!["session"]);
+        let shown = serde_json::to_value(saved.without_sessions()).unwrap();
+        assert_eq!(
+            shown,
+            serde_json::json!({ "jsessionid": "", "session": "" })
+        );
+
+        let saved = || {
+            serde_json::from_value::<MegaboxUserDetail>(
+                serde_json::json!({ "jsessionid": "java", "session": "login" }),
+            )
+            .unwrap()
+        };
+        let mut detail: MegaboxUserDetail =
+            serde_json::from_value(serde_json::json!({ "session": "renewed" })).unwrap();
+        detail.keep_sessions_of(saved(), None);
+        assert_eq!(
+            serde_json::to_value(&detail).unwrap(),
+            serde_json::json!({ "jsessionid": "java", "session": "renewed" })
+        );
+
+        let mut detail: MegaboxUserDetail = serde_json::from_value(serde_json::json!({})).unwrap();
+        detail.keep_sessions_of(saved(), Some("jsessionid"));
+        assert_eq!(
+            serde_json::to_value(&detail).unwrap(),
+            serde_json::json!({ "jsessionid": "", "session": "login" })
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_ping_marks_session_expired() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        let expired = || async {
+            sqlx::query!(
+                "SELECT `expired`, `last_error` FROM `source_session` WHERE `user_id` = 1 AND `source` = 'cgv'"
+            )
+            .fetch_one(&db)
+            .await
+            .map(|row| (row.expired, row.last_error))
+            .unwrap()
+        };
+
+        record_session_status(&db, UserId(1), "cgv", Some(&anyhow::anyhow!("login page")))
+            .await
+            .unwrap();
+        assert_eq!(expired().await, (true, Some("login page".to_string())));
+
+        record_session_status(&db, UserId(1), "cgv", None)
+            .await
+            .unwrap();
+        assert_eq!(expired().await, (false, None));
+    }
+
+    #[test]
+    fn session_expired_is_told_from_other_failures() {
+        let expired = anyhow::Error::from(SessionExpired("login page".to_string()))
+            .context("Error occurred while sending ping");
+        assert!(is_session_expired(&expired));
+        assert!(!is_session_expired(&anyhow::anyhow!("connection reset")));
+    }
+
+    #[test]
+    fn retry_delay_grows_with_jitter() {
+        for attempt in 0..3 {
+            let base = PING_RETRY_DELAY * 2u32.pow(attempt);
+            let delay = retry_delay(attempt);
+            assert!(base <= delay && delay < base * 2, "{delay:?}");
+        }
+        assert!(jitter(Duration::from_secs(60)) < Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn stored_reservations_cancel_missing_ones_of_source() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        let detail: KobusUserDetail =
+            serde_json::from_value(serde_json::json!({ "jsessionid": "session" })).unwrap();
+        let user = KobusUser::from((UserId(1), detail));
+        let upcoming = |id| {
+            CalendarEvent::builder(
+                id,
+                "title",
+                chrono::Utc::now().date_naive() + chrono::Duration::days(7),
+            )
+            .build()
+            .unwrap()
+        };
+
+        let stats = user
+            .store_reservations(&db, &[upcoming("kobus/1"), upcoming("kobus/2")])
+            .await
+            .unwrap();
+        assert_eq!((stats.new, stats.cancelled), (2, 0));
+
+        let stats = user
+            .store_reservations(&db, &[upcoming("kobus/1")])
+            .await
+            .unwrap();
+        assert_eq!((stats.new, stats.cancelled), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn removed_source_takes_its_reservations() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+        let user_id = UserId(1);
+        sqlx::query("INSERT INTO `kobus_user` (`user_id`, `jsessionid`) VALUES (1, 'session')")
+            .execute(&db)
+            .await
+            .unwrap();
+        for id in ["kobus/1", "kobus/2", "cgv/1"] {
+            let event = CalendarEvent::builder(id, "title", chrono::Utc::now().date_naive())
+                .build()
+                .unwrap();
+            CalendarEvent::upsert_events_to_db(user_id, &db, [event].iter())
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(remove_source::<KobusUser>(&db, user_id).await.unwrap(), 2);
+
+        assert!(KobusUser::from_user_id(db.clone(), user_id)
+            .await
+            .unwrap()
+            .is_none());
+        let ids: Vec<String> = sqlx::query_scalar("SELECT `id` FROM `reservation`")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(ids, vec!["cgv/1"]);
+    }
+
+    #[tokio::test]
+    async fn history_is_imported_once() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&db).await.unwrap();
+
+        assert!(history_imported_at(&db, UserId(1), CountingUser::SOURCE)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            import_history(&db, &CountingUser).await.unwrap(),
+            Some(CrawlStats::default())
+        );
+        assert!(history_imported_at(&db, UserId(1), CountingUser::SOURCE)
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(import_history(&db, &CountingUser).await.unwrap(), None);
+    }
 }
@@ -1,5 +1,17 @@
 export type AsyncReturnType<T extends (...args: any) => any> = T extends (...args: any) => Promise<infer R> ? R : never;
 
+// names of the sources, keyed by the id prefix of their reservations
+export const SOURCES = {
+    naver: "Naver",
+    kobus: "kobus",
+    catch_table: "catch table",
+    cgv: "cgv",
+    megabox: "MEGABOX",
+    lotte_cinema: "LOTTE CINEMA",
+    bustago: "Bustago",
+    ical: "iCal",
+} as const;
+
 export function formDataToJsonString(data: FormData) {
     const ret: Record<string, string> = {};
     data.forEach((value, key) => {
@@ -7,3 +19,51 @@ export function formDataToJsonString(data: FormData) {
     });
     return JSON.stringify(ret);
 }
+
+// Value of the field, blank when the form doesn't have it
+export function formValue(data: FormData, name: string) {
+    return data.get(name)?.toString() ?? "";
+}
+
+// Reservation as the server shows it, date and time in KST
+export interface FoundReservation {
+    id: string,
+    source: string,
+    title: string,
+    detail: string,
+    date_begin: string,
+    time_begin: string,
+    date_end: string,
+    time_end: string,
+    location: string | null,
+    url: string | null,
+    invalid: boolean,
+}
+
+export interface ActionResult {
+    error: string | null;
+}
+
+// Sends request and turns failure into a message to show instead of throwing
+export async function request(url: string, init: RequestInit): Promise<ActionResult> {
+    try {
+        const resp = await fetch(url, { credentials: "same-origin", ...init });
+        if (resp.ok) {
+            return { error: null };
+        }
+        const message = await resp.text();
+        return { error: message !== "" ? message : `${resp.status} ${resp.statusText}` };
+    } catch (e) {
+        return { error: `${e}` };
+    }
+}
+
+export async function postJson(url: string, body: string): Promise<ActionResult> {
+    return await request(url, {
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        method: "post",
+        body,
+    });
+}
@@ -0,0 +1,191 @@
+//! Recorded HTTP sessions of the sources, to run crawlers in tests without
+//! reaching the services. Requests sent by [`crate::http::execute`] in
+//! [`Cassette::scope`] are answered from the cassette file, or recorded into it
+//! when it doesn't exist yet or `VCR_RECORD=1` is set. Only built for tests
+//! and with the `vcr` feature.
+
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+
+use anyhow::Context as _;
+use log::info;
+use tokio::sync::Mutex;
+
+tokio::task_local! {
+    static CASSETTE: Arc<Cassette>;
+}
+
+/// Request and its response. The body of the request is not kept to be
+/// matched, as it often has the date of the crawl.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Interaction {
+    method: String,
+    url: String,
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    body: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Replay,
+    Record,
+}
+
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+    interactions: Mutex<Vec<Interaction>>,
+}
+
+impl Cassette {
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let record = matches!(std::env::var("VCR_RECORD").as_deref(), Ok("1"));
+        let (mode, interactions) = if !record && path.exists() {
+            let file = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let interactions = serde_json::from_str(&file)
+                .with_context(|| format!("Invalid cassette {}", path.display()))?;
+            (Mode::Replay, interactions)
+        } else {
+            info!("Recording HTTP session into {}", path.display());
+            (Mode::Record, Vec::new())
+        };
+
+        Ok(Self {
+            path,
+            mode,
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    /// Runs `future` with the cassette, and writes the recorded session after
+    /// it is done
+    pub async fn scope<F: std::future::Future>(self, future: F) -> anyhow::Result<F::Output> {
+        let cassette = Arc::new(self);
+        let output = CASSETTE.scope(cassette.clone(), future).await;
+        if cassette.mode == Mode::Record {
+            let interactions = cassette.interactions.lock().await;
+            if let Some(parent) = cassette.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(
+                &cassette.path,
+                serde_json::to_string_pretty(&*interactions)?,
+            )
+            .with_context(|| format!("Failed to write {}", cassette.path.display()))?;
+        }
+
+        Ok(output)
+    }
+
+    pub(crate) async fn execute(
+        &self,
+        client: &reqwest::Client,
+        req: reqwest::Request,
+    ) -> anyhow::Result<reqwest::Response> {
+        let method = req.method().to_string();
+        let url = req.url().to_string();
+        let interaction = match self.mode {
+            Mode::Replay => {
+                let mut interactions = self.interactions.lock().await;
+                let index = interactions
+                    .iter()
+                    .position(|interaction| interaction.method == method && interaction.url == url)
+                    .with_context(|| format!("No recorded response for {method} {url}"))?;
+                interactions.remove(index)
+            }
+            Mode::Record => {
+                let res = crate::dump::RawResponse::read(client.execute(req).await?).await?;
+                let interaction = Interaction {
+                    method,
+                    url,
+                    status: res.status.as_u16(),
+                    headers: res
+                        .headers
+                        .iter()
+                        .filter(|(name, _)| !crate::dump::REDACTED_HEADERS.contains(name))
+                        .filter_map(|(name, value)| {
+                            Some((name.to_string(), value.to_str().ok()?.to_string()))
+                        })
+                        .collect(),
+                    body: String::from_utf8(res.body.to_vec())
+                        .context("Binary responses can't be recorded")?,
+                };
+                self.interactions.lock().await.push(interaction.clone());
+                interaction
+            }
+        };
+
+        let mut res = hyper::http::Response::builder().status(interaction.status);
+        for (name, value) in &interaction.headers {
+            res = res.header(name, value);
+        }
+        Ok(res
+            .body(interaction.body)
+            .context("Invalid recorded response")?
+            .into())
+    }
+}
+
+/// Cassette of the current scope
+pub(crate) fn current() -> Option<Arc<Cassette>> {
+    CASSETTE.try_with(Arc::clone).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn session_is_replayed_after_recorded() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(
+            axum::Server::from_tcp(listener).unwrap().serve(
+                axum::Router::new()
+                    .route("/list", axum::routing::get(|| async { "first" }))
+                    .route("/detail", axum::routing::post(|| async { "second" }))
+                    .into_make_service(),
+            ),
+        );
+        let path = std::env::temp_dir().join(format!("calendar-hub-{}.json", uuid::Uuid::new_v4()));
+        let crawl = || async {
+            let client = crate::http::client();
+            let mut bodies = Vec::new();
+            for req in [
+                client.get(format!("http://{address}/list")),
+                client.post(format!("http://{address}/detail")),
+            ] {
+                let res = crate::http::execute(&client, req.build().unwrap())
+                    .await
+                    .unwrap();
+                bodies.push(res.text().await.unwrap());
+            }
+            bodies
+        };
+
+        let recorded = Cassette::load(&path).unwrap().scope(crawl()).await.unwrap();
+        assert_eq!(recorded, ["first", "second"]);
+
+        server.abort();
+        let replayed = Cassette::load(&path).unwrap().scope(crawl()).await.unwrap();
+        assert_eq!(replayed, recorded);
+
+        // not recorded
+        let client = crate::http::client();
+        let req = client
+            .get(format!("http://{address}/other"))
+            .build()
+            .unwrap();
+        let result = Cassette::load(&path)
+            .unwrap()
+            .scope(crate::http::execute(&client, req))
+            .await
+            .unwrap();
+        assert!(result.is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}